@@ -0,0 +1,221 @@
+//! Handing every resolved address to an address aware connector.
+
+use futures::future::{BoxFuture, FutureExt};
+use hyper::service::Service;
+use hyper::Uri;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, SocketAddr, SocketAddrV6};
+use std::sync::PoisonError;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::dial::is_link_local;
+use crate::{pre_connect, Connection, DnsConnector};
+
+/// A connector which is given every address a host resolved to, so that it
+/// can implement its own retry or racing logic.
+pub trait AddrConnect {
+    /// The connection established.
+    type Transport: AsyncRead + AsyncWrite + Send + 'static;
+    /// The future establishing the connection.
+    type Future: Future<Output = io::Result<Self::Transport>> + Send + 'static;
+
+    /// Connect for the request to `uri` using `addrs`, which are ordered with
+    /// the address picked by the selection policy first followed by the rest
+    /// in order of preference.
+    fn connect(&self, uri: &Uri, addrs: Vec<SocketAddr>) -> Self::Future;
+}
+
+/// A connector which resolves hosts using a `DnsConnector`, and passes the
+/// complete list of addresses to an `AddrConnect` rather than a URI for a
+/// single address.
+///
+/// Settings on the `DnsConnector` which govern connecting, such as retrying and
+/// racing, do not apply as these are left to the `AddrConnect`.
+#[derive(Debug, Clone)]
+pub struct AddrsConnector<A> {
+    dns: DnsConnector<A>,
+}
+
+impl<A> AddrsConnector<A>
+where
+    A: AddrConnect,
+{
+    /// Wrap a `DnsConnector` which was created with an `AddrConnect`.
+    pub fn new(dns: DnsConnector<A>) -> AddrsConnector<A> {
+        AddrsConnector { dns: dns }
+    }
+}
+
+impl<A> Service<Uri> for AddrsConnector<A>
+where
+    A: AddrConnect + Send + 'static,
+{
+    type Response = Connection<A::Transport>;
+    type Error = io::Error;
+    type Future = BoxFuture<'static, io::Result<Connection<A::Transport>>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let connector = self.dns.connector.clone();
+        let interceptors = self.dns.interceptors.clone();
+        let scope_id = self
+            .dns
+            .ipv6_zone
+            .as_ref()
+            .and_then(|zone| zone.parse::<u32>().ok());
+
+        let resolving = self.dns.resolve(&uri, false);
+        let future = async move {
+            let resolved = resolving.await?;
+            let mut uri = uri;
+            pre_connect(&interceptors, &mut uri)?;
+
+            let resolution = resolved.resolution;
+            // A port of 0, as an SRV record may have, is as good as none.
+            let port = resolution
+                .port
+                .filter(|&port| port != 0)
+                .unwrap_or_else(|| default_port(uri.scheme_str().unwrap_or("http")));
+
+            let mut addrs = Vec::with_capacity(resolution.addrs.len());
+            addrs.push(socket_addr(resolution.ip, port, scope_id));
+            addrs.extend(
+                resolution
+                    .addrs
+                    .iter()
+                    .filter(|ip| **ip != resolution.ip)
+                    .map(|ip| socket_addr(*ip, port, scope_id)),
+            );
+
+            let connecting = connector
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .connect(&uri, addrs);
+            let io = connecting.await?;
+            Ok(Connection::new(io, resolution, resolved.elapsed, None))
+        };
+
+        future.boxed()
+    }
+}
+
+/// The address to connect to for `ip`, scoped to `scope_id` if it is
+/// link-local.
+fn socket_addr(ip: IpAddr, port: u16, scope_id: Option<u32>) -> SocketAddr {
+    match (ip, scope_id) {
+        (IpAddr::V6(v6), Some(scope_id)) if is_link_local(&ip) => {
+            SocketAddr::V6(SocketAddrV6::new(v6, port, 0, scope_id))
+        }
+        _ => SocketAddr::new(ip, port),
+    }
+}
+
+/// The port to use for `scheme` when the request did not specify one.
+pub(crate) fn default_port(scheme: &str) -> u16 {
+    match scheme {
+        "https" => 443,
+        _ => 80,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Interceptor, Query, Resolution};
+    use futures::executor::block_on;
+    use futures::future;
+    use std::io::Cursor;
+    use std::net::IpAddr;
+    use std::sync::{Arc, Mutex};
+
+    /// Resolves every host to `addrs`, with `port`.
+    struct Fixed {
+        addrs: Vec<IpAddr>,
+        port: Option<u16>,
+    }
+
+    impl Interceptor for Fixed {
+        fn pre_query(&self, _query: &mut Query) -> io::Result<Option<Resolution>> {
+            Ok(Some(Resolution {
+                ip: self.addrs[0],
+                addrs: self.addrs.clone(),
+                port: self.port,
+                record_type: None,
+                ttl: None,
+                nameserver: None,
+                chain: Vec::new(),
+            }))
+        }
+    }
+
+    /// Records the addresses it is asked to connect to.
+    #[derive(Clone, Default)]
+    struct Record {
+        addrs: Arc<Mutex<Vec<SocketAddr>>>,
+    }
+
+    impl AddrConnect for Record {
+        type Transport = Cursor<Vec<u8>>;
+        type Future = future::Ready<io::Result<Cursor<Vec<u8>>>>;
+
+        fn connect(&self, _uri: &Uri, addrs: Vec<SocketAddr>) -> Self::Future {
+            *self.addrs.lock().unwrap() = addrs;
+            future::ok(Cursor::new(Vec::new()))
+        }
+    }
+
+    fn connect(uri: &str, addrs: &[&str], port: Option<u16>) -> Vec<SocketAddr> {
+        let record = Record::default();
+        let mut dns = DnsConnector::new("127.0.0.1:53".parse().unwrap(), record.clone());
+        dns.add_interceptor(Fixed {
+            addrs: addrs.iter().map(|addr| addr.parse().unwrap()).collect(),
+            port: port,
+        });
+
+        block_on(AddrsConnector::new(dns).call(uri.parse().unwrap())).unwrap();
+        let addrs = record.addrs.lock().unwrap().clone();
+        addrs
+    }
+
+    #[test]
+    fn uses_the_default_port_for_the_scheme() {
+        assert_eq!(
+            connect("http://foo", &["10.0.0.1"], None),
+            vec!["10.0.0.1:80".parse().unwrap()]
+        );
+        assert_eq!(
+            connect("https://foo", &["10.0.0.1"], None),
+            vec!["10.0.0.1:443".parse().unwrap()]
+        );
+        assert_eq!(
+            connect("https://foo", &["10.0.0.1"], Some(8443)),
+            vec!["10.0.0.1:8443".parse().unwrap()]
+        );
+    }
+
+    #[test]
+    fn fills_in_a_port_of_zero() {
+        assert_eq!(
+            connect("https://foo", &["10.0.0.1"], Some(0)),
+            vec!["10.0.0.1:443".parse().unwrap()]
+        );
+    }
+
+    #[test]
+    fn falls_back_across_every_address() {
+        let addrs: Vec<SocketAddr> = vec![
+            "10.0.0.2:80".parse().unwrap(),
+            "10.0.0.1:80".parse().unwrap(),
+            "[::1]:80".parse().unwrap(),
+        ];
+        assert_eq!(
+            connect("http://foo", &["10.0.0.2", "10.0.0.1", "::1"], None),
+            addrs
+        );
+    }
+}
@@ -0,0 +1,117 @@
+//! Resolution on a background thread, for callers which need lookups that
+//! can be sent between threads.
+
+use futures::channel::mpsc;
+use futures::{FutureExt, StreamExt};
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::thread;
+use tokio::runtime;
+
+use crate::DnsResolver;
+
+/// Called with the addresses a host resolved to.
+type Reply = Box<dyn FnMut(io::Result<Vec<SocketAddr>>) + Send>;
+
+/// A lookup for the background thread, and where to send its addresses.
+struct Lookup {
+    host: String,
+    port: u16,
+    reply: Reply,
+}
+
+/// A handle to a thread which resolves hosts with a `DnsResolver` on its own
+/// tokio runtime. The thread exits once every clone of the handle has been
+/// dropped.
+#[derive(Clone)]
+pub struct Background {
+    lookups: mpsc::UnboundedSender<Lookup>,
+}
+
+impl Background {
+    pub fn new(resolver: DnsResolver) -> io::Result<Background> {
+        let (lookups, mut receiver) = mpsc::unbounded::<Lookup>();
+
+        thread::Builder::new()
+            .name("hyper-dns-resolver".to_string())
+            .spawn(move || {
+                let runtime = match runtime::Builder::new_current_thread().enable_all().build() {
+                    Ok(runtime) => runtime,
+                    Err(e) => {
+                        error!("Failed to create resolver runtime: {}", e);
+                        return;
+                    }
+                };
+
+                runtime.block_on(async move {
+                    while let Some(lookup) = receiver.next().await {
+                        let reply = lookup.reply;
+                        let addrs = resolver.resolve(&lookup.host, lookup.port);
+                        tokio::spawn(addrs.map(reply));
+                    }
+                });
+            })?;
+
+        Ok(Background { lookups: lookups })
+    }
+
+    /// Resolve `host` for `port`, calling `reply` with its addresses, or an
+    /// error if the thread has stopped.
+    pub fn lookup<F>(&self, host: &str, port: u16, reply: F)
+    where
+        F: FnOnce(io::Result<Vec<SocketAddr>>) + Send + 'static,
+    {
+        let mut reply = Some(reply);
+        let lookup = Lookup {
+            host: host.to_string(),
+            port: port,
+            reply: Box::new(move |addrs| {
+                if let Some(reply) = reply.take() {
+                    reply(addrs)
+                }
+            }),
+        };
+
+        if let Err(e) = self.lookups.unbounded_send(lookup) {
+            (e.into_inner().reply)(Err(stopped()));
+        }
+    }
+}
+
+/// The error for lookups made after the thread has stopped.
+pub fn stopped() -> io::Error {
+    io::Error::other("DNS resolver thread has stopped")
+}
+
+impl fmt::Debug for Background {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Background").finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::StaticResolver;
+    use std::sync::mpsc;
+
+    #[test]
+    fn replies_from_the_thread() {
+        let static_resolver = StaticResolver::new();
+        static_resolver.add_ip("web.example.com", "192.0.2.1".parse().unwrap());
+        let background = Background::new(static_resolver.connector(()).resolver()).unwrap();
+
+        let lookup = |host: &str| {
+            let (reply, addrs) = mpsc::channel();
+            background.lookup(host, 443, move |res| reply.send(res).unwrap());
+            addrs.recv().unwrap()
+        };
+
+        assert_eq!(
+            lookup("web.example.com").unwrap(),
+            vec!["192.0.2.1:443".parse::<SocketAddr>().unwrap()]
+        );
+        assert!(lookup("missing.example.com").is_err());
+    }
+}
@@ -0,0 +1,104 @@
+//! Backing off from re-querying hosts which don't exist.
+
+use std::cmp;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::time::Instant;
+
+/// The backoff after the first NXDOMAIN response for a host, which doubles
+/// with each one after it.
+const INITIAL: Duration = Duration::from_secs(1);
+
+/// Hosts which recently didn't exist, and aren't queried again until their
+/// backoff expires.
+#[derive(Debug)]
+pub struct NxBackoff {
+    max: Duration,
+    hosts: Mutex<HashMap<String, Backoff>>,
+}
+
+#[derive(Debug)]
+struct Backoff {
+    failures: u32,
+    until: Instant,
+}
+
+impl NxBackoff {
+    pub fn new(max: Duration) -> NxBackoff {
+        NxBackoff {
+            max: max,
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether `host` shouldn't be queried yet.
+    pub fn backing_off(&self, host: &str) -> bool {
+        let hosts = self.hosts.lock().unwrap();
+        hosts
+            .get(host)
+            .is_some_and(|backoff| backoff.until > Instant::now())
+    }
+
+    /// Record that `host` didn't exist, backing off for twice as long as the
+    /// last time, up to the maximum.
+    pub fn nxdomain(&self, host: &str) {
+        let mut hosts = self.hosts.lock().unwrap();
+        let now = Instant::now();
+        hosts.retain(|_, backoff| backoff.until + self.max > now);
+
+        let failures = hosts.get(host).map_or(0, |backoff| backoff.failures) + 1;
+        let delay = cmp::min(INITIAL * 2u32.pow(cmp::min(failures - 1, 16)), self.max);
+        hosts.insert(
+            host.to_string(),
+            Backoff {
+                failures: failures,
+                until: now + delay,
+            },
+        );
+    }
+
+    /// Record that `host` resolved, so it is no longer backed off from.
+    pub fn resolved(&self, host: &str) {
+        self.hosts.lock().unwrap().remove(host);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backs_off_from_missing_hosts() {
+        let backoff = NxBackoff::new(Duration::from_secs(60));
+        assert!(!backoff.backing_off("missing.example.com"));
+
+        backoff.nxdomain("missing.example.com");
+        assert!(backoff.backing_off("missing.example.com"));
+        assert!(!backoff.backing_off("example.com"));
+
+        backoff.resolved("missing.example.com");
+        assert!(!backoff.backing_off("missing.example.com"));
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let backoff = NxBackoff::new(Duration::from_secs(5));
+        let until = |host: &str| {
+            let hosts = backoff.hosts.lock().unwrap();
+            hosts[host].until.duration_since(Instant::now())
+        };
+
+        backoff.nxdomain("missing");
+        assert!(until("missing") <= Duration::from_secs(1));
+        backoff.nxdomain("missing");
+        assert!(until("missing") > Duration::from_secs(1));
+        assert!(until("missing") <= Duration::from_secs(2));
+        for _ in 0..10 {
+            backoff.nxdomain("missing");
+        }
+        assert!(until("missing") > Duration::from_secs(4));
+        assert!(until("missing") <= Duration::from_secs(5));
+    }
+}
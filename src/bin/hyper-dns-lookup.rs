@@ -0,0 +1,168 @@
+//! Resolve a URL the way `DnsConnector` would, printing each query sent and
+//! the address the connector would connect to, to reproduce resolution
+//! issues outside of an application.
+
+extern crate futures;
+extern crate hyper;
+extern crate hyper_dns;
+extern crate tokio;
+
+use futures::future::{self, Ready};
+use hyper::service::Service;
+use hyper::Uri;
+use hyper_dns::{AddressFamily, DnsConnector, Event, RecordType};
+use std::env;
+use std::io;
+use std::net::SocketAddr;
+use std::process;
+use std::task::{Context, Poll};
+use tokio::runtime;
+
+const USAGE: &str = "Usage: hyper-dns-lookup [--server ADDR]... [--type a|srv|auto] \
+                     [--family ipv4|ipv6|prefer-ipv4|prefer-ipv6] URL";
+
+/// A connector which doesn't connect, but returns the URI it was asked to
+/// connect to.
+#[derive(Debug, Clone)]
+struct DryRun;
+
+impl Service<Uri> for DryRun {
+    type Response = Uri;
+    type Error = io::Error;
+    type Future = Ready<io::Result<Uri>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        future::ok(uri)
+    }
+}
+
+struct Args {
+    servers: Vec<SocketAddr>,
+    record_type: RecordType,
+    family: AddressFamily,
+    uri: Uri,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut servers = Vec::new();
+    let mut record_type = RecordType::AUTO;
+    let mut family = AddressFamily::Ipv4Only;
+    let mut uri = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or_else(|| format!("{} needs a value", name));
+
+        match arg.as_str() {
+            "--server" => {
+                let server = value("--server")?;
+                let server = server
+                    .parse()
+                    .or_else(|_| server.parse().map(|ip| SocketAddr::new(ip, 53)))
+                    .map_err(|_| format!("Invalid server: {}", server))?;
+                servers.push(server);
+            }
+            "--type" => {
+                record_type = match value("--type")?.as_str() {
+                    "a" => RecordType::A,
+                    "srv" => RecordType::SRV,
+                    "auto" => RecordType::AUTO,
+                    other => return Err(format!("Invalid record type: {}", other)),
+                }
+            }
+            "--family" => {
+                family = match value("--family")?.as_str() {
+                    "ipv4" => AddressFamily::Ipv4Only,
+                    "ipv6" => AddressFamily::Ipv6Only,
+                    "prefer-ipv4" => AddressFamily::PreferIpv4,
+                    "prefer-ipv6" => AddressFamily::PreferIpv6,
+                    other => return Err(format!("Invalid address family: {}", other)),
+                }
+            }
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            _ if uri.is_none() => {
+                uri = Some(
+                    arg.parse::<Uri>()
+                        .map_err(|e| format!("Invalid URL {}: {}", arg, e))?,
+                );
+            }
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+
+    if servers.is_empty() {
+        servers.push(SocketAddr::from(([127, 0, 0, 1], 53)));
+    }
+
+    Ok(Args {
+        servers: servers,
+        record_type: record_type,
+        family: family,
+        uri: uri.ok_or_else(|| USAGE.to_string())?,
+    })
+}
+
+fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    };
+
+    let mut connector =
+        DnsConnector::new_with_resolve_type(args.servers[0], DryRun, args.record_type);
+    if args.servers.len() > 1 {
+        connector.set_nameservers(args.servers.iter().map(|server| (*server, 1)));
+    }
+    connector.set_address_family(args.family);
+    connector.set_event_sink(|event: &Event| match *event {
+        Event::QueryStarted {
+            ref server,
+            ref name,
+            ref record_type,
+            ..
+        } => println!("query {} {} @{}", record_type, name, server),
+        Event::QueryFinished {
+            ref rcode,
+            answers,
+            elapsed_ms,
+            ..
+        } => println!("  {} with {} answers in {}ms", rcode, answers, elapsed_ms),
+        Event::QueryFailed {
+            ref error,
+            elapsed_ms,
+            ..
+        } => println!("  failed after {}ms: {}", elapsed_ms, error),
+    });
+
+    let runtime = runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("Failed to create runtime");
+    match runtime.block_on(connector.call(args.uri)) {
+        Ok(connection) => {
+            let resolution = connection.resolution();
+            for hop in &resolution.chain {
+                println!(
+                    "{} {} {} ttl={}",
+                    hop.name, hop.record_type, hop.data, hop.ttl
+                );
+            }
+            println!("addresses: {:?}", resolution.addrs);
+            if let Some(ttl) = resolution.ttl {
+                println!("ttl: {}", ttl);
+            }
+            println!("would connect to: {}", connection.get_ref());
+        }
+        Err(e) => {
+            println!("error: {}", e);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,54 @@
+//! Temporary blacklisting of addresses which could not be connected to.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::time::Instant;
+
+/// Addresses which recently failed to connect, and are avoided until their
+/// cooldown expires.
+#[derive(Debug)]
+pub struct Blacklist {
+    cooldown: Duration,
+    until: Mutex<HashMap<IpAddr, Instant>>,
+}
+
+impl Blacklist {
+    pub fn new(cooldown: Duration) -> Blacklist {
+        Blacklist {
+            cooldown: cooldown,
+            until: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Blacklist `addr` for the cooldown period.
+    pub fn add(&self, addr: IpAddr) {
+        debug!("Blacklisting {} for {:?}", addr, self.cooldown);
+        self.until
+            .lock()
+            .unwrap()
+            .insert(addr, Instant::now() + self.cooldown);
+    }
+
+    /// The candidates which are not blacklisted, or all of them if every
+    /// candidate is blacklisted, as trying a bad address beats not trying at all.
+    pub fn filter(&self, candidates: &[IpAddr]) -> Vec<IpAddr> {
+        let mut until = self.until.lock().unwrap();
+        let now = Instant::now();
+        until.retain(|_, expiry| *expiry > now);
+
+        let allowed: Vec<IpAddr> = candidates
+            .iter()
+            .cloned()
+            .filter(|addr| !until.contains_key(addr))
+            .collect();
+
+        if allowed.is_empty() {
+            candidates.to_vec()
+        } else {
+            allowed
+        }
+    }
+}
@@ -0,0 +1,119 @@
+//! Resolution for code which isn't asynchronous, such as CLI tools and setup
+//! code, with the connector's servers, interceptors and SRV handling.
+//!
+//! Each lookup is driven to completion on the calling thread, with the
+//! `tokio` feature on a tokio runtime of its own, so these mustn't be called
+//! from a task running on a runtime. As the runtime is dropped after the lookup, its queries aren't
+//! sent on the connections to DNS servers pooled by the connector, but on a
+//! connection of their own which is closed afterwards, and so aren't
+//! answered from responses memoized on the pooled connections either:
+//! nothing is cached between blocking lookups.
+
+use futures::Future;
+use std::io;
+use std::net::SocketAddr;
+#[cfg(feature = "tokio")]
+use tokio::runtime;
+
+use crate::DnsResolver;
+
+/// Resolve `host` to the addresses to connect to on `port`, as with
+/// `DnsResolver::resolve`, blocking until it resolves.
+pub fn resolve_blocking(
+    resolver: &DnsResolver,
+    host: &str,
+    port: u16,
+) -> io::Result<Vec<SocketAddr>> {
+    run(resolver.unpooled().resolve(host, port))
+}
+
+/// Resolve the SRV records for `name`, as with `DnsResolver::resolve_srv`,
+/// blocking until it resolves.
+pub fn resolve_srv_blocking(resolver: &DnsResolver, name: &str) -> io::Result<Vec<SocketAddr>> {
+    run(resolver.unpooled().resolve_srv(name))
+}
+
+/// Resolve `host` as for a request to it, as with
+/// `DnsResolver::resolve_request`, blocking until it resolves.
+pub fn resolve_request_blocking(
+    resolver: &DnsResolver,
+    host: &str,
+    port: Option<u16>,
+) -> io::Result<Vec<SocketAddr>> {
+    run(resolver.unpooled().resolve_request(host, port))
+}
+
+fn run<F>(future: F) -> io::Result<Vec<SocketAddr>>
+where
+    F: Future<Output = io::Result<Vec<SocketAddr>>>,
+{
+    #[cfg(feature = "tokio")]
+    return runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?
+        .block_on(future);
+    // async-std and smol run their clients' connections on their own
+    // threads, so the lookup can be polled on any executor.
+    #[cfg(not(feature = "tokio"))]
+    return futures::executor::block_on(future);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(any(feature = "tokio", feature = "async-std", feature = "smol"))]
+    use crate::testing::{TestServer, Zone};
+    use crate::{RecordType, Redaction, Resolution};
+    use futures::future::{self, FutureExt};
+    #[cfg(any(feature = "tokio", feature = "async-std", feature = "smol"))]
+    use std::net::Ipv4Addr;
+    use std::sync::Arc;
+    use trust_dns::rr;
+    #[cfg(any(feature = "tokio", feature = "async-std", feature = "smol"))]
+    use trust_dns::rr::RData;
+
+    #[test]
+    fn resolves_on_own_runtime() {
+        let resolver = DnsResolver::new(
+            Arc::new(|_: &str, port: Option<u16>, record_type: RecordType| {
+                let resolution = Resolution {
+                    ip: "10.0.0.1".parse().unwrap(),
+                    addrs: vec!["10.0.0.1".parse().unwrap()],
+                    port: port,
+                    record_type: Some(record_type),
+                    ttl: Some(60),
+                    nameserver: None,
+                    chain: Vec::new(),
+                };
+                future::ok(resolution).boxed()
+            }),
+            Arc::new(|_: &str| future::ok(Vec::new()).boxed()),
+            Arc::new(|_: &str, _: rr::RecordType| future::err(io::Error::other("unused")).boxed()),
+            RecordType::AUTO,
+            Redaction::Off,
+        );
+
+        assert_eq!(
+            resolve_blocking(&resolver, "example.com", 443).unwrap(),
+            vec!["10.0.0.1:443".parse::<SocketAddr>().unwrap()]
+        );
+    }
+
+    #[cfg(any(feature = "tokio", feature = "async-std", feature = "smol"))]
+    #[test]
+    fn leaves_pooled_connections_alone() {
+        let mut zone = Zone::new();
+        zone.add("web.example.com", 60, RData::A(Ipv4Addr::new(192, 0, 2, 1)));
+        let server = TestServer::start(zone).unwrap();
+        let connector = server.connector(());
+        let resolver = connector.resolver();
+
+        for _ in 0..2 {
+            assert_eq!(
+                resolve_blocking(&resolver, "web.example.com", 80).unwrap(),
+                vec!["192.0.2.1:80".parse::<SocketAddr>().unwrap()]
+            );
+        }
+        assert_eq!(connector.clients.servers(), 0);
+    }
+}
@@ -0,0 +1,131 @@
+//! The chain of records followed to resolve a host.
+
+use trust_dns::rr::{Name, RData, Record, RecordType};
+
+/// A record which was followed to resolve a host, e.g. the SRV record for a
+/// service, a CNAME record for its target, or the A record for the address.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hop {
+    /// The name the record is for.
+    pub name: String,
+    /// The type of the record.
+    pub record_type: RecordType,
+    /// What the record points to: the target name (and port, for SRV
+    /// records), or the address.
+    pub data: String,
+    /// The TTL of the record.
+    pub ttl: u32,
+}
+
+impl Hop {
+    pub fn new(record: &Record) -> Hop {
+        let data = match *record.rdata() {
+            RData::A(ref addr) => addr.to_string(),
+            RData::AAAA(ref addr) => addr.to_string(),
+            RData::CNAME(ref name) => name.to_string(),
+            RData::SRV(ref srv) => format!("{}:{}", srv.target(), srv.port()),
+            ref other => format!("{:?}", other),
+        };
+
+        Hop {
+            name: record.name().to_string(),
+            record_type: record.rr_type(),
+            data: data,
+            ttl: record.ttl(),
+        }
+    }
+}
+
+/// Follow any CNAME records in `records` from `name`, adding them to `chain`,
+/// and return the canonical name the addresses are for, or `None` if the
+/// chain is circular or longer than `max_depth`.
+pub fn follow_cnames(
+    records: &[Record],
+    name: &Name,
+    max_depth: usize,
+    chain: &mut Vec<Hop>,
+) -> Option<Name> {
+    let mut name = name.clone();
+    let mut seen = vec![name.clone()];
+
+    loop {
+        let cname = records
+            .iter()
+            .filter(|record| record.name() == &name)
+            .filter_map(|record| match *record.rdata() {
+                RData::CNAME(ref target) => Some((record, target)),
+                _ => None,
+            })
+            .next();
+
+        match cname {
+            Some((record, target)) => {
+                if seen.len() > max_depth || seen.contains(target) {
+                    return None;
+                }
+                chain.push(Hop::new(record));
+                seen.push(target.clone());
+                name = target.clone();
+            }
+            None => return Some(name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use std::str::FromStr;
+
+    #[test]
+    fn follows_cnames() {
+        let name = |name: &str| Name::from_str(name).unwrap();
+        let records = vec![
+            Record::from_rdata(
+                name("api.example.com."),
+                300,
+                RData::CNAME(name("lb.example.net.")),
+            ),
+            Record::from_rdata(
+                name("lb.example.net."),
+                60,
+                RData::A(Ipv4Addr::new(10, 0, 0, 1)),
+            ),
+        ];
+
+        let mut chain = Vec::new();
+        let canonical = follow_cnames(&records, &name("api.example.com."), 8, &mut chain);
+
+        assert_eq!(canonical, Some(name("lb.example.net.")));
+        assert_eq!(
+            chain,
+            vec![Hop {
+                name: "api.example.com.".to_string(),
+                record_type: RecordType::CNAME,
+                data: "lb.example.net.".to_string(),
+                ttl: 300,
+            }]
+        );
+    }
+
+    #[test]
+    fn limits_cname_chains() {
+        let name = |name: &str| Name::from_str(name).unwrap();
+        let cname =
+            |from: &str, to: &str| Record::from_rdata(name(from), 300, RData::CNAME(name(to)));
+
+        let long = vec![cname("a.", "b."), cname("b.", "c."), cname("c.", "d.")];
+        assert_eq!(
+            follow_cnames(&long, &name("a."), 3, &mut Vec::new()),
+            Some(name("d."))
+        );
+        assert_eq!(follow_cnames(&long, &name("a."), 2, &mut Vec::new()), None);
+
+        let circular = vec![cname("a.", "b."), cname("b.", "a.")];
+        assert_eq!(
+            follow_cnames(&circular, &name("a."), 8, &mut Vec::new()),
+            None
+        );
+    }
+}
@@ -0,0 +1,745 @@
+//! Connections to DNS servers, kept open between queries.
+
+use futures::future::{BoxFuture, FutureExt, TryFutureExt};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use trust_dns::client::MemoizeClientHandle;
+use trust_dns::error::ClientError;
+use trust_dns_proto::xfer::DnsExchange;
+
+use crate::time::Instant;
+use crate::{Executor, Runtime};
+
+/// Handles to a pool of clients for each DNS server, shared by the connector
+/// and its clones.
+///
+/// Each client's connection is owned by a task on the runtime it was
+/// connected from, and queries are sent to it through the handle's channel,
+/// rather than each query connecting to the server itself. A task stops
+/// when its runtime does, so each executor has pools of its own, which are
+/// kept until their clients fail or the executor stops. Queries are
+/// spread across the clients in the pool in turn, so that a slow response on
+/// one connection doesn't hold up the rest.
+///
+/// The pools' settings are shared too, so changing them through one
+/// connector changes them for its clones.
+pub struct Clients {
+    next: AtomicUsize,
+    pools: Mutex<Pools>,
+}
+
+/// The pool of clients for each server and executor, and the settings they
+/// were made with.
+struct Pools {
+    size: usize,
+    memoize: Option<Duration>,
+    mark: Option<u32>,
+    servers: HashMap<(Executor, SocketAddr), Vec<Option<Pooled>>>,
+    /// The executors whose pools are dropped once they stop.
+    watched: HashSet<Executor>,
+}
+
+/// A client in a pool.
+#[derive(Clone)]
+struct Pooled {
+    handle: DnsExchange,
+    /// The handle memoizing responses, if enabled, and when it was made.
+    memoized: Option<(MemoizeClientHandle<DnsExchange>, Instant)>,
+}
+
+impl Pooled {
+    /// The handle to send a query with, memoizing responses for up to
+    /// `memoize`, after which a new memoizing handle is made. A new one is
+    /// also made if `fresh`, so that the query's response replaces any
+    /// memoized one.
+    fn handle(&mut self, memoize: Option<Duration>, fresh: bool) -> Handle {
+        let lifetime = match memoize {
+            Some(lifetime) => lifetime,
+            None => return Handle::Basic(self.handle.clone()),
+        };
+
+        let now = Instant::now();
+        let expired = match self.memoized {
+            Some((_, since)) => now.duration_since(since) >= lifetime,
+            None => true,
+        };
+        if expired || fresh {
+            self.memoized = Some((MemoizeClientHandle::new(self.handle.clone()), now));
+        }
+        match self.memoized {
+            Some((ref handle, _)) => Handle::Memoized(handle.clone()),
+            None => unreachable!(),
+        }
+    }
+}
+
+/// A handle to send queries to a server with.
+#[derive(Clone)]
+pub enum Handle {
+    Basic(DnsExchange),
+    /// Answers repeated queries from the response to the first.
+    Memoized(MemoizeClientHandle<DnsExchange>),
+}
+
+/// A client to send a query with.
+pub struct Client {
+    pub handle: Handle,
+    /// Where the client is in its server's pool, if it is in one.
+    pub slot: Option<Slot>,
+    /// Whether the client had been used for earlier queries.
+    pub reused: bool,
+}
+
+/// Where a client is in the pools of its server.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Slot {
+    /// The executor whose pool the client is in.
+    pub executor: Executor,
+    /// Where the client is in that pool.
+    pub index: usize,
+}
+
+/// A client being taken from its server's pool by `Clients::client`. Only
+/// connecting a new client to put in the pool is boxed.
+pub struct ClientFuture {
+    clients: Arc<Clients>,
+    runtime: Arc<dyn Runtime>,
+    server: SocketAddr,
+    timeout: Duration,
+    fresh: bool,
+    connecting: Option<BoxFuture<'static, Result<Client, ClientError>>>,
+}
+
+impl Future for ClientFuture {
+    type Output = Result<Client, ClientError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        if this.connecting.is_none() {
+            let executor = this.runtime.executor();
+            match this.clients.pooled(executor, this.server, this.fresh) {
+                Ok(client) => return Poll::Ready(Ok(client)),
+                Err(empty) => {
+                    let (clients, runtime) = (this.clients.clone(), this.runtime.clone());
+                    let connect = Clients::connect(
+                        clients,
+                        runtime,
+                        this.server,
+                        this.timeout,
+                        this.fresh,
+                        empty,
+                    );
+                    this.connecting = Some(connect);
+                }
+            }
+        }
+        match this.connecting {
+            Some(ref mut connecting) => connecting.as_mut().poll(cx),
+            None => unreachable!(),
+        }
+    }
+}
+
+impl Client {
+    /// A client which isn't in a pool.
+    pub fn unpooled(handle: DnsExchange) -> Client {
+        Client {
+            handle: Handle::Basic(handle),
+            slot: None,
+            reused: false,
+        }
+    }
+}
+
+impl Clients {
+    /// Keep up to `size` connections open to each server, memoizing the
+    /// responses to queries on each for up to `memoize`.
+    pub fn new(size: usize, memoize: Option<Duration>) -> Clients {
+        Clients {
+            next: AtomicUsize::new(0),
+            pools: Mutex::new(Pools {
+                size: size.max(1),
+                memoize: memoize,
+                mark: None,
+                servers: HashMap::new(),
+                watched: HashSet::new(),
+            }),
+        }
+    }
+
+    /// The next client for `server` in turn from the pool for the executor
+    /// the future is polled on, connecting one on `runtime` with `timeout`
+    /// if its slot is empty. If `fresh`, the client doesn't answer from
+    /// memoized responses.
+    pub fn client(
+        clients: &Arc<Clients>,
+        runtime: &Arc<dyn Runtime>,
+        server: SocketAddr,
+        timeout: Duration,
+        fresh: bool,
+    ) -> ClientFuture {
+        ClientFuture {
+            clients: clients.clone(),
+            runtime: runtime.clone(),
+            server: server,
+            timeout: timeout,
+            fresh: fresh,
+            connecting: None,
+        }
+    }
+
+    /// The next client for `server` in turn from the pool for `executor`, or
+    /// the empty slot to connect one for and the mark to connect it with.
+    fn pooled(
+        &self,
+        executor: Executor,
+        server: SocketAddr,
+        fresh: bool,
+    ) -> Result<Client, (Slot, Option<u32>)> {
+        let mut pools = self.pools.lock().unwrap();
+        let index = self.next.fetch_add(1, Ordering::Relaxed) % pools.size;
+        let slot = Slot {
+            executor: executor,
+            index: index,
+        };
+        let memoize = pools.memoize;
+        let pooled = pools
+            .servers
+            .get_mut(&(executor, server))
+            .and_then(|pool| pool.get_mut(index));
+        match pooled {
+            Some(&mut Some(ref mut pooled)) => Ok(Client {
+                handle: pooled.handle(memoize, fresh),
+                slot: Some(slot),
+                reused: true,
+            }),
+            _ => Err((slot, pools.mark)),
+        }
+    }
+
+    /// Connect a client to `server` for `slot`, which was empty, with the
+    /// sockets marked with `mark`.
+    fn connect(
+        clients: Arc<Clients>,
+        runtime: Arc<dyn Runtime>,
+        server: SocketAddr,
+        timeout: Duration,
+        fresh: bool,
+        (slot, mark): (Slot, Option<u32>),
+    ) -> BoxFuture<'static, Result<Client, ClientError>> {
+        async move {
+            let executor = slot.executor;
+            let handle = connect(&*runtime, server, timeout, mark).await?;
+            let mut pools = clients.pools.lock().unwrap();
+            // The settings may have changed while connecting, in which case
+            // the client is used for this query only.
+            if pools.mark != mark || slot.index >= pools.size {
+                return Ok(Client::unpooled(handle));
+            }
+            let (size, memoize) = (pools.size, pools.memoize);
+            let pool = pools
+                .servers
+                .entry((executor, server))
+                .or_insert_with(|| vec![None; size]);
+            let mut pooled = Pooled {
+                handle: handle,
+                memoized: None,
+            };
+            let handle = pooled.handle(memoize, fresh);
+            pool[slot.index] = Some(pooled);
+            let watch = pools.watched.insert(executor);
+            drop(pools);
+
+            // Registered without the pools locked, as the executor may be
+            // stopping already, running `evict` straight away.
+            if watch {
+                let weak = Arc::downgrade(&clients);
+                runtime.on_shutdown(Box::new(move || {
+                    if let Some(clients) = weak.upgrade() {
+                        clients.evict(executor);
+                    }
+                }));
+            }
+            Ok(Client {
+                handle: handle,
+                slot: Some(slot),
+                reused: false,
+            })
+        }
+        .boxed()
+    }
+
+    /// Forget the client in `slot` of `server`'s pool, after a query on it
+    /// failed, so that the next query for the slot connects again. The pool
+    /// is dropped once it has no clients left.
+    pub fn remove(&self, server: SocketAddr, slot: Slot) {
+        let mut pools = self.pools.lock().unwrap();
+        let key = (slot.executor, server);
+        if let Some(pool) = pools.servers.get_mut(&key) {
+            if let Some(pooled) = pool.get_mut(slot.index) {
+                *pooled = None;
+            }
+            if pool.iter().all(Option::is_none) {
+                pools.servers.remove(&key);
+            }
+        }
+    }
+
+    /// Drop the pools for `executor`, which has stopped, so the tasks owning
+    /// their clients' connections have too.
+    fn evict(&self, executor: Executor) {
+        let mut pools = self.pools.lock().unwrap();
+        pools.servers.retain(|&(pooled, _), _| pooled != executor);
+        pools.watched.remove(&executor);
+    }
+
+    /// Keep up to `size` connections open to each server, closing those in
+    /// slots beyond it.
+    pub fn set_size(&self, size: usize) {
+        let mut pools = self.pools.lock().unwrap();
+        pools.size = size.max(1);
+        let size = pools.size;
+        for pool in pools.servers.values_mut() {
+            pool.truncate(size);
+            pool.resize(size, None);
+        }
+    }
+
+    /// Memoize responses for up to `memoize`, replacing the memoizing
+    /// handles made with the previous setting.
+    pub fn set_memoize(&self, memoize: Option<Duration>) {
+        let mut pools = self.pools.lock().unwrap();
+        if pools.memoize == memoize {
+            return;
+        }
+        pools.memoize = memoize;
+        for pooled in pools.servers.values_mut().flat_map(|pool| pool.iter_mut()) {
+            if let Some(ref mut pooled) = *pooled {
+                pooled.memoized = None;
+            }
+        }
+    }
+
+    /// Mark the sockets of new connections with `mark`, closing those open
+    /// without it.
+    #[cfg(target_os = "linux")]
+    pub fn set_mark(&self, mark: Option<u32>) {
+        let mut pools = self.pools.lock().unwrap();
+        if pools.mark != mark {
+            pools.mark = mark;
+            pools.servers.clear();
+        }
+    }
+
+    /// The mark new connections' sockets are given, if any.
+    pub fn mark(&self) -> Option<u32> {
+        self.pools.lock().unwrap().mark
+    }
+
+    /// How many pools of connections there are, one for each server and
+    /// executor.
+    #[cfg(all(test, any(feature = "tokio", feature = "async-std", feature = "smol")))]
+    pub fn servers(&self) -> usize {
+        self.pools.lock().unwrap().servers.len()
+    }
+}
+
+impl Default for Clients {
+    fn default() -> Clients {
+        Clients::new(1, None)
+    }
+}
+
+impl fmt::Debug for Clients {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let pools = self.pools.lock().unwrap();
+        f.debug_struct("Clients")
+            .field("size", &pools.size)
+            .field("memoize", &pools.memoize)
+            .field("mark", &pools.mark)
+            .field("servers", &pools.servers.len())
+            .finish()
+    }
+}
+
+/// Connect a new client to `server` on `runtime`, marking its socket with
+/// `mark` for policy routing.
+pub fn connect(
+    runtime: &dyn Runtime,
+    server: SocketAddr,
+    timeout: Duration,
+    mark: Option<u32>,
+) -> BoxFuture<'static, Result<DnsExchange, ClientError>> {
+    runtime
+        .connect(server, timeout, mark)
+        .map_err(ClientError::from)
+        .boxed()
+}
+
+// Without a runtime, clients can't be connected.
+#[cfg(all(test, any(feature = "tokio", feature = "async-std", feature = "smol")))]
+mod tests {
+    use super::*;
+    use crate::runtime;
+    use crate::testing::{TestServer, Zone};
+    use std::net::Ipv4Addr;
+    use std::str::FromStr;
+    use std::thread;
+    use tokio::runtime::Runtime;
+    use trust_dns::client::ClientHandle;
+    use trust_dns::rr::{DNSClass, Name, RData, RecordType};
+
+    /// The runtime clients are connected on, as connectors do by default.
+    fn default() -> Arc<dyn crate::Runtime> {
+        runtime::default()
+    }
+
+    #[test]
+    fn clients_are_reused_until_removed() {
+        let runtime = Runtime::new().unwrap();
+        let clients = Arc::new(Clients::default());
+        let mut zone = Zone::new();
+        zone.add("web.example.com", 60, RData::A(Ipv4Addr::new(192, 0, 2, 1)));
+        let test_server = TestServer::start(zone).unwrap();
+        let server = test_server.addr();
+        let timeout = Duration::from_secs(1);
+        let name = Name::from_str("web.example.com.").unwrap();
+
+        let client = runtime
+            .block_on(Clients::client(
+                &clients,
+                &default(),
+                server,
+                timeout,
+                false,
+            ))
+            .unwrap();
+        assert!(!client.reused);
+        let client = runtime
+            .block_on(Clients::client(
+                &clients,
+                &default(),
+                server,
+                timeout,
+                false,
+            ))
+            .unwrap();
+        assert!(client.reused);
+
+        // The reused connection is still open to the server.
+        let mut handle = match client.handle {
+            Handle::Basic(handle) => handle,
+            Handle::Memoized(_) => panic!("responses aren't memoized"),
+        };
+        let res = runtime
+            .block_on(handle.query(name, DNSClass::IN, RecordType::A))
+            .unwrap();
+        assert_eq!(res.answers().len(), 1);
+
+        clients.remove(server, client.slot.unwrap());
+        let client = runtime
+            .block_on(Clients::client(
+                &clients,
+                &default(),
+                server,
+                timeout,
+                false,
+            ))
+            .unwrap();
+        assert!(!client.reused);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn clients_are_pooled_per_runtime() {
+        let current_thread = || {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap()
+        };
+        let (first, second) = (current_thread(), current_thread());
+        let clients = Arc::new(Clients::default());
+        let mut zone = Zone::new();
+        zone.add("web.example.com", 60, RData::A(Ipv4Addr::new(192, 0, 2, 1)));
+        let test_server = TestServer::start(zone).unwrap();
+        let server = test_server.addr();
+        let timeout = Duration::from_secs(1);
+
+        let client = first
+            .block_on(Clients::client(
+                &clients,
+                &default(),
+                server,
+                timeout,
+                false,
+            ))
+            .unwrap();
+        assert!(!client.reused);
+
+        // The first runtime isn't running, so the task owning its client's
+        // connection can't answer queries from the second.
+        let res = second.block_on(async {
+            let client = Clients::client(&clients, &default(), server, timeout, false)
+                .await
+                .unwrap();
+            assert!(!client.reused);
+            let mut handle = match client.handle {
+                Handle::Basic(handle) => handle,
+                Handle::Memoized(_) => panic!("responses aren't memoized"),
+            };
+            let name = Name::from_str("web.example.com.").unwrap();
+            tokio::time::timeout(timeout, handle.query(name, DNSClass::IN, RecordType::A)).await
+        });
+        assert_eq!(res.unwrap().unwrap().answers().len(), 1);
+        assert_eq!(clients.servers(), 2);
+
+        let client = first
+            .block_on(Clients::client(
+                &clients,
+                &default(),
+                server,
+                timeout,
+                false,
+            ))
+            .unwrap();
+        assert!(client.reused);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn pools_are_dropped_when_their_runtime_stops() {
+        let clients = Arc::new(Clients::default());
+        let test_server = TestServer::start(Zone::new()).unwrap();
+        let server = test_server.addr();
+        let timeout = Duration::from_secs(1);
+
+        for _ in 0..2 {
+            let runtime = Runtime::new().unwrap();
+            let client = runtime
+                .block_on(Clients::client(
+                    &clients,
+                    &default(),
+                    server,
+                    timeout,
+                    false,
+                ))
+                .unwrap();
+            assert!(!client.reused);
+            assert_eq!(clients.servers(), 1);
+
+            drop(runtime);
+            assert_eq!(clients.servers(), 0);
+        }
+    }
+
+    #[test]
+    fn queries_are_spread_across_pool() {
+        let runtime = Runtime::new().unwrap();
+        let clients = Arc::new(Clients::new(2, None));
+        let test_server = TestServer::start(Zone::new()).unwrap();
+        let server = test_server.addr();
+        let timeout = Duration::from_secs(1);
+
+        let slots: Vec<(Option<usize>, bool)> = (0..4)
+            .map(|_| {
+                let client = runtime
+                    .block_on(Clients::client(
+                        &clients,
+                        &default(),
+                        server,
+                        timeout,
+                        false,
+                    ))
+                    .unwrap();
+                (client.slot.map(|slot| slot.index), client.reused)
+            })
+            .collect();
+        assert_eq!(
+            slots,
+            vec![
+                (Some(0), false),
+                (Some(1), false),
+                (Some(0), true),
+                (Some(1), true)
+            ]
+        );
+    }
+
+    #[test]
+    fn set_size_changes_the_shared_pools() {
+        let runtime = Runtime::new().unwrap();
+        let clients = Arc::new(Clients::new(2, None));
+        let test_server = TestServer::start(Zone::new()).unwrap();
+        let server = test_server.addr();
+        let timeout = Duration::from_secs(1);
+        let pooled = |clients: &Clients| {
+            let pools = clients.pools.lock().unwrap();
+            pools.servers.values().map(|pool| pool.len()).sum::<usize>()
+        };
+
+        for _ in 0..2 {
+            runtime
+                .block_on(Clients::client(
+                    &clients,
+                    &default(),
+                    server,
+                    timeout,
+                    false,
+                ))
+                .unwrap();
+        }
+        assert_eq!(pooled(&clients), 2);
+        clients.set_size(1);
+        assert_eq!(pooled(&clients), 1);
+    }
+
+    #[test]
+    fn memoizing_handles_are_replaced_when_expired_or_fresh() {
+        let runtime = Runtime::new().unwrap();
+        let test_server = TestServer::start(Zone::new()).unwrap();
+        let server = test_server.addr();
+        let timeout = Duration::from_secs(1);
+
+        let clients = Arc::new(Clients::default());
+        let client = runtime
+            .block_on(Clients::client(
+                &clients,
+                &default(),
+                server,
+                timeout,
+                false,
+            ))
+            .unwrap();
+        assert!(match client.handle {
+            Handle::Basic(_) => true,
+            Handle::Memoized(_) => false,
+        });
+
+        let clients = Arc::new(Clients::new(1, Some(Duration::from_secs(0))));
+        runtime
+            .block_on(Clients::client(
+                &clients,
+                &default(),
+                server,
+                timeout,
+                false,
+            ))
+            .unwrap();
+        let since = |clients: &Clients| {
+            let pools = clients.pools.lock().unwrap();
+            pools.servers.values().next().unwrap()[0]
+                .as_ref()
+                .unwrap()
+                .memoized
+                .as_ref()
+                .unwrap()
+                .1
+        };
+        let first = since(&clients);
+        thread::sleep(Duration::from_millis(1));
+        runtime
+            .block_on(Clients::client(
+                &clients,
+                &default(),
+                server,
+                timeout,
+                false,
+            ))
+            .unwrap();
+        assert!(since(&clients) > first);
+
+        let clients = Arc::new(Clients::new(1, Some(Duration::from_secs(60))));
+        runtime
+            .block_on(Clients::client(
+                &clients,
+                &default(),
+                server,
+                timeout,
+                false,
+            ))
+            .unwrap();
+        let first = since(&clients);
+        thread::sleep(Duration::from_millis(1));
+        runtime
+            .block_on(Clients::client(
+                &clients,
+                &default(),
+                server,
+                timeout,
+                false,
+            ))
+            .unwrap();
+        assert_eq!(since(&clients), first);
+        runtime
+            .block_on(Clients::client(&clients, &default(), server, timeout, true))
+            .unwrap();
+        assert!(since(&clients) > first);
+    }
+
+    #[test]
+    fn set_memoize_changes_the_shared_pools() {
+        let runtime = Runtime::new().unwrap();
+        let clients = Arc::new(Clients::default());
+        let test_server = TestServer::start(Zone::new()).unwrap();
+        let server = test_server.addr();
+        let timeout = Duration::from_secs(1);
+
+        runtime
+            .block_on(Clients::client(
+                &clients,
+                &default(),
+                server,
+                timeout,
+                false,
+            ))
+            .unwrap();
+        clients.set_memoize(Some(Duration::from_secs(60)));
+        let client = runtime
+            .block_on(Clients::client(
+                &clients,
+                &default(),
+                server,
+                timeout,
+                false,
+            ))
+            .unwrap();
+        assert!(client.reused);
+        assert!(match client.handle {
+            Handle::Basic(_) => false,
+            Handle::Memoized(_) => true,
+        });
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn set_mark_closes_the_shared_pools() {
+        let runtime = Runtime::new().unwrap();
+        let clients = Arc::new(Clients::default());
+        let test_server = TestServer::start(Zone::new()).unwrap();
+        let server = test_server.addr();
+        let timeout = Duration::from_secs(1);
+
+        runtime
+            .block_on(Clients::client(
+                &clients,
+                &default(),
+                server,
+                timeout,
+                false,
+            ))
+            .unwrap();
+        clients.set_mark(None);
+        assert_eq!(clients.servers(), 1);
+        clients.set_mark(Some(1));
+        assert_eq!(clients.mark(), Some(1));
+        assert_eq!(clients.servers(), 0);
+    }
+}
@@ -0,0 +1,216 @@
+//! The future returned by the connector, which resolves the request's host
+//! and then dials its addresses, or calls the wrapped connector directly for
+//! requests to IP addresses.
+
+use hyper::service::Service;
+use hyper::Uri;
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use crate::dial::{Call, Dial, Dialer};
+use crate::{pre_connect, Connection, Resolution, ResolutionSource, Resolve, Resolved};
+
+/// A connection being made by `DnsConnector`.
+pub struct ConnectFuture<C>
+where
+    C: Service<Uri>,
+{
+    state: State<C>,
+}
+
+impl<C> fmt::Debug for ConnectFuture<C>
+where
+    C: Service<Uri>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ConnectFuture").finish()
+    }
+}
+
+// The states are kept inline, like those of the lookup futures, rather than
+// boxing the larger ones.
+#[allow(clippy::large_enum_variant)]
+enum State<C>
+where
+    C: Service<Uri>,
+{
+    /// Resolving the host, before dialing the addresses found.
+    Resolving(Resolve, Option<(Dialer<C>, Uri)>),
+    /// Connecting to an IP address directly with the wrapped connector.
+    Calling(Call<C>, Option<(Resolution, Duration)>),
+    /// Dialing the resolved addresses.
+    Dialing(Dial<C>),
+    Failed(Option<io::Error>),
+}
+
+impl<C> ConnectFuture<C>
+where
+    C: Service<Uri> + Send + 'static,
+    C::Response: Send + 'static,
+    C::Future: Send + 'static,
+    C::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    /// Connect to `uri` once `resolving` has resolved its host.
+    pub(crate) fn resolving(resolving: Resolve, dialer: Dialer<C>, uri: Uri) -> ConnectFuture<C> {
+        ConnectFuture {
+            state: State::Resolving(resolving, Some((dialer, uri))),
+        }
+    }
+
+    /// Connect to `uri`, whose host has already been resolved.
+    pub(crate) fn resolved(
+        resolved: io::Result<Resolved>,
+        dialer: Dialer<C>,
+        uri: Uri,
+    ) -> ConnectFuture<C> {
+        let state = match resolved {
+            Ok(resolved) => State::connect(resolved, dialer, uri),
+            Err(e) => State::Failed(Some(e)),
+        };
+        ConnectFuture { state: state }
+    }
+}
+
+impl<C> State<C>
+where
+    C: Service<Uri> + Send + 'static,
+    C::Response: Send + 'static,
+    C::Future: Send + 'static,
+    C::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    fn connect(resolved: Resolved, mut dialer: Dialer<C>, mut uri: Uri) -> State<C> {
+        // An IP address host needs nothing doing, so just pass it along to
+        // the main connector. One the host was rewritten to is connected to
+        // like any other resolution.
+        if resolved.source == ResolutionSource::Literal && Some(&*resolved.host) == uri.host() {
+            if let Err(e) = pre_connect(&dialer.interceptors, &mut uri) {
+                return State::Failed(Some(e));
+            }
+
+            return State::Calling(
+                Call::new(dialer.connector, uri),
+                Some((resolved.resolution, resolved.elapsed)),
+            );
+        }
+
+        dialer.host = resolved.host;
+        dialer.elapsed = resolved.elapsed;
+        State::Dialing(dialer.dial(resolved.resolution, Vec::new()))
+    }
+}
+
+impl<C> Future for ConnectFuture<C>
+where
+    C: Service<Uri> + Send + 'static,
+    C::Response: Send + 'static,
+    C::Future: Send + Unpin + 'static,
+    C::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    type Output = io::Result<Connection<C::Response>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        loop {
+            let next = match self.state {
+                State::Resolving(ref mut resolving, ref mut pending) => {
+                    let resolved = match Pin::new(resolving).poll(cx) {
+                        Poll::Ready(resolved) => resolved?,
+                        Poll::Pending => return Poll::Pending,
+                    };
+                    let (dialer, uri) = pending.take().expect("polled after completion");
+                    State::connect(resolved, dialer, uri)
+                }
+                State::Calling(ref mut calling, ref mut resolution) => {
+                    let io = match Pin::new(calling).poll(cx) {
+                        Poll::Ready(io) => io?,
+                        Poll::Pending => return Poll::Pending,
+                    };
+                    let (resolution, elapsed) = resolution.take().expect("polled after completion");
+                    return Poll::Ready(Ok(Connection::new(io, resolution, elapsed, None)));
+                }
+                State::Dialing(ref mut dialing) => return Pin::new(dialing).poll(cx),
+                State::Failed(ref mut e) => {
+                    return Poll::Ready(Err(e.take().expect("polled after completion")));
+                }
+            };
+            self.state = next;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::StaticResolver;
+    use futures::executor::block_on;
+    use futures::future;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
+    /// Counts the allocations made by each thread, so that tests running
+    /// in parallel don't count each other's.
+    struct Counting;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for Counting {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: Counting = Counting;
+
+    /// The allocations made by `f` on this thread.
+    fn allocations<F: FnOnce()>(f: F) -> usize {
+        let before = ALLOCATIONS.with(Cell::get);
+        f();
+        ALLOCATIONS.with(Cell::get) - before
+    }
+
+    struct Noop;
+
+    impl Service<Uri> for Noop {
+        type Response = ();
+        type Error = io::Error;
+        type Future = future::Ready<io::Result<()>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _uri: Uri) -> Self::Future {
+            future::ok(())
+        }
+    }
+
+    #[test]
+    fn ip_addresses_make_few_allocations() {
+        let hosts = StaticResolver::new();
+        let mut connector = hosts.connector(Noop);
+        let mut connect = |uri: &str| {
+            let uri: Uri = uri.parse().unwrap();
+            allocations(|| {
+                block_on(connector.call(uri)).unwrap();
+            })
+        };
+
+        // The first call sets up what later calls share.
+        connect("http://192.0.2.1:80/");
+
+        let literal = connect("http://192.0.2.1:80/");
+        assert!(literal <= 8, "{} allocations for an IP address", literal);
+    }
+}
@@ -0,0 +1,154 @@
+//! Connections annotated with how they were resolved.
+
+use hyper::client::connect::{Connected, Connection as HyperConnection};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::{Resolution, SelectionPolicy};
+
+/// Lets the selection policy know when a connection to the address it picked
+/// is closed.
+pub(crate) struct Release {
+    selection: Arc<dyn SelectionPolicy>,
+    host: String,
+    addr: IpAddr,
+}
+
+impl Release {
+    pub(crate) fn new(selection: Arc<dyn SelectionPolicy>, host: String, addr: IpAddr) -> Release {
+        Release {
+            selection: selection,
+            host: host,
+            addr: addr,
+        }
+    }
+}
+
+impl fmt::Debug for Release {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Release")
+            .field("host", &self.host)
+            .field("addr", &self.addr)
+            .finish()
+    }
+}
+
+impl Drop for Release {
+    fn drop(&mut self) {
+        self.selection.released(&self.host, self.addr);
+    }
+}
+
+/// A connection returned by the wrapped connector, along with the details of
+/// the resolution which was used to establish it.
+#[derive(Debug)]
+pub struct Connection<T> {
+    io: T,
+    resolution: Resolution,
+    resolution_time: Duration,
+    /// Held only so the address is released when the connection is dropped.
+    _release: Option<Release>,
+}
+
+impl<T> Connection<T> {
+    pub(crate) fn new(
+        io: T,
+        resolution: Resolution,
+        resolution_time: Duration,
+        release: Option<Release>,
+    ) -> Connection<T> {
+        Connection {
+            io: io,
+            resolution: resolution,
+            resolution_time: resolution_time,
+            _release: release,
+        }
+    }
+
+    /// The resolution used to establish this connection, including the IP
+    /// address connected to.
+    pub fn resolution(&self) -> &Resolution {
+        &self.resolution
+    }
+
+    /// How long the DNS phase of establishing this connection took, excluding
+    /// the time taken by the wrapped connector to connect.
+    pub fn resolution_time(&self) -> Duration {
+        self.resolution_time
+    }
+
+    /// Get a reference to the underlying connection.
+    pub fn get_ref(&self) -> &T {
+        &self.io
+    }
+
+    /// Get a mutable reference to the underlying connection.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.io
+    }
+
+    /// Consume this wrapper, returning the underlying connection.
+    ///
+    /// The selection policy treats the connection as closed from this point.
+    pub fn into_inner(self) -> T {
+        self.io
+    }
+}
+
+impl<T: Read> Read for Connection<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.io.read(buf)
+    }
+}
+
+impl<T: Write> Write for Connection<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.io.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Connection<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_read(cx, buf)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Connection<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.io).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_shutdown(cx)
+    }
+}
+
+/// hyper asks the wrapped connection whether it was proxied or negotiated
+/// HTTP/2.
+impl<T: HyperConnection> HyperConnection for Connection<T> {
+    fn connected(&self) -> Connected {
+        self.io.connected()
+    }
+}
@@ -0,0 +1,154 @@
+//! Conventions for resolving services registered with Consul.
+
+use std::io;
+
+use crate::{Interceptor, Query, RecordType, Resolution};
+
+/// An interceptor which follows Consul's DNS conventions, so that a
+/// connector pointed at a Consul agent (e.g. `127.0.0.1:8600`) can be used
+/// for service discovery.
+///
+/// Hosts which are Consul service or prepared query names, such as
+/// `web.service.consul`, `v2.web.service.consul` (with a tag) or
+/// `_web._v2.service.dc1.consul` (in the RFC 2782 form), are qualified with
+/// the configured datacenter when they don't have one, and are looked up
+/// with SRV queries unless the request gives a port, so that requests go to
+/// the port the service was registered with.
+///
+/// Consul answers with a TTL of `0` by default, which is reported in
+/// `Resolution::ttl`: the connector doesn't cache resolutions, so every
+/// request is resolved again and sees the current healthy instances.
+#[derive(Debug, Clone)]
+pub struct Consul {
+    domain: String,
+    datacenter: Option<String>,
+}
+
+impl Consul {
+    /// Follow the conventions for the default `consul` domain, in the
+    /// agent's own datacenter.
+    pub fn new() -> Consul {
+        Consul {
+            domain: "consul".to_string(),
+            datacenter: None,
+        }
+    }
+
+    /// Set the domain Consul serves, if it was configured with another.
+    ///
+    /// Defaults to `consul`.
+    pub fn set_domain<S>(&mut self, domain: S)
+    where
+        S: Into<String>,
+    {
+        self.domain = domain.into().trim_matches('.').to_ascii_lowercase();
+    }
+
+    /// Set the datacenter added to names which don't have one.
+    ///
+    /// Defaults to `None`, where the agent's own datacenter is used.
+    pub fn set_datacenter<S>(&mut self, datacenter: Option<S>)
+    where
+        S: Into<String>,
+    {
+        self.datacenter = datacenter.map(|dc| dc.into().to_ascii_lowercase());
+    }
+
+    /// `host` qualified with the datacenter, or `None` if it isn't a Consul
+    /// service or prepared query name.
+    fn qualify(&self, host: &str) -> Option<String> {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        let suffix = format!(".{}", self.domain);
+        if !host.ends_with(&suffix) {
+            return None;
+        }
+
+        let name = &host[..host.len() - suffix.len()];
+        let labels: Vec<&str> = name.split('.').collect();
+        let kind = |label: &str| label == "service" || label == "query";
+
+        match labels.len() {
+            n if n >= 2 && kind(labels[n - 1]) => Some(match self.datacenter {
+                Some(ref dc) => format!("{}.{}{}", name, dc, suffix),
+                None => host.clone(),
+            }),
+            n if n >= 3 && kind(labels[n - 2]) => Some(host.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Consul {
+    fn default() -> Consul {
+        Consul::new()
+    }
+}
+
+impl Interceptor for Consul {
+    fn pre_query(&self, query: &mut Query) -> io::Result<Option<Resolution>> {
+        if let Some(host) = self.qualify(&query.host) {
+            query.host = host;
+            if query.port.is_none() {
+                query.record_type = RecordType::SRV;
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(host: &str, port: Option<u16>) -> Query {
+        let mut query = Query {
+            host: host.to_string(),
+            port: port,
+            record_type: RecordType::A,
+        };
+        let mut consul = Consul::new();
+        consul.set_datacenter(Some("dc1"));
+        assert!(consul.pre_query(&mut query).unwrap().is_none());
+        query
+    }
+
+    #[test]
+    fn qualifies_service_names() {
+        let consul = Consul::new();
+        assert_eq!(
+            consul.qualify("web.service.consul"),
+            Some("web.service.consul".to_string())
+        );
+        assert_eq!(
+            consul.qualify("v2.web.service.dc2.consul"),
+            Some("v2.web.service.dc2.consul".to_string())
+        );
+        assert_eq!(consul.qualify("node1.node.consul"), None);
+        assert_eq!(consul.qualify("web.example.com"), None);
+
+        let web = query("web.service.consul", None);
+        assert_eq!(web.host, "web.service.dc1.consul");
+        assert_eq!(web.record_type, RecordType::SRV);
+
+        let tagged = query("_web._v2.service.consul", None);
+        assert_eq!(tagged.host, "_web._v2.service.dc1.consul");
+
+        let other = query("web.service.dc2.consul", None);
+        assert_eq!(other.host, "web.service.dc2.consul");
+
+        let with_port = query("web.service.consul", Some(8080));
+        assert_eq!(with_port.record_type, RecordType::A);
+
+        let unrelated = query("example.com", None);
+        assert_eq!(unrelated.host, "example.com");
+        assert_eq!(unrelated.record_type, RecordType::A);
+    }
+
+    #[test]
+    fn custom_domain() {
+        let mut consul = Consul::new();
+        consul.set_domain("Sd.Internal.");
+        assert!(consul.qualify("web.service.sd.internal").is_some());
+        assert!(consul.qualify("web.service.consul").is_none());
+    }
+}
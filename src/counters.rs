@@ -0,0 +1,136 @@
+//! Counters and histograms emitted through the `metrics` facade, enabled by
+//! the `metrics` feature.
+//!
+//! Without the feature these are no-ops.
+
+use std::io;
+use std::time::Duration;
+
+use crate::{Error, ResolutionSource};
+
+/// A query was sent to a DNS server.
+#[cfg(feature = "metrics")]
+pub fn query_sent() {
+    metrics::counter!("hyper_dns.queries_sent").increment(1);
+}
+
+/// Resolving a request's host failed with `err`.
+#[cfg(feature = "metrics")]
+pub fn lookup_failed(err: &io::Error) {
+    metrics::counter!("hyper_dns.lookup_failures", "class" => failure_class(err)).increment(1);
+}
+
+/// A request's host was resolved from `source` in `elapsed`, an `Interceptor`
+/// source being a cache hit.
+#[cfg(feature = "metrics")]
+pub fn lookup_succeeded(source: ResolutionSource, elapsed: Duration) {
+    match source {
+        ResolutionSource::Interceptor => metrics::counter!("hyper_dns.cache_hits").increment(1),
+        ResolutionSource::Dns => {
+            metrics::histogram!("hyper_dns.lookup_duration_ns").record(elapsed.as_nanos() as f64)
+        }
+        ResolutionSource::Literal => {}
+    }
+}
+
+/// `count` lookups are now in flight.
+#[cfg(feature = "metrics")]
+pub fn lookups_in_flight(count: usize) {
+    metrics::gauge!("hyper_dns.lookups_in_flight").set(count as f64);
+}
+
+#[cfg(not(feature = "metrics"))]
+pub fn query_sent() {}
+
+#[cfg(not(feature = "metrics"))]
+pub fn lookup_failed(_err: &io::Error) {}
+
+#[cfg(not(feature = "metrics"))]
+pub fn lookup_succeeded(_source: ResolutionSource, _elapsed: Duration) {}
+
+#[cfg(not(feature = "metrics"))]
+pub fn lookups_in_flight(_count: usize) {}
+
+/// The label used for the class of a failure.
+pub fn failure_class(err: &io::Error) -> &'static str {
+    match err.get_ref().and_then(|e| e.downcast_ref::<Error>()) {
+        Some(&Error::InvalidHost(_)) => "invalid_host",
+        Some(&Error::Timeout) => "timeout",
+        Some(&Error::Transport(_)) => "transport",
+        Some(&Error::NxDomain(_)) => "nxdomain",
+        Some(&Error::ServFail(_)) => "servfail",
+        Some(&Error::Rejected(..)) => "rejected",
+        Some(&Error::NoRecords(_)) => "no_records",
+        Some(&Error::InvalidResponse(_)) => "validation",
+        Some(&Error::CnameLoop(_)) => "cname_loop",
+        Some(&Error::RateLimited) => "rate_limited",
+        Some(&Error::HostDenied(_)) => "host_denied",
+        Some(&Error::ForbiddenAddress(_)) => "forbidden",
+        Some(&Error::BogusAddress(_)) => "bogus_address",
+        Some(&Error::Rebinding(_)) => "rebinding",
+        Some(&Error::Vetoed(_)) => "vetoed",
+        None => match err.kind() {
+            io::ErrorKind::TimedOut => "timeout",
+            _ => "other",
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use trust_dns::op::ResponseCode;
+
+    #[test]
+    fn classifies_failures() {
+        let err = |e: Error| io::Error::from(e);
+
+        assert_eq!(failure_class(&err(Error::Timeout)), "timeout");
+        assert_eq!(
+            failure_class(&err(Error::NxDomain("foo.".into()))),
+            "nxdomain"
+        );
+        assert_eq!(
+            failure_class(&err(Error::ServFail("foo.".into()))),
+            "servfail"
+        );
+        assert_eq!(
+            failure_class(&err(Error::Rejected("foo.".into(), ResponseCode::Refused))),
+            "rejected"
+        );
+        assert_eq!(
+            failure_class(&err(Error::Transport("connection refused".into()))),
+            "transport"
+        );
+        assert_eq!(
+            failure_class(&err(Error::InvalidResponse("expected SRV record".into()))),
+            "validation"
+        );
+        assert_eq!(
+            failure_class(&err(Error::CnameLoop("example.com.".into()))),
+            "cname_loop"
+        );
+        assert_eq!(failure_class(&err(Error::RateLimited)), "rate_limited");
+        assert_eq!(
+            failure_class(&err(Error::HostDenied("example.com".into()))),
+            "host_denied"
+        );
+        assert_eq!(
+            failure_class(&err(Error::ForbiddenAddress("localhost.".into()))),
+            "forbidden"
+        );
+        assert_eq!(
+            failure_class(&err(Error::BogusAddress("example.com.".into()))),
+            "bogus_address"
+        );
+        assert_eq!(
+            failure_class(&err(Error::Rebinding("example.com.".into()))),
+            "rebinding"
+        );
+        assert_eq!(
+            failure_class(&err(Error::Vetoed("denied".into()))),
+            "vetoed"
+        );
+        assert_eq!(failure_class(&io::Error::other("interceptor")), "other");
+    }
+}
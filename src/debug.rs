@@ -0,0 +1,62 @@
+//! A hyper `Service` serving the resolver's state, enabled by the `json`
+//! feature.
+
+use futures::future::{self, Ready};
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+use hyper::service::Service;
+use hyper::{Body, Request, Response};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use crate::snapshot::snapshot;
+use crate::stats::Stats;
+use crate::Redaction;
+
+/// Serves a JSON snapshot of a connector's state, as returned by
+/// `DnsConnector::snapshot_json`, to every request.
+///
+/// Mount it in an existing server by passing requests for e.g. `/debug/dns`
+/// to it. It reflects the connector it was created from and all of its clones.
+#[derive(Debug, Clone)]
+pub struct DebugService {
+    stats: Arc<Stats>,
+    nameservers: Vec<(SocketAddr, u32)>,
+    redaction: Redaction,
+}
+
+impl DebugService {
+    pub(crate) fn new(
+        stats: Arc<Stats>,
+        nameservers: Vec<(SocketAddr, u32)>,
+        redaction: Redaction,
+    ) -> DebugService {
+        DebugService {
+            stats: stats,
+            nameservers: nameservers,
+            redaction: redaction,
+        }
+    }
+}
+
+impl Service<Request<Body>> for DebugService {
+    type Response = Response<Body>;
+    type Error = hyper::Error;
+    type Future = Ready<Result<Response<Body>, hyper::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), hyper::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _req: Request<Body>) -> Self::Future {
+        let body =
+            serde_json::to_string_pretty(&snapshot(&self.stats, &self.nameservers, self.redaction))
+                .expect("JSON values always serialize");
+
+        let mut response = Response::new(Body::from(body));
+        response
+            .headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        future::ok(response)
+    }
+}
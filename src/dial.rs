@@ -0,0 +1,640 @@
+//! Connecting to resolved addresses.
+
+use futures::future::{BoxFuture, Future};
+use hyper::http::uri::{Authority, Parts, PathAndQuery, Scheme};
+use hyper::service::Service;
+use hyper::Uri;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use std::vec;
+
+use crate::blacklist::Blacklist;
+use crate::connection::Release;
+use crate::{
+    pre_connect, Connection, Interceptor, Redaction, Resolution, Runtime, SelectionPolicy,
+};
+
+/// Everything needed to connect the wrapped connector to a resolved host.
+pub struct Dialer<C> {
+    pub connector: Arc<Mutex<C>>,
+    /// The request's URI, which the URI to connect with is based on.
+    pub uri: Uri,
+    pub host: String,
+    pub interceptors: Vec<Arc<dyn Interceptor>>,
+    pub selection: Arc<dyn SelectionPolicy>,
+    pub blacklist: Option<Arc<Blacklist>>,
+    pub retry: bool,
+    pub race: usize,
+    pub happy_eyeballs: Option<Duration>,
+    /// Times the delay between happy eyeballs attempts.
+    pub runtime: Arc<dyn Runtime>,
+    pub redaction: Redaction,
+    pub ipv6_zone: Option<String>,
+    pub elapsed: Duration,
+}
+
+impl<C> Dialer<C>
+where
+    C: Service<Uri> + Send + 'static,
+    C::Response: Send + 'static,
+    C::Future: Send + 'static,
+    C::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    /// Build the URI to pass to the wrapped connector to connect to `ip`.
+    ///
+    /// A zone is written as `%25` followed by the zone, as RFC 6874 describes
+    /// for URIs.
+    fn uri(&self, ip: IpAddr, port: Option<u16>) -> io::Result<Uri> {
+        let host = match (ip, &self.ipv6_zone) {
+            (IpAddr::V6(ip), Some(zone)) if is_link_local(&IpAddr::V6(ip)) => {
+                format!("[{}%25{}]", ip, zone)
+            }
+            (IpAddr::V4(ip), _) => ip.to_string(),
+            (IpAddr::V6(ip), _) => format!("[{}]", ip),
+        };
+
+        let mut new_uri = replace_authority(&self.uri, &host, port)?;
+
+        debug!("Resolved request to {}", new_uri);
+
+        pre_connect(&self.interceptors, &mut new_uri)?;
+
+        Ok(new_uri)
+    }
+
+    /// Pick the next address to try from `remaining`.
+    fn next(&self, remaining: &[IpAddr]) -> IpAddr {
+        match self.blacklist {
+            Some(ref blacklist) => self
+                .selection
+                .select(&self.host, &blacklist.filter(remaining)),
+            None => self.selection.select(&self.host, remaining),
+        }
+    }
+
+    /// Start connecting to `ip`.
+    fn attempt(&self, ip: IpAddr, port: Option<u16>) -> (IpAddr, Call<C>) {
+        match self.uri(ip, port) {
+            Ok(uri) => (ip, Call::new(self.connector.clone(), uri)),
+            Err(e) => (ip, Call::Failed(Some(e))),
+        }
+    }
+
+    /// Let the selection policy know how connecting to `ip` fared.
+    fn record(&self, ip: IpAddr, connected: bool) {
+        if connected {
+            self.selection.connected(&self.host, ip);
+            return;
+        }
+        self.selection.failed(&self.host, ip);
+        if let Some(ref blacklist) = self.blacklist {
+            blacklist.add(ip);
+        }
+    }
+
+    /// Connect to the selected address in `resolution`, racing it against up
+    /// to `race - 1` other addresses. If that fails, the host's other addresses
+    /// are tried in turn (unless retrying is disabled), skipping those in
+    /// `tried`.
+    ///
+    /// With Happy Eyeballs (RFC 8305) and addresses of both families, the
+    /// addresses are tried starting with the selected one and alternating
+    /// between families instead. Each attempt starts the delay after the
+    /// previous one did, or as soon as the previous one fails. Unless
+    /// retrying is enabled, only the selected address and the first address
+    /// of the other family are tried.
+    pub fn dial(self, resolution: Resolution, tried: Vec<IpAddr>) -> Dial<C> {
+        let mut dial = Dial {
+            dialer: self,
+            resolution: Some(resolution),
+            tried: tried,
+            attempts: Vec::new(),
+            racing: Vec::new(),
+            remaining: Vec::new(),
+            staggered: None,
+            error: None,
+        };
+        dial.start();
+        dial
+    }
+}
+
+/// A connection being made to a resolved host by `Dialer::dial`.
+pub struct Dial<C>
+where
+    C: Service<Uri>,
+{
+    dialer: Dialer<C>,
+    /// Taken once connected.
+    resolution: Option<Resolution>,
+    tried: Vec<IpAddr>,
+    /// The addresses being connected to.
+    attempts: Vec<(IpAddr, Call<C>)>,
+    /// The addresses raced against each other, and those which may be tried
+    /// once they all fail.
+    racing: Vec<IpAddr>,
+    remaining: Vec<IpAddr>,
+    staggered: Option<Staggered>,
+    /// Why the last attempt to fail did.
+    error: Option<io::Error>,
+}
+
+/// The Happy Eyeballs attempts yet to start.
+struct Staggered {
+    ips: vec::IntoIter<IpAddr>,
+    delay: Duration,
+    /// The address attempted last, and the delay before the next attempt,
+    /// made once polled as timers may need to be made on the runtime.
+    latest: Option<IpAddr>,
+    timer: Option<BoxFuture<'static, ()>>,
+}
+
+impl<C> Dial<C>
+where
+    C: Service<Uri> + Send + 'static,
+    C::Response: Send + 'static,
+    C::Future: Send + 'static,
+    C::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    fn resolution(&self) -> &Resolution {
+        self.resolution.as_ref().expect("polled after completion")
+    }
+
+    /// Start the attempts to connect to the selected address, skipping those
+    /// already tried.
+    fn start(&mut self) {
+        let resolution = self.resolution();
+        if let Some(delay) = self.dialer.happy_eyeballs {
+            let untried: Vec<&IpAddr> = resolution
+                .addrs
+                .iter()
+                .filter(|addr| !self.tried.contains(addr))
+                .collect();
+            let dual_stack = untried.iter().any(|addr| addr.is_ipv4())
+                && untried.iter().any(|addr| addr.is_ipv6());
+
+            if dual_stack {
+                let mut ips = interleave(resolution.ip, &resolution.addrs);
+                ips.retain(|addr| !self.tried.contains(addr));
+                if !self.dialer.retry {
+                    ips.truncate(2);
+                }
+
+                debug!(
+                    "Connecting to {:?} for {} using Happy Eyeballs",
+                    ips,
+                    self.dialer.redaction.apply(&self.dialer.host)
+                );
+
+                self.staggered = Some(Staggered {
+                    ips: ips.into_iter(),
+                    delay: delay,
+                    latest: None,
+                    timer: None,
+                });
+                self.start_next();
+                return;
+            }
+        }
+
+        let mut ips = vec![resolution.ip];
+        let mut remaining: Vec<IpAddr> = resolution
+            .addrs
+            .iter()
+            .cloned()
+            .filter(|addr| *addr != resolution.ip && !self.tried.contains(addr))
+            .collect();
+
+        while ips.len() < self.dialer.race && !remaining.is_empty() {
+            let next = self.dialer.next(&remaining);
+            remaining.retain(|addr| *addr != next);
+            ips.push(next);
+        }
+
+        if ips.len() > 1 {
+            debug!(
+                "Racing connections to {:?} for {}",
+                ips,
+                self.dialer.redaction.apply(&self.dialer.host)
+            );
+        }
+
+        let port = resolution.port;
+        self.attempts = ips
+            .iter()
+            .map(|ip| self.dialer.attempt(*ip, port))
+            .collect();
+        self.racing = ips;
+        self.remaining = remaining;
+    }
+
+    /// Start the next Happy Eyeballs attempt, if there are any left.
+    fn start_next(&mut self) {
+        let port = self.resolution().port;
+        let staggered = match self.staggered {
+            Some(ref mut staggered) => staggered,
+            None => return,
+        };
+        staggered.timer = None;
+        staggered.latest = staggered.ips.next();
+        if let Some(ip) = staggered.latest {
+            self.attempts.push(self.dialer.attempt(ip, port));
+        }
+    }
+
+    /// The connection made to `ip` with `io`.
+    fn connected(&mut self, ip: IpAddr, io: C::Response) -> Connection<C::Response> {
+        let mut resolution = self.resolution.take().expect("polled after completion");
+        resolution.ip = ip;
+
+        let dialer = &self.dialer;
+        let release = Release::new(dialer.selection.clone(), dialer.host.clone(), ip);
+        Connection::new(io, resolution, dialer.elapsed, Some(release))
+    }
+
+    /// Try the next address after the raced ones all failed, if retrying and
+    /// there are any left, returning whether it was.
+    fn retry(&mut self) -> bool {
+        if self.staggered.is_some() || !self.dialer.retry || self.remaining.is_empty() {
+            return false;
+        }
+
+        debug!(
+            "Failed to connect to {:?} for {}, trying the next address: {}",
+            self.racing,
+            self.dialer.redaction.apply(&self.dialer.host),
+            self.error.as_ref().expect("an attempt failed")
+        );
+
+        self.tried.append(&mut self.racing);
+        let next = self.dialer.next(&self.remaining);
+        if let Some(ref mut resolution) = self.resolution {
+            resolution.ip = next;
+        }
+        self.start();
+        true
+    }
+}
+
+impl<C> Future for Dial<C>
+where
+    C: Service<Uri> + Send + 'static,
+    C::Response: Send + 'static,
+    C::Future: Send + Unpin + 'static,
+    C::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    type Output = io::Result<Connection<C::Response>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        loop {
+            // The next Happy Eyeballs attempt starts once the latest has been
+            // going for the delay, or as soon as it fails.
+            let mut start_next = match this.staggered {
+                Some(ref mut staggered) if staggered.latest.is_some() => {
+                    let runtime = &this.dialer.runtime;
+                    let delay = staggered.delay;
+                    let timer = staggered.timer.get_or_insert_with(|| runtime.sleep(delay));
+                    timer.as_mut().poll(cx).is_ready()
+                }
+                _ => false,
+            };
+
+            // The first connection to succeed wins, dropping the others
+            // cancels them.
+            let mut i = 0;
+            while i < this.attempts.len() {
+                let (ip, ref mut call) = this.attempts[i];
+                match Pin::new(call).poll(cx) {
+                    Poll::Ready(Ok(io)) => {
+                        this.dialer.record(ip, true);
+                        return Poll::Ready(Ok(this.connected(ip, io)));
+                    }
+                    Poll::Ready(Err(e)) => {
+                        this.dialer.record(ip, false);
+                        this.attempts.remove(i);
+                        this.error = Some(e);
+                        if let Some(ref staggered) = this.staggered {
+                            start_next |= staggered.latest == Some(ip);
+                        }
+                    }
+                    Poll::Pending => i += 1,
+                }
+            }
+
+            if start_next {
+                this.start_next();
+                continue;
+            }
+            if !this.attempts.is_empty() {
+                return Poll::Pending;
+            }
+            if !this.retry() {
+                let e = this.error.take().expect("polled after completion");
+                return Poll::Ready(Err(e));
+            }
+        }
+    }
+}
+
+/// A connection being made with the wrapped connector, once it is ready.
+/// The connector is shared by every connection being made, so is only locked
+/// while it is polled for readiness and called, not while the future it
+/// returns connects. A connector which panicked while locked is still
+/// called, as it is up to it whether it can carry on.
+pub enum Call<C>
+where
+    C: Service<Uri>,
+{
+    Calling(Arc<Mutex<C>>, Option<Uri>),
+    Connecting(C::Future),
+    Failed(Option<io::Error>),
+}
+
+impl<C> Call<C>
+where
+    C: Service<Uri>,
+{
+    /// Connect to `uri` with `connector`.
+    pub fn new(connector: Arc<Mutex<C>>, uri: Uri) -> Call<C> {
+        Call::Calling(connector, Some(uri))
+    }
+}
+
+impl<C> Future for Call<C>
+where
+    C: Service<Uri>,
+    C::Future: Unpin,
+    C::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    type Output = io::Result<C::Response>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        loop {
+            let connecting = match *self {
+                Call::Calling(ref connector, ref mut uri) => {
+                    let mut connector = connector.lock().unwrap_or_else(PoisonError::into_inner);
+                    match connector.poll_ready(cx) {
+                        Poll::Ready(Ok(())) => {}
+                        Poll::Ready(Err(e)) => return Poll::Ready(Err(connect_error(e))),
+                        Poll::Pending => return Poll::Pending,
+                    }
+                    connector.call(uri.take().expect("polled after completion"))
+                }
+                Call::Connecting(ref mut connecting) => {
+                    return Pin::new(connecting).poll(cx).map_err(connect_error);
+                }
+                Call::Failed(ref mut e) => {
+                    return Poll::Ready(Err(e.take().expect("polled after completion")));
+                }
+            };
+            *self = Call::Connecting(connecting);
+        }
+    }
+}
+
+/// An error from the wrapped connector as an `io::Error`, unwrapping those
+/// which already were one.
+pub fn connect_error<E>(err: E) -> io::Error
+where
+    E: Into<Box<dyn Error + Send + Sync>>,
+{
+    match err.into().downcast::<io::Error>() {
+        Ok(err) => *err,
+        Err(err) => io::Error::other(err),
+    }
+}
+
+/// Whether `ip` is an IPv6 link-local address, which needs a zone to say which
+/// interface it is reachable on.
+pub fn is_link_local(ip: &IpAddr) -> bool {
+    match *ip {
+        IpAddr::V6(ip) => ip.segments()[0] & 0xffc0 == 0xfe80,
+        IpAddr::V4(_) => false,
+    }
+}
+
+/// `uri` with its authority replaced by `host` and `port`, keeping its
+/// scheme, path and query.
+fn replace_authority(uri: &Uri, host: &str, port: Option<u16>) -> io::Result<Uri> {
+    let invalid = |e: &dyn fmt::Display| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid URI to connect to {}: {}", host, e),
+        )
+    };
+
+    let authority = match port {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    };
+    let mut parts = Parts::from(uri.clone());
+    parts.authority = Some(authority.parse::<Authority>().map_err(|e| invalid(&e))?);
+    parts.scheme.get_or_insert(Scheme::HTTP);
+    parts
+        .path_and_query
+        .get_or_insert(PathAndQuery::from_static("/"));
+
+    Uri::from_parts(parts).map_err(|e| invalid(&e))
+}
+
+/// Order `addrs` starting with `first` and then alternating between address
+/// families, as described by RFC 8305.
+fn interleave(first: IpAddr, addrs: &[IpAddr]) -> Vec<IpAddr> {
+    let (same, other): (Vec<IpAddr>, Vec<IpAddr>) = addrs
+        .iter()
+        .cloned()
+        .filter(|addr| *addr != first)
+        .partition(|addr| addr.is_ipv4() == first.is_ipv4());
+
+    let mut ordered = Vec::with_capacity(addrs.len());
+    ordered.push(first);
+
+    let mut same = same.into_iter();
+    let mut other = other.into_iter();
+    loop {
+        let (next_other, next_same) = (other.next(), same.next());
+        if next_other.is_none() && next_same.is_none() {
+            break;
+        }
+        ordered.extend(next_other);
+        ordered.extend(next_same);
+    }
+
+    ordered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{interleave, is_link_local, replace_authority, Call, Dialer};
+    use crate::runtime;
+    use crate::selection::First;
+    use crate::{Redaction, Resolution};
+    use futures::executor::block_on;
+    use futures::future;
+    use hyper::service::Service;
+    use hyper::Uri;
+    use std::io;
+    use std::net::IpAddr;
+    use std::sync::{Arc, Mutex, PoisonError};
+    use std::task::{Context, Poll};
+    use std::thread;
+    use std::time::Duration;
+    use tokio::runtime::Runtime;
+
+    /// A connector which fails to connect to IPv6 addresses straight away, and
+    /// records the hosts it was asked to connect to.
+    #[derive(Clone, Default)]
+    struct Ipv4Only {
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Service<Uri> for Ipv4Only {
+        type Response = ();
+        type Error = io::Error;
+        type Future = future::Ready<io::Result<()>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, uri: Uri) -> Self::Future {
+            let host = uri.host().unwrap().to_string();
+            self.calls.lock().unwrap().push(host.clone());
+            if host.contains(':') {
+                future::err(io::Error::new(io::ErrorKind::ConnectionRefused, "refused"))
+            } else {
+                future::ok(())
+            }
+        }
+    }
+
+    #[test]
+    fn interleaves_address_families() {
+        let addrs: Vec<IpAddr> = ["::1", "::2", "::3", "10.0.0.1", "10.0.0.2"]
+            .iter()
+            .map(|addr| addr.parse().unwrap())
+            .collect();
+
+        assert_eq!(
+            interleave(addrs[1], &addrs),
+            vec![addrs[1], addrs[3], addrs[0], addrs[4], addrs[2]]
+        );
+    }
+
+    #[test]
+    fn detects_link_local_addresses() {
+        assert!(is_link_local(&"fe80::1".parse().unwrap()));
+        assert!(is_link_local(&"febf::1".parse().unwrap()));
+        assert!(!is_link_local(&"fec0::1".parse().unwrap()));
+        assert!(!is_link_local(&"169.254.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn adds_zone_to_link_local_addresses() {
+        let dialer = Dialer {
+            connector: Arc::new(Mutex::new(Ipv4Only::default())),
+            uri: "http://example.com/".parse().unwrap(),
+            host: "example.com".to_string(),
+            interceptors: Vec::new(),
+            selection: Arc::new(First),
+            blacklist: None,
+            retry: true,
+            race: 1,
+            happy_eyeballs: None,
+            runtime: runtime::default(),
+            redaction: Redaction::Off,
+            ipv6_zone: Some("eth0".to_string()),
+            elapsed: Duration::from_secs(0),
+        };
+
+        assert_eq!(
+            dialer.uri("fe80::1".parse().unwrap(), Some(80)).unwrap(),
+            "http://[fe80::1%25eth0]:80/"
+        );
+        assert_eq!(
+            dialer.uri("2001:db8::1".parse().unwrap(), None).unwrap(),
+            "http://[2001:db8::1]/"
+        );
+    }
+
+    #[test]
+    fn starts_next_attempt_when_one_fails() {
+        let connector = Ipv4Only::default();
+        let addrs: Vec<IpAddr> = ["::1", "::2", "10.0.0.1"]
+            .iter()
+            .map(|addr| addr.parse().unwrap())
+            .collect();
+
+        let dialer = Dialer {
+            connector: Arc::new(Mutex::new(connector.clone())),
+            uri: "http://example.com/".parse().unwrap(),
+            host: "example.com".to_string(),
+            interceptors: Vec::new(),
+            selection: Arc::new(First),
+            blacklist: None,
+            retry: true,
+            race: 1,
+            happy_eyeballs: Some(Duration::from_secs(3600)),
+            runtime: runtime::default(),
+            redaction: Redaction::Off,
+            ipv6_zone: None,
+            elapsed: Duration::from_secs(0),
+        };
+        let resolution = Resolution {
+            ip: addrs[0],
+            addrs: addrs.clone(),
+            port: Some(80),
+            record_type: None,
+            ttl: None,
+            nameserver: None,
+            chain: Vec::new(),
+        };
+
+        // The IPv4 address is only reached in time if the failure of the
+        // first attempt starts it, rather than the hour long delay.
+        let runtime = Runtime::new().unwrap();
+        let connection = runtime
+            .block_on(dialer.dial(resolution, Vec::new()))
+            .unwrap();
+        assert_eq!(connection.resolution().ip, addrs[2]);
+        assert_eq!(*connector.calls.lock().unwrap(), vec!["[::1]", "10.0.0.1"]);
+    }
+
+    #[test]
+    fn calls_connector_after_a_panic() {
+        let connector = Arc::new(Mutex::new(Ipv4Only::default()));
+        let panicking = connector.clone();
+        let _ = thread::spawn(move || {
+            let _locked = panicking.lock().unwrap();
+            panic!("connector panicked");
+        })
+        .join();
+        assert!(connector.is_poisoned());
+
+        let uri = "http://10.0.0.1/".parse().unwrap();
+        block_on(Call::new(connector.clone(), uri)).unwrap();
+        let connector = connector.lock().unwrap_or_else(PoisonError::into_inner);
+        assert_eq!(*connector.calls.lock().unwrap(), vec!["10.0.0.1"]);
+    }
+
+    #[test]
+    fn replaces_authority() {
+        let uri: Uri = "https://example.com/path?query=1".parse().unwrap();
+
+        assert_eq!(
+            replace_authority(&uri, "192.0.2.1", Some(8443)).unwrap(),
+            "https://192.0.2.1:8443/path?query=1"
+        );
+        assert_eq!(
+            replace_authority(&uri, "[2001:db8::1]", None).unwrap(),
+            "https://[2001:db8::1]/path?query=1"
+        );
+    }
+}
@@ -0,0 +1,282 @@
+//! DNS64 (RFC 6147) synthesis of IPv6 addresses for IPv4 only hosts, so that
+//! they can be reached through a NAT64 gateway from IPv6 only networks.
+
+use futures::future::{self, BoxFuture, Either, FutureExt, Ready};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use trust_dns::rr::{Name, RData, RecordType};
+
+use crate::time::Instant;
+use crate::{send_query, QueryOptions};
+
+/// The prefix lengths allowed by RFC 6052.
+const PREFIX_LENGTHS: [u8; 6] = [32, 40, 48, 56, 64, 96];
+
+/// The IPv4 only name queried to discover the NAT64 prefix (RFC 7050).
+const DISCOVERY_NAME: &str = "ipv4only.arpa.";
+
+/// How long after failing to discover the prefix lookups go without DNS64,
+/// before discovery is tried again.
+const DISCOVERY_RETRY: Duration = Duration::from_secs(60);
+
+/// The prefix returned by `Synthesizer::prefix`, which is only a boxed
+/// future while it is being discovered.
+pub(crate) type PrefixFuture = Either<
+    Ready<io::Result<Option<Nat64Prefix>>>,
+    BoxFuture<'static, io::Result<Option<Nat64Prefix>>>,
+>;
+
+/// A NAT64 prefix which IPv4 addresses are embedded in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Nat64Prefix {
+    prefix: Ipv6Addr,
+    len: u8,
+}
+
+impl Nat64Prefix {
+    /// Create a prefix, returning `None` unless `len` is one of the lengths
+    /// allowed by RFC 6052 (32, 40, 48, 56, 64 or 96).
+    pub fn new(prefix: Ipv6Addr, len: u8) -> Option<Nat64Prefix> {
+        if PREFIX_LENGTHS.contains(&len) {
+            Some(Nat64Prefix {
+                prefix: prefix,
+                len: len,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// The well-known prefix, `64:ff9b::/96`.
+    pub fn well_known() -> Nat64Prefix {
+        Nat64Prefix {
+            prefix: Ipv6Addr::new(0x64, 0xff9b, 0, 0, 0, 0, 0, 0),
+            len: 96,
+        }
+    }
+
+    /// The bytes of the IPv6 address which hold the IPv4 address. Bits 64 to
+    /// 71 are reserved and skipped.
+    fn positions(&self) -> Vec<usize> {
+        (self.len as usize / 8..16)
+            .filter(|&i| i != 8)
+            .take(4)
+            .collect()
+    }
+
+    /// Embed `addr` in the prefix.
+    pub fn synthesize(&self, addr: Ipv4Addr) -> Ipv6Addr {
+        let mut octets = [0u8; 16];
+        octets[..self.len as usize / 8]
+            .copy_from_slice(&self.prefix.octets()[..self.len as usize / 8]);
+
+        for (i, octet) in self.positions().into_iter().zip(addr.octets().iter()) {
+            octets[i] = *octet;
+        }
+
+        Ipv6Addr::from(octets)
+    }
+
+    /// Extract the IPv4 address embedded in `addr`.
+    fn extract(&self, addr: &Ipv6Addr) -> Ipv4Addr {
+        let octets = addr.octets();
+        let mut v4 = [0u8; 4];
+        for (i, octet) in self.positions().into_iter().zip(v4.iter_mut()) {
+            *octet = octets[i];
+        }
+        Ipv4Addr::from(v4)
+    }
+
+    /// Find the prefix used to synthesize `addr` from one of the well-known
+    /// IPv4 addresses of `ipv4only.arpa`.
+    fn discover(addr: &Ipv6Addr) -> Option<Nat64Prefix> {
+        let well_known = [Ipv4Addr::new(192, 0, 0, 170), Ipv4Addr::new(192, 0, 0, 171)];
+
+        PREFIX_LENGTHS
+            .iter()
+            .rev()
+            .filter_map(|&len| {
+                let mut octets = [0u8; 16];
+                octets[..len as usize / 8].copy_from_slice(&addr.octets()[..len as usize / 8]);
+                let prefix = Nat64Prefix {
+                    prefix: Ipv6Addr::from(octets),
+                    len: len,
+                };
+
+                if well_known.contains(&prefix.extract(addr)) {
+                    Some(prefix)
+                } else {
+                    None
+                }
+            })
+            .next()
+    }
+}
+
+/// Where the NAT64 prefix comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dns64 {
+    /// Use the given prefix.
+    Prefix(Nat64Prefix),
+    /// Discover the prefix from the DNS server, as described in RFC 7050.
+    Discover,
+}
+
+/// What is known of the prefix to discover.
+#[derive(Debug, Clone, Copy)]
+enum Discovery {
+    Unknown,
+    Found(Nat64Prefix),
+    /// Discovery failed, and isn't tried again until the given time.
+    Failed(Instant),
+}
+
+/// Synthesizes IPv6 addresses using the configured or discovered prefix.
+#[derive(Debug)]
+pub struct Synthesizer {
+    config: Dns64,
+    discovered: Arc<Mutex<Discovery>>,
+}
+
+impl Synthesizer {
+    pub fn new(config: Dns64) -> Synthesizer {
+        Synthesizer {
+            config: config,
+            discovered: Arc::new(Mutex::new(Discovery::Unknown)),
+        }
+    }
+
+    /// The prefix to synthesize addresses with, which is discovered from the
+    /// DNS server the first time it is needed if it was not configured. A
+    /// failure to discover it is remembered for a minute, so that every
+    /// lookup doesn't query for it.
+    pub(crate) fn prefix(&self, dns_addr: SocketAddr, options: QueryOptions) -> PrefixFuture {
+        if let Dns64::Prefix(prefix) = self.config {
+            return Either::Left(future::ok(Some(prefix)));
+        }
+
+        match *self.discovered.lock().unwrap() {
+            Discovery::Found(prefix) => return Either::Left(future::ok(Some(prefix))),
+            Discovery::Failed(until) if Instant::now() < until => {
+                return Either::Left(future::ok(None))
+            }
+            _ => {}
+        }
+
+        let discovered = self.discovered.clone();
+        let name = Name::parse(DISCOVERY_NAME, None).expect("valid discovery name");
+        let future = send_query(dns_addr, name, RecordType::AAAA, options).map(move |res| {
+            let prefix = res.ok().and_then(|res| {
+                res.answers()
+                    .iter()
+                    .filter_map(|record| match *record.rdata() {
+                        RData::AAAA(ref addr) => Nat64Prefix::discover(addr),
+                        _ => None,
+                    })
+                    .next()
+            });
+
+            *discovered.lock().unwrap() = match prefix {
+                Some(prefix) => {
+                    debug!("Discovered NAT64 prefix {}/{}", prefix.prefix, prefix.len);
+                    Discovery::Found(prefix)
+                }
+                None => {
+                    debug!("Failed to discover NAT64 prefix");
+                    Discovery::Failed(Instant::now() + DISCOVERY_RETRY)
+                }
+            };
+
+            Ok(prefix)
+        });
+
+        Either::Right(future.boxed())
+    }
+}
+
+/// Replace `addrs` with addresses synthesized from the IPv4 ones, unless they
+/// already contain IPv6 addresses. Returns whether they were replaced.
+pub fn synthesize(prefix: &Nat64Prefix, addrs: &mut Vec<IpAddr>) -> bool {
+    if addrs.is_empty() || addrs.iter().any(|addr| addr.is_ipv6()) {
+        return false;
+    }
+
+    *addrs = addrs
+        .iter()
+        .filter_map(|addr| match *addr {
+            IpAddr::V4(addr) => Some(IpAddr::V6(prefix.synthesize(addr))),
+            IpAddr::V6(_) => None,
+        })
+        .collect();
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DnsConnector;
+    use futures::executor::block_on;
+
+    #[test]
+    fn synthesizes_rfc6052_examples() {
+        let addr = Ipv4Addr::new(192, 0, 2, 33);
+        let prefix = |len| Nat64Prefix::new("2001:db8:122:344::".parse().unwrap(), len).unwrap();
+
+        assert_eq!(
+            prefix(32).synthesize(addr),
+            "2001:db8:c000:221::".parse::<Ipv6Addr>().unwrap()
+        );
+        assert_eq!(
+            prefix(48).synthesize(addr),
+            "2001:db8:122:c000:2:2100::".parse::<Ipv6Addr>().unwrap()
+        );
+        assert_eq!(
+            prefix(64).synthesize(addr),
+            "2001:db8:122:344:c0:2:2100:0".parse::<Ipv6Addr>().unwrap()
+        );
+        assert_eq!(
+            Nat64Prefix::well_known().synthesize(addr),
+            "64:ff9b::c000:221".parse::<Ipv6Addr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn discovers_prefix() {
+        let prefix = Nat64Prefix::new("2001:db8:122::".parse().unwrap(), 48).unwrap();
+        let addr = prefix.synthesize(Ipv4Addr::new(192, 0, 0, 170));
+
+        assert_eq!(Nat64Prefix::discover(&addr), Some(prefix));
+        assert_eq!(Nat64Prefix::discover(&"2001:db8::1".parse().unwrap()), None);
+    }
+
+    #[test]
+    fn synthesizes_only_from_ipv4_addresses() {
+        let prefix = Nat64Prefix::well_known();
+
+        let mut addrs = vec!["192.0.2.33".parse().unwrap()];
+        assert!(synthesize(&prefix, &mut addrs));
+        assert_eq!(addrs, vec!["64:ff9b::c000:221".parse::<IpAddr>().unwrap()]);
+
+        let mut addrs = vec!["2001:db8::1".parse().unwrap()];
+        assert!(!synthesize(&prefix, &mut addrs));
+
+        let mut addrs = Vec::new();
+        assert!(!synthesize(&prefix, &mut addrs));
+    }
+
+    #[test]
+    fn failed_discovery_is_remembered() {
+        let synthesizer = Synthesizer::new(Dns64::Discover);
+        *synthesizer.discovered.lock().unwrap() =
+            Discovery::Failed(Instant::now() + DISCOVERY_RETRY);
+
+        // Nothing listens on the discard port, so this would fail if the
+        // prefix was looked for again.
+        let dns_addr = "127.0.0.1:9".parse().unwrap();
+        let options = DnsConnector::new(dns_addr, ()).query_options();
+        let prefix = synthesizer.prefix(dns_addr, options);
+        assert_eq!(block_on(prefix).unwrap(), None);
+    }
+}
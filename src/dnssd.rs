@@ -0,0 +1,206 @@
+//! DNS-SD (RFC 6763) browsing for the instances of a service.
+
+use futures::future::{self, BoxFuture, FutureExt};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use trust_dns::rr::{Name, RData, Record, RecordType};
+use trust_dns_proto::xfer::DnsResponse;
+
+use crate::{query_types, send_query, QueryOptions};
+
+/// An instance of a service, and where it can be connected to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServiceInstance {
+    /// The name of the instance, e.g. `Office._ipp._tcp.local.`.
+    pub name: String,
+    /// The host the instance is on.
+    pub target: String,
+    /// The port the instance listens on.
+    pub port: u16,
+    /// The addresses of the host.
+    pub addrs: Vec<IpAddr>,
+    /// The strings in the instance's TXT record, e.g. `path=/ipp`.
+    pub txt: Vec<String>,
+}
+
+impl ServiceInstance {
+    /// The addresses the instance can be connected to.
+    pub fn socket_addrs(&self) -> Vec<SocketAddr> {
+        self.addrs
+            .iter()
+            .map(|&addr| SocketAddr::new(addr, self.port))
+            .collect()
+    }
+}
+
+/// Find the instances of `service` advertised to `dns_addr`, following the
+/// PTR record for each instance to its SRV and TXT records, and the SRV
+/// record to the addresses of the instance's host. Instances which can't be
+/// resolved are left out.
+pub(crate) fn browse(
+    dns_addr: SocketAddr,
+    service: Name,
+    record_types: Vec<RecordType>,
+    options: QueryOptions,
+) -> BoxFuture<'static, io::Result<Vec<ServiceInstance>>> {
+    let future = async move {
+        let res = send_query(dns_addr, service, RecordType::PTR, options.clone()).await?;
+        let records = records(&res);
+        let instances = res
+            .answers()
+            .iter()
+            .filter_map(|record| match *record.rdata() {
+                RData::PTR(ref instance) => Some(resolve(
+                    dns_addr,
+                    instance.clone(),
+                    records.clone(),
+                    record_types.clone(),
+                    options.clone(),
+                )),
+                _ => None,
+            });
+
+        let instances = future::join_all(instances).await;
+        Ok(instances.into_iter().flatten().collect())
+    };
+
+    future.boxed()
+}
+
+/// Resolve the instance `name`, using `records` from earlier responses
+/// where they have the addresses needed.
+///
+/// One instance failing to resolve doesn't stop the others being found, so
+/// any failure is `None`.
+async fn resolve(
+    dns_addr: SocketAddr,
+    name: Name,
+    mut records: Vec<Record>,
+    record_types: Vec<RecordType>,
+    options: QueryOptions,
+) -> Option<ServiceInstance> {
+    let srv = send_query(dns_addr, name.clone(), RecordType::SRV, options.clone());
+    let txt = send_query(dns_addr, name.clone(), RecordType::TXT, options.clone());
+    let (srv, txt) = future::join(srv, txt).await;
+
+    records.extend(self::records(&srv.ok()?));
+    if let Ok(txt) = txt {
+        records.extend(txt.answers().iter().cloned());
+    }
+
+    let instance = assemble(&name, &records)?;
+    if !instance.addrs.is_empty() {
+        return Some(instance);
+    }
+    let target = match Name::parse(&instance.target, None) {
+        Ok(target) => target,
+        Err(_) => return Some(instance),
+    };
+
+    if let Ok(responses) = query_types(dns_addr, target, record_types, options).await {
+        for res in responses {
+            records.extend(res.answers().iter().cloned());
+        }
+    }
+    assemble(&name, &records)
+}
+
+/// The answers and additional records in `res`.
+fn records(res: &DnsResponse) -> Vec<Record> {
+    res.answers()
+        .iter()
+        .chain(res.additionals())
+        .cloned()
+        .collect()
+}
+
+/// The instance `name` as described by the SRV, TXT and address records in
+/// `records`, or `None` if it has no SRV record.
+fn assemble(name: &Name, records: &[Record]) -> Option<ServiceInstance> {
+    let srv = records
+        .iter()
+        .filter(|record| record.name() == name)
+        .filter_map(|record| match *record.rdata() {
+            RData::SRV(ref srv) => Some(srv),
+            _ => None,
+        })
+        .min_by_key(|srv| srv.priority())?;
+
+    let txt = records
+        .iter()
+        .filter(|record| record.name() == name)
+        .filter_map(|record| match *record.rdata() {
+            RData::TXT(ref txt) => Some(txt),
+            _ => None,
+        })
+        .flat_map(|txt| txt.iter())
+        .map(|data| String::from_utf8_lossy(data).into_owned())
+        .collect();
+
+    let mut addrs = Vec::new();
+    for record in records
+        .iter()
+        .filter(|record| record.name() == srv.target())
+    {
+        let addr = match *record.rdata() {
+            RData::A(ref addr) => IpAddr::V4(*addr),
+            RData::AAAA(ref addr) => IpAddr::V6(*addr),
+            _ => continue,
+        };
+        if !addrs.contains(&addr) {
+            addrs.push(addr);
+        }
+    }
+
+    Some(ServiceInstance {
+        name: name.to_string(),
+        target: srv.target().to_string(),
+        port: srv.port(),
+        addrs: addrs,
+        txt: txt,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use std::str::FromStr;
+    use trust_dns::rr::rdata::{SRV, TXT};
+
+    fn name(name: &str) -> Name {
+        Name::from_str(name).unwrap()
+    }
+
+    #[test]
+    fn assembles_instances() {
+        let instance = name("Office._ipp._tcp.local.");
+        let records = vec![
+            Record::from_rdata(
+                instance.clone(),
+                120,
+                RData::SRV(SRV::new(0, 0, 631, name("printer.local."))),
+            ),
+            Record::from_rdata(
+                instance.clone(),
+                120,
+                RData::TXT(TXT::new(vec!["path=/ipp".to_string()])),
+            ),
+            Record::from_rdata(
+                name("printer.local."),
+                120,
+                RData::A(Ipv4Addr::new(192, 168, 1, 20)),
+            ),
+        ];
+
+        let assembled = assemble(&instance, &records).unwrap();
+        assert_eq!(assembled.target, "printer.local.");
+        assert_eq!(assembled.txt, vec!["path=/ipp".to_string()]);
+        assert_eq!(
+            assembled.socket_addrs(),
+            vec!["192.168.1.20:631".parse::<SocketAddr>().unwrap()]
+        );
+
+        assert!(assemble(&name("Other._ipp._tcp.local."), &records).is_none());
+    }
+}
@@ -0,0 +1,282 @@
+//! DNSSEC validation of responses, enabled by the `dnssec` feature.
+
+use futures::future::{self, BoxFuture, FutureExt, TryFutureExt};
+use std::str::FromStr;
+use trust_dns::client::ClientHandle;
+use trust_dns::error::ClientError;
+use trust_dns::op::{DnsResponse, Message};
+use trust_dns::rr::{DNSClass, Name, RData, RecordType};
+use trust_dns_proto::rr::dnssec::rdata::{DNSSECRData, DNSSECRecordType, DS};
+use trust_dns_proto::rr::dnssec::{Algorithm, DigestType, PublicKeyBuf, TrustAnchor};
+use trust_dns_proto::DnssecDnsHandle;
+
+/// The keys which responses must be signed by a chain of trust from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Dnssec {
+    /// The root zone's key signing keys, which are built in.
+    Root,
+    /// The given DNSKEY public keys, in their wire format, in place of the
+    /// root zone's keys, e.g. for air-gapped environments with private trust
+    /// anchors.
+    TrustAnchors(Vec<Vec<u8>>),
+    /// The keys of `zone` matching the given DS records, in their wire
+    /// format, in place of the root zone's keys. The zone's DNSKEY records
+    /// are queried along with each query, and those whose digest matches one
+    /// of the DS records are used as the trust anchors; queries fail if none
+    /// do.
+    DelegationSigners {
+        /// The zone the DS records are for, e.g. `.` or `corp.example.`.
+        zone: String,
+        /// The DS records' RDATA: the key tag, algorithm, digest type and
+        /// digest.
+        records: Vec<Vec<u8>>,
+    },
+}
+
+impl Dnssec {
+    /// The trust anchor for validating responses, found by querying `client`
+    /// for the zone's keys when it is given by DS records.
+    fn trust_anchor<H>(&self, mut client: H) -> BoxFuture<'static, Result<TrustAnchor, ClientError>>
+    where
+        H: ClientHandle + Sync + Unpin,
+    {
+        let keys = match *self {
+            Dnssec::Root => return future::ok(TrustAnchor::default()).boxed(),
+            Dnssec::TrustAnchors(ref keys) => return future::ok(anchor(keys.clone())).boxed(),
+            Dnssec::DelegationSigners {
+                ref zone,
+                ref records,
+            } => {
+                let zone = match Name::from_str(zone) {
+                    Ok(zone) => zone,
+                    Err(e) => return future::err(e.into()).boxed(),
+                };
+                let records = match records
+                    .iter()
+                    .map(|ds| parse_ds(ds))
+                    .collect::<Option<Vec<_>>>()
+                {
+                    Some(records) => records,
+                    None => return future::err("Invalid DS trust anchor".into()).boxed(),
+                };
+                client
+                    .query(
+                        zone.clone(),
+                        DNSClass::IN,
+                        RecordType::DNSSEC(DNSSECRecordType::DNSKEY),
+                    )
+                    .map_ok(move |res| matching_keys(&res, &zone, &records))
+            }
+        };
+
+        keys.and_then(|keys: Vec<Vec<u8>>| {
+            future::ready(if keys.is_empty() {
+                Err("No DNSKEY matches the DS trust anchors".into())
+            } else {
+                Ok(anchor(keys))
+            })
+        })
+        .boxed()
+    }
+
+    /// Query `client` for `name`, failing unless the response validates. Only
+    /// the verified records are kept.
+    pub fn query<H>(
+        &self,
+        client: H,
+        name: Name,
+        record_type: RecordType,
+    ) -> BoxFuture<'static, Result<DnsResponse, ClientError>>
+    where
+        H: ClientHandle + Sync + Unpin,
+    {
+        self.trust_anchor(client.clone())
+            .and_then(move |anchor| {
+                let mut client = DnssecDnsHandle::with_trust_anchor(client, anchor);
+                client
+                    .query(name.clone(), DNSClass::IN, record_type)
+                    .map_ok(move |mut response| {
+                        restore_additionals(&mut response, &name, record_type);
+                        response
+                    })
+            })
+            .boxed()
+    }
+}
+
+/// A trust anchor of the DNSKEY public keys `keys`.
+fn anchor(keys: Vec<Vec<u8>>) -> TrustAnchor {
+    let mut anchor = TrustAnchor::new();
+    for key in keys {
+        anchor.insert_trust_anchor(&PublicKeyBuf::new(key));
+    }
+    anchor
+}
+
+/// The DS record with the RDATA `rdata`, or `None` if it is too short.
+fn parse_ds(rdata: &[u8]) -> Option<DS> {
+    if rdata.len() < 4 {
+        return None;
+    }
+    let digest_type = DigestType::from_u8(rdata[3]).ok()?;
+    Some(DS::new(
+        u16::from_be_bytes([rdata[0], rdata[1]]),
+        Algorithm::from_u8(rdata[2]),
+        digest_type,
+        rdata[4..].to_vec(),
+    ))
+}
+
+/// The public keys of the DNSKEY records for `zone` in `response` which one
+/// of `records` has the digest of.
+fn matching_keys(response: &Message, zone: &Name, records: &[DS]) -> Vec<Vec<u8>> {
+    response
+        .answers()
+        .iter()
+        .filter(|answer| answer.name() == zone)
+        .filter_map(|answer| match *answer.rdata() {
+            RData::DNSSEC(DNSSECRData::DNSKEY(ref key)) => Some(key),
+            _ => None,
+        })
+        .filter(|key| {
+            records.iter().any(|ds| {
+                ds.key_tag() == key.calculate_key_tag().unwrap_or(0)
+                    && ds.algorithm() == key.algorithm()
+                    && ds.covers(zone, key).unwrap_or(false)
+            })
+        })
+        .map(|key| key.public_key().to_vec())
+        .collect()
+}
+
+/// `DnssecDnsHandle` returns the verified additional records among the
+/// answers, so move those which aren't `record_type` records for `name`, or
+/// CNAME records leading from it, back to the additional section, where the
+/// addresses of SRV targets are looked for.
+fn restore_additionals(response: &mut Message, name: &Name, record_type: RecordType) {
+    let mut owners = vec![name.clone()];
+    let mut answers = Vec::new();
+    let mut additionals = response.take_additionals();
+
+    for record in response.take_answers() {
+        if !owners.contains(record.name()) {
+            additionals.push(record);
+            continue;
+        }
+        match *record.rdata() {
+            RData::CNAME(ref target) if record.rr_type() == RecordType::CNAME => {
+                owners.push(target.clone())
+            }
+            _ if record.rr_type() == record_type => {}
+            _ => {
+                additionals.push(record);
+                continue;
+            }
+        }
+        answers.push(record);
+    }
+
+    response.insert_answers(answers);
+    response.insert_additionals(additionals);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients;
+    use crate::testing::{TestServer, Zone};
+    use crate::validate::validate;
+    use crate::{runtime, Redaction};
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+    use tokio::runtime::Runtime;
+    use trust_dns::op::Query;
+    use trust_dns::rr::rdata::SRV;
+    use trust_dns::rr::Record;
+    use trust_dns_proto::rr::dnssec::rdata::DNSKEY;
+
+    fn key(public_key: &[u8]) -> DNSKEY {
+        DNSKEY::new(true, true, false, Algorithm::ED25519, public_key.to_vec())
+    }
+
+    /// The RDATA of a DS record for `key` of `zone`.
+    fn ds(zone: &str, key: &DNSKEY) -> Vec<u8> {
+        let zone = Name::from_str(zone).unwrap();
+        let mut rdata = key.calculate_key_tag().unwrap().to_be_bytes().to_vec();
+        rdata.push(u8::from(key.algorithm()));
+        rdata.push(u8::from(DigestType::SHA256));
+        rdata.extend_from_slice(key.to_digest(&zone, DigestType::SHA256).unwrap().as_ref());
+        rdata
+    }
+
+    /// The trust anchor for `dnssec`, querying `server` for any keys.
+    fn trust_anchor(dnssec: &Dnssec, server: &TestServer) -> Result<TrustAnchor, ClientError> {
+        Runtime::new().unwrap().block_on(async {
+            let timeout = Duration::from_secs(1);
+            let client =
+                clients::connect(&*runtime::default(), server.addr(), timeout, None).await?;
+            dnssec.trust_anchor(client).await
+        })
+    }
+
+    #[test]
+    fn custom_trust_anchors_replace_root() {
+        let server = TestServer::start(Zone::new()).unwrap();
+        let key = vec![3, 1, 0, 1, 0xab, 0xcd];
+        let anchor = trust_anchor(&Dnssec::TrustAnchors(vec![key.clone()]), &server).unwrap();
+
+        assert!(anchor.contains_dnskey_bytes(&key));
+        assert!(!anchor.contains_dnskey_bytes(TrustAnchor::default().get(0)));
+    }
+
+    #[test]
+    fn ds_trust_anchors_select_the_zones_matching_keys() {
+        let (ksk, zsk) = (key(&[1; 32]), key(&[2; 32]));
+        let mut zone = Zone::new();
+        for key in &[&ksk, &zsk] {
+            let dnskey = RData::DNSSEC(DNSSECRData::DNSKEY((*key).clone()));
+            zone.add("corp.example", 60, dnskey);
+        }
+        let server = TestServer::start(zone).unwrap();
+
+        let dnssec = Dnssec::DelegationSigners {
+            zone: "corp.example.".to_string(),
+            records: vec![ds("corp.example.", &ksk)],
+        };
+        let anchor = trust_anchor(&dnssec, &server).unwrap();
+        assert!(anchor.contains_dnskey_bytes(ksk.public_key()));
+        assert!(!anchor.contains_dnskey_bytes(zsk.public_key()));
+
+        // A digest of the key for another zone doesn't match it.
+        let dnssec = Dnssec::DelegationSigners {
+            zone: "corp.example.".to_string(),
+            records: vec![ds("other.example.", &ksk)],
+        };
+        assert!(trust_anchor(&dnssec, &server).is_err());
+    }
+
+    #[test]
+    fn verified_additionals_are_moved_back_out_of_the_answers() {
+        let name = Name::from_str("_http._tcp.example.com.").unwrap();
+        let target = Name::from_str("web.example.com.").unwrap();
+        let srv = Record::from_rdata(
+            name.clone(),
+            60,
+            RData::SRV(SRV::new(0, 0, 80, target.clone())),
+        );
+        let a = Record::from_rdata(target, 60, RData::A(Ipv4Addr::new(192, 0, 2, 1)));
+        let mut response = Message::new();
+        response.add_query(Query::query(name.clone(), RecordType::SRV));
+        response.add_answer(srv.clone());
+        response.add_answer(a.clone());
+
+        restore_additionals(&mut response, &name, RecordType::SRV);
+
+        assert_eq!(response.answers(), &[srv][..]);
+        assert_eq!(response.additionals(), &[a][..]);
+        assert_eq!(
+            validate(&response, &name, RecordType::SRV, Redaction::Off),
+            Ok(())
+        );
+    }
+}
@@ -0,0 +1,198 @@
+//! DNS-over-HTTPS (RFC 8484) queries, with the HTTP requests made by the
+//! caller, for targets where the connector can't open sockets itself.
+
+use futures::future::{self, BoxFuture, FutureExt, TryFutureExt};
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use trust_dns::op::{Message, Query};
+use trust_dns::rr::{Name, RecordType};
+use trust_dns_proto::xfer::DnsResponse;
+
+use crate::{Backend, Error};
+
+const BASE64URL: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// Fetches a URL with a GET request, with an `Accept` header of
+/// `application/dns-message`, returning the body of the response.
+pub type Fetch = dyn Fn(String) -> BoxFuture<'static, io::Result<Vec<u8>>> + Send + Sync;
+
+/// A `Backend` sending queries to a DNS-over-HTTPS server, with the HTTP
+/// requests made by a function given by the caller, such as one using the
+/// browser's `fetch` on wasm32 targets or an HTTP client of the
+/// application's.
+///
+/// Backend futures must be `Send`, so on wasm32, where `fetch`'s futures
+/// aren't, they need wrapping, e.g. with `send_wrapper`, which is sound as
+/// there is only the one thread.
+pub struct DnsOverHttps {
+    url: String,
+    fetch: Box<Fetch>,
+}
+
+impl DnsOverHttps {
+    /// Query the server at `url`, e.g. `https://dns.example/dns-query`,
+    /// fetching with `fetch`.
+    pub fn new<F>(url: &str, fetch: F) -> DnsOverHttps
+    where
+        F: Fn(String) -> BoxFuture<'static, io::Result<Vec<u8>>> + Send + Sync + 'static,
+    {
+        DnsOverHttps {
+            url: url.to_string(),
+            fetch: Box::new(fetch),
+        }
+    }
+
+    /// The URL to fetch for a query of `record_type` records of `name`, with
+    /// the query in the `dns` parameter. The query's ID is 0, so responses
+    /// can be cached by HTTP caches.
+    fn url(&self, name: Name, record_type: RecordType) -> io::Result<String> {
+        let mut message = Message::new();
+        message
+            .set_recursion_desired(true)
+            .add_query(Query::query(name, record_type));
+        let query = message
+            .to_vec()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+
+        let separator = if self.url.contains('?') { '&' } else { '?' };
+        Ok(format!(
+            "{}{}dns={}",
+            self.url,
+            separator,
+            base64url(&query)
+        ))
+    }
+}
+
+/// The server the query was for is ignored, queries go to the URL.
+impl Backend for DnsOverHttps {
+    fn query(
+        &self,
+        _server: SocketAddr,
+        name: Name,
+        record_type: RecordType,
+    ) -> BoxFuture<'static, io::Result<DnsResponse>> {
+        let url = match self.url(name, record_type) {
+            Ok(url) => url,
+            Err(e) => return future::err(e).boxed(),
+        };
+
+        (self.fetch)(url)
+            .and_then(|body| {
+                future::ready(
+                    Message::from_vec(&body)
+                        .map(DnsResponse::from)
+                        .map_err(|e| Error::InvalidResponse(e.to_string()).into()),
+                )
+            })
+            .boxed()
+    }
+}
+
+impl fmt::Debug for DnsOverHttps {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DnsOverHttps")
+            .field("url", &self.url)
+            .finish()
+    }
+}
+
+/// `data` in unpadded base64url, as RFC 8484 requires.
+fn base64url(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity((data.len() * 4).div_ceil(3));
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, &byte)| {
+            bits | u32::from(byte) << (16 - 8 * i)
+        });
+        for i in 0..chunk.len() + 1 {
+            encoded.push(BASE64URL[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::response;
+    use futures::executor::block_on;
+    use std::net::Ipv4Addr;
+    use std::str::FromStr;
+    use std::sync::{Arc, Mutex};
+    use trust_dns::op::ResponseCode;
+    use trust_dns::rr::{RData, Record};
+
+    #[test]
+    fn encodes_base64url() {
+        assert_eq!(base64url(b""), "");
+        assert_eq!(base64url(b"f"), "Zg");
+        assert_eq!(base64url(b"fo"), "Zm8");
+        assert_eq!(base64url(b"foo"), "Zm9v");
+        assert_eq!(base64url(&[0xfb, 0xff]), "-_8");
+    }
+
+    #[test]
+    fn urls_carry_query() {
+        let doh = DnsOverHttps::new("https://dns.example/dns-query", |_| {
+            future::ok(Vec::new()).boxed()
+        });
+
+        // RFC 8484's example query for www.example.com, with RD set.
+        let url = doh
+            .url(Name::from_str("www.example.com.").unwrap(), RecordType::A)
+            .unwrap();
+        assert_eq!(
+            url,
+            "https://dns.example/dns-query?dns=AAABAAABAAAAAAAAA3d3dwdleGFtcGxlA2NvbQAAAQAB"
+        );
+    }
+
+    #[test]
+    fn answers_from_fetched_responses() {
+        let name = Name::from_str("www.example.com.").unwrap();
+        let answer = Record::from_rdata(name.clone(), 60, RData::A(Ipv4Addr::new(192, 0, 2, 1)));
+        let body = response(
+            name.clone(),
+            RecordType::A,
+            ResponseCode::NoError,
+            vec![answer.clone()],
+        )
+        .to_vec()
+        .unwrap();
+
+        let fetched = Arc::new(Mutex::new(Vec::new()));
+        let doh = {
+            let fetched = fetched.clone();
+            DnsOverHttps::new("https://dns.example/dns-query?ct", move |url| {
+                fetched.lock().unwrap().push(url);
+                future::ok(body.clone()).boxed()
+            })
+        };
+
+        let server = "127.0.0.1:53".parse().unwrap();
+        let res = block_on(doh.query(server, name, RecordType::A)).unwrap();
+        assert_eq!(res.answers(), &[answer][..]);
+        assert_eq!(
+            *fetched.lock().unwrap(),
+            vec![
+                "https://dns.example/dns-query?ct&dns=AAABAAABAAAAAAAAA3d3dwdleGFtcGxlA2NvbQAAAQAB"
+                    .to_string()
+            ]
+        );
+
+        let garbled = DnsOverHttps::new("https://dns.example/dns-query", |_| {
+            future::ok(vec![0xff]).boxed()
+        });
+        let err = block_on(garbled.query(
+            server,
+            Name::from_str("www.example.com.").unwrap(),
+            RecordType::A,
+        ))
+        .unwrap_err();
+        match err.get_ref().and_then(|e| e.downcast_ref::<Error>()) {
+            Some(Error::InvalidResponse(_)) => {}
+            other => panic!("unexpected error: {:?}", other),
+        }
+    }
+}
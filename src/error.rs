@@ -0,0 +1,159 @@
+//! Errors returned when resolving a request's host.
+
+use std::error;
+use std::fmt;
+use std::io;
+use trust_dns::op::ResponseCode;
+
+use crate::Redaction;
+
+/// An error which occurred while resolving a request's host.
+///
+/// Hyper requires connectors to fail with an `io::Error`, so these are
+/// returned wrapped in one, and can be recovered with `io::Error::get_ref`
+/// and `downcast_ref::<Error>()`.
+#[derive(Debug)]
+pub enum Error {
+    /// The host is not a valid DNS name.
+    InvalidHost(String),
+    /// The DNS server did not respond in time.
+    Timeout,
+    /// The query could not be sent to, or the response received from, the
+    /// DNS server.
+    Transport(String),
+    /// The host does not exist (NXDOMAIN).
+    NxDomain(String),
+    /// The DNS server failed to resolve the host (SERVFAIL).
+    ServFail(String),
+    /// The DNS server answered for the host with another error response
+    /// code, such as REFUSED when it won't resolve the host for this client,
+    /// NOTIMP or FORMERR.
+    Rejected(String, ResponseCode),
+    /// The host exists but has no records of the type queried.
+    NoRecords(String),
+    /// The response from the DNS server was not valid.
+    InvalidResponse(String),
+    /// Following the CNAME records for the host led round in a circle, or
+    /// through more records than allowed.
+    CnameLoop(String),
+    /// The query was not sent, as queries are being sent faster than the
+    /// configured rate limit.
+    RateLimited,
+    /// The host may not be resolved, as it isn't allowed by the host policy.
+    HostDenied(String),
+    /// The host only resolved to addresses which may not be connected to,
+    /// e.g. private addresses when they are rejected.
+    ForbiddenAddress(String),
+    /// The host only resolved to addresses which can never be connected to,
+    /// such as `0.0.0.0` or multicast addresses, when they are rejected.
+    BogusAddress(String),
+    /// The host resolved to a public address, and now resolves to private
+    /// ones, which suggests a DNS rebinding attack.
+    Rebinding(String),
+    /// The audit hook vetoed connecting to the resolved address, for the
+    /// reason given.
+    Vetoed(String),
+}
+
+impl Error {
+    /// Redact any hostname in the error.
+    pub(crate) fn redact(self, redaction: Redaction) -> Error {
+        match self {
+            Error::InvalidHost(host) => Error::InvalidHost(redaction.apply(&host).into_owned()),
+            Error::NxDomain(host) => Error::NxDomain(redaction.apply(&host).into_owned()),
+            Error::ServFail(host) => Error::ServFail(redaction.apply(&host).into_owned()),
+            Error::Rejected(host, code) => {
+                Error::Rejected(redaction.apply(&host).into_owned(), code)
+            }
+            Error::NoRecords(host) => Error::NoRecords(redaction.apply(&host).into_owned()),
+            Error::HostDenied(host) => Error::HostDenied(redaction.apply(&host).into_owned()),
+            Error::ForbiddenAddress(host) => {
+                Error::ForbiddenAddress(redaction.apply(&host).into_owned())
+            }
+            Error::BogusAddress(host) => Error::BogusAddress(redaction.apply(&host).into_owned()),
+            Error::Rebinding(host) => Error::Rebinding(redaction.apply(&host).into_owned()),
+            Error::CnameLoop(host) => Error::CnameLoop(redaction.apply(&host).into_owned()),
+            err => err,
+        }
+    }
+
+    /// Redact each mention of `host` in the reason given by an error whose
+    /// reason is free text, such as one from the DNS client or audit hook.
+    pub(crate) fn redact_reason(self, redaction: Redaction, host: &str) -> Error {
+        let redact = |reason: String| redaction.apply_in(&reason, host).into_owned();
+        match self {
+            Error::Transport(reason) => Error::Transport(redact(reason)),
+            Error::InvalidResponse(reason) => Error::InvalidResponse(redact(reason)),
+            Error::Vetoed(reason) => Error::Vetoed(redact(reason)),
+            err => err,
+        }
+    }
+}
+
+/// Redact each mention of `host` in the reason given by `err`, if it's an
+/// `Error` whose reason is free text.
+pub(crate) fn redact_reason(err: io::Error, redaction: Redaction, host: &str) -> io::Error {
+    if redaction == Redaction::Off || !err.get_ref().is_some_and(|e| e.is::<Error>()) {
+        return err;
+    }
+
+    match err.into_inner().map(|inner| inner.downcast::<Error>()) {
+        Some(Ok(err)) => err.redact_reason(redaction, host).into(),
+        _ => unreachable!("the error was checked to be an Error"),
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::InvalidHost(ref host) => write!(f, "Invalid host name: {}", host),
+            Error::Timeout => write!(f, "DNS query timed out"),
+            Error::Transport(ref reason) => write!(f, "Failed to query DNS server: {}", reason),
+            Error::NxDomain(ref host) => write!(f, "Host does not exist: {}", host),
+            Error::ServFail(ref host) => write!(f, "DNS server failed to resolve: {}", host),
+            Error::Rejected(ref host, code) => {
+                write!(f, "DNS server rejected the query ({}) for: {}", code, host)
+            }
+            Error::NoRecords(ref host) => write!(f, "No valid DNS answers for: {}", host),
+            Error::InvalidResponse(ref reason) => write!(f, "Invalid DNS response: {}", reason),
+            Error::CnameLoop(ref host) => {
+                write!(f, "CNAME chain is circular or too long for: {}", host)
+            }
+            Error::RateLimited => write!(f, "DNS query rate limit exceeded"),
+            Error::HostDenied(ref host) => write!(f, "Host is not allowed: {}", host),
+            Error::ForbiddenAddress(ref host) => {
+                write!(f, "Host only resolved to forbidden addresses: {}", host)
+            }
+            Error::BogusAddress(ref host) => {
+                write!(f, "Host only resolved to unusable addresses: {}", host)
+            }
+            Error::Rebinding(ref host) => {
+                write!(f, "Host changed from public to private addresses: {}", host)
+            }
+            Error::Vetoed(ref reason) => write!(f, "Connection vetoed: {}", reason),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> io::Error {
+        let kind = match err {
+            Error::InvalidHost(_) => io::ErrorKind::InvalidInput,
+            Error::Timeout => io::ErrorKind::TimedOut,
+            Error::NxDomain(_) | Error::NoRecords(_) => io::ErrorKind::NotFound,
+            Error::InvalidResponse(_) | Error::CnameLoop(_) | Error::BogusAddress(_) => {
+                io::ErrorKind::InvalidData
+            }
+            Error::HostDenied(_)
+            | Error::ForbiddenAddress(_)
+            | Error::Rebinding(_)
+            | Error::Vetoed(_) => io::ErrorKind::PermissionDenied,
+            Error::Transport(_) | Error::ServFail(_) | Error::Rejected(..) | Error::RateLimited => {
+                io::ErrorKind::Other
+            }
+        };
+        io::Error::new(kind, err)
+    }
+}
@@ -0,0 +1,136 @@
+//! Structured events for each query sent to a DNS server, for platforms
+//! which consume telemetry as data rather than log text.
+
+use std::net::SocketAddr;
+
+#[cfg(feature = "json")]
+use std::fmt;
+#[cfg(feature = "json")]
+use std::io::Write;
+#[cfg(feature = "json")]
+use std::sync::Mutex;
+
+/// An event in the life of a query. With the `serde` feature, events are
+/// serializable, e.g. as `{"QueryFinished": {"id": 1, ...}}` in JSON.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub enum Event {
+    /// A query is being sent.
+    QueryStarted {
+        /// Identifies the query, so that its events can be correlated.
+        id: usize,
+        /// The DNS server the query is sent to.
+        server: SocketAddr,
+        /// The name queried, redacted if redaction is enabled.
+        name: String,
+        /// The record type queried.
+        record_type: String,
+    },
+    /// A response to a query was received.
+    QueryFinished {
+        id: usize,
+        server: SocketAddr,
+        name: String,
+        record_type: String,
+        /// The response code, e.g. `NoError` or `NXDomain`.
+        rcode: String,
+        /// How many answers the response had.
+        answers: usize,
+        /// How long the query took, in milliseconds.
+        elapsed_ms: u64,
+    },
+    /// A query failed.
+    QueryFailed {
+        id: usize,
+        server: SocketAddr,
+        name: String,
+        record_type: String,
+        /// The error the query failed with.
+        error: String,
+        /// The class of the error, as used for metrics labels, e.g. `timeout`.
+        class: String,
+        /// How long it was before the query failed, in milliseconds.
+        elapsed_ms: u64,
+    },
+}
+
+/// Receives an event for each query sent.
+///
+/// This is called on the runtime, so implementations should not block.
+pub trait EventSink: Send + Sync {
+    fn event(&self, event: &Event);
+}
+
+impl<F> EventSink for F
+where
+    F: Fn(&Event) + Send + Sync,
+{
+    fn event(&self, event: &Event) {
+        self(event)
+    }
+}
+
+/// Writes each event as a line of JSON, enabled by the `json` feature.
+#[cfg(feature = "json")]
+pub struct JsonLines<W> {
+    writer: Mutex<W>,
+}
+
+#[cfg(feature = "json")]
+impl<W> JsonLines<W>
+where
+    W: Write + Send,
+{
+    pub fn new(writer: W) -> JsonLines<W> {
+        JsonLines {
+            writer: Mutex::new(writer),
+        }
+    }
+}
+
+#[cfg(feature = "json")]
+impl<W> EventSink for JsonLines<W>
+where
+    W: Write + Send,
+{
+    fn event(&self, event: &Event) {
+        let mut writer = self.writer.lock().unwrap();
+        let written = serde_json::to_writer(&mut *writer, event)
+            .map_err(|e| e.to_string())
+            .and_then(|_| writer.write_all(b"\n").map_err(|e| e.to_string()));
+
+        if let Err(e) = written {
+            debug!("Failed to write event: {}", e);
+        }
+    }
+}
+
+#[cfg(feature = "json")]
+impl<W> fmt::Debug for JsonLines<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("JsonLines")
+    }
+}
+
+#[cfg(all(test, feature = "json"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_json_lines() {
+        let sink = JsonLines::new(Vec::new());
+        sink.event(&Event::QueryStarted {
+            id: 1,
+            server: "127.0.0.1:53".parse().unwrap(),
+            name: "example.com.".to_string(),
+            record_type: "A".to_string(),
+        });
+
+        let written = String::from_utf8(sink.writer.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            written,
+            "{\"QueryStarted\":{\"id\":1,\"server\":\"127.0.0.1:53\",\
+             \"name\":\"example.com.\",\"record_type\":\"A\"}}\n"
+        );
+    }
+}
@@ -0,0 +1,88 @@
+//! Which address families to look up, and which to prefer.
+
+use std::net::IpAddr;
+use trust_dns::rr::RecordType;
+
+/// The address families a host is resolved to, and which family is preferred
+/// when it resolves to both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AddressFamily {
+    /// Only look up IPv4 (A) addresses.
+    #[default]
+    Ipv4Only,
+    /// Only look up IPv6 (AAAA) addresses.
+    Ipv6Only,
+    /// Look up both, and connect to IPv4 addresses first.
+    PreferIpv4,
+    /// Look up both, and connect to IPv6 addresses first.
+    PreferIpv6,
+}
+
+impl AddressFamily {
+    /// The record types to query for, preferred family first.
+    pub(crate) fn record_types(&self) -> Vec<RecordType> {
+        match *self {
+            AddressFamily::Ipv4Only => vec![RecordType::A],
+            AddressFamily::Ipv6Only => vec![RecordType::AAAA],
+            AddressFamily::PreferIpv4 => vec![RecordType::A, RecordType::AAAA],
+            AddressFamily::PreferIpv6 => vec![RecordType::AAAA, RecordType::A],
+        }
+    }
+
+    /// Whether `addr` is of an allowed family.
+    pub(crate) fn allows(&self, addr: &IpAddr) -> bool {
+        match *self {
+            AddressFamily::Ipv4Only => addr.is_ipv4(),
+            AddressFamily::Ipv6Only => addr.is_ipv6(),
+            AddressFamily::PreferIpv4 | AddressFamily::PreferIpv6 => true,
+        }
+    }
+
+    /// How strongly `addr` is preferred, lower is better.
+    fn preference(&self, addr: &IpAddr) -> usize {
+        match (*self, addr.is_ipv6()) {
+            (AddressFamily::PreferIpv4, true) | (AddressFamily::PreferIpv6, false) => 1,
+            _ => 0,
+        }
+    }
+
+    /// Move the addresses of the preferred family ahead of the others,
+    /// keeping the existing order (and `ranks`) within each family.
+    pub(crate) fn order(&self, addrs: &mut Vec<IpAddr>, ranks: &mut Vec<usize>) {
+        let worst = ranks.iter().cloned().max().unwrap_or(0) + 1;
+        let mut ranked: Vec<(IpAddr, usize)> = addrs
+            .iter()
+            .zip(ranks.iter())
+            .map(|(addr, rank)| (*addr, self.preference(addr) * worst + rank))
+            .collect();
+
+        ranked.sort_by_key(|(addr, _)| self.preference(addr));
+
+        *addrs = ranked.iter().map(|&(addr, _)| addr).collect();
+        *ranks = ranked.iter().map(|&(_, rank)| rank).collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefers_family_then_rank() {
+        let v4: IpAddr = "10.0.0.1".parse().unwrap();
+        let v6: IpAddr = "2001:db8::1".parse().unwrap();
+        let other: IpAddr = "2001:db8::2".parse().unwrap();
+
+        let mut addrs = vec![v4, v6, other];
+        let mut ranks = vec![0, 0, 1];
+        AddressFamily::PreferIpv6.order(&mut addrs, &mut ranks);
+        assert_eq!(addrs, vec![v6, other, v4]);
+        assert_eq!(ranks, vec![0, 1, 2]);
+
+        let mut addrs = vec![v6, v4];
+        let mut ranks = vec![0, 1];
+        AddressFamily::PreferIpv4.order(&mut addrs, &mut ranks);
+        assert_eq!(addrs, vec![v4, v6]);
+        assert_eq!(ranks, vec![1, 2]);
+    }
+}
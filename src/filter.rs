@@ -0,0 +1,304 @@
+//! Filtering of the addresses hosts resolve to, to enforce egress policy.
+
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::AddressFilterCallback;
+
+/// Which resolved addresses may be connected to.
+#[derive(Clone, Default)]
+pub struct AddressFilter {
+    /// Whether private, loopback, link-local and other non-public addresses
+    /// are rejected.
+    pub reject_private: bool,
+    /// Networks which addresses must be in, if there are any.
+    pub allow: Vec<Cidr>,
+    /// Networks which addresses may not be in.
+    pub deny: Vec<Cidr>,
+    /// A callback deciding whether each address may be connected to.
+    pub callback: Option<Arc<AddressFilterCallback>>,
+}
+
+impl AddressFilter {
+    /// Whether `addr`, which `host` resolved to, may be connected to.
+    pub fn allows(&self, host: &str, addr: &IpAddr) -> bool {
+        if self.reject_private && is_private(addr) {
+            return false;
+        }
+
+        if self.deny.iter().any(|cidr| cidr.contains(addr))
+            || !(self.allow.is_empty() || self.allow.iter().any(|cidr| cidr.contains(addr)))
+        {
+            return false;
+        }
+
+        match self.callback {
+            Some(ref callback) => callback(*addr, host),
+            None => true,
+        }
+    }
+}
+
+impl fmt::Debug for AddressFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AddressFilter")
+            .field("reject_private", &self.reject_private)
+            .field("allow", &self.allow)
+            .field("deny", &self.deny)
+            .field("callback", &self.callback.is_some())
+            .finish()
+    }
+}
+
+/// A network, such as `10.0.0.0/8` or `2001:db8::/32`.
+///
+/// IPv4 networks also contain the IPv4-mapped IPv6 addresses of their
+/// addresses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// The network of `addr` with a `prefix` bit long prefix, which is
+    /// truncated to the length of the address.
+    pub fn new(addr: IpAddr, prefix: u8) -> Cidr {
+        let bits = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        Cidr {
+            addr: addr,
+            prefix: prefix.min(bits),
+        }
+    }
+
+    /// Whether `addr` is in this network.
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        let addr = match *addr {
+            IpAddr::V6(ref v6) => match v6.to_ipv4() {
+                Some(v4) if v6.segments()[5] == 0xffff => IpAddr::V4(v4),
+                _ => *addr,
+            },
+            addr => addr,
+        };
+
+        match (self.addr, addr) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => {
+                prefix_matches(&net.octets(), &addr.octets(), self.prefix)
+            }
+            (IpAddr::V6(net), IpAddr::V6(addr)) => {
+                prefix_matches(&net.octets(), &addr.octets(), self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = io::Error;
+
+    /// Parse a network such as `10.0.0.0/8`, or a single address.
+    fn from_str(s: &str) -> Result<Cidr, io::Error> {
+        let invalid =
+            || io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid CIDR: {}", s));
+
+        let mut parts = s.splitn(2, '/');
+        let addr: IpAddr = parts.next().unwrap().parse().map_err(|_| invalid())?;
+        let bits = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let prefix = match parts.next() {
+            Some(prefix) => prefix.parse().map_err(|_| invalid())?,
+            None => bits,
+        };
+
+        if prefix > bits {
+            return Err(invalid());
+        }
+        Ok(Cidr::new(addr, prefix))
+    }
+}
+
+/// Whether the first `prefix` bits of `net` and `addr` are the same.
+fn prefix_matches(net: &[u8], addr: &[u8], prefix: u8) -> bool {
+    let prefix = prefix as usize;
+    let (bytes, bits) = (prefix / 8, prefix % 8);
+
+    if net[..bytes] != addr[..bytes] {
+        return false;
+    }
+
+    let mask = !(0xffu8 >> bits);
+    bits == 0 || net[bytes] & mask == addr[bytes] & mask
+}
+
+/// Whether `addr` is not a public unicast address: it is private (RFC 1918,
+/// RFC 4193), loopback, link-local, shared (RFC 6598), unspecified, broadcast
+/// or multicast, or an IPv4-mapped or NAT64 address embedding one of those.
+pub fn is_private(addr: &IpAddr) -> bool {
+    match *addr {
+        IpAddr::V4(ref addr) => is_private_v4(addr),
+        IpAddr::V6(ref addr) => is_private_v6(addr),
+    }
+}
+
+fn is_private_v4(addr: &Ipv4Addr) -> bool {
+    let octets = addr.octets();
+    addr.is_private()
+        || addr.is_loopback()
+        || addr.is_link_local()
+        || addr.is_broadcast()
+        || addr.is_multicast()
+        || octets[0] == 0
+        || (octets[0] == 100 && octets[1] & 0xc0 == 64)
+}
+
+fn is_private_v6(addr: &Ipv6Addr) -> bool {
+    let segments = addr.segments();
+    let embedded = Ipv4Addr::new(
+        (segments[6] >> 8) as u8,
+        segments[6] as u8,
+        (segments[7] >> 8) as u8,
+        segments[7] as u8,
+    );
+
+    match segments {
+        // IPv4-mapped addresses.
+        [0, 0, 0, 0, 0, 0xffff, _, _] => is_private_v4(&embedded),
+        // The NAT64 well-known prefix (RFC 6052).
+        [0x64, 0xff9b, 0, 0, 0, 0, _, _] => is_private_v4(&embedded),
+        _ => {
+            addr.is_loopback()
+                || addr.is_unspecified()
+                || addr.is_multicast()
+                || segments[0] & 0xfe00 == 0xfc00
+                || segments[0] & 0xffc0 == 0xfe80
+                || segments[0] & 0xffc0 == 0xfec0
+        }
+    }
+}
+
+/// Whether `addr` can never be connected to: it is unspecified, broadcast or
+/// multicast.
+pub fn is_bogus(addr: &IpAddr) -> bool {
+    match *addr {
+        IpAddr::V4(ref addr) => addr.is_unspecified() || addr.is_broadcast() || addr.is_multicast(),
+        IpAddr::V6(ref addr) => addr.is_unspecified() || addr.is_multicast(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn private_addresses() {
+        let private = |addr: &str| is_private(&addr.parse().unwrap());
+
+        for addr in &[
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "127.0.0.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:10.0.0.1",
+            "64:ff9b::a9fe:a9fe",
+        ] {
+            assert!(private(addr), "{} should be private", addr);
+        }
+
+        for addr in &[
+            "8.8.8.8",
+            "172.32.0.1",
+            "100.128.0.1",
+            "2001:4860:4860::8888",
+            "::ffff:8.8.8.8",
+            "64:ff9b::808:808",
+        ] {
+            assert!(!private(addr), "{} should be public", addr);
+        }
+    }
+
+    #[test]
+    fn bogus_addresses() {
+        let bogus = |addr: &str| is_bogus(&addr.parse().unwrap());
+
+        for addr in &["0.0.0.0", "255.255.255.255", "224.0.0.1", "::", "ff02::1"] {
+            assert!(bogus(addr), "{} should be bogus", addr);
+        }
+        for addr in &["8.8.8.8", "10.0.0.1", "127.0.0.1", "::1", "2001:db8::1"] {
+            assert!(!bogus(addr), "{} should be usable", addr);
+        }
+    }
+
+    #[test]
+    fn cidrs() {
+        let cidr = |s: &str| s.parse::<Cidr>().unwrap();
+        let contains = |cidr: &Cidr, addr: &str| cidr.contains(&addr.parse().unwrap());
+
+        assert!(contains(&cidr("10.0.0.0/8"), "10.20.30.40"));
+        assert!(!contains(&cidr("10.0.0.0/8"), "11.0.0.1"));
+        assert!(contains(&cidr("192.168.4.0/22"), "192.168.7.255"));
+        assert!(!contains(&cidr("192.168.4.0/22"), "192.168.8.0"));
+        assert!(contains(&cidr("10.0.0.0/8"), "::ffff:10.0.0.1"));
+        assert!(!contains(&cidr("10.0.0.0/8"), "::a00:1"));
+        assert!(contains(&cidr("2001:db8::/32"), "2001:db8:1::1"));
+        assert!(!contains(&cidr("2001:db8::/32"), "10.0.0.1"));
+        assert!(contains(&cidr("0.0.0.0/0"), "8.8.8.8"));
+        assert!(contains(&cidr("8.8.8.8"), "8.8.8.8"));
+        assert!(!contains(&cidr("8.8.8.8"), "8.8.4.4"));
+
+        assert_eq!(cidr("10.0.0.0/8").to_string(), "10.0.0.0/8");
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("example.com/8".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn cidr_lists() {
+        let filter = AddressFilter {
+            allow: vec!["10.0.0.0/8".parse().unwrap()],
+            deny: vec!["10.1.0.0/16".parse().unwrap()],
+            ..AddressFilter::default()
+        };
+
+        let allows = |addr: &str| filter.allows("example.com", &addr.parse().unwrap());
+        assert!(allows("10.2.0.1"));
+        assert!(!allows("10.1.0.1"));
+        assert!(!allows("8.8.8.8"));
+    }
+
+    #[test]
+    fn callback_filters_per_host() {
+        let filter = AddressFilter {
+            reject_private: true,
+            callback: Some(Arc::new(|addr: IpAddr, host: &str| {
+                host != "tenant-a.example.com" || addr == IpAddr::from([8, 8, 8, 8])
+            })),
+            ..AddressFilter::default()
+        };
+
+        let allows = |host: &str, addr: &str| filter.allows(host, &addr.parse().unwrap());
+        assert!(allows("tenant-a.example.com", "8.8.8.8"));
+        assert!(!allows("tenant-a.example.com", "8.8.4.4"));
+        assert!(allows("tenant-b.example.com", "8.8.4.4"));
+        assert!(!allows("tenant-b.example.com", "10.0.0.1"));
+    }
+}
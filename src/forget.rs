@@ -0,0 +1,40 @@
+//! Hosts whose cached state has been forgotten, so that their next lookup
+//! is fresh.
+
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+/// Hosts which have been forgotten since they were last looked up.
+#[derive(Debug, Default)]
+pub struct Forgotten {
+    hosts: Mutex<HashSet<String>>,
+}
+
+impl Forgotten {
+    /// Forget `host`, so that its next lookup is fresh.
+    pub fn forget(&self, host: String) {
+        self.hosts.lock().unwrap().insert(host);
+    }
+
+    /// Whether `host` has been forgotten since it was last looked up. It is
+    /// remembered again once this returns, as it is about to be looked up.
+    pub fn take(&self, host: &str) -> bool {
+        self.hosts.lock().unwrap().remove(host)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forgets_hosts_until_their_next_lookup() {
+        let forgotten = Forgotten::default();
+        assert!(!forgotten.take("example.com"));
+
+        forgotten.forget("example.com".to_string());
+        assert!(!forgotten.take("other.example.com"));
+        assert!(forgotten.take("example.com"));
+        assert!(!forgotten.take("example.com"));
+    }
+}
@@ -0,0 +1,173 @@
+//! Queries through hickory's resolver, enabled by the `hickory` feature.
+
+use futures::future::{BoxFuture, FutureExt};
+use hickory_resolver::config::{ResolverConfig, ResolverOpts};
+use hickory_resolver::error::{ResolveError, ResolveErrorKind};
+use hickory_resolver::lookup::Lookup;
+use hickory_resolver::proto::rr::{RData as HickoryRData, Record as HickoryRecord};
+use hickory_resolver::TokioAsyncResolver;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use trust_dns::op::{DnsResponse, ResponseCode};
+use trust_dns::rr::rdata::{SRV, TXT};
+use trust_dns::rr::{Name, RData, Record, RecordType};
+
+use crate::backend::response;
+use crate::{Backend, Error};
+
+/// A `Backend` sending the connector's queries to hickory's `AsyncResolver`,
+/// for its cache, retries and handling of the system's configuration.
+///
+/// Lookups run on the tokio runtime polling the connector's futures.
+pub struct Hickory {
+    resolver: TokioAsyncResolver,
+}
+
+impl Hickory {
+    /// Resolve with the system's configuration, e.g. `/etc/resolv.conf`.
+    pub fn from_system_conf() -> io::Result<Hickory> {
+        let resolver = TokioAsyncResolver::tokio_from_system_conf().map_err(resolve_error)?;
+        Ok(Hickory { resolver: resolver })
+    }
+
+    /// Resolve with the given configuration and options.
+    pub fn new(config: ResolverConfig, options: ResolverOpts) -> io::Result<Hickory> {
+        let resolver = TokioAsyncResolver::tokio(config, options);
+        Ok(Hickory { resolver: resolver })
+    }
+}
+
+/// Names which don't exist, or have no records of the type queried, are
+/// responses with no answers rather than errors, as they would be from the
+/// connector's own client. The server the query was for is ignored, hickory
+/// picks its own.
+impl Backend for Hickory {
+    fn query(
+        &self,
+        _server: SocketAddr,
+        name: Name,
+        record_type: RecordType,
+    ) -> BoxFuture<'static, io::Result<DnsResponse>> {
+        let hickory_type = u16::from(record_type).into();
+        // The lookup borrows the resolver, so it's given its own clone.
+        let resolver = self.resolver.clone();
+        let host = name.to_string();
+
+        let lookup = async move { resolver.lookup(host, hickory_type).await };
+        lookup
+            .map(move |res| match res {
+                Ok(lookup) => Ok(response(
+                    name,
+                    record_type,
+                    ResponseCode::NoError,
+                    answers(&lookup),
+                )),
+                Err(e) => match *e.kind() {
+                    ResolveErrorKind::NoRecordsFound { response_code, .. } => {
+                        let response_code: ResponseCode = u16::from(response_code).into();
+                        Ok(response(name, record_type, response_code, Vec::new()))
+                    }
+                    _ => Err(resolve_error(e)),
+                },
+            })
+            .boxed()
+    }
+}
+
+impl fmt::Debug for Hickory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Hickory").finish()
+    }
+}
+
+/// The records of `lookup` the connector understands, converted to its DNS
+/// client's types.
+fn answers(lookup: &Lookup) -> Vec<Record> {
+    lookup.record_iter().filter_map(convert).collect()
+}
+
+fn convert(record: &HickoryRecord) -> Option<Record> {
+    let name = Name::from_str(&record.name().to_string()).ok()?;
+    let rdata = match *record.data()? {
+        HickoryRData::A(ref addr) => RData::A(addr.0),
+        HickoryRData::AAAA(ref addr) => RData::AAAA(addr.0),
+        HickoryRData::CNAME(ref target) => {
+            RData::CNAME(Name::from_str(&target.0.to_string()).ok()?)
+        }
+        HickoryRData::PTR(ref target) => RData::PTR(Name::from_str(&target.0.to_string()).ok()?),
+        HickoryRData::SRV(ref srv) => RData::SRV(SRV::new(
+            srv.priority(),
+            srv.weight(),
+            srv.port(),
+            Name::from_str(&srv.target().to_string()).ok()?,
+        )),
+        HickoryRData::TXT(ref txt) => RData::TXT(TXT::new(
+            txt.iter()
+                .map(|data| String::from_utf8_lossy(data).into_owned())
+                .collect(),
+        )),
+        _ => return None,
+    };
+
+    Some(Record::from_rdata(name, record.ttl(), rdata))
+}
+
+fn resolve_error(err: ResolveError) -> io::Error {
+    match *err.kind() {
+        ResolveErrorKind::Timeout => Error::Timeout.into(),
+        _ => Error::Transport(err.to_string()).into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_resolver::proto::rr::rdata;
+    use hickory_resolver::proto::rr::Name as HickoryName;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn converts_records() {
+        let name = HickoryName::from_str("_http._tcp.example.com.").unwrap();
+        let target = HickoryName::from_str("web.example.com.").unwrap();
+
+        let srv = convert(&HickoryRecord::from_rdata(
+            name,
+            60,
+            HickoryRData::SRV(rdata::SRV::new(10, 5, 8080, target.clone())),
+        ))
+        .unwrap();
+        assert_eq!(
+            srv.name(),
+            &Name::from_str("_http._tcp.example.com.").unwrap()
+        );
+        assert_eq!(srv.ttl(), 60);
+        assert_eq!(
+            srv.rdata(),
+            &RData::SRV(SRV::new(
+                10,
+                5,
+                8080,
+                Name::from_str("web.example.com.").unwrap()
+            ))
+        );
+
+        let a = convert(&HickoryRecord::from_rdata(
+            target.clone(),
+            30,
+            HickoryRData::A(rdata::A(Ipv4Addr::new(10, 0, 0, 1))),
+        ))
+        .unwrap();
+        assert_eq!(a.rr_type(), RecordType::A);
+        assert_eq!(a.rdata(), &RData::A(Ipv4Addr::new(10, 0, 0, 1)));
+
+        assert!(convert(&HickoryRecord::from_rdata(
+            target,
+            30,
+            HickoryRData::NULL(rdata::NULL::new()),
+        ))
+        .is_none());
+    }
+}
@@ -0,0 +1,77 @@
+//! A record of the most recent resolutions.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::time::SystemTime;
+use crate::{Resolution, ResolutionSource};
+
+/// A past resolution of a request's host.
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+    /// When the resolution finished.
+    pub time: SystemTime,
+    /// The host which was resolved, after any rewriting.
+    pub host: String,
+    /// Where the addresses came from, or would have come from had it
+    /// succeeded.
+    pub source: ResolutionSource,
+    /// The resolution, including the address selected and the nameserver
+    /// which answered, or the error resolving failed with.
+    pub outcome: Result<Resolution, String>,
+    /// How long resolving the host took.
+    pub duration: Duration,
+}
+
+/// The last `size` resolutions.
+#[derive(Debug)]
+pub struct History {
+    size: usize,
+    entries: Mutex<VecDeque<HistoryEntry>>,
+}
+
+impl History {
+    pub fn new(size: usize) -> History {
+        History {
+            size: size,
+            entries: Mutex::new(VecDeque::with_capacity(size)),
+        }
+    }
+
+    /// Add `entry`, forgetting the oldest entry if the history is full.
+    pub fn push(&self, entry: HistoryEntry) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() == self.size {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    /// The entries, oldest first.
+    pub fn entries(&self) -> Vec<HistoryEntry> {
+        self.entries.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_most_recent() {
+        let history = History::new(2);
+        for host in &["a", "b", "c"] {
+            history.push(HistoryEntry {
+                time: SystemTime::now(),
+                host: host.to_string(),
+                source: ResolutionSource::Dns,
+                outcome: Err("No valid DNS answers".to_string()),
+                duration: Duration::from_millis(1),
+            });
+        }
+
+        let hosts: Vec<String> = history.entries().into_iter().map(|e| e.host).collect();
+        assert_eq!(hosts, vec!["b", "c"]);
+    }
+}
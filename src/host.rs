@@ -0,0 +1,107 @@
+//! Normalization and validation of hosts.
+
+use std::borrow::Cow;
+
+use crate::Error;
+
+/// The longest host name which can be represented in a DNS query.
+const MAX_HOST_LEN: usize = 253;
+
+/// The longest label which can be represented in a DNS query.
+const MAX_LABEL_LEN: usize = 63;
+
+/// Normalize `host` so that equivalent spellings produce identical queries,
+/// rejecting hosts which could never be resolved.
+///
+/// The host is lower cased and, unless `keep_root` is set, any trailing `.`
+/// is removed. Internationalized hosts are converted to their ASCII (punycode)
+/// form if `allow_idn` is set, and rejected otherwise.
+pub fn normalize(host: &str, keep_root: bool, allow_idn: bool) -> Result<String, Error> {
+    let original = host;
+    let host = if host.is_ascii() {
+        Cow::Borrowed(host)
+    } else if allow_idn {
+        Cow::Owned(
+            idna::domain_to_ascii(host).map_err(|_| Error::InvalidHost(original.to_string()))?,
+        )
+    } else {
+        return Err(Error::InvalidHost(original.to_string()));
+    };
+
+    let rooted = host.ends_with('.');
+    let bare = if rooted {
+        &host[..host.len() - 1]
+    } else {
+        &*host
+    };
+
+    if bare.is_empty() || bare.len() > MAX_HOST_LEN {
+        return Err(Error::InvalidHost(original.to_string()));
+    }
+
+    for label in bare.split('.') {
+        let valid = !label.is_empty()
+            && label.len() <= MAX_LABEL_LEN
+            && label
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+
+        if !valid {
+            return Err(Error::InvalidHost(original.to_string()));
+        }
+    }
+
+    let mut normalized = bare.to_ascii_lowercase();
+    if rooted && keep_root {
+        normalized.push('.');
+    }
+
+    Ok(normalized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::normalize;
+
+    #[test]
+    fn normalizes_case_and_root() {
+        assert_eq!(
+            normalize("API.Example.com", false, true).unwrap(),
+            "api.example.com"
+        );
+        assert_eq!(
+            normalize("api.example.com.", false, true).unwrap(),
+            "api.example.com"
+        );
+        assert_eq!(
+            normalize("api.example.com.", true, true).unwrap(),
+            "api.example.com."
+        );
+        assert_eq!(
+            normalize("_http._tcp.Example.com", false, true).unwrap(),
+            "_http._tcp.example.com"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_hosts() {
+        assert!(normalize("", false, true).is_err());
+        assert!(normalize(".", false, true).is_err());
+        assert!(normalize("foo..bar", false, true).is_err());
+        assert!(normalize("foo bar", false, true).is_err());
+        assert!(normalize(&"a".repeat(64), false, true).is_err());
+    }
+
+    #[test]
+    fn converts_idn_hosts() {
+        assert_eq!(
+            normalize("bücher.example", false, true).unwrap(),
+            "xn--bcher-kva.example"
+        );
+        assert_eq!(
+            normalize("BÜCHER.example", false, true).unwrap(),
+            "xn--bcher-kva.example"
+        );
+        assert!(normalize("bücher.example", false, false).is_err());
+    }
+}
@@ -0,0 +1,93 @@
+//! Allow and deny lists of the hosts a connector may resolve.
+
+/// Patterns of hosts which may and may not be resolved.
+///
+/// A pattern is either a suffix starting with `.`, such as `.example.com`,
+/// which matches `example.com` and every host under it, or a glob where `*`
+/// matches any run of characters, such as `api-*.example.com` or `*`.
+#[derive(Debug, Clone, Default)]
+pub struct HostPolicy {
+    allow: Vec<String>,
+    deny: Vec<String>,
+}
+
+impl HostPolicy {
+    pub fn new<I, J, S, T>(allow: I, deny: J) -> HostPolicy
+    where
+        I: IntoIterator<Item = S>,
+        J: IntoIterator<Item = T>,
+        S: AsRef<str>,
+        T: AsRef<str>,
+    {
+        HostPolicy {
+            allow: allow.into_iter().map(|p| normalize(p.as_ref())).collect(),
+            deny: deny.into_iter().map(|p| normalize(p.as_ref())).collect(),
+        }
+    }
+
+    /// Whether `host` may be resolved: it matches none of the deny patterns,
+    /// and one of the allow patterns if there are any.
+    pub fn allows(&self, host: &str) -> bool {
+        let host = normalize(host);
+        let matching = |pattern: &String| matches(pattern, &host);
+
+        !self.deny.iter().any(&matching)
+            && (self.allow.is_empty() || self.allow.iter().any(&matching))
+    }
+}
+
+/// Lower case `host`, without any trailing `.`.
+fn normalize(host: &str) -> String {
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+fn matches(pattern: &str, host: &str) -> bool {
+    if pattern.starts_with('.') {
+        return host == &pattern[1..] || host.ends_with(pattern);
+    }
+
+    glob(pattern.as_bytes(), host.as_bytes())
+}
+
+/// Match `text` against `pattern`, where `*` matches any run of bytes.
+fn glob(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some((&b'*', rest)) => (0..=text.len()).any(|skip| glob(rest, &text[skip..])),
+        Some((&c, rest)) => match text.split_first() {
+            Some((&t, text)) if t == c => glob(rest, text),
+            _ => false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_patterns() {
+        assert!(matches("example.com", "example.com"));
+        assert!(!matches("example.com", "www.example.com"));
+        assert!(matches(".example.com", "example.com"));
+        assert!(matches(".example.com", "www.example.com"));
+        assert!(!matches(".example.com", "badexample.com"));
+        assert!(matches("api-*.example.com", "api-eu.example.com"));
+        assert!(!matches("api-*.example.com", "web.example.com"));
+        assert!(matches("*", "anything"));
+    }
+
+    #[test]
+    fn deny_overrides_allow() {
+        let policy = HostPolicy::new(vec![".internal"], vec!["secrets.internal"]);
+
+        assert!(policy.allows("billing.internal"));
+        assert!(policy.allows("Billing.Internal."));
+        assert!(!policy.allows("secrets.internal"));
+        assert!(!policy.allows("example.com"));
+
+        let open = HostPolicy::new(Vec::<String>::new(), vec![".evil.com"]);
+        assert!(open.allows("example.com"));
+        assert!(!open.allows("www.evil.com"));
+    }
+}
@@ -0,0 +1,73 @@
+//! A resolver for hyper 0.12's `HttpConnector`, enabled by the `hyper-012`
+//! feature.
+
+use futures::channel::oneshot;
+use futures::compat::Compat;
+use futures::future::{BoxFuture, FutureExt, TryFutureExt};
+use hyper_012::client::connect::dns::{Name, Resolve};
+use std::io;
+use std::net::IpAddr;
+use std::vec;
+
+use crate::background::{self, Background};
+use crate::DnsResolver;
+
+/// A resolver implementing hyper 0.12's `Resolve` trait, so that it can be
+/// given to `HttpConnector::new_with_resolver` and requests keep their URIs,
+/// with TLS and the `Host` header handled by hyper as usual.
+///
+/// Hosts are resolved by a `DnsResolver` on a background thread with a tokio
+/// 1.x runtime of its own, as hyper 0.12 runs on tokio 0.1, and the addresses
+/// are returned through a futures 0.1 compatible future. The thread exits
+/// once every clone of the resolver has been dropped.
+#[derive(Debug, Clone)]
+pub struct HyperResolver {
+    background: Background,
+}
+
+impl HyperResolver {
+    /// Resolve hosts with `resolver`, e.g. from `DnsConnector::resolver`.
+    pub fn new(resolver: DnsResolver) -> io::Result<HyperResolver> {
+        Ok(HyperResolver {
+            background: Background::new(resolver)?,
+        })
+    }
+}
+
+impl Resolve for HyperResolver {
+    type Addrs = vec::IntoIter<IpAddr>;
+    type Future = Compat<BoxFuture<'static, io::Result<Self::Addrs>>>;
+
+    fn resolve(&self, name: Name) -> Self::Future {
+        let (reply, addrs) = oneshot::channel();
+        self.background.lookup(name.as_str(), 0, move |addrs| {
+            let _ = reply
+                .send(addrs.map(|addrs| addrs.iter().map(|addr| addr.ip()).collect::<Vec<_>>()));
+        });
+
+        let addrs = addrs.map(|res| match res {
+            Ok(Ok(addrs)) => Ok(addrs.into_iter()),
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err(background::stopped()),
+        });
+        addrs.boxed().compat()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::check_adapter;
+    use futures::compat::Future01CompatExt;
+    use futures::executor::block_on;
+    use std::str::FromStr;
+
+    #[test]
+    fn resolves_with_the_resolver() {
+        check_adapter(|resolver, host| {
+            let resolver = HyperResolver::new(resolver).unwrap();
+            let resolving = resolver.resolve(Name::from_str(host).unwrap());
+            Ok(block_on(resolving.compat())?.collect())
+        });
+    }
+}
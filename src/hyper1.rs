@@ -0,0 +1,212 @@
+//! A connector for hyper 1.x through hyper-util's legacy client, enabled by
+//! the `hyper-1` feature.
+
+use futures::future::BoxFuture;
+use http::Uri;
+use hyper_util::rt::TokioIo;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::vec;
+use tokio::net::TcpStream;
+use tower_service::Service;
+
+use crate::addrs::default_port;
+use crate::DnsResolver;
+
+/// A connector for hyper-util's legacy `Client`, which resolves the host of
+/// each request as the connector a `DnsResolver` was created from does,
+/// following SRV records for the port where its record type calls for them,
+/// and connects to the addresses in the order picked.
+///
+/// Connections are plain TCP, on the tokio 1.x runtime the client runs on;
+/// wrap the connector with e.g. hyper-rustls for TLS. Hosts are resolved on
+/// that runtime too, as for `TowerResolver`.
+#[derive(Debug, Clone)]
+pub struct HyperConnector {
+    resolver: DnsResolver,
+}
+
+impl HyperConnector {
+    /// Resolve hosts with `resolver`, e.g. from `DnsConnector::resolver`.
+    pub fn new(resolver: DnsResolver) -> io::Result<HyperConnector> {
+        Ok(HyperConnector { resolver: resolver })
+    }
+}
+
+impl Service<Uri> for HyperConnector {
+    type Response = TokioIo<TcpStream>;
+    type Error = io::Error;
+    type Future = Connecting;
+
+    fn poll_ready(&mut self, _: &mut Context) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let host = match uri.host() {
+            Some(host) => host.trim_start_matches('[').trim_end_matches(']'),
+            None => {
+                return Connecting {
+                    state: State::Failed(Some(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "URI has no host",
+                    ))),
+                }
+            }
+        };
+
+        let addrs = self.resolver.resolve_request(host, uri.port_u16());
+        Connecting {
+            state: State::Resolving(addrs, default_port(uri.scheme_str().unwrap_or("http"))),
+        }
+    }
+}
+
+/// A connection being made to the host of a request.
+pub struct Connecting {
+    state: State,
+}
+
+impl fmt::Debug for Connecting {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Connecting").finish()
+    }
+}
+
+enum State {
+    /// Waiting for the host's addresses, and the port to use if the
+    /// resolution didn't give one.
+    Resolving(BoxFuture<'static, io::Result<Vec<SocketAddr>>>, u16),
+    /// Connecting to an address, with the ones to try after it.
+    Connecting(
+        Pin<Box<dyn Future<Output = io::Result<TcpStream>> + Send>>,
+        vec::IntoIter<SocketAddr>,
+    ),
+    Failed(Option<io::Error>),
+}
+
+impl Future for Connecting {
+    type Output = io::Result<TokioIo<TcpStream>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        loop {
+            let next = match self.state {
+                State::Resolving(ref mut addrs, port) => match addrs.as_mut().poll(cx) {
+                    Poll::Ready(Ok(addrs)) => {
+                        let mut addrs = addrs
+                            .into_iter()
+                            .map(|addr| match addr.port() {
+                                0 => SocketAddr::new(addr.ip(), port),
+                                _ => addr,
+                            })
+                            .collect::<Vec<_>>()
+                            .into_iter();
+                        match addrs.next() {
+                            Some(addr) => {
+                                State::Connecting(Box::pin(TcpStream::connect(addr)), addrs)
+                            }
+                            None => State::Failed(Some(io::Error::new(
+                                io::ErrorKind::NotFound,
+                                "Host resolved to no addresses",
+                            ))),
+                        }
+                    }
+                    Poll::Ready(Err(e)) => State::Failed(Some(e)),
+                    Poll::Pending => return Poll::Pending,
+                },
+                State::Connecting(ref mut connect, ref mut addrs) => {
+                    match connect.as_mut().poll(cx) {
+                        Poll::Ready(Ok(stream)) => return Poll::Ready(Ok(TokioIo::new(stream))),
+                        // Fall back to the next address, as the connector does.
+                        Poll::Ready(Err(e)) => match addrs.next() {
+                            Some(addr) => {
+                                debug!(
+                                    "Failed to connect, trying the next address {}: {}",
+                                    addr, e
+                                );
+                                *connect = Box::pin(TcpStream::connect(addr));
+                                continue;
+                            }
+                            None => State::Failed(Some(e)),
+                        },
+                        Poll::Pending => return Poll::Pending,
+                    }
+                }
+                State::Failed(ref mut e) => {
+                    return Poll::Ready(Err(e
+                        .take()
+                        .unwrap_or_else(|| io::Error::other("Connection already failed"))))
+                }
+            };
+            self.state = next;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::selection::First;
+    use crate::testing::StaticResolver;
+    use std::net::TcpListener;
+    use tokio::runtime::{Builder, Runtime};
+
+    fn runtime() -> Runtime {
+        Builder::new_current_thread().enable_io().build().unwrap()
+    }
+
+    #[test]
+    fn connects_to_the_resolved_addresses_in_order() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        // Take the port on 127.0.0.2 too, then close it, so that connecting
+        // there is refused and falls back to 127.0.0.1.
+        drop(TcpListener::bind(("127.0.0.2", port)).unwrap());
+
+        let static_resolver = StaticResolver::new();
+        static_resolver.add_ip("web.example.com", "127.0.0.2".parse().unwrap());
+        static_resolver.add_ip("web.example.com", "127.0.0.1".parse().unwrap());
+        let mut dns = static_resolver.connector(());
+        dns.set_selection_policy(First);
+        let mut connector = HyperConnector::new(dns.resolver()).unwrap();
+
+        let uri = format!("http://web.example.com:{}/", port).parse().unwrap();
+        let stream = runtime().block_on(connector.call(uri)).unwrap();
+        assert_eq!(
+            stream.inner().peer_addr().unwrap(),
+            listener.local_addr().unwrap()
+        );
+
+        // With only the refusing address, its error is returned.
+        static_resolver.remove("web.example.com");
+        static_resolver.add_ip("web.example.com", "127.0.0.2".parse().unwrap());
+        let uri = format!("http://web.example.com:{}/", port).parse().unwrap();
+        let err = runtime().block_on(connector.call(uri)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+    }
+
+    #[test]
+    fn follows_srv_records_for_the_port() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let static_resolver = StaticResolver::new();
+        static_resolver.add_ip("web.example.com", "127.0.0.1".parse().unwrap());
+        static_resolver.add_srv("srv.example.com", 0, 0, port, "web.example.com");
+        let mut connector = HyperConnector::new(static_resolver.connector(()).resolver()).unwrap();
+
+        let uri = "http://srv.example.com/".parse().unwrap();
+        let stream = runtime().block_on(connector.call(uri)).unwrap();
+        assert_eq!(
+            stream.inner().peer_addr().unwrap(),
+            listener.local_addr().unwrap()
+        );
+
+        let missing = "http://missing.example.com:80/".parse().unwrap();
+        let err = runtime().block_on(connector.call(missing)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+}
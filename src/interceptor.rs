@@ -0,0 +1,34 @@
+//! Hooks around the lookup pipeline.
+
+use hyper::Uri;
+use std::io;
+
+use crate::{Query, Resolution};
+
+/// An interceptor which is called at each stage of resolving and connecting
+/// a request.
+///
+/// Interceptors are run in the order they were added to the connector, and
+/// any error returned by one of them fails the request.
+pub trait Interceptor: Send + Sync {
+    /// Called before the DNS query for a request is sent, the query may be
+    /// modified. Returning a resolution skips the DNS query, e.g. to serve it
+    /// from a custom cache, and the interceptors after this one, unless the
+    /// request was made with `DnsConnector::call_fresh`, in which case the
+    /// resolution is ignored and `post_response` can replace it.
+    fn pre_query(&self, _query: &mut Query) -> io::Result<Option<Resolution>> {
+        Ok(None)
+    }
+
+    /// Called with the resolution for a request, which may be modified. This
+    /// is called for every resolution, including those returned by
+    /// `pre_query`.
+    fn post_response(&self, _query: &Query, _resolution: &mut Resolution) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called with the URI that is about to be passed to the wrapped connector.
+    fn pre_connect(&self, _uri: &mut Uri) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,495 @@
+//! Iterative resolution, starting from the root servers and following
+//! referrals, for when there is no trusted recursive resolver.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::future::{self, BoxFuture, FutureExt, TryFutureExt};
+use rand::{self, Rng};
+use trust_dns::op::ResponseCode;
+use trust_dns::rr::{Name, RData, Record, RecordType};
+use trust_dns_proto::xfer::DnsResponse;
+
+use crate::time::Instant;
+use crate::{send_query, Error, QueryOptions, RootHints};
+
+/// How many referrals and CNAME records may be followed to answer a query,
+/// so that misconfigured or malicious zones can't trap the resolver.
+const MAX_STEPS: usize = 16;
+
+/// How many labels below a zone are revealed one at a time with QNAME
+/// minimization, after which its servers are asked about the full name, as
+/// RFC 9156 recommends for names with many labels.
+const MAX_MINIMIZE: usize = 10;
+
+/// The servers for the root zone, and the zones they have delegated to.
+#[derive(Debug)]
+pub struct Iterative {
+    roots: Vec<SocketAddr>,
+    delegations: Mutex<HashMap<Name, Delegation>>,
+}
+
+#[derive(Debug)]
+struct Delegation {
+    servers: Vec<SocketAddr>,
+    until: Instant,
+}
+
+impl Iterative {
+    /// Start from the root servers in `hints`.
+    pub fn new(hints: RootHints) -> Iterative {
+        Iterative {
+            roots: hints.servers,
+            delegations: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The closest zone enclosing `name` which has known servers, and those
+    /// servers in a random order.
+    fn closest(&self, name: &Name) -> (Name, Vec<SocketAddr>) {
+        let mut delegations = self.delegations.lock().unwrap();
+        let now = Instant::now();
+        delegations.retain(|_, delegation| delegation.until > now);
+
+        let mut zone = name.to_lowercase();
+        let (zone, mut servers) = loop {
+            if let Some(delegation) = delegations.get(&zone) {
+                break (zone, delegation.servers.clone());
+            }
+            if zone.is_root() {
+                break (zone, self.roots.clone());
+            }
+            zone = zone.base_name();
+        };
+
+        rand::thread_rng().shuffle(&mut servers);
+        (zone, servers)
+    }
+
+    /// Remember that `zone` is served by `servers` for `ttl` seconds.
+    fn delegate(&self, zone: &Name, servers: Vec<SocketAddr>, ttl: u32) {
+        self.delegations.lock().unwrap().insert(
+            zone.to_lowercase(),
+            Delegation {
+                servers: servers,
+                until: Instant::now() + Duration::from_secs(u64::from(ttl)),
+            },
+        );
+    }
+}
+
+/// Resolve `name` iteratively, from the closest zone with known servers.
+///
+/// The queries are sent on connections of their own, rather than ones kept
+/// open, as each authoritative server is usually only asked once or twice.
+pub fn query(
+    iterative: Arc<Iterative>,
+    name: Name,
+    record_type: RecordType,
+    options: QueryOptions,
+) -> BoxFuture<'static, io::Result<DnsResponse>> {
+    #[cfg(not(target_arch = "wasm32"))]
+    let options = QueryOptions {
+        clients: None,
+        ..options
+    };
+    step(iterative, name, record_type, options, MAX_STEPS, 0)
+}
+
+/// The name to ask the servers for `zone` about when resolving `name` with
+/// QNAME minimization, revealing at least `labels` labels: the name one label
+/// below the zone, or `None` once that is the full name.
+fn minimized(zone: &Name, name: &Name, labels: usize) -> Option<Name> {
+    let zone_labels = zone.num_labels() as usize;
+    let reveal = labels.max(zone_labels + 1);
+    if reveal >= name.num_labels() as usize || reveal > zone_labels + MAX_MINIMIZE {
+        return None;
+    }
+    Some(name.trim_to(reveal))
+}
+
+/// Take a step towards resolving `name`, asking the servers of the closest
+/// zone with known servers. With QNAME minimization, they are asked about
+/// no more than `labels` labels of the name, or one label below the zone.
+fn step(
+    iterative: Arc<Iterative>,
+    name: Name,
+    record_type: RecordType,
+    options: QueryOptions,
+    steps: usize,
+    labels: usize,
+) -> BoxFuture<'static, io::Result<DnsResponse>> {
+    if steps == 0 {
+        let host = options.redaction.apply(&name.to_string()).into_owned();
+        return future::err(
+            Error::InvalidResponse(format!("too many referrals resolving {}", host)).into(),
+        )
+        .boxed();
+    }
+
+    let (zone, servers) = iterative.closest(&name);
+    if options.minimize {
+        if let Some(qname) = minimized(&zone, &name, labels) {
+            return step_minimized(
+                iterative,
+                (zone, servers),
+                qname,
+                name,
+                record_type,
+                options,
+                steps,
+            );
+        }
+    }
+
+    let future = ask(servers, name.clone(), record_type, options.clone()).and_then(
+        move |res| -> BoxFuture<'static, io::Result<DnsResponse>> {
+            if !res.answers().is_empty() || res.response_code() != ResponseCode::NoError {
+                return follow_cname(iterative, name, record_type, options, res, steps);
+            }
+
+            // A response with no answers either delegates a zone closer to
+            // the name to other servers, or means there are no records.
+            match referral(&iterative, &zone, &name, res, &options, steps) {
+                Ok(delegated) => delegated
+                    .and_then(move |()| step(iterative, name, record_type, options, steps - 1, 0))
+                    .boxed(),
+                Err(res) => future::ok(*res).boxed(),
+            }
+        },
+    );
+
+    future.boxed()
+}
+
+/// Ask the servers of the `closest` zone about `qname`, an ancestor of `name`
+/// one label or more below the zone, so that they don't learn the full name
+/// (RFC 9156). A referral is followed as for the full name, and otherwise
+/// `qname` isn't a zone cut, so the same servers are asked about one more
+/// label.
+fn step_minimized(
+    iterative: Arc<Iterative>,
+    closest: (Name, Vec<SocketAddr>),
+    qname: Name,
+    name: Name,
+    record_type: RecordType,
+    options: QueryOptions,
+    steps: usize,
+) -> BoxFuture<'static, io::Result<DnsResponse>> {
+    let (zone, servers) = closest;
+    let revealed = qname.num_labels() as usize;
+    let future = ask(servers, qname, RecordType::A, options.clone()).then(
+        move |res| -> BoxFuture<'static, io::Result<DnsResponse>> {
+            // Some servers answer NXDOMAIN for names without records of their
+            // own but with names below them, and some refuse minimized
+            // queries, so those are asked about the full name instead.
+            let res = match res {
+                Ok(ref res) if res.response_code() == ResponseCode::NXDomain => None,
+                Ok(res) => Some(res),
+                Err(_) => None,
+            };
+            let res = match res {
+                Some(res) => res,
+                None => {
+                    let full = name.num_labels() as usize;
+                    return step(iterative, name, record_type, options, steps, full);
+                }
+            };
+
+            if res.answers().is_empty() && res.response_code() == ResponseCode::NoError {
+                if let Ok(delegated) = referral(&iterative, &zone, &name, res, &options, steps) {
+                    return delegated
+                        .and_then(move |()| {
+                            step(iterative, name, record_type, options, steps - 1, 0)
+                        })
+                        .boxed();
+                }
+            }
+            step(iterative, name, record_type, options, steps, revealed + 1)
+        },
+    );
+
+    future.boxed()
+}
+
+/// If `res` from the servers for `zone` delegates a zone closer to `name`
+/// to other servers, a future remembering those servers once their
+/// addresses are known, and otherwise `res` back.
+fn referral(
+    iterative: &Arc<Iterative>,
+    zone: &Name,
+    name: &Name,
+    res: DnsResponse,
+    options: &QueryOptions,
+    steps: usize,
+) -> Result<BoxFuture<'static, io::Result<()>>, Box<DnsResponse>> {
+    let (child, targets, ttl) = {
+        let ns: Vec<&Record> = res
+            .name_servers()
+            .iter()
+            .filter(|record| {
+                record.rr_type() == RecordType::NS
+                    && record.name() != zone
+                    && zone.zone_of(record.name())
+                    && record.name().zone_of(name)
+            })
+            .collect();
+        let child = match ns.first() {
+            Some(record) => record.name().clone(),
+            None => return Err(Box::new(res)),
+        };
+        let targets: Vec<Name> = ns
+            .iter()
+            .filter(|record| record.name() == &child)
+            .filter_map(|record| match *record.rdata() {
+                RData::NS(ref target) => Some(target.clone()),
+                _ => None,
+            })
+            .collect();
+        let ttl = ns.iter().map(|record| record.ttl()).min().unwrap_or(0);
+        (child, targets, ttl)
+    };
+
+    let glue = glue(res.additionals(), &child, &targets);
+
+    // Without glue, the name servers' addresses have to be resolved before
+    // they can be asked. Those outside the child zone can be resolved
+    // without going through this referral again.
+    let target = targets
+        .iter()
+        .find(|target| !child.zone_of(target))
+        .or_else(|| targets.first())
+        .cloned();
+    let servers: BoxFuture<'static, io::Result<Vec<SocketAddr>>> = if !glue.is_empty() {
+        future::ok(glue).boxed()
+    } else if let Some(target) = target {
+        let resolved = step(
+            iterative.clone(),
+            target,
+            RecordType::A,
+            options.clone(),
+            steps - 1,
+            0,
+        );
+        resolved
+            .map_ok(|res| {
+                res.answers()
+                    .iter()
+                    .filter_map(|record| match *record.rdata() {
+                        RData::A(addr) => Some(SocketAddr::new(IpAddr::V4(addr), 53)),
+                        _ => None,
+                    })
+                    .collect()
+            })
+            .boxed()
+    } else {
+        return Err(Box::new(res));
+    };
+
+    let iterative = iterative.clone();
+    let redaction = options.redaction;
+    let delegated = servers.and_then(move |servers| {
+        if servers.is_empty() {
+            let zone = redaction.apply(&child.to_string()).into_owned();
+            return future::err(Error::ServFail(zone).into());
+        }
+
+        iterative.delegate(&child, servers, ttl);
+        future::ok(())
+    });
+    Ok(delegated.boxed())
+}
+
+/// The addresses in `additionals` of the name servers `targets` for `child`.
+/// Only addresses of servers inside the child zone are trusted, as a server
+/// could otherwise claim any address for a name outside the zone it was
+/// asked about.
+fn glue(additionals: &[Record], child: &Name, targets: &[Name]) -> Vec<SocketAddr> {
+    additionals
+        .iter()
+        .filter(|record| targets.contains(record.name()) && child.zone_of(record.name()))
+        .filter_map(|record| match *record.rdata() {
+            RData::A(addr) => Some(SocketAddr::new(IpAddr::V4(addr), 53)),
+            RData::AAAA(addr) => Some(SocketAddr::new(IpAddr::from(addr), 53)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Ask each of `servers` in turn until one of them answers, rather than
+/// failing or refusing to.
+fn ask(
+    servers: Vec<SocketAddr>,
+    name: Name,
+    record_type: RecordType,
+    options: QueryOptions,
+) -> BoxFuture<'static, io::Result<DnsResponse>> {
+    let host = options.redaction.apply(&name.to_string()).into_owned();
+    let mut future: BoxFuture<'static, io::Result<DnsResponse>> =
+        future::err(Error::ServFail(host.clone()).into()).boxed();
+
+    for server in servers {
+        let name = name.clone();
+        let options = options.clone();
+        let host = host.clone();
+        future = future
+            .or_else(move |_| {
+                send_query(server, name, record_type, options).and_then(move |res| {
+                    future::ready(match res.response_code() {
+                        ResponseCode::ServFail => Err(Error::ServFail(host).into()),
+                        ResponseCode::Refused => {
+                            Err(Error::Rejected(host, ResponseCode::Refused).into())
+                        }
+                        _ => Ok(res),
+                    })
+                })
+            })
+            .boxed();
+    }
+
+    future
+}
+
+/// Follow any CNAME records in `res` which lead from `name` to a name it
+/// has no answers for, resolving that name and adding its answers to `res`.
+fn follow_cname(
+    iterative: Arc<Iterative>,
+    name: Name,
+    record_type: RecordType,
+    options: QueryOptions,
+    mut res: DnsResponse,
+    steps: usize,
+) -> BoxFuture<'static, io::Result<DnsResponse>> {
+    if record_type == RecordType::CNAME {
+        return future::ok(res).boxed();
+    }
+
+    let mut target = name;
+    let mut seen = 0;
+    while let Some(next) = res
+        .answers()
+        .iter()
+        .filter(|record| record.name() == &target)
+        .filter_map(|record| match *record.rdata() {
+            RData::CNAME(ref next) => Some(next.clone()),
+            _ => None,
+        })
+        .next()
+    {
+        target = next;
+        seen += 1;
+        if seen > MAX_STEPS {
+            // Circular, which is reported when the chain is followed.
+            return future::ok(res).boxed();
+        }
+    }
+
+    let answered = res
+        .answers()
+        .iter()
+        .any(|record| record.name() == &target && record.rr_type() == record_type);
+    if seen == 0 || answered {
+        return future::ok(res).boxed();
+    }
+
+    step(iterative, target, record_type, options, steps - 1, 0)
+        .map_ok(move |target_res| {
+            res.add_answers(target_res.answers().to_vec());
+            res
+        })
+        .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(any(feature = "tokio", feature = "async-std", feature = "smol"))]
+    use crate::testing::{TestServer, Zone};
+    #[cfg(any(feature = "tokio", feature = "async-std", feature = "smol"))]
+    use std::net::Ipv4Addr;
+    use std::str::FromStr;
+    #[cfg(any(feature = "tokio", feature = "async-std", feature = "smol"))]
+    use tokio::runtime::Runtime;
+
+    fn name(name: &str) -> Name {
+        Name::from_str(name).unwrap()
+    }
+
+    #[test]
+    fn starts_from_closest_delegation() {
+        let iterative = Iterative::new(RootHints::default());
+        let servers = vec!["192.0.2.1:53".parse().unwrap()];
+
+        let (zone, roots) = iterative.closest(&name("www.example.com."));
+        assert!(zone.is_root());
+        assert_eq!(roots.len(), 13);
+
+        iterative.delegate(&name("Example.com."), servers.clone(), 60);
+        assert_eq!(
+            iterative.closest(&name("www.example.com.")),
+            (name("example.com."), servers.clone())
+        );
+        assert!(iterative.closest(&name("example.net.")).0.is_root());
+
+        iterative.delegate(&name("example.org."), servers, 0);
+        assert!(iterative.closest(&name("www.example.org.")).0.is_root());
+    }
+
+    #[test]
+    fn minimizes_one_label_at_a_time() {
+        let host = name("www.dev.example.com.");
+        assert_eq!(minimized(&Name::root(), &host, 0), Some(name("com.")));
+        assert_eq!(
+            minimized(&name("com."), &host, 0),
+            Some(name("example.com."))
+        );
+        assert_eq!(
+            minimized(&name("example.com."), &host, 3),
+            Some(name("dev.example.com."))
+        );
+        assert_eq!(minimized(&name("example.com."), &host, 4), None);
+        assert_eq!(minimized(&name("dev.example.com."), &host, 0), None);
+
+        let deep = name("a.b.c.d.e.f.g.h.i.j.k.l.example.com.");
+        assert!(minimized(&name("example.com."), &deep, 12).is_some());
+        assert_eq!(minimized(&name("example.com."), &deep, 13), None);
+    }
+
+    #[test]
+    fn only_trusts_glue_inside_the_child_zone() {
+        let a = |host: &str, addr: &str| {
+            Record::from_rdata(name(host), 300, RData::A(addr.parse().unwrap()))
+        };
+        let additionals = vec![
+            a("ns1.example.com.", "192.0.2.1"),
+            a("ns.example.net.", "192.0.2.2"),
+            a("www.example.com.", "192.0.2.3"),
+        ];
+        let targets = vec![name("ns1.example.com."), name("ns.example.net.")];
+
+        assert_eq!(
+            glue(&additionals, &name("example.com."), &targets),
+            vec!["192.0.2.1:53".parse::<SocketAddr>().unwrap()]
+        );
+    }
+
+    #[cfg(any(feature = "tokio", feature = "async-std", feature = "smol"))]
+    #[test]
+    fn leaves_pooled_connections_alone() {
+        let mut zone = Zone::new();
+        zone.add("web.example.com", 60, RData::A(Ipv4Addr::new(192, 0, 2, 1)));
+        let server = TestServer::start(zone).unwrap();
+        let mut connector = server.connector(());
+        connector.set_root_hints(RootHints::new(vec![server.addr()]));
+        let runtime = Runtime::new().unwrap();
+
+        let addrs = runtime
+            .block_on(connector.resolver().resolve("web.example.com", 80))
+            .unwrap();
+        assert_eq!(addrs, vec!["192.0.2.1:80".parse::<SocketAddr>().unwrap()]);
+        assert_eq!(connector.clients.servers(), 0);
+    }
+}
@@ -0,0 +1,159 @@
+//! Conventions for resolving the named ports of Kubernetes services.
+
+use std::collections::HashMap;
+use std::io;
+
+use crate::{Interceptor, Query, RecordType, Resolution};
+
+/// An interceptor which resolves Kubernetes services by the name of one of
+/// their ports, using the SRV records cluster DNS publishes for them.
+///
+/// Hosts of the form `<service>.<namespace>.svc`, optionally followed by the
+/// cluster domain, are looked up as
+/// `_<port>._<protocol>.<service>.<namespace>.svc.<cluster domain>`, unless
+/// the request gives a port. For a headless service there is a record for
+/// each ready pod, which the connector picks between, and the request goes
+/// to the port the name refers to.
+#[derive(Debug, Clone)]
+pub struct Kubernetes {
+    port_name: String,
+    protocol: String,
+    cluster_domain: String,
+    port_names: HashMap<String, String>,
+}
+
+impl Kubernetes {
+    /// Resolve services by their port named `port_name`, e.g. `http`.
+    pub fn new<S>(port_name: S) -> Kubernetes
+    where
+        S: Into<String>,
+    {
+        Kubernetes {
+            port_name: port_name.into(),
+            protocol: "tcp".to_string(),
+            cluster_domain: "cluster.local".to_string(),
+            port_names: HashMap::new(),
+        }
+    }
+
+    /// Use the port named `port_name` for `service` in `namespace`, instead
+    /// of the default.
+    pub fn set_port_name<S, T, U>(&mut self, service: S, namespace: T, port_name: U)
+    where
+        S: AsRef<str>,
+        T: AsRef<str>,
+        U: Into<String>,
+    {
+        let key = format!(
+            "{}.{}",
+            service.as_ref().to_ascii_lowercase(),
+            namespace.as_ref().to_ascii_lowercase()
+        );
+        self.port_names.insert(key, port_name.into());
+    }
+
+    /// Set the protocol of the ports.
+    ///
+    /// Defaults to `tcp`.
+    pub fn set_protocol<S>(&mut self, protocol: S)
+    where
+        S: Into<String>,
+    {
+        self.protocol = protocol.into();
+    }
+
+    /// Set the cluster's DNS domain.
+    ///
+    /// Defaults to `cluster.local`.
+    pub fn set_cluster_domain<S>(&mut self, domain: S)
+    where
+        S: Into<String>,
+    {
+        self.cluster_domain = domain.into().trim_matches('.').to_ascii_lowercase();
+    }
+
+    /// The SRV name for the named port of `host`, or `None` if it isn't a
+    /// service name.
+    fn srv_name(&self, host: &str) -> Option<String> {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        let suffix = format!(".{}", self.cluster_domain);
+        let name = if host.ends_with(&suffix) {
+            &host[..host.len() - suffix.len()]
+        } else {
+            &host[..]
+        };
+
+        let labels: Vec<&str> = name.split('.').collect();
+        match labels[..] {
+            [service, namespace, "svc"] if !service.starts_with('_') => {
+                let key = format!("{}.{}", service, namespace);
+                let port_name = self.port_names.get(&key).unwrap_or(&self.port_name);
+                Some(format!(
+                    "_{}._{}.{}.svc{}",
+                    port_name, self.protocol, key, suffix
+                ))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Interceptor for Kubernetes {
+    fn pre_query(&self, query: &mut Query) -> io::Result<Option<Resolution>> {
+        if query.port.is_none() {
+            if let Some(name) = self.srv_name(&query.host) {
+                query.host = name;
+                query.record_type = RecordType::SRV;
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_srv_names() {
+        let mut kubernetes = Kubernetes::new("http");
+        kubernetes.set_port_name("grpc-api", "prod", "grpc");
+
+        assert_eq!(
+            kubernetes.srv_name("web.prod.svc"),
+            Some("_http._tcp.web.prod.svc.cluster.local".to_string())
+        );
+        assert_eq!(
+            kubernetes.srv_name("Web.Prod.svc.cluster.local."),
+            Some("_http._tcp.web.prod.svc.cluster.local".to_string())
+        );
+        assert_eq!(
+            kubernetes.srv_name("grpc-api.prod.svc.cluster.local"),
+            Some("_grpc._tcp.grpc-api.prod.svc.cluster.local".to_string())
+        );
+        assert_eq!(kubernetes.srv_name("web.prod"), None);
+        assert_eq!(
+            kubernetes.srv_name("_http._tcp.web.prod.svc.cluster.local"),
+            None
+        );
+        assert_eq!(kubernetes.srv_name("example.com"), None);
+    }
+
+    #[test]
+    fn only_rewrites_requests_without_ports() {
+        let kubernetes = Kubernetes::new("http");
+        let mut query = Query {
+            host: "web.prod.svc".to_string(),
+            port: Some(8080),
+            record_type: RecordType::A,
+        };
+
+        kubernetes.pre_query(&mut query).unwrap();
+        assert_eq!(query.host, "web.prod.svc");
+
+        query.port = None;
+        kubernetes.pre_query(&mut query).unwrap();
+        assert_eq!(query.host, "_http._tcp.web.prod.svc.cluster.local");
+        assert_eq!(query.record_type, RecordType::SRV);
+    }
+}
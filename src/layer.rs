@@ -0,0 +1,378 @@
+//! A `tower::Layer` for composing DNS resolution into middleware stacks,
+//! enabled by the `tower` feature.
+
+use futures::future::BoxFuture;
+use http::uri::{Authority, Parts};
+use http::Uri;
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::vec;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::addrs::default_port;
+use crate::{DnsConnector, DnsResolver};
+
+/// A layer wrapping `tower::Service` connectors, such as hyper-util's
+/// `HttpConnector`, so that the DNS behaviour can be added with
+/// `ServiceBuilder` alongside timeouts and retries.
+///
+/// Hosts are resolved as the `DnsConnector` the layer was created from
+/// resolves them for requests, sharing its state as clones of it do, on the
+/// runtime the connections are made on, as for `TowerResolver`.
+#[derive(Debug, Clone)]
+pub struct DnsLayer {
+    resolver: DnsResolver,
+}
+
+impl DnsLayer {
+    /// Wrap connectors to resolve hosts with the configuration of `config`,
+    /// whose own connector isn't used, e.g. `DnsConnector::new(dns_addr, ())`.
+    pub fn new<C>(config: DnsConnector<C>) -> io::Result<DnsLayer> {
+        Ok(DnsLayer {
+            resolver: config.with_connector(()).resolver(),
+        })
+    }
+}
+
+impl<S> Layer<S> for DnsLayer {
+    type Service = DnsService<S>;
+
+    fn layer(&self, inner: S) -> DnsService<S> {
+        DnsService {
+            resolver: self.resolver.clone(),
+            inner: inner,
+        }
+    }
+}
+
+/// A connector wrapped by `DnsLayer`, which resolves the host of each URI and
+/// calls the wrapped connector with the host replaced by each of its
+/// addresses in turn, in the order picked, until one connects.
+#[derive(Debug, Clone)]
+pub struct DnsService<S> {
+    resolver: DnsResolver,
+    inner: S,
+}
+
+impl<S> Service<Uri> for DnsService<S>
+where
+    S: Service<Uri> + Clone,
+    S::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    type Response = S::Response;
+    type Error = io::Error;
+    type Future = DnsFuture<S>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        self.inner.poll_ready(cx).map_err(other)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        // Take the connector which was made ready, leaving a clone.
+        let inner = self.inner.clone();
+        let inner = ::std::mem::replace(&mut self.inner, inner);
+
+        let host = match uri.host() {
+            Some(host) => host.trim_start_matches('[').trim_end_matches(']'),
+            None => {
+                return DnsFuture {
+                    inner: inner,
+                    uri: uri,
+                    port: 0,
+                    state: State::Failed(Some(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "URI has no host",
+                    ))),
+                }
+            }
+        };
+
+        let addrs = self.resolver.resolve_request(host, uri.port_u16());
+        let port = default_port(uri.scheme_str().unwrap_or("http"));
+        DnsFuture {
+            inner: inner,
+            uri: uri,
+            port: port,
+            // The connector was made ready by `poll_ready`.
+            state: State::Resolving(addrs),
+        }
+    }
+}
+
+/// A connection being made through the wrapped connector.
+pub struct DnsFuture<S>
+where
+    S: Service<Uri>,
+{
+    inner: S,
+    uri: Uri,
+    /// The port to use if the resolution didn't give one.
+    port: u16,
+    state: State<S::Future>,
+}
+
+/// The wrapped connector and its future are only used through `&mut`, and
+/// the future is boxed, so neither is pinned.
+impl<S> Unpin for DnsFuture<S> where S: Service<Uri> {}
+
+impl<S> fmt::Debug for DnsFuture<S>
+where
+    S: Service<Uri>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DnsFuture").field("uri", &self.uri).finish()
+    }
+}
+
+enum State<F> {
+    /// Waiting for the host's addresses.
+    Resolving(BoxFuture<'static, io::Result<Vec<SocketAddr>>>),
+    /// Waiting for the wrapped connector to be ready to connect to the next
+    /// address.
+    Ready(vec::IntoIter<SocketAddr>),
+    /// Connecting to an address, with the ones to try after it.
+    Connecting(Pin<Box<F>>, vec::IntoIter<SocketAddr>),
+    Failed(Option<io::Error>),
+}
+
+impl<S> Future for DnsFuture<S>
+where
+    S: Service<Uri>,
+    S::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    type Output = io::Result<S::Response>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        loop {
+            let next = match this.state {
+                State::Resolving(ref mut addrs) => match addrs.as_mut().poll(cx) {
+                    Poll::Ready(Ok(addrs)) => {
+                        let port = this.port;
+                        let addrs = addrs
+                            .into_iter()
+                            .map(|addr| match addr.port() {
+                                0 => SocketAddr::new(addr.ip(), port),
+                                _ => addr,
+                            })
+                            .collect::<Vec<_>>();
+                        match connect(&mut this.inner, &this.uri, addrs.into_iter()) {
+                            Ok(state) => state,
+                            Err(e) => State::Failed(Some(e)),
+                        }
+                    }
+                    Poll::Ready(Err(e)) => State::Failed(Some(e)),
+                    Poll::Pending => return Poll::Pending,
+                },
+                State::Ready(ref mut addrs) => match this.inner.poll_ready(cx) {
+                    Poll::Ready(Ok(())) => {
+                        let addrs = ::std::mem::replace(addrs, Vec::new().into_iter());
+                        match connect(&mut this.inner, &this.uri, addrs) {
+                            Ok(state) => state,
+                            Err(e) => State::Failed(Some(e)),
+                        }
+                    }
+                    Poll::Ready(Err(e)) => State::Failed(Some(other(e))),
+                    Poll::Pending => return Poll::Pending,
+                },
+                State::Connecting(ref mut connect, ref mut addrs) => {
+                    match connect.as_mut().poll(cx) {
+                        Poll::Ready(Ok(connection)) => return Poll::Ready(Ok(connection)),
+                        // Fall back to the next address, as the connector does.
+                        Poll::Ready(Err(e)) => {
+                            let e = other(e);
+                            if addrs.as_slice().is_empty() {
+                                State::Failed(Some(e))
+                            } else {
+                                debug!("Failed to connect, trying the next address: {}", e);
+                                let addrs = ::std::mem::replace(addrs, Vec::new().into_iter());
+                                State::Ready(addrs)
+                            }
+                        }
+                        Poll::Pending => return Poll::Pending,
+                    }
+                }
+                State::Failed(ref mut e) => {
+                    return Poll::Ready(Err(e
+                        .take()
+                        .unwrap_or_else(|| io::Error::other("Connection already failed"))))
+                }
+            };
+            this.state = next;
+        }
+    }
+}
+
+/// Call `inner`, which must be ready, with `uri` for the first of `addrs`.
+fn connect<S>(
+    inner: &mut S,
+    uri: &Uri,
+    mut addrs: vec::IntoIter<SocketAddr>,
+) -> io::Result<State<S::Future>>
+where
+    S: Service<Uri>,
+{
+    let addr = addrs
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Host resolved to no addresses"))?;
+    let uri = with_addr(uri, addr)?;
+    Ok(State::Connecting(Box::pin(inner.call(uri)), addrs))
+}
+
+/// `uri` with its host and port replaced by `addr`.
+fn with_addr(uri: &Uri, addr: SocketAddr) -> io::Result<Uri> {
+    let mut parts = Parts::from(uri.clone());
+    parts.authority = Some(
+        addr.to_string()
+            .parse::<Authority>()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?,
+    );
+    Uri::from_parts(parts).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+/// An error from the wrapped connector, as an `io::Error`.
+fn other<E>(e: E) -> io::Error
+where
+    E: Into<Box<dyn Error + Send + Sync>>,
+{
+    io::Error::other(e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::StaticResolver;
+    use crate::{Interceptor, Query, RecordType, Resolution};
+    use futures::executor::block_on;
+    use std::future;
+    use std::net::IpAddr;
+    use std::sync::{Arc, Mutex};
+
+    /// A connector which records the URIs it is called with, failing the
+    /// first call.
+    #[derive(Clone)]
+    struct Recording(Arc<Mutex<Vec<Uri>>>);
+
+    impl Service<Uri> for Recording {
+        type Response = ();
+        type Error = io::Error;
+        type Future = future::Ready<io::Result<()>>;
+
+        fn poll_ready(&mut self, _: &mut Context) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, uri: Uri) -> Self::Future {
+            let mut uris = self.0.lock().unwrap();
+            uris.push(uri);
+            future::ready(if uris.len() == 1 {
+                Err(io::Error::new(io::ErrorKind::ConnectionRefused, "refused"))
+            } else {
+                Ok(())
+            })
+        }
+    }
+
+    /// Resolves every host to the same addresses.
+    struct Fixed(Vec<IpAddr>);
+
+    impl Interceptor for Fixed {
+        fn pre_query(&self, _query: &mut Query) -> io::Result<Option<Resolution>> {
+            Ok(Some(Resolution {
+                ip: self.0[0],
+                addrs: self.0.clone(),
+                port: None,
+                record_type: None,
+                ttl: None,
+                nameserver: None,
+                chain: Vec::new(),
+            }))
+        }
+    }
+
+    #[test]
+    fn wrapped_connectors_connect_to_each_address() {
+        let mut config = DnsConnector::new("127.0.0.1:53".parse().unwrap(), ());
+        config.add_interceptor(Fixed(vec![
+            "192.0.2.1".parse().unwrap(),
+            "192.0.2.2".parse().unwrap(),
+        ]));
+        let layer = DnsLayer::new(config).unwrap();
+        let uris = Arc::new(Mutex::new(Vec::new()));
+        let mut service = layer.layer(Recording(uris.clone()));
+
+        let uri: Uri = "http://web.example.com/index.html".parse().unwrap();
+        block_on(service.call(uri)).unwrap();
+
+        let mut hosts: Vec<String> = uris
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|uri| {
+                assert_eq!(uri.path(), "/index.html");
+                uri.authority().unwrap().to_string()
+            })
+            .collect();
+        hosts.sort();
+        assert_eq!(hosts, vec!["192.0.2.1:80", "192.0.2.2:80"]);
+    }
+
+    #[test]
+    fn ports_come_from_the_uri_srv_records_or_scheme() {
+        let hosts = StaticResolver::new();
+        hosts.add_ip("web.example.com", "192.0.2.1".parse().unwrap());
+        hosts.add_srv("srv.example.com", 0, 0, 8443, "web.example.com");
+        let connect = |config: DnsConnector<()>, uri: &str| {
+            let uris = Arc::new(Mutex::new(Vec::new()));
+            // The connector fails its first call, which is the one looked at.
+            let mut service = DnsLayer::new(config)
+                .unwrap()
+                .layer(Recording(uris.clone()));
+            let _ = block_on(service.call(uri.parse().unwrap()));
+            let uri = uris.lock().unwrap()[0].clone();
+            uri.port_u16().unwrap()
+        };
+
+        assert_eq!(
+            connect(hosts.connector(()), "http://web.example.com:8080/"),
+            8080
+        );
+        assert_eq!(
+            connect(hosts.connector(()), "https://srv.example.com/"),
+            8443
+        );
+
+        // Without SRV records, the scheme's port is used.
+        let mut config =
+            DnsConnector::new_with_resolve_type("127.0.0.1:53".parse().unwrap(), (), RecordType::A);
+        config.set_backend(hosts.clone());
+        assert_eq!(connect(config, "https://web.example.com/"), 443);
+    }
+
+    #[test]
+    fn errors_are_io_errors() {
+        let hosts = StaticResolver::new();
+        hosts.add_ip("web.example.com", "192.0.2.1".parse().unwrap());
+        let layer = DnsLayer::new(hosts.connector(())).unwrap();
+        let mut service = layer.layer(Recording(Arc::new(Mutex::new(Vec::new()))));
+
+        // The wrapped connector's error, once the only address fails.
+        let uri = "http://web.example.com:80/".parse().unwrap();
+        let err = block_on(service.call(uri)).unwrap_err();
+        assert_eq!(err.to_string(), "refused");
+
+        let uri = "http://missing.example.com:80/".parse().unwrap();
+        let err = block_on(service.call(uri)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+        let uri = "/index.html".parse().unwrap();
+        let err = block_on(service.call(uri)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -2,24 +2,199 @@
 
 #[macro_use]
 extern crate log;
-extern crate futures;
-extern crate hyper;
-extern crate rand;
-extern crate tokio_core;
-extern crate tokio_reactor;
-extern crate trust_dns;
-
-use futures::future;
-use futures::future::Future;
-use hyper::client::{Connect, Service};
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+#[cfg(feature = "json")]
+#[macro_use]
+extern crate serde_json;
+// The DNS client pulls in tokio's networking, which wasm32 lacks, so there
+// only the protocol crate's records and messages are used.
+#[cfg(target_arch = "wasm32")]
+extern crate trust_dns_proto as trust_dns;
+
+mod addrs;
+pub mod backend;
+#[cfg(feature = "hyper-012")]
+mod background;
+mod backoff;
+mod blacklist;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod blocking;
+mod chain;
+#[cfg(not(target_arch = "wasm32"))]
+mod clients;
+mod connect;
+mod connection;
+mod consul;
+mod counters;
+#[cfg(feature = "json")]
+mod debug;
+mod dial;
+mod dns64;
+mod dnssd;
+#[cfg(feature = "dnssec")]
+mod dnssec;
+#[cfg(feature = "doh")]
+mod doh;
+mod error;
+mod events;
+mod family;
+mod filter;
+mod forget;
+#[cfg(feature = "hickory")]
+mod hickory;
+mod history;
+mod host;
+mod hostpolicy;
+#[cfg(feature = "hyper-012")]
+mod hyper012;
+#[cfg(feature = "hyper-1")]
+mod hyper1;
+mod interceptor;
+mod iterative;
+mod kubernetes;
+#[cfg(feature = "tower")]
+mod layer;
+mod limit;
+#[cfg(feature = "llmnr")]
+mod llmnr;
+#[cfg(all(target_os = "linux", feature = "tokio"))]
+mod mark;
+#[cfg(feature = "mdns")]
+mod mdns;
+mod nameserver;
+mod pending;
+mod pinning;
+#[cfg(feature = "prometheus")]
+pub mod prometheus;
+mod ratelimit;
+mod redact;
+#[cfg(feature = "reqwest")]
+mod reqwest;
+mod resolver;
+mod rfc6724;
+mod rng;
+mod roothints;
+mod runtime;
+pub mod selection;
+#[cfg(feature = "json")]
+mod snapshot;
+mod stats;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod testing;
+#[cfg(not(target_arch = "wasm32"))]
+mod testserver;
+mod time;
+#[cfg(feature = "tower")]
+mod tower;
+mod trace;
+mod validate;
+mod warm;
+
+use crate::backoff::NxBackoff;
+use crate::blacklist::Blacklist;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::clients::{Client, Clients, Handle};
+use crate::dial::Dialer;
+use crate::dns64::Synthesizer;
+use crate::filter::AddressFilter;
+use crate::forget::Forgotten;
+use crate::history::History;
+use crate::hostpolicy::HostPolicy;
+use crate::iterative::Iterative;
+use crate::limit::{Acquire, ConcurrencyLimit, Permit};
+use crate::nameserver::Nameservers;
+use crate::pending::Pending;
+use crate::pinning::Pins;
+use crate::ratelimit::RateLimiter;
+use crate::rfc6724::Sources;
+use crate::stats::Stats;
+use crate::time::{Instant, SystemTime};
+use crate::trace::{Instrumented, Span};
+
+pub use crate::addrs::{AddrConnect, AddrsConnector};
+pub use crate::backend::Backend;
+pub use crate::chain::Hop;
+pub use crate::connect::ConnectFuture;
+pub use crate::connection::Connection;
+pub use crate::consul::Consul;
+#[cfg(feature = "json")]
+pub use crate::debug::DebugService;
+pub use crate::dns64::{Dns64, Nat64Prefix};
+pub use crate::dnssd::ServiceInstance;
+#[cfg(feature = "dnssec")]
+pub use crate::dnssec::Dnssec;
+#[cfg(feature = "doh")]
+pub use crate::doh::DnsOverHttps;
+pub use crate::error::Error;
+#[cfg(feature = "json")]
+pub use crate::events::JsonLines;
+pub use crate::events::{Event, EventSink};
+pub use crate::family::AddressFamily;
+pub use crate::filter::Cidr;
+#[cfg(feature = "hickory")]
+pub use crate::hickory::Hickory;
+pub use crate::history::HistoryEntry;
+#[cfg(feature = "hyper-012")]
+pub use crate::hyper012::HyperResolver;
+#[cfg(feature = "hyper-1")]
+pub use crate::hyper1::{Connecting, HyperConnector};
+pub use crate::interceptor::Interceptor;
+pub use crate::kubernetes::Kubernetes;
+#[cfg(feature = "tower")]
+pub use crate::layer::{DnsFuture, DnsLayer, DnsService};
+pub use crate::redact::Redaction;
+#[cfg(feature = "reqwest")]
+pub use crate::reqwest::ReqwestResolver;
+pub use crate::resolver::{DnsResolver, MxRecord, Soa, SrvTarget};
+pub use crate::rng::SharedRng;
+pub use crate::roothints::RootHints;
+#[cfg(feature = "async-std")]
+pub use crate::runtime::AsyncStd;
+#[cfg(feature = "smol")]
+pub use crate::runtime::Smol;
+#[cfg(feature = "tokio")]
+pub use crate::runtime::Tokio;
+pub use crate::runtime::{Executor, Runtime};
+pub use crate::selection::SelectionPolicy;
+pub use crate::stats::{HostStats, NameserverStats, RecentError};
+#[cfg(feature = "tower")]
+pub use crate::tower::{ResolveFuture, TowerResolver};
+pub use crate::warm::WarmConnector;
+
+use futures::future::{self, BoxFuture, Either, FutureExt, TryFutureExt};
+use futures::stream::{self, BoxStream, StreamExt};
+use hyper::service::Service;
 use hyper::Uri;
 use rand::Rng;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
 use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
 use std::time::Duration;
-use trust_dns::client::ClientHandle;
+#[cfg(not(target_arch = "wasm32"))]
+use trust_dns::client::{ClientHandle, ClientResponse};
+#[cfg(not(target_arch = "wasm32"))]
+use trust_dns::error::{ClientError, ClientErrorKind};
+use trust_dns::op::ResponseCode;
+#[cfg(not(target_arch = "wasm32"))]
+use trust_dns_proto::error::{ProtoError, ProtoErrorKind};
+#[cfg(not(target_arch = "wasm32"))]
+use trust_dns_proto::xfer::DnsExchangeSend;
+#[cfg(not(target_arch = "wasm32"))]
+use trust_dns_proto::RetryDnsHandle;
+
+pub use trust_dns_proto::xfer::DnsResponse;
 
 /// Docs
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum RecordType {
     /// A
     A,
@@ -29,18 +204,281 @@ pub enum RecordType {
     AUTO,
 }
 
-/// A connector that wraps another connector and provides custom DNS resolution.
+impl RecordType {
+    /// Resolve `AUTO` into the record type to query for a request on `port`.
+    fn for_port(&self, port: Option<u16>) -> RecordType {
+        match *self {
+            RecordType::A => RecordType::A,
+            RecordType::SRV => RecordType::SRV,
+            RecordType::AUTO => {
+                // If the port is not provided, then and perform SRV lookup, otherwise lookup
+                // A records.
+                if port.is_none() {
+                    RecordType::SRV
+                } else {
+                    RecordType::A
+                }
+            }
+        }
+    }
+}
+
+/// A DNS query which is about to be made for a request.
+#[derive(Debug, Clone)]
+pub struct Query {
+    /// The host to resolve.
+    pub host: String,
+    /// The port from the request, if there was one.
+    pub port: Option<u16>,
+    /// The record type to query for.
+    pub record_type: RecordType,
+}
+
+/// The address a request has been resolved to.
 #[derive(Debug, Clone)]
+pub struct Resolution {
+    /// The IP address to connect to.
+    pub ip: IpAddr,
+    /// All of the addresses the host resolved to, which `ip` was selected
+    /// from.
+    pub addrs: Vec<IpAddr>,
+    /// The port to connect to, if one was resolved or provided on the request.
+    pub port: Option<u16>,
+    /// The record type which was queried, if a DNS lookup was made.
+    pub record_type: Option<RecordType>,
+    /// The TTL of the records used, if a DNS lookup was made.
+    pub ttl: Option<u32>,
+    /// The DNS server which answered the query, if a DNS lookup was made.
+    pub nameserver: Option<SocketAddr>,
+    /// The records followed to resolve the host, in order: the SRV record,
+    /// any CNAME records, then the address records, each with its TTL.
+    pub chain: Vec<Hop>,
+}
+
+/// Where the addresses for a request came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResolutionSource {
+    /// The request was made to an IP address, so no lookup was needed.
+    Literal,
+    /// An interceptor provided the resolution from `pre_query`.
+    Interceptor,
+    /// The addresses were looked up on the DNS server.
+    Dns,
+}
+
+/// A hook used to rewrite a host before it is resolved.
+pub type HostRewrite = dyn Fn(&str) -> Cow<str> + Send + Sync;
+
+/// A callback invoked with the host, resolved addresses, where they came from
+/// and how long the resolution took.
+pub type ResolveCallback = dyn Fn(&str, &[IpAddr], ResolutionSource, Duration) + Send + Sync;
+
+/// A callback invoked with the host and error when resolution fails.
+pub type ErrorCallback = dyn Fn(&str, &io::Error) + Send + Sync;
+
+/// A hook invoked with the host of each request (before any rewriting) and
+/// the resolution the connector has decided on, which can veto connecting by
+/// returning the reason.
+pub type AuditHook = dyn Fn(&str, &Resolution) -> Result<(), String> + Send + Sync;
+
+/// A callback deciding whether an address a host resolved to may be
+/// connected to.
+pub type AddressFilterCallback = dyn Fn(IpAddr, &str) -> bool + Send + Sync;
+
+/// A connector that wraps another connector and provides custom DNS resolution.
+///
+/// Clones share the wrapped connector, which needn't be `Clone` itself. It is
+/// only locked while it is polled for readiness and called, so requests still
+/// connect concurrently, and stays usable if it panics while locked.
 pub struct DnsConnector<C> {
-    connector: C,
+    connector: Arc<Mutex<C>>,
     record_type: RecordType,
     dns_addr: std::net::SocketAddr,
+    nameservers: Option<Arc<Nameservers>>,
+    iterative: Option<Arc<Iterative>>,
+    qname_minimization: bool,
+    backend: Option<Arc<dyn Backend>>,
+    host_rewrite: Option<Arc<HostRewrite>>,
+    interceptors: Vec<Arc<dyn Interceptor>>,
+    on_resolve: Option<Arc<ResolveCallback>>,
+    on_error: Option<Arc<ErrorCallback>>,
+    audit: Option<Arc<AuditHook>>,
+    address_filter: AddressFilter,
+    reject_bogus_addresses: bool,
+    host_policy: Option<Arc<HostPolicy>>,
+    pins: Option<Arc<Pins>>,
+    nxdomain_backoff: Option<Arc<NxBackoff>>,
+    strict_validation: bool,
+    max_response_records: Option<usize>,
+    max_response_size: Option<usize>,
+    max_cname_depth: usize,
+    search_domains: Vec<String>,
+    allow_idn: bool,
+    selection: Option<Arc<dyn SelectionPolicy>>,
+    rng: SharedRng,
+    blacklist: Option<Arc<Blacklist>>,
+    retry_addresses: bool,
+    connect_race: usize,
+    happy_eyeballs: Option<Duration>,
+    sources: Option<Arc<Sources>>,
+    address_family: AddressFamily,
+    dns64: Option<Arc<Synthesizer>>,
+    ipv6_zone: Option<String>,
+    #[cfg(feature = "prometheus")]
+    prometheus: Option<prometheus::Registry>,
+    #[cfg(feature = "dnssec")]
+    dnssec: Option<Arc<Dnssec>>,
+    #[cfg(feature = "mdns")]
+    mdns: bool,
+    #[cfg(feature = "llmnr")]
+    llmnr: bool,
+    stats: Arc<Stats>,
+    #[cfg(not(target_arch = "wasm32"))]
+    clients: Arc<Clients>,
+    runtime: Arc<dyn Runtime>,
+    reuse_connections: bool,
+    #[cfg(not(target_arch = "wasm32"))]
+    query_retries: usize,
+    redaction: Redaction,
+    slow_lookup: Option<Duration>,
+    history: Option<Arc<History>>,
+    events: Option<Arc<dyn EventSink>>,
+    rate_limit: Option<Arc<RateLimiter>>,
+    concurrency: Option<Arc<ConcurrencyLimit>>,
+    pending: Arc<Pending>,
+    max_pending: Option<usize>,
+    forgotten: Arc<Forgotten>,
 }
 
-impl<C> DnsConnector<C>
-where
-    C: Connect,
-{
+impl<C> Clone for DnsConnector<C> {
+    fn clone(&self) -> DnsConnector<C> {
+        DnsConnector {
+            connector: self.connector.clone(),
+            record_type: self.record_type,
+            dns_addr: self.dns_addr,
+            nameservers: self.nameservers.clone(),
+            iterative: self.iterative.clone(),
+            qname_minimization: self.qname_minimization,
+            backend: self.backend.clone(),
+            host_rewrite: self.host_rewrite.clone(),
+            interceptors: self.interceptors.clone(),
+            on_resolve: self.on_resolve.clone(),
+            on_error: self.on_error.clone(),
+            audit: self.audit.clone(),
+            address_filter: self.address_filter.clone(),
+            reject_bogus_addresses: self.reject_bogus_addresses,
+            host_policy: self.host_policy.clone(),
+            pins: self.pins.clone(),
+            nxdomain_backoff: self.nxdomain_backoff.clone(),
+            strict_validation: self.strict_validation,
+            max_response_records: self.max_response_records,
+            max_response_size: self.max_response_size,
+            max_cname_depth: self.max_cname_depth,
+            search_domains: self.search_domains.clone(),
+            allow_idn: self.allow_idn,
+            selection: self.selection.clone(),
+            rng: self.rng.clone(),
+            blacklist: self.blacklist.clone(),
+            retry_addresses: self.retry_addresses,
+            connect_race: self.connect_race,
+            happy_eyeballs: self.happy_eyeballs,
+            sources: self.sources.clone(),
+            address_family: self.address_family,
+            dns64: self.dns64.clone(),
+            ipv6_zone: self.ipv6_zone.clone(),
+            #[cfg(feature = "prometheus")]
+            prometheus: self.prometheus.clone(),
+            #[cfg(feature = "dnssec")]
+            dnssec: self.dnssec.clone(),
+            #[cfg(feature = "mdns")]
+            mdns: self.mdns,
+            #[cfg(feature = "llmnr")]
+            llmnr: self.llmnr,
+            stats: self.stats.clone(),
+            #[cfg(not(target_arch = "wasm32"))]
+            clients: self.clients.clone(),
+            runtime: self.runtime.clone(),
+            reuse_connections: self.reuse_connections,
+            #[cfg(not(target_arch = "wasm32"))]
+            query_retries: self.query_retries,
+            redaction: self.redaction,
+            slow_lookup: self.slow_lookup,
+            history: self.history.clone(),
+            events: self.events.clone(),
+            rate_limit: self.rate_limit.clone(),
+            concurrency: self.concurrency.clone(),
+            pending: self.pending.clone(),
+            max_pending: self.max_pending,
+            forgotten: self.forgotten.clone(),
+        }
+    }
+}
+
+impl<C: fmt::Debug> fmt::Debug for DnsConnector<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut debug = f.debug_struct("DnsConnector");
+        debug
+            .field("connector", &self.connector)
+            .field("record_type", &self.record_type)
+            .field("dns_addr", &self.dns_addr)
+            .field("nameservers", &self.nameservers)
+            .field("iterative", &self.iterative.is_some())
+            .field("qname_minimization", &self.qname_minimization)
+            .field("backend", &self.backend.is_some())
+            .field("host_rewrite", &self.host_rewrite.is_some())
+            .field("interceptors", &self.interceptors.len())
+            .field("on_resolve", &self.on_resolve.is_some())
+            .field("on_error", &self.on_error.is_some())
+            .field("audit", &self.audit.is_some())
+            .field("address_filter", &self.address_filter)
+            .field("reject_bogus_addresses", &self.reject_bogus_addresses)
+            .field("host_policy", &self.host_policy)
+            .field("pins", &self.pins)
+            .field("nxdomain_backoff", &self.nxdomain_backoff)
+            .field("strict_validation", &self.strict_validation)
+            .field("max_response_records", &self.max_response_records)
+            .field("max_response_size", &self.max_response_size)
+            .field("max_cname_depth", &self.max_cname_depth)
+            .field("search_domains", &self.search_domains)
+            .field("allow_idn", &self.allow_idn)
+            .field("rng", &self.rng)
+            .field("blacklist", &self.blacklist)
+            .field("retry_addresses", &self.retry_addresses)
+            .field("connect_race", &self.connect_race)
+            .field("happy_eyeballs", &self.happy_eyeballs)
+            .field("sort_addresses", &self.sources.is_some())
+            .field("address_family", &self.address_family)
+            .field("dns64", &self.dns64)
+            .field("ipv6_zone", &self.ipv6_zone)
+            .field("stats", &self.stats)
+            .field("reuse_connections", &self.reuse_connections)
+            .field("redaction", &self.redaction)
+            .field("slow_lookup", &self.slow_lookup)
+            .field("history", &self.history)
+            .field("events", &self.events.is_some())
+            .field("rate_limit", &self.rate_limit)
+            .field("concurrency", &self.concurrency)
+            .field("pending", &self.pending.count())
+            .field("max_pending", &self.max_pending);
+
+        #[cfg(not(target_arch = "wasm32"))]
+        debug
+            .field("clients", &self.clients)
+            .field("query_retries", &self.query_retries);
+        #[cfg(feature = "prometheus")]
+        debug.field("prometheus", &self.prometheus);
+        #[cfg(feature = "dnssec")]
+        debug.field("dnssec", &self.dnssec);
+        #[cfg(feature = "mdns")]
+        debug.field("mdns", &self.mdns);
+        #[cfg(feature = "llmnr")]
+        debug.field("llmnr", &self.llmnr);
+
+        debug.finish()
+    }
+}
+
+impl<C> DnsConnector<C> {
     pub fn new(dns_addr: std::net::SocketAddr, connector: C) -> DnsConnector<C> {
         Self::new_with_resolve_type(dns_addr, connector, RecordType::AUTO)
     }
@@ -52,173 +490,3038 @@ where
         record_type: RecordType,
     ) -> DnsConnector<C> {
         DnsConnector {
-            connector: connector,
+            connector: Arc::new(Mutex::new(connector)),
             record_type: record_type,
             dns_addr: dns_addr,
+            nameservers: None,
+            iterative: None,
+            qname_minimization: true,
+            backend: None,
+            host_rewrite: None,
+            interceptors: Vec::new(),
+            on_resolve: None,
+            on_error: None,
+            audit: None,
+            address_filter: AddressFilter::default(),
+            reject_bogus_addresses: false,
+            host_policy: None,
+            pins: None,
+            nxdomain_backoff: None,
+            strict_validation: true,
+            max_response_records: None,
+            max_response_size: None,
+            max_cname_depth: 8,
+            search_domains: Vec::new(),
+            allow_idn: true,
+            selection: None,
+            rng: SharedRng::thread(),
+            blacklist: None,
+            retry_addresses: true,
+            connect_race: 1,
+            happy_eyeballs: None,
+            sources: Some(Arc::new(Sources::default())),
+            address_family: AddressFamily::default(),
+            dns64: None,
+            ipv6_zone: None,
+            #[cfg(feature = "prometheus")]
+            prometheus: None,
+            #[cfg(feature = "dnssec")]
+            dnssec: None,
+            #[cfg(feature = "mdns")]
+            mdns: false,
+            #[cfg(feature = "llmnr")]
+            llmnr: false,
+            stats: Arc::new(Stats::default()),
+            #[cfg(not(target_arch = "wasm32"))]
+            clients: Arc::new(Clients::default()),
+            runtime: runtime::default(),
+            reuse_connections: true,
+            #[cfg(not(target_arch = "wasm32"))]
+            query_retries: 0,
+            redaction: Redaction::default(),
+            slow_lookup: None,
+            history: None,
+            events: None,
+            rate_limit: None,
+            concurrency: None,
+            pending: Arc::new(Pending::default()),
+            max_pending: None,
+            forgotten: Arc::new(Forgotten::default()),
         }
     }
+
+    /// This connector's configuration and shared state, such as statistics
+    /// and caches, wrapping `connector` instead.
+    pub fn with_connector<D>(self, connector: D) -> DnsConnector<D> {
+        DnsConnector {
+            connector: Arc::new(Mutex::new(connector)),
+            record_type: self.record_type,
+            dns_addr: self.dns_addr,
+            nameservers: self.nameservers,
+            iterative: self.iterative,
+            qname_minimization: self.qname_minimization,
+            backend: self.backend,
+            host_rewrite: self.host_rewrite,
+            interceptors: self.interceptors,
+            on_resolve: self.on_resolve,
+            on_error: self.on_error,
+            audit: self.audit,
+            address_filter: self.address_filter,
+            reject_bogus_addresses: self.reject_bogus_addresses,
+            host_policy: self.host_policy,
+            pins: self.pins,
+            nxdomain_backoff: self.nxdomain_backoff,
+            strict_validation: self.strict_validation,
+            max_response_records: self.max_response_records,
+            max_response_size: self.max_response_size,
+            max_cname_depth: self.max_cname_depth,
+            search_domains: self.search_domains,
+            allow_idn: self.allow_idn,
+            selection: self.selection,
+            rng: self.rng,
+            blacklist: self.blacklist,
+            retry_addresses: self.retry_addresses,
+            connect_race: self.connect_race,
+            happy_eyeballs: self.happy_eyeballs,
+            sources: self.sources,
+            address_family: self.address_family,
+            dns64: self.dns64,
+            ipv6_zone: self.ipv6_zone,
+            #[cfg(feature = "prometheus")]
+            prometheus: self.prometheus,
+            #[cfg(feature = "dnssec")]
+            dnssec: self.dnssec,
+            #[cfg(feature = "mdns")]
+            mdns: self.mdns,
+            #[cfg(feature = "llmnr")]
+            llmnr: self.llmnr,
+            stats: self.stats,
+            #[cfg(not(target_arch = "wasm32"))]
+            clients: self.clients,
+            runtime: self.runtime,
+            reuse_connections: self.reuse_connections,
+            #[cfg(not(target_arch = "wasm32"))]
+            query_retries: self.query_retries,
+            redaction: self.redaction,
+            slow_lookup: self.slow_lookup,
+            history: self.history,
+            events: self.events,
+            rate_limit: self.rate_limit,
+            concurrency: self.concurrency,
+            pending: self.pending,
+            max_pending: self.max_pending,
+            forgotten: self.forgotten,
+        }
+    }
+
+    /// Set a hook which is applied to the host of each request before it is
+    /// resolved, for example to map `foo.internal` to
+    /// `foo.prod.svc.cluster.local`.
+    ///
+    /// IP address hosts are passed to the wrapped connector untouched, and
+    /// hosts rewritten to an IP address are connected to without a lookup.
+    pub fn set_host_rewrite<F>(&mut self, rewrite: F)
+    where
+        F: Fn(&str) -> Cow<str> + Send + Sync + 'static,
+    {
+        self.host_rewrite = Some(Arc::new(rewrite));
+    }
+
+    /// Add an interceptor to the end of the chain run for each request.
+    pub fn add_interceptor<I>(&mut self, interceptor: I)
+    where
+        I: Interceptor + 'static,
+    {
+        self.interceptors.push(Arc::new(interceptor));
+    }
+
+    /// Set a callback which is invoked each time a request's host has been
+    /// resolved.
+    pub fn set_on_resolve<F>(&mut self, callback: F)
+    where
+        F: Fn(&str, &[IpAddr], ResolutionSource, Duration) + Send + Sync + 'static,
+    {
+        self.on_resolve = Some(Arc::new(callback));
+    }
+
+    /// Set a callback which is invoked each time resolving a request's host
+    /// fails.
+    pub fn set_on_error<F>(&mut self, callback: F)
+    where
+        F: Fn(&str, &io::Error) + Send + Sync + 'static,
+    {
+        self.on_error = Some(Arc::new(callback));
+    }
+
+    /// Set a hook which audits every resolution, including requests to IP
+    /// addresses and resolutions provided by interceptors, before connecting.
+    /// When it returns an error the request fails with `Error::Vetoed`, so
+    /// egress policy can be enforced in one place rather than at every call
+    /// site.
+    pub fn set_audit_hook<F>(&mut self, hook: F)
+    where
+        F: Fn(&str, &Resolution) -> Result<(), String> + Send + Sync + 'static,
+    {
+        self.audit = Some(Arc::new(hook));
+    }
+
+    /// Set whether requests fail when their host only resolves to private
+    /// (RFC 1918 and RFC 4193), loopback, link-local or other non-public
+    /// addresses, for SSRF protection when fetching user supplied URLs. Such
+    /// addresses are never connected to, including when given in the URL or
+    /// returned by an interceptor, and when there are no others the request
+    /// fails with `Error::ForbiddenAddress`.
+    ///
+    /// Defaults to `false`.
+    pub fn set_reject_private_addresses(&mut self, reject: bool) {
+        self.address_filter.reject_private = reject;
+    }
+
+    /// Set a callback deciding whether each address a host resolves to (or
+    /// which is given in the URL) may be connected to, called with the
+    /// address and host, so that e.g. tenant specific egress policies can be
+    /// enforced. Addresses it rejects are treated as with
+    /// `set_reject_private_addresses`.
+    pub fn set_address_filter<F>(&mut self, filter: F)
+    where
+        F: Fn(IpAddr, &str) -> bool + Send + Sync + 'static,
+    {
+        self.address_filter.callback = Some(Arc::new(filter));
+    }
+
+    /// Set whether resolved addresses which can never be connected to, i.e.
+    /// `0.0.0.0`, `::`, broadcast and multicast addresses, are discarded, as
+    /// connecting to them only fails with confusing errors. When a host has
+    /// no other addresses the request fails with `Error::BogusAddress`.
+    ///
+    /// Defaults to `false`.
+    pub fn set_reject_bogus_addresses(&mut self, reject: bool) {
+        self.reject_bogus_addresses = reject;
+    }
+
+    /// Restrict which networks the addresses hosts resolve to (or which are
+    /// given in URLs) may be in: addresses in any of the `deny` networks, or
+    /// none of the `allow` networks when there are any, are never connected
+    /// to, as with `set_reject_private_addresses`.
+    pub fn set_address_policy<I, J>(&mut self, allow: I, deny: J)
+    where
+        I: IntoIterator<Item = Cidr>,
+        J: IntoIterator<Item = Cidr>,
+    {
+        self.address_filter.allow = allow.into_iter().collect();
+        self.address_filter.deny = deny.into_iter().collect();
+    }
+
+    /// Protect against DNS rebinding by pinning the address chosen for a host
+    /// for `duration`, so that every connection to the host in that period,
+    /// such as those following redirects, uses the same address. Once it
+    /// expires the host is resolved again, but fails with `Error::Rebinding`
+    /// if it resolved to a public address and now resolves to private ones.
+    /// A pin which has been expired for another `duration` is stale, and the
+    /// host is pinned to whatever it resolves to next. Pins are shared with
+    /// clones of the connector.
+    ///
+    /// Disabled by default.
+    pub fn set_rebinding_protection(&mut self, duration: Option<Duration>) {
+        self.pins = duration.map(|duration| Arc::new(Pins::new(duration)));
+    }
+
+    /// Back off from re-querying hosts which don't exist: after an NXDOMAIN
+    /// response for a host, lookups of it fail with `Error::NxDomain` without
+    /// querying the DNS server for a second, doubling with each further
+    /// NXDOMAIN response up to `max`, so that callers retrying a dead
+    /// hostname don't generate sustained query load. Backoffs are shared with
+    /// clones of the connector.
+    ///
+    /// Disabled by default.
+    pub fn set_nxdomain_backoff(&mut self, max: Option<Duration>) {
+        self.nxdomain_backoff = max.map(|max| Arc::new(NxBackoff::new(max)));
+    }
+
+    /// Set whether responses are checked to be for the question which was
+    /// asked, with answers of the type queried for the name queried (or the
+    /// CNAME records leading from it), before any of their records are used.
+    /// Responses which aren't fail with `Error::InvalidResponse`.
+    ///
+    /// Defaults to `true`, which is a breaking change from 0.3, where
+    /// responses weren't checked.
+    pub fn set_strict_validation(&mut self, strict: bool) {
+        self.strict_validation = strict;
+    }
+
+    /// Fail responses with more than `max` records (answers, authority and
+    /// additional records combined) with `Error::InvalidResponse`, before any
+    /// of them are used, to guard against pathological responses.
+    ///
+    /// Disabled by default.
+    pub fn set_max_response_records(&mut self, max: Option<usize>) {
+        self.max_response_records = max;
+    }
+
+    /// Fail responses larger than `max` bytes with `Error::InvalidResponse`,
+    /// before any of their records are used. The size is measured by encoding
+    /// the response again, as the DNS client doesn't expose the payload.
+    ///
+    /// Disabled by default.
+    pub fn set_max_response_size(&mut self, max: Option<usize>) {
+        self.max_response_size = max;
+    }
+
+    /// Set how many CNAME records may be followed to reach a host's
+    /// addresses. Longer or circular chains fail with `Error::CnameLoop`, so
+    /// that a malicious zone can't trap the resolver.
+    ///
+    /// Defaults to `8`.
+    pub fn set_max_cname_depth(&mut self, depth: usize) {
+        self.max_cname_depth = depth;
+    }
+
+    /// Restrict which hosts this connector may resolve: hosts matching any of
+    /// the `deny` patterns, or none of the `allow` patterns when there are
+    /// any, fail with `Error::HostDenied` without being queried. This applies
+    /// to hosts after any rewriting, including by interceptors, and to IP
+    /// addresses given in URLs.
+    ///
+    /// A pattern starting with `.`, such as `.example.com`, matches the domain
+    /// and every host under it, otherwise `*` matches any run of characters,
+    /// as in `api-*.example.com`.
+    pub fn set_host_policy<I, J, S, T>(&mut self, allow: I, deny: J)
+    where
+        I: IntoIterator<Item = S>,
+        J: IntoIterator<Item = T>,
+        S: AsRef<str>,
+        T: AsRef<str>,
+    {
+        self.host_policy = Some(Arc::new(HostPolicy::new(allow, deny)));
+    }
+
+    /// Set the domains to search when resolving hosts which are not fully
+    /// qualified (i.e. do not end with a `.`).
+    ///
+    /// Hosts containing a `.` are tried as given first, single label hosts
+    /// are tried against the search domains first. By default no search
+    /// domains are used and all hosts are treated as fully qualified.
+    pub fn set_search_domains<I, S>(&mut self, domains: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.search_domains = domains.into_iter().map(Into::into).collect();
+    }
+
+    /// Set whether internationalized hosts are allowed, in which case they
+    /// are converted to their ASCII (punycode) form before being queried.
+    ///
+    /// Defaults to `true`, when disabled requests to non-ASCII hosts fail.
+    pub fn set_allow_idn(&mut self, allow_idn: bool) {
+        self.allow_idn = allow_idn;
+    }
+
+    /// Set the policy used to pick which of a host's resolved addresses to
+    /// connect to.
+    ///
+    /// Defaults to `selection::Random`, using the connector's RNG.
+    pub fn set_selection_policy<P>(&mut self, policy: P)
+    where
+        P: SelectionPolicy + 'static,
+    {
+        self.selection = Some(Arc::new(policy));
+    }
+
+    /// Spread lookups across `nameservers` in proportion to their weights,
+    /// e.g. to send a small share of lookups to a canary resolver, instead of
+    /// sending them all to the server the connector was created with.
+    ///
+    /// The server the connector was created with is still used if none of
+    /// the servers have a non-zero weight.
+    pub fn set_nameservers<I>(&mut self, nameservers: I)
+    where
+        I: IntoIterator<Item = (SocketAddr, u32)>,
+    {
+        self.nameservers = Some(Arc::new(Nameservers::new(
+            nameservers.into_iter().collect(),
+        )));
+    }
+
+    /// Set whether hosts are resolved iteratively, starting from the root
+    /// servers and following referrals to the servers for the host's zone,
+    /// rather than by asking the configured server to resolve them, for
+    /// environments without a trusted recursive resolver. The servers found
+    /// for each zone are cached for the TTL of their NS records, and shared
+    /// with clones of the connector.
+    ///
+    /// Defaults to `false`.
+    pub fn set_iterative(&mut self, iterative: bool) {
+        self.iterative = if iterative {
+            Some(Arc::new(Iterative::new(RootHints::default())))
+        } else {
+            None
+        };
+    }
+
+    /// Resolve hosts iteratively, as with `set_iterative`, starting from the
+    /// root servers in `hints` rather than the IANA ones, e.g. for private
+    /// root zones or test networks.
+    pub fn set_root_hints(&mut self, hints: RootHints) {
+        self.iterative = Some(Arc::new(Iterative::new(hints)));
+    }
+
+    /// Set whether iterative resolution minimizes the names it sends (RFC
+    /// 9156): each zone's servers are asked about the name one label below
+    /// the zone, rather than the full host, so that the root and TLD servers
+    /// don't learn the hosts being resolved. Servers which fail or answer
+    /// NXDOMAIN for a minimized name are asked about the full one instead.
+    ///
+    /// Defaults to `true`.
+    pub fn set_qname_minimization(&mut self, minimize: bool) {
+        self.qname_minimization = minimize;
+    }
+
+    /// Answer queries with `backend` rather than by sending them to the
+    /// configured server with the built-in trust-dns client, e.g. to resolve
+    /// hosts from a service discovery system or with `Hickory`. Hosts are
+    /// still rewritten, SRV records followed and addresses picked by the
+    /// connector.
+    pub fn set_backend<B>(&mut self, backend: B)
+    where
+        B: Backend + 'static,
+    {
+        self.backend = Some(Arc::new(backend));
+    }
+
+    /// Set the RNG used to pick between SRV records and nameservers, and by
+    /// the default selection policy. Injecting a seeded RNG makes resolution
+    /// reproducible, e.g. `SharedRng::seeded(42)`.
+    pub fn set_rng(&mut self, rng: SharedRng) {
+        self.rng = rng;
+    }
+
+    /// Avoid selecting an address for `cooldown` after connecting to it fails,
+    /// unless every address a host resolves to is being avoided.
+    pub fn set_failure_cooldown(&mut self, cooldown: Option<Duration>) {
+        self.blacklist = cooldown.map(|cooldown| Arc::new(Blacklist::new(cooldown)));
+    }
+
+    /// Set whether a host's other addresses are tried in turn when connecting
+    /// to the selected one fails, before the error is returned to hyper.
+    ///
+    /// Defaults to `true`.
+    pub fn set_retry_addresses(&mut self, retry: bool) {
+        self.retry_addresses = retry;
+    }
+
+    /// Start connecting to up to `count` of a host's addresses at once, using
+    /// whichever connects first and cancelling the rest.
+    ///
+    /// Defaults to `1`, which connects to a single address at a time.
+    pub fn set_connect_race(&mut self, count: usize) {
+        self.connect_race = std::cmp::max(count, 1);
+    }
+
+    /// Connect using Happy Eyeballs (RFC 8305) when a host resolves to both
+    /// IPv4 and IPv6 addresses, alternating between the address families and
+    /// starting a new connection attempt every `delay` (RFC 8305 recommends
+    /// 250ms), or as soon as the previous attempt fails, until one succeeds.
+    /// With `set_retry_addresses(false)`, only the first address of each
+    /// family is tried.
+    ///
+    /// Disabled by default.
+    pub fn set_happy_eyeballs(&mut self, delay: Option<Duration>) {
+        self.happy_eyeballs = delay;
+    }
+
+    /// Set whether resolved addresses are sorted using the destination address
+    /// selection rules from RFC 6724, in which case the selection policy only
+    /// chooses between the most preferred addresses. The source addresses the
+    /// rules compare against are found for each destination prefix, and
+    /// remembered for a short time, shared with clones of the connector.
+    ///
+    /// Defaults to `true`.
+    pub fn set_sort_addresses(&mut self, sort: bool) {
+        self.sources = if sort {
+            Some(Arc::new(Sources::default()))
+        } else {
+            None
+        };
+    }
+
+    /// Set which address families are looked up for hosts, and which family
+    /// is connected to first when a host has addresses of both. When both
+    /// families are looked up the A and AAAA queries are made concurrently.
+    ///
+    /// Defaults to `AddressFamily::Ipv4Only`, which only queries A records.
+    pub fn set_address_family(&mut self, family: AddressFamily) {
+        self.address_family = family;
+    }
+
+    /// Synthesize IPv6 addresses for hosts which only have IPv4 addresses,
+    /// using a NAT64 prefix which is either given or discovered from the DNS
+    /// server (RFC 7050), in place of their IPv4 addresses so that they can be
+    /// reached from IPv6 only networks. A records are looked up even when using
+    /// `AddressFamily::Ipv6Only`, and the synthesized addresses are used even
+    /// with the default `AddressFamily::Ipv4Only`.
+    ///
+    /// Disabled by default.
+    pub fn set_dns64(&mut self, dns64: Option<Dns64>) {
+        self.dns64 = dns64.map(|dns64| Arc::new(Synthesizer::new(dns64)));
+    }
+
+    /// Set the zone (e.g. `eth0`) appended to link-local IPv6 addresses, so
+    /// that connections to them are made on the right interface.
+    ///
+    /// `AddrsConnector` can only apply numeric zones, which are used as the
+    /// address's scope ID. Defaults to `None`, where no zone is added.
+    pub fn set_ipv6_zone<S>(&mut self, zone: Option<S>)
+    where
+        S: Into<String>,
+    {
+        self.ipv6_zone = zone.map(Into::into);
+    }
+
+    /// Set how hostnames are redacted in log lines, error messages, tracing
+    /// spans and diagnostics such as `snapshot_json`, including the names in
+    /// reasons given by the DNS client, response validation and the audit
+    /// hook. The hostname is still passed in full to callbacks and
+    /// interceptors, and by `stats`.
+    ///
+    /// Defaults to `Redaction::Off`.
+    pub fn set_redaction(&mut self, redaction: Redaction) {
+        self.redaction = redaction;
+    }
+
+    /// Log resolutions which take longer than `threshold` (e.g. 250ms) at
+    /// warn level, with the details of the lookup, so that sporadic slowness
+    /// is visible without enabling debug logging.
+    ///
+    /// Disabled by default.
+    pub fn set_slow_lookup_threshold(&mut self, threshold: Option<Duration>) {
+        self.slow_lookup = threshold;
+    }
+
+    /// Keep a history of the last `size` resolutions, available from
+    /// `history`, e.g. to find out which address a host resolved to when an
+    /// incident happened.
+    ///
+    /// Disabled by default.
+    pub fn set_history_size(&mut self, size: usize) {
+        self.history = if size > 0 {
+            Some(Arc::new(History::new(size)))
+        } else {
+            None
+        };
+    }
+
+    /// Set a sink which receives a structured event when each query is sent,
+    /// answered or fails, e.g. `JsonLines` to write them as JSON.
+    pub fn set_event_sink<S>(&mut self, sink: S)
+    where
+        S: EventSink + 'static,
+    {
+        self.events = Some(Arc::new(sink));
+    }
+
+    /// Limit the queries sent to DNS servers to `rate` per second, with bursts
+    /// of up to `rate` queries, so that a retry storm cannot overload them.
+    /// Queries over the limit fail with `Error::RateLimited` rather than
+    /// being queued. The limit is shared with clones of the connector.
+    ///
+    /// Disabled by default.
+    pub fn set_query_rate_limit(&mut self, rate: Option<u32>) {
+        self.rate_limit = rate.map(|rate| Arc::new(RateLimiter::new(rate)));
+    }
+
+    /// Allow at most `max` queries to be in flight to DNS servers at once, to
+    /// bound socket usage and protect small resolvers during traffic spikes.
+    /// Further queries wait, in the order they were made, for one of those
+    /// in flight to finish. The limit is shared with clones of the connector.
+    ///
+    /// Disabled by default.
+    pub fn set_max_concurrent_queries(&mut self, max: Option<usize>) {
+        self.concurrency = max.map(|max| Arc::new(ConcurrencyLimit::new(max)));
+    }
+
+    /// Set whether the connection to each DNS server is kept open and reused
+    /// by later queries, and clones of the connector, rather than a new one
+    /// being made for every query. A reused connection is dropped when a
+    /// query on it fails.
+    ///
+    /// Defaults to `true`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn set_reuse_connections(&mut self, reuse: bool) {
+        self.reuse_connections = reuse;
+    }
+
+    /// Run the DNS clients the connector queries its nameservers with on
+    /// `runtime`, e.g. `AsyncStd` or `Smol` to embed the connector in an
+    /// application which doesn't run tokio. The wrapped connector, backends
+    /// and mDNS and LLMNR queries bring their own runtimes.
+    ///
+    /// Defaults to `Tokio` with the `tokio` feature, otherwise `AsyncStd` or
+    /// `Smol` if their features are enabled.
+    pub fn set_runtime<R>(&mut self, runtime: R)
+    where
+        R: Runtime + 'static,
+    {
+        self.runtime = Arc::new(runtime);
+    }
+
+    /// Keep up to `count` connections open to each DNS server, sending
+    /// queries on each in turn, so that lookups at high rates aren't held up
+    /// behind a slow response on a single connection. The connections, and
+    /// this setting, are shared with clones of the connector.
+    ///
+    /// Defaults to 1.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn set_connections_per_server(&mut self, count: usize) {
+        self.clients.set_size(count);
+    }
+
+    /// Answer repeated queries on each reused connection from the response
+    /// to the first for up to `lifetime`, with trust-dns'
+    /// `MemoizeClientHandle`, as a short-term cache. Failed queries are
+    /// memoized too, until their connection is replaced. The connections, and
+    /// this setting, are shared with clones of the connector.
+    ///
+    /// Disabled by default.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn set_memoize_queries(&mut self, lifetime: Option<Duration>) {
+        self.clients.set_memoize(lifetime);
+    }
+
+    /// Resend a query up to `retries` times on its connection when it fails,
+    /// with trust-dns' `RetryDnsHandle`, before it is treated as failed.
+    ///
+    /// Defaults to 0.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn set_query_retries(&mut self, retries: usize) {
+        self.query_retries = retries;
+    }
+
+    /// Set `SO_MARK` to `mark` on the sockets of connections to DNS servers,
+    /// so that policy routing and VPN split-tunnel rules can steer DNS
+    /// traffic differently from the connections made for requests. Setting
+    /// the mark needs the `CAP_NET_ADMIN` capability, without which queries
+    /// fail. Connections already open to DNS servers are closed, so that
+    /// every query uses the mark. Like the connections, the mark is shared
+    /// with clones of the connector.
+    ///
+    /// Only connections the connector makes to its own nameservers are
+    /// marked: lookups made by a backend given to `set_backend`, and mDNS and
+    /// LLMNR queries, are sent without the mark. Only the `Tokio` runtime
+    /// marks sockets, queries on others fail while a mark is set.
+    ///
+    /// Disabled by default.
+    #[cfg(target_os = "linux")]
+    pub fn set_socket_mark(&mut self, mark: Option<u32>) {
+        self.clients.set_mark(mark);
+    }
+
+    /// Set how many lookups may be in flight before `poll_ready` reports that
+    /// the connector is busy. Lookups are still made when it is busy, it is up
+    /// to the caller to shed load.
+    ///
+    /// Defaults to `None`, where the connector is always ready.
+    pub fn set_max_pending_lookups(&mut self, max: Option<usize>) {
+        self.max_pending = max;
+    }
+
+    /// Validate responses with DNSSEC, trusting either the root zone's keys or
+    /// the given trust anchors. Responses which don't validate fail with
+    /// `Error::Transport`.
+    ///
+    /// Disabled by default.
+    #[cfg(feature = "dnssec")]
+    pub fn set_dnssec(&mut self, dnssec: Option<Dnssec>) {
+        self.dnssec = dnssec.map(Arc::new);
+    }
+
+    /// Set whether hosts in the `.local` domain, such as `printer.local`, are
+    /// resolved with multicast DNS on the local network rather than by the
+    /// configured server, so that LAN and IoT devices can be reached.
+    ///
+    /// Defaults to `false`.
+    #[cfg(feature = "mdns")]
+    pub fn set_mdns(&mut self, mdns: bool) {
+        self.mdns = mdns;
+    }
+
+    /// Set whether single-label hosts, such as `fileserver`, which fail to
+    /// resolve with the configured server are then resolved with LLMNR on
+    /// the local network, as Windows peers on small networks expect.
+    ///
+    /// Defaults to `false`.
+    #[cfg(feature = "llmnr")]
+    pub fn set_llmnr(&mut self, llmnr: bool) {
+        self.llmnr = llmnr;
+    }
+
+    /// Record resolutions into `registry`, which can be shared between
+    /// connectors and serves the metrics in the Prometheus text format.
+    #[cfg(feature = "prometheus")]
+    pub fn set_prometheus(&mut self, registry: prometheus::Registry) {
+        self.prometheus = Some(registry);
+    }
 }
 
-impl<C> Service for DnsConnector<C>
-where
-    C: Service<Request = Uri, Error = io::Error> + 'static,
-    C: Clone,
-{
-    type Request = C::Request;
-    type Response = C::Response;
-    type Error = C::Error;
-    type Future =
-        Box<Future<Item = <C::Future as Future>::Item, Error = <C::Future as Future>::Error>>;
+impl<C> DnsConnector<C> {
+    /// Statistics for each host this connector (or a clone of it) has
+    /// resolved, e.g. to find hosts whose lookups are flapping.
+    ///
+    /// Requests to IP addresses are not included.
+    pub fn stats(&self) -> HashMap<String, HostStats> {
+        self.stats.snapshot()
+    }
 
-    fn call(&self, uri: Uri) -> Self::Future {
-        let connector = self.connector.clone();
+    /// The most recent failures to resolve a host, oldest first.
+    pub fn recent_errors(&self) -> Vec<RecentError> {
+        self.stats.recent_errors()
+    }
 
-        // We would expect a DNS request to be responded to quickly, but add a timeout
-        // to ensure that we don't wait for ever if the DNS server does not respond.
-        let timeout = Duration::from_millis(30000);
+    /// A JSON snapshot of the connector's state for diagnostics: how many
+    /// lookups each nameserver has answered and failed, a summary of the
+    /// hosts which have been resolved, and the most recent errors.
+    #[cfg(feature = "json")]
+    pub fn snapshot_json(&self) -> serde_json::Value {
+        snapshot::snapshot(&self.stats, &self.nameserver_weights(), self.redaction)
+    }
 
-        let (stream, sender) =
-            trust_dns::tcp::TcpClientStream::with_timeout(self.dns_addr, timeout);
-
-        let dns_client = trust_dns::client::ClientFuture::new(stream, sender, None);
-
-        // Check if this is a domain name or not before trying to use DNS resolution.
-        match uri.host().unwrap().to_string().parse() {
-            Ok(std::net::Ipv4Addr { .. }) => {
-                // Nothing to do, so just pass it along to the main connector
-                Box::new(connector.call(uri.clone()))
-            }
-            Err(_) => {
-                let port = uri.port().clone();
-                let scheme = uri.scheme().unwrap().to_string();
-                let host = uri.host().unwrap().to_string();
-
-                debug!("Trying to resolve {}://{}", scheme, &host);
-
-                // Add a `.` to the end of the host so that we can query the domain records.
-                let name = trust_dns::rr::Name::parse(&format!("{}.", host), None).unwrap();
-
-                let trust_record_type = match self.record_type {
-                    RecordType::A => trust_dns::rr::RecordType::A,
-                    RecordType::SRV => trust_dns::rr::RecordType::SRV,
-                    RecordType::AUTO => {
-                        // If the port is not provided, then and perform SRV lookup, otherwise lookup
-                        // A records.
-                        if port.is_none() {
-                            trust_dns::rr::RecordType::SRV
-                        } else {
-                            debug!("Using A record lookup for: {}", &host);
-                            trust_dns::rr::RecordType::A
-                        }
-                    }
-                };
+    /// A hyper `Service` serving `snapshot_json`, which can be mounted in a
+    /// server (e.g. on `/debug/dns`) so that operators can inspect the
+    /// resolver of a running service.
+    #[cfg(feature = "json")]
+    pub fn debug_service(&self) -> DebugService {
+        DebugService::new(
+            self.stats.clone(),
+            self.nameserver_weights(),
+            self.redaction,
+        )
+    }
+
+    /// The configured nameservers with their weights.
+    #[cfg(feature = "json")]
+    fn nameserver_weights(&self) -> Vec<(SocketAddr, u32)> {
+        match self.nameservers {
+            Some(ref nameservers) => nameservers.servers().to_vec(),
+            None => vec![(self.dns_addr, 1)],
+        }
+    }
+
+    /// The most recent resolutions, oldest first, if `set_history_size` has
+    /// been used.
+    pub fn history(&self) -> Vec<HistoryEntry> {
+        match self.history {
+            Some(ref history) => history.entries(),
+            None => Vec::new(),
+        }
+    }
 
-                debug!("Sending DNS request");
+    /// The health of each nameserver: the configured nameservers, or the DNS
+    /// server the connector was created with, and any others which have been
+    /// sent lookups, e.g. to alert when the primary nameserver is unhealthy.
+    pub fn nameserver_health(&self) -> HashMap<SocketAddr, NameserverStats> {
+        let mut health = self.stats.nameservers();
+        match self.nameservers {
+            Some(ref nameservers) => {
+                for &(addr, _) in nameservers.servers() {
+                    health.entry(addr).or_default();
+                }
+            }
+            None => {
+                health.entry(self.dns_addr).or_default();
+            }
+        }
+        health
+    }
 
-                let name_clone = name.clone();
+    /// How many lookups this connector (and its clones) have in flight, also
+    /// available as the `hyper_dns.lookups_in_flight` gauge.
+    pub fn pending_lookups(&self) -> usize {
+        self.pending.count()
+    }
 
-                let future = dns_client
-                    .and_then(move |mut client| {
-                        client.query(
-                            name_clone.clone(),
-                            trust_dns::rr::DNSClass::IN,
-                            trust_record_type,
-                        )
-                    })
-                    .or_else(|_| {
-                        return future::err(
-                            std::io::Error::new(
-                                std::io::ErrorKind::Other,
-                                "Failed to query DNS server",
-                            ).into(),
-                        );
-                    })
-                    .and_then(move |res| {
-                        let answers = res.answers();
-
-                        if answers.is_empty() {
-                            return future::err(
-                                std::io::Error::new(
-                                    std::io::ErrorKind::Other,
-                                    "No valid DNS answers",
-                                ).into(),
-                            );
-                        }
+    /// Whether fewer than the `set_max_pending_lookups` limit of lookups are
+    /// in flight, so that load shedding layers can react when resolution is
+    /// the bottleneck. When not ready, the current task is notified once a
+    /// lookup finishes.
+    pub fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<()> {
+        match self.max_pending {
+            Some(max) => self.pending.poll_ready(cx, max),
+            None => Poll::Ready(()),
+        }
+    }
 
-                        let mut rng = rand::thread_rng();
-
-                        // First find the SRV records if they were requested
-                        let (target, a_records, new_port) = if let trust_dns::rr::RecordType::SRV =
-                            trust_record_type
-                        {
-                            let answer = rng.choose(answers).expect("Sort out what to return here");
-
-                            let srv = match *answer.rdata() {
-                                trust_dns::rr::RData::SRV(ref srv) => srv,
-                                _ => {
-                                    return future::err(
-                                        std::io::Error::new(
-                                            std::io::ErrorKind::Other,
-                                            "Unexpected DNS response",
-                                        ).into(),
-                                    )
-                                }
-                            };
-
-                            (srv.target().clone(), res.additionals(), Some(srv.port()))
-                        } else {
-                            // For A record requests it is the domain name that
-                            // we want to use.
-                            (name.clone(), answers, port)
-                        };
-
-                        let entry = a_records.iter().find(|record| record.name() == &target);
-
-                        if let Some(entry) = entry {
-                            let addr = match *entry.rdata() {
-                                trust_dns::rr::RData::A(ref addr) => addr,
-                                _ => {
-                                    return future::err(
-                                        std::io::Error::new(
-                                            std::io::ErrorKind::Other,
-                                            "Did not receive a valid record",
-                                        ).into(),
-                                    )
-                                }
-                            };
-
-                            future::ok((addr.to_string(), new_port))
-                        } else {
-                            return future::err(
-                                std::io::Error::new(
-                                    std::io::ErrorKind::Other,
-                                    "Did not receive a valid record",
-                                ).into(),
-                            );
-                        }
-                    })
-                    .and_then(move |(ip, port)| {
-                        let new_uri_str = if let Some(port) = port {
-                            format!("{}://{}:{}", scheme, &ip, port)
-                        } else {
-                            format!("{}://{}", scheme, &ip)
-                        };
+    /// How many resolutions have failed of each class, e.g. `timeout`,
+    /// `transport` and `servfail` which suggest the DNS server is unhealthy,
+    /// `rejected` which suggests it is misconfigured for this client, or
+    /// `nxdomain` and `invalid_host` which suggest a misconfigured host.
+    ///
+    /// The class of an error is also available by recovering the `Error`
+    /// from the `io::Error` returned to hyper.
+    pub fn failure_counts(&self) -> HashMap<&'static str, u64> {
+        self.stats.failure_classes()
+    }
+
+    /// Forget what is known about `host`, so that its next lookup by this
+    /// connector or any clone of it is fresh, as with `call_fresh`: it
+    /// ignores and then replaces the host's pinned addresses, NXDOMAIN
+    /// backoff, memoized responses and any resolution interceptors return
+    /// from `pre_query`. This is how requests made through hyper's `Client`
+    /// get a fresh lookup, e.g. after a DNS failover.
+    pub fn forget(&self, host: &str) {
+        let host = match self.host_rewrite {
+            Some(ref rewrite) => self.normalize_host(&rewrite(host)),
+            None => self.normalize_host(host),
+        };
+        if let Ok(host) = host {
+            self.forgotten.forget(host);
+        }
+    }
+
+    /// Browse for the instances of a DNS-SD service, such as
+    /// `_http._tcp.local`, following each instance's PTR record to its SRV
+    /// and TXT records and the addresses of its host, so that they can be
+    /// connected to. Services in `.local` are browsed with multicast DNS when
+    /// it is enabled, in which case only the first responder's instances are
+    /// found.
+    pub fn browse(&self, service: &str) -> BoxFuture<'static, io::Result<Vec<ServiceInstance>>> {
+        match parse_name(service, self.redaction) {
+            Ok(name) => dnssd::browse(
+                self.nameserver(service),
+                name,
+                self.address_family.record_types(),
+                self.query_options(),
+            ),
+            Err(e) => future::err(e).boxed(),
+        }
+    }
+
+    /// Query the DNS server for `host`, returning the full response rather
+    /// than the single address the connector would pick from it.
+    pub fn lookup_raw(
+        &self,
+        host: &str,
+        record_type: trust_dns::rr::RecordType,
+    ) -> BoxFuture<'static, io::Result<DnsResponse>> {
+        let name = self
+            .normalize_host(host)
+            .map_err(io::Error::from)
+            .and_then(|host| parse_name(&host, self.redaction));
+
+        match name {
+            Ok(name) => send_query(
+                self.nameserver(host),
+                name,
+                record_type,
+                self.query_options(),
+            )
+            .boxed(),
+            Err(e) => future::err(e).boxed(),
+        }
+    }
+
+    /// Resolve `host` as a request to it would be, including its interceptors
+    /// and callbacks, yielding its addresses one at a time in the order they
+    /// should be tried: the address the selection policy picks first, then
+    /// the rest in order of preference.
+    ///
+    /// This is useful for callers which run their own connection loop and
+    /// only want the next candidate when the previous one failed.
+    pub fn resolve_stream(&self, host: &str) -> BoxStream<'static, io::Result<IpAddr>> {
+        let addrs = self.resolve_for_request(host).map_ok(|resolution| {
+            let ip = resolution.ip;
+            let rest = resolution.addrs.into_iter().filter(move |addr| *addr != ip);
+            stream::iter(Some(ip).into_iter().chain(rest).map(Ok))
+        });
+
+        addrs.try_flatten_stream().boxed()
+    }
+
+    /// Resolve each of `hosts` as for a request to it, with the queries sent
+    /// concurrently over the connections shared with requests, e.g. to warm
+    /// or audit many hosts at once. Each host's resolution or error is keyed
+    /// by the host as given; the future itself doesn't fail.
+    pub fn resolve_many(
+        &self,
+        hosts: &[&str],
+    ) -> BoxFuture<'static, io::Result<HashMap<String, io::Result<Resolution>>>> {
+        let mut unique: Vec<&str> = hosts.to_vec();
+        unique.sort();
+        unique.dedup();
+
+        let lookups: Vec<_> = unique
+            .into_iter()
+            .map(|host| {
+                let key = host.to_string();
+                self.resolve_for_request(host).map(move |res| (key, res))
+            })
+            .collect();
+
+        future::join_all(lookups)
+            .map(|results| Ok(results.into_iter().collect()))
+            .boxed()
+    }
 
-                        debug!("Resolved request to {}", &new_uri_str);
+    /// Resolve `host` as for a request to it without a port, which follows
+    /// SRV records unless the connector only queries A records.
+    fn resolve_for_request(&self, host: &str) -> BoxFuture<'static, io::Result<Resolution>> {
+        self.resolve_host(host, None, self.record_type.for_port(None), false)
+            .map_ok(|resolved| resolved.resolution)
+            .boxed()
+    }
+
+    /// The selection policy to use, which is `Random` using the connector's RNG
+    /// unless one has been set.
+    fn selection(&self) -> Arc<dyn SelectionPolicy> {
+        match self.selection {
+            Some(ref selection) => selection.clone(),
+            None => Arc::new(selection::Random::with_rng(self.rng.clone())),
+        }
+    }
+
+    /// The settings for queries sent to the DNS server.
+    fn query_options(&self) -> QueryOptions {
+        QueryOptions {
+            redaction: self.redaction,
+            events: self.events.clone(),
+            rate_limit: self.rate_limit.clone(),
+            concurrency: self.concurrency.clone(),
+            validate: self.strict_validation,
+            max_records: self.max_response_records,
+            max_size: self.max_response_size,
+            iterative: self.iterative.clone(),
+            minimize: self.qname_minimization,
+            backend: self.backend.clone(),
+            #[cfg(not(target_arch = "wasm32"))]
+            clients: if self.reuse_connections {
+                Some(self.clients.clone())
+            } else {
+                None
+            },
+            #[cfg(not(target_arch = "wasm32"))]
+            runtime: self.runtime.clone(),
+            #[cfg(not(target_arch = "wasm32"))]
+            retries: self.query_retries,
+            #[cfg(not(target_arch = "wasm32"))]
+            mark: self.clients.mark(),
+            fresh: false,
+            #[cfg(feature = "dnssec")]
+            dnssec: self.dnssec.clone(),
+        }
+    }
+
+    /// The nameserver to send a lookup of `host` to.
+    #[cfg_attr(not(feature = "mdns"), allow(unused_variables))]
+    fn nameserver(&self, host: &str) -> SocketAddr {
+        #[cfg(feature = "mdns")]
+        {
+            if self.mdns && mdns::is_local(host) {
+                return mdns::addr();
+            }
+        }
+
+        self.nameservers
+            .as_ref()
+            .and_then(|nameservers| nameservers.pick(&self.rng))
+            .unwrap_or(self.dns_addr)
+    }
+
+    /// Normalize `host` for querying, checking that the host policy allows it.
+    /// A trailing `.` is only significant when search domains are in use.
+    fn normalize_host(&self, host: &str) -> Result<String, Error> {
+        host::normalize(host, !self.search_domains.is_empty(), self.allow_idn)
+            .and_then(|host| self.check_host_policy(host))
+            .map_err(|e| e.redact(self.redaction))
+    }
+
+    /// Fail with `Error::HostDenied` if the host policy doesn't allow `host`.
+    fn check_host_policy(&self, host: String) -> Result<String, Error> {
+        match self.host_policy {
+            Some(ref policy) if !policy.allows(&host) => Err(Error::HostDenied(host)),
+            _ => Ok(host),
+        }
+    }
+
+    /// The names to query for `host`, in the order they should be tried.
+    fn candidate_names(&self, host: &str) -> io::Result<Vec<trust_dns::rr::Name>> {
+        let parse = |host: &str| parse_name(host, self.redaction);
+
+        if host.ends_with('.') || self.search_domains.is_empty() {
+            return Ok(vec![parse(host)?]);
+        }
+
+        let mut names = Vec::with_capacity(self.search_domains.len() + 1);
+        for domain in &self.search_domains {
+            names.push(parse(&format!("{}.{}", host, domain.trim_matches('.')))?);
+        }
+
+        if host.contains('.') {
+            names.insert(0, parse(host)?);
+        } else {
+            names.push(parse(host)?);
+        }
+
+        Ok(names)
+    }
+
+    /// Query `dns_addr` for each of `names` in turn until one of them has
+    /// answers for any of `record_types`, returning the responses which had
+    /// answers.
+    ///
+    /// If none of them do, the error is from the last name tried.
+    fn search(
+        &self,
+        dns_addr: SocketAddr,
+        names: Vec<trust_dns::rr::Name>,
+        record_types: Vec<trust_dns::rr::RecordType>,
+        fresh: bool,
+    ) -> Search {
+        let mut options = self.query_options();
+        options.fresh = fresh;
+
+        Search {
+            dns_addr: dns_addr,
+            names: names.into_iter(),
+            record_types: record_types,
+            options: options,
+            redaction: self.redaction,
+            querying: None,
+            error: None,
+            #[cfg(feature = "llmnr")]
+            fallback: None,
+            #[cfg(feature = "llmnr")]
+            failed: None,
+        }
+    }
+
+    /// Fall back to resolving `host` with LLMNR if `search` fails, when it
+    /// is a single label and LLMNR is enabled, failing with the error from
+    /// `search` if that does too.
+    #[cfg(feature = "llmnr")]
+    fn llmnr_fallback(&self, mut search: Search, host: &str) -> Search {
+        if !self.llmnr || !llmnr::is_single_label(host) {
+            return search;
+        }
+
+        if let Ok(name) = parse_name(host, self.redaction) {
+            search.fallback = Some((llmnr::addr(), vec![name]));
+        }
+        search
+    }
 
-                        let new_uri = new_uri_str.parse::<Uri>().unwrap();
+    /// Query the DNS server and pick the address to connect to. If `fresh`,
+    /// pins, NXDOMAIN backoff and memoized responses are ignored, and then
+    /// replaced by the lookup.
+    fn lookup(&self, query: &Query, fresh: bool) -> Lookup {
+        let names = match self.candidate_names(&query.host) {
+            Ok(names) => names,
+            Err(e) => return Lookup::Ready(Some(Err(e))),
+        };
 
-                        connector.call(new_uri)
-                    });
+        let port = query.port;
+        let record_type = query.record_type.for_port(port);
 
-                Box::new(future)
+        let pinned = match self.pins {
+            Some(ref pins) if !fresh => pins.get(&query.host, record_type),
+            _ => None,
+        };
+        if let Some(mut resolution) = pinned {
+            debug!(
+                "Using pinned address for {}",
+                self.redaction.apply(&query.host)
+            );
+            if record_type != RecordType::SRV {
+                resolution.port = port;
             }
+            return Lookup::Ready(Some(Ok(resolution)));
         }
+
+        if let Some(ref backoff) = self.nxdomain_backoff {
+            if !fresh && backoff.backing_off(&query.host) {
+                debug!("Backing off from {}", self.redaction.apply(&query.host));
+                let err = Error::NxDomain(query.host.clone()).redact(self.redaction);
+                return Lookup::Ready(Some(Err(err.into())));
+            }
+        }
+
+        let nameserver = self.nameserver(&query.host);
+        let in_flight = Pending::start(&self.pending);
+        let start = Instant::now();
+        let span = Span::lookup(&self.redaction.apply(&query.host), record_type, nameserver);
+        let _entered = span.enter();
+
+        let trust_record_types = match record_type {
+            RecordType::SRV => vec![trust_dns::rr::RecordType::SRV],
+            _ => {
+                debug!(
+                    "Using {:?} address lookup for: {}",
+                    self.address_family,
+                    self.redaction.apply(&query.host)
+                );
+                let mut record_types = self.address_family.record_types();
+                if self.dns64.is_some() && !record_types.contains(&trust_dns::rr::RecordType::A) {
+                    record_types.push(trust_dns::rr::RecordType::A);
+                }
+                record_types
+            }
+        };
+
+        let nat64_prefix = match self.dns64 {
+            Some(ref dns64) => dns64.prefix(nameserver, self.query_options()),
+            None => Either::Left(future::ok(None)),
+        };
+
+        let search = self.search(nameserver, names, trust_record_types, fresh);
+        #[cfg(feature = "llmnr")]
+        let search = self.llmnr_fallback(search, &query.host);
+
+        let querying = Querying {
+            queries: future::try_join(search, nat64_prefix),
+            host: query.host.clone(),
+            port: port,
+            record_type: record_type,
+            nameserver: nameserver,
+            selection: self.selection(),
+            rng: self.rng.clone(),
+            blacklist: self.blacklist.clone(),
+            sources: self.sources.clone(),
+            address_family: self.address_family,
+            max_cname_depth: self.max_cname_depth,
+            redaction: self.redaction,
+            pins: self.pins.clone(),
+            nxdomain_backoff: self.nxdomain_backoff.clone(),
+            stats: self.stats.clone(),
+            in_flight: Some(in_flight),
+            start: start,
+            span: span.clone(),
+        };
+        Lookup::Querying(span.instrument(querying))
     }
 }
 
-#[cfg(test)]
-mod tests {
-    #[test]
-    fn it_works() {}
+/// A lookup on the DNS server, returned by `DnsConnector::lookup`.
+#[allow(clippy::large_enum_variant)]
+enum Lookup {
+    /// Pinned, backing off, or already failed, so not querying the server.
+    Ready(Option<io::Result<Resolution>>),
+    Querying(Instrumented<Querying>),
+}
+
+impl Future for Lookup {
+    type Output = io::Result<Resolution>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match *self {
+            Lookup::Ready(ref mut result) => {
+                Poll::Ready(result.take().expect("polled after completion"))
+            }
+            Lookup::Querying(ref mut querying) => Pin::new(querying).poll(cx),
+        }
+    }
+}
+
+/// The queries for a lookup, picking the address to connect to from their
+/// answers.
+struct Querying {
+    queries: future::TryJoin<Search, dns64::PrefixFuture>,
+    host: String,
+    port: Option<u16>,
+    record_type: RecordType,
+    nameserver: SocketAddr,
+    selection: Arc<dyn SelectionPolicy>,
+    rng: SharedRng,
+    blacklist: Option<Arc<Blacklist>>,
+    sources: Option<Arc<Sources>>,
+    address_family: AddressFamily,
+    max_cname_depth: usize,
+    redaction: Redaction,
+    pins: Option<Arc<Pins>>,
+    nxdomain_backoff: Option<Arc<NxBackoff>>,
+    stats: Arc<Stats>,
+    in_flight: Option<pending::InFlight>,
+    start: Instant,
+    span: Span,
+}
+
+impl Querying {
+    /// Pick the address to connect to from the responses for `name`.
+    fn pick(
+        &self,
+        name: trust_dns::rr::Name,
+        responses: Vec<DnsResponse>,
+        nat64_prefix: Option<Nat64Prefix>,
+    ) -> io::Result<Resolution> {
+        let Querying {
+            ref host,
+            port,
+            record_type,
+            nameserver,
+            ref selection,
+            ref rng,
+            ref blacklist,
+            ref sources,
+            address_family,
+            max_cname_depth,
+            redaction,
+            span: ref lookup_span,
+            ..
+        } = *self;
+
+        if responses.is_empty() {
+            return Err(Error::NoRecords(name.to_string()).redact(redaction).into());
+        }
+
+        let mut chain = Vec::new();
+
+        // First find the SRV records if they were requested
+        let (target, a_records, new_port) = if let RecordType::SRV = record_type {
+            let res = &responses[0];
+            let answers: Vec<_> = res
+                .answers()
+                .iter()
+                .filter(|answer| answer.rr_type() == trust_dns::rr::RecordType::SRV)
+                .collect();
+            let answer = match rng.with(|rng| rng.choose(&answers).cloned()) {
+                Some(answer) => answer,
+                None => return Err(Error::NoRecords(name.to_string()).redact(redaction).into()),
+            };
+
+            let srv = match *answer.rdata() {
+                trust_dns::rr::RData::SRV(ref srv) => srv,
+                _ => return Err(Error::InvalidResponse("expected SRV record".to_string()).into()),
+            };
+
+            chain.push(Hop::new(answer));
+            (
+                srv.target().clone(),
+                res.additionals().to_vec(),
+                Some(srv.port()),
+            )
+        } else {
+            // For address requests it is the domain name that
+            // we want to use.
+            let answers = responses
+                .iter()
+                .flat_map(|res| res.answers().iter().cloned())
+                .collect();
+            (name.clone(), answers, port)
+        };
+
+        // The addresses are for the end of any CNAME chain.
+        let target = match chain::follow_cnames(&a_records, &target, max_cname_depth, &mut chain) {
+            Some(target) => target,
+            None => {
+                return Err(Error::CnameLoop(target.to_string())
+                    .redact(redaction)
+                    .into())
+            }
+        };
+
+        // Collect every address for the target, rather than just the
+        // first, so that the selection policy can spread load across them.
+        let mut addrs = Vec::new();
+
+        for entry in a_records.iter().filter(|record| record.name() == &target) {
+            let addr = match *entry.rdata() {
+                trust_dns::rr::RData::A(ref addr) => IpAddr::V4(*addr),
+                trust_dns::rr::RData::AAAA(ref addr) => IpAddr::V6(*addr),
+                _ => continue,
+            };
+
+            addrs.push(addr);
+            chain.push(Hop::new(entry));
+        }
+
+        // The resolution is only valid for as long as every record
+        // followed is.
+        let ttl = chain.iter().map(|hop| hop.ttl).min();
+
+        // Synthesized addresses are kept whatever the family, as they
+        // stand in for the IPv4 ones which may be all that is allowed.
+        let synthesized = match nat64_prefix {
+            Some(ref prefix) => dns64::synthesize(prefix, &mut addrs),
+            None => false,
+        };
+        if !synthesized {
+            addrs.retain(|addr| address_family.allows(addr));
+        }
+
+        if addrs.is_empty() {
+            return Err(Error::NoRecords(target.to_string())
+                .redact(redaction)
+                .into());
+        }
+
+        // Order the addresses by preference, and only let the selection
+        // policy choose between the most preferred ones.
+        let mut ranks = match sources {
+            Some(ref sources) => rfc6724::sort(&mut addrs, sources),
+            None => vec![0; addrs.len()],
+        };
+        address_family.order(&mut addrs, &mut ranks);
+
+        let allowed = match blacklist {
+            Some(ref blacklist) => blacklist.filter(&addrs),
+            None => addrs.clone(),
+        };
+
+        let rank = |addr: &IpAddr| ranks[addrs.iter().position(|a| a == addr).unwrap()];
+        let best = allowed.iter().map(&rank).min().unwrap_or(0);
+        let candidates: Vec<IpAddr> = allowed
+            .into_iter()
+            .filter(|addr| rank(addr) == best)
+            .collect();
+
+        let ip = selection.select(host, &candidates);
+        lookup_span.record_selected(ip);
+
+        Ok(Resolution {
+            ip: ip,
+            addrs: addrs,
+            port: new_port,
+            record_type: Some(record_type),
+            ttl: ttl,
+            nameserver: Some(nameserver),
+            chain: chain,
+        })
+    }
+
+    /// Pin the resolution, and record the outcome of the lookup.
+    fn finish(&mut self, result: io::Result<Resolution>) -> io::Result<Resolution> {
+        let result = result.and_then(|resolution| match self.pins {
+            Some(ref pins) if !pins.pin(&self.host, self.record_type, &resolution) => {
+                Err(Error::Rebinding(self.host.clone())
+                    .redact(self.redaction)
+                    .into())
+            }
+            _ => Ok(resolution),
+        });
+
+        drop(self.in_flight.take());
+        self.stats
+            .nameserver(self.nameserver, self.start.elapsed(), result.as_ref().err());
+        if let Some(ref backoff) = self.nxdomain_backoff {
+            match result {
+                Ok(_) => backoff.resolved(&self.host),
+                Err(ref e) if counters::failure_class(e) == "nxdomain" => {
+                    backoff.nxdomain(&self.host)
+                }
+                Err(_) => {}
+            }
+        }
+        if let Err(ref e) = result {
+            self.span.record_error(e);
+        }
+        result
+    }
+}
+
+impl Future for Querying {
+    type Output = io::Result<Resolution>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let result = match Pin::new(&mut this.queries).poll(cx) {
+            Poll::Ready(result) => result,
+            Poll::Pending => return Poll::Pending,
+        };
+        let result = result
+            .and_then(|((name, responses), nat64_prefix)| this.pick(name, responses, nat64_prefix));
+        Poll::Ready(this.finish(result))
+    }
+}
+
+/// Settings which apply to each query sent to the DNS server.
+#[derive(Clone)]
+struct QueryOptions {
+    redaction: Redaction,
+    events: Option<Arc<dyn EventSink>>,
+    rate_limit: Option<Arc<RateLimiter>>,
+    concurrency: Option<Arc<ConcurrencyLimit>>,
+    validate: bool,
+    max_records: Option<usize>,
+    max_size: Option<usize>,
+    iterative: Option<Arc<Iterative>>,
+    minimize: bool,
+    backend: Option<Arc<dyn Backend>>,
+    #[cfg(not(target_arch = "wasm32"))]
+    clients: Option<Arc<Clients>>,
+    #[cfg(not(target_arch = "wasm32"))]
+    runtime: Arc<dyn Runtime>,
+    #[cfg(not(target_arch = "wasm32"))]
+    retries: usize,
+    #[cfg(not(target_arch = "wasm32"))]
+    mark: Option<u32>,
+    /// Whether memoized responses are replaced rather than used.
+    fresh: bool,
+    #[cfg(feature = "dnssec")]
+    dnssec: Option<Arc<Dnssec>>,
+}
+
+impl QueryOptions {
+    fn event(&self, event: Event) {
+        if let Some(ref events) = self.events {
+            events.event(&event);
+        }
+    }
+}
+
+/// Identifies each query in the logs, so that its sending and outcome can be
+/// correlated.
+static NEXT_QUERY_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Send a single query to the DNS server.
+fn send_query(
+    dns_addr: SocketAddr,
+    name: trust_dns::rr::Name,
+    record_type: trust_dns::rr::RecordType,
+    mut options: QueryOptions,
+) -> SendQuery {
+    // mDNS and LLMNR queries are answered by the hosts themselves.
+    #[cfg(feature = "mdns")]
+    {
+        if dns_addr == mdns::addr() {
+            options.iterative = None;
+            options.backend = None;
+        }
+    }
+    #[cfg(feature = "llmnr")]
+    {
+        if dns_addr == llmnr::addr() {
+            options.iterative = None;
+            options.backend = None;
+        }
+    }
+
+    // Resolve the name from the root servers instead, sending each query
+    // this makes on its way to the name's servers.
+    if let Some(iterative) = options.iterative.take() {
+        let query = iterative::query(iterative, name, record_type, options);
+        return SendQuery {
+            state: SendState::Iterative(query),
+        };
+    }
+
+    // Wait until the query is allowed to be in flight before sending it,
+    // holding the permit until it completes.
+    if let Some(limit) = options.concurrency.take() {
+        let acquire = ConcurrencyLimit::acquire(&limit);
+        return SendQuery {
+            state: SendState::Acquiring(acquire, Some((dns_addr, name, record_type, options))),
+        };
+    }
+
+    SendQuery {
+        state: SendState::send(dns_addr, name, record_type, options, None),
+    }
+}
+
+/// A query being sent to a DNS server, returned by `send_query`.
+struct SendQuery {
+    state: SendState,
+}
+
+#[allow(clippy::large_enum_variant)]
+enum SendState {
+    /// Resolving the name from the root servers.
+    Iterative(BoxFuture<'static, io::Result<DnsResponse>>),
+    /// Waiting for the concurrency limit to allow the query to be sent.
+    Acquiring(
+        Acquire,
+        Option<(
+            SocketAddr,
+            trust_dns::rr::Name,
+            trust_dns::rr::RecordType,
+            QueryOptions,
+        )>,
+    ),
+    Sending(Instrumented<ServerQuery>),
+    Done(Option<io::Result<DnsResponse>>),
+}
+
+impl SendState {
+    /// Send the query, unless it is rate limited.
+    fn send(
+        dns_addr: SocketAddr,
+        name: trust_dns::rr::Name,
+        record_type: trust_dns::rr::RecordType,
+        options: QueryOptions,
+        permit: Option<Permit>,
+    ) -> SendState {
+        let query_name = options.redaction.apply(&name.to_string()).into_owned();
+
+        if let Some(ref rate_limit) = options.rate_limit {
+            if !rate_limit.try_acquire() {
+                debug!(
+                    "server={} name={} type={:?}: rate limited",
+                    dns_addr, query_name, record_type
+                );
+                return SendState::Done(Some(Err(Error::RateLimited.into())));
+            }
+        }
+
+        let span = Span::query(&query_name, record_type, dns_addr);
+        let question = name.clone();
+
+        let id = NEXT_QUERY_ID.fetch_add(1, Ordering::Relaxed);
+        let start = Instant::now();
+        debug!(
+            "query={} server={} name={} type={:?}: sending",
+            id, dns_addr, query_name, record_type
+        );
+        counters::query_sent();
+        options.event(Event::QueryStarted {
+            id: id,
+            server: dns_addr,
+            name: query_name.clone(),
+            record_type: record_type.to_string(),
+        });
+
+        let response = match options.backend {
+            Some(ref backend) => Response::Backend(backend.query(dns_addr, name, record_type)),
+            #[cfg(not(target_arch = "wasm32"))]
+            None => Response::Server(ask_server(dns_addr, name, record_type, &options)),
+            // There are no sockets to query DNS servers with.
+            #[cfg(target_arch = "wasm32")]
+            None => Response::Backend(
+                future::err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "DNS servers can't be queried on wasm32, set a backend such as DnsOverHttps",
+                ))
+                .boxed(),
+            ),
+        };
+
+        let query = ServerQuery {
+            response: response,
+            id: id,
+            dns_addr: dns_addr,
+            query_name: query_name,
+            question: question,
+            record_type: record_type,
+            start: start,
+            options: options,
+            span: span.clone(),
+            permit: permit,
+        };
+        SendState::Sending(span.instrument(query))
+    }
+}
+
+impl Future for SendQuery {
+    type Output = io::Result<DnsResponse>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        loop {
+            let next = match self.state {
+                SendState::Iterative(ref mut query) => return query.as_mut().poll(cx),
+                SendState::Acquiring(ref mut acquire, ref mut query) => {
+                    match Pin::new(acquire).poll(cx) {
+                        Poll::Ready(Ok(permit)) => {
+                            let (dns_addr, name, record_type, options) =
+                                query.take().expect("polled after completion");
+                            SendState::send(dns_addr, name, record_type, options, Some(permit))
+                        }
+                        Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                        Poll::Pending => return Poll::Pending,
+                    }
+                }
+                SendState::Sending(ref mut query) => match Pin::new(query).poll(cx) {
+                    Poll::Ready(res) => SendState::Done(Some(res)),
+                    Poll::Pending => return Poll::Pending,
+                },
+                SendState::Done(ref mut res) => {
+                    return Poll::Ready(res.take().expect("polled after completion"))
+                }
+            };
+            self.state = next;
+        }
+    }
+}
+
+/// Where the response to a query comes from.
+#[allow(clippy::large_enum_variant)]
+enum Response {
+    Backend(BoxFuture<'static, io::Result<DnsResponse>>),
+    #[cfg(not(target_arch = "wasm32"))]
+    Server(AskServer),
+}
+
+/// A query sent to a DNS server, checking and logging its response.
+struct ServerQuery {
+    response: Response,
+    id: usize,
+    dns_addr: SocketAddr,
+    query_name: String,
+    question: trust_dns::rr::Name,
+    record_type: trust_dns::rr::RecordType,
+    start: Instant,
+    options: QueryOptions,
+    span: Span,
+    /// The concurrency limit's permit, held until the query completes.
+    permit: Option<Permit>,
+}
+
+impl ServerQuery {
+    fn finish(&mut self, res: io::Result<DnsResponse>) -> io::Result<DnsResponse> {
+        drop(self.permit.take());
+
+        let (id, dns_addr, record_type, start) =
+            (self.id, self.dns_addr, self.record_type, self.start);
+        let options = &self.options;
+        let question = &self.question;
+        let query_name = std::mem::take(&mut self.query_name);
+
+        let res = res.and_then(|res| {
+            validate::check_limits(&res, options.max_records, options.max_size)
+                .map_err(|reason| io::Error::from(Error::InvalidResponse(reason)))?;
+            if options.validate {
+                validate::validate(&res, question, record_type, options.redaction)
+                    .map_err(|reason| io::Error::from(Error::InvalidResponse(reason)))?;
+            }
+            Ok(res)
+        });
+        let res =
+            res.map_err(|e| error::redact_reason(e, options.redaction, &question.to_string()));
+
+        match res {
+            Ok(res) => {
+                debug!(
+                    "query={} server={} name={} type={:?}: rcode={:?} answers={} elapsed={:?}",
+                    id,
+                    dns_addr,
+                    query_name,
+                    record_type,
+                    res.response_code(),
+                    res.answers().len(),
+                    start.elapsed()
+                );
+                options.event(Event::QueryFinished {
+                    id: id,
+                    server: dns_addr,
+                    name: query_name,
+                    record_type: record_type.to_string(),
+                    rcode: format!("{:?}", res.response_code()),
+                    answers: res.answers().len(),
+                    elapsed_ms: elapsed_ms(start),
+                });
+                Ok(res)
+            }
+            Err(err) => {
+                debug!(
+                    "query={} server={} name={} type={:?}: failed elapsed={:?}: {}",
+                    id,
+                    dns_addr,
+                    query_name,
+                    record_type,
+                    start.elapsed(),
+                    err
+                );
+                self.span.record_error(&err);
+                options.event(Event::QueryFailed {
+                    id: id,
+                    server: dns_addr,
+                    name: query_name,
+                    record_type: record_type.to_string(),
+                    error: err.to_string(),
+                    class: counters::failure_class(&err).to_string(),
+                    elapsed_ms: elapsed_ms(start),
+                });
+                Err(err)
+            }
+        }
+    }
+}
+
+impl Future for ServerQuery {
+    type Output = io::Result<DnsResponse>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let res = match self.response {
+            Response::Backend(ref mut response) => response.as_mut().poll(cx),
+            #[cfg(not(target_arch = "wasm32"))]
+            Response::Server(ref mut response) => Pin::new(response).poll(cx),
+        };
+        match res {
+            Poll::Ready(res) => Poll::Ready(self.finish(res)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Send a query to the DNS server at `dns_addr` with the DNS client.
+#[cfg(not(target_arch = "wasm32"))]
+fn ask_server(
+    dns_addr: SocketAddr,
+    name: trust_dns::rr::Name,
+    record_type: trust_dns::rr::RecordType,
+    options: &QueryOptions,
+) -> AskServer {
+    AskServer {
+        dns_addr: dns_addr,
+        name: name,
+        record_type: record_type,
+        options: options.clone(),
+        state: AskState::Start,
+    }
+}
+
+/// A query being sent to a DNS server with the DNS client, returned by
+/// `ask_server`.
+#[cfg(not(target_arch = "wasm32"))]
+struct AskServer {
+    dns_addr: SocketAddr,
+    name: trust_dns::rr::Name,
+    record_type: trust_dns::rr::RecordType,
+    options: QueryOptions,
+    state: AskState,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+enum AskState {
+    /// About to get a client, which is only done once polled, as the pool it
+    /// comes from is that of the executor polling it.
+    Start,
+    /// Taking a client from the server's pool.
+    Pooled(clients::ClientFuture),
+    /// Connecting a client of its own for mDNS, LLMNR or when connections
+    /// aren't reused.
+    Connecting(BoxFuture<'static, Result<Client, ClientError>>),
+    /// Waiting for the response, with where the client is in its pool and
+    /// whether it had been used before.
+    Querying(ClientQuery, Option<clients::Slot>, bool),
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl AskServer {
+    /// Get the client to send the query with.
+    fn client(&self) -> AskState {
+        // We would expect a DNS request to be responded to quickly, but add a timeout
+        // to ensure that we don't wait for ever if the DNS server does not respond.
+        let timeout = Duration::from_millis(30000);
+
+        let (dns_addr, options) = (self.dns_addr, &self.options);
+        let connecting = match dns_addr {
+            #[cfg(feature = "mdns")]
+            addr if addr == mdns::addr() => mdns::client().map_ok(Client::unpooled).boxed(),
+            #[cfg(feature = "llmnr")]
+            addr if addr == llmnr::addr() => llmnr::client().map_ok(Client::unpooled).boxed(),
+            _ => match options.clients {
+                Some(ref clients) => {
+                    return AskState::Pooled(Clients::client(
+                        clients,
+                        &options.runtime,
+                        dns_addr,
+                        timeout,
+                        options.fresh,
+                    ))
+                }
+                None => clients::connect(&*options.runtime, dns_addr, timeout, options.mark)
+                    .map_ok(Client::unpooled)
+                    .boxed(),
+            },
+        };
+        AskState::Connecting(connecting)
+    }
+
+    /// Send the query with `client`.
+    fn query(&self, client: Client) -> AskState {
+        let query = client_query(
+            client.handle,
+            self.name.clone(),
+            self.record_type,
+            &self.options,
+        );
+        AskState::Querying(query, client.slot, client.reused)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Future for AskServer {
+    type Output = io::Result<DnsResponse>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        loop {
+            let next = match self.state {
+                AskState::Start => self.client(),
+                AskState::Pooled(ref mut client) => match Pin::new(client).poll(cx) {
+                    Poll::Ready(Ok(client)) => self.query(client),
+                    Poll::Ready(Err(e)) => return Poll::Ready(Err(query_error(&e).into())),
+                    Poll::Pending => return Poll::Pending,
+                },
+                AskState::Connecting(ref mut client) => match client.as_mut().poll(cx) {
+                    Poll::Ready(Ok(client)) => self.query(client),
+                    Poll::Ready(Err(e)) => return Poll::Ready(Err(query_error(&e).into())),
+                    Poll::Pending => return Poll::Pending,
+                },
+                AskState::Querying(ref mut query, slot, reused) => {
+                    let e = match Pin::new(query).poll(cx) {
+                        Poll::Ready(Ok(res)) => return Poll::Ready(Ok(res)),
+                        Poll::Ready(Err(e)) => io::Error::from(query_error(&e)),
+                        Poll::Pending => return Poll::Pending,
+                    };
+                    let (clients, slot) = match (self.options.clients.clone(), slot) {
+                        (Some(clients), Some(slot)) => (clients, slot),
+                        _ => return Poll::Ready(Err(e)),
+                    };
+
+                    // The connection may be half-open, so is replaced
+                    // whatever the error. The server may also have closed a
+                    // reused one since its last query, so that is retried
+                    // once on a new connection, but a timeout isn't retried.
+                    debug!("server={}: dropping connection after {}", self.dns_addr, e);
+                    clients.remove(self.dns_addr, slot);
+                    if !reused || counters::failure_class(&e) == "timeout" {
+                        return Poll::Ready(Err(e));
+                    }
+                    AskState::Start
+                }
+            };
+            self.state = next;
+        }
+    }
+}
+
+/// Send a query with `client`, resending it on failure and validating the
+/// response with DNSSEC if enabled.
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg_attr(not(feature = "dnssec"), allow(unused_variables))]
+fn client_query(
+    client: Handle,
+    name: trust_dns::rr::Name,
+    record_type: trust_dns::rr::RecordType,
+    options: &QueryOptions,
+) -> ClientQuery {
+    #[cfg(feature = "dnssec")]
+    let dnssec = options.dnssec.is_some();
+    #[cfg(not(feature = "dnssec"))]
+    let dnssec = false;
+
+    match client {
+        Handle::Basic(mut client) if options.retries == 0 && !dnssec => {
+            ClientQuery::Basic(client.query(name, trust_dns::rr::DNSClass::IN, record_type))
+        }
+        Handle::Basic(client) => {
+            ClientQuery::Boxed(retry_query(client, name, record_type, options))
+        }
+        Handle::Memoized(client) => {
+            ClientQuery::Boxed(retry_query(client, name, record_type, options))
+        }
+    }
+}
+
+/// A query sent with a client, returned by `client_query`.
+#[cfg(not(target_arch = "wasm32"))]
+enum ClientQuery {
+    Basic(ClientResponse<DnsExchangeSend>),
+    /// Memoized, resent or validated queries, whose futures trust-dns boxes
+    /// anyway.
+    Boxed(BoxFuture<'static, Result<DnsResponse, ClientError>>),
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Future for ClientQuery {
+    type Output = Result<DnsResponse, ClientError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match *self {
+            ClientQuery::Basic(ref mut query) => Pin::new(query).poll(cx),
+            ClientQuery::Boxed(ref mut query) => query.as_mut().poll(cx),
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn retry_query<H>(
+    client: H,
+    name: trust_dns::rr::Name,
+    record_type: trust_dns::rr::RecordType,
+    options: &QueryOptions,
+) -> BoxFuture<'static, Result<DnsResponse, ClientError>>
+where
+    H: ClientHandle + Sync + Unpin,
+{
+    if options.retries > 0 {
+        let client = RetryDnsHandle::new(client, options.retries);
+        return secure_query(client, name, record_type, options);
+    }
+    secure_query(client, name, record_type, options)
+}
+
+#[cfg_attr(not(feature = "dnssec"), allow(unused_variables))]
+#[cfg(not(target_arch = "wasm32"))]
+fn secure_query<H>(
+    mut client: H,
+    name: trust_dns::rr::Name,
+    record_type: trust_dns::rr::RecordType,
+    options: &QueryOptions,
+) -> BoxFuture<'static, Result<DnsResponse, ClientError>>
+where
+    H: ClientHandle + Sync + Unpin,
+{
+    #[cfg(feature = "dnssec")]
+    {
+        if let Some(ref dnssec) = options.dnssec {
+            return dnssec.query(client, name, record_type);
+        }
+    }
+
+    client
+        .query(name, trust_dns::rr::DNSClass::IN, record_type)
+        .boxed()
+}
+
+/// Milliseconds since `start`, for events.
+fn elapsed_ms(start: Instant) -> u64 {
+    let elapsed = start.elapsed();
+    elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis())
+}
+
+/// Classify an error from the DNS client.
+#[cfg(not(target_arch = "wasm32"))]
+fn query_error(err: &ClientError) -> Error {
+    match *err.kind() {
+        ClientErrorKind::Timeout => Error::Timeout,
+        ClientErrorKind::Io(ref e) if e.kind() == io::ErrorKind::TimedOut => Error::Timeout,
+        ClientErrorKind::Proto(ref e) if is_timed_out(e) => Error::Timeout,
+        _ => Error::Transport(err.to_string()),
+    }
+}
+
+/// Whether `err` is a timeout, or an I/O error which timed out.
+#[cfg(not(target_arch = "wasm32"))]
+fn is_timed_out(err: &ProtoError) -> bool {
+    match *err.kind() {
+        ProtoErrorKind::Timeout => true,
+        ProtoErrorKind::Io(ref e) => e.kind() == io::ErrorKind::TimedOut,
+        _ => false,
+    }
+}
+
+/// Query for each of `record_types` concurrently, returning the responses in
+/// the order of `record_types`. A failed query only fails the lookup if none
+/// of the others had answers.
+fn query_types(
+    dns_addr: SocketAddr,
+    name: trust_dns::rr::Name,
+    record_types: Vec<trust_dns::rr::RecordType>,
+    options: QueryOptions,
+) -> QueryTypes {
+    let queries = record_types
+        .iter()
+        .map(|&record_type| send_query(dns_addr, name.clone(), record_type, options.clone()));
+
+    QueryTypes {
+        queries: future::join_all(queries),
+        record_types: record_types,
+    }
+}
+
+/// The queries for each record type, returned by `query_types`.
+struct QueryTypes {
+    queries: future::JoinAll<SendQuery>,
+    record_types: Vec<trust_dns::rr::RecordType>,
+}
+
+impl Future for QueryTypes {
+    type Output = io::Result<Vec<DnsResponse>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let results = match Pin::new(&mut self.queries).poll(cx) {
+            Poll::Ready(results) => results,
+            Poll::Pending => return Poll::Pending,
+        };
+
+        let mut responses = Vec::new();
+        let mut error = None;
+
+        for (result, record_type) in results.into_iter().zip(&self.record_types) {
+            match result {
+                Ok(res) => responses.push(res),
+                Err(e) => {
+                    debug!("{:?} query failed: {}", record_type, e);
+                    error = error.or(Some(e));
+                }
+            }
+        }
+
+        let answered = responses.iter().any(|res| !res.answers().is_empty());
+        Poll::Ready(match error {
+            Some(e) if !answered => Err(e),
+            _ => Ok(responses),
+        })
+    }
+}
+
+/// The queries for each of a host's candidate names in turn, returned by
+/// `DnsConnector::search`.
+struct Search {
+    dns_addr: SocketAddr,
+    names: std::vec::IntoIter<trust_dns::rr::Name>,
+    record_types: Vec<trust_dns::rr::RecordType>,
+    options: QueryOptions,
+    redaction: Redaction,
+    querying: Option<(trust_dns::rr::Name, QueryTypes)>,
+    /// The error from the last name tried.
+    error: Option<io::Error>,
+    /// The server and names to try if none of the names are answered.
+    #[cfg(feature = "llmnr")]
+    fallback: Option<(SocketAddr, Vec<trust_dns::rr::Name>)>,
+    /// The error to fail with if the fallback fails too.
+    #[cfg(feature = "llmnr")]
+    failed: Option<io::Error>,
+}
+
+impl Search {
+    /// The responses for `name` which had answers, or the error for it if
+    /// none did.
+    fn answered(
+        &self,
+        name: trust_dns::rr::Name,
+        responses: io::Result<Vec<DnsResponse>>,
+    ) -> io::Result<(trust_dns::rr::Name, Vec<DnsResponse>)> {
+        let responses = responses?;
+        if responses.iter().any(|res| !res.answers().is_empty()) {
+            let answered = responses
+                .into_iter()
+                .filter(|res| !res.answers().is_empty())
+                .collect();
+            return Ok((name, answered));
+        }
+
+        let host = name.to_string();
+        let rcode = |code| responses.iter().any(|res| res.response_code() == code);
+        let rejected = responses
+            .iter()
+            .map(|res| res.response_code())
+            .find(|&code| code != ResponseCode::NoError);
+        let err = if rcode(ResponseCode::NXDomain) {
+            Error::NxDomain(host)
+        } else if rcode(ResponseCode::ServFail) {
+            Error::ServFail(host)
+        } else if let Some(code) = rejected {
+            Error::Rejected(host, code)
+        } else {
+            Error::NoRecords(host)
+        };
+
+        Err(io::Error::from(err.redact(self.redaction)))
+    }
+}
+
+impl Future for Search {
+    type Output = io::Result<(trust_dns::rr::Name, Vec<DnsResponse>)>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        loop {
+            if let Some((_, ref mut query)) = this.querying {
+                let responses = match Pin::new(query).poll(cx) {
+                    Poll::Ready(responses) => responses,
+                    Poll::Pending => return Poll::Pending,
+                };
+                let (name, _) = this.querying.take().expect("polled after completion");
+                match this.answered(name, responses) {
+                    Ok(answered) => return Poll::Ready(Ok(answered)),
+                    Err(e) => this.error = Some(e),
+                }
+            }
+
+            if let Some(name) = this.names.next() {
+                let query = query_types(
+                    this.dns_addr,
+                    name.clone(),
+                    this.record_types.clone(),
+                    this.options.clone(),
+                );
+                this.querying = Some((name, query));
+                continue;
+            }
+
+            #[cfg(feature = "llmnr")]
+            {
+                if let Some((dns_addr, names)) = this.fallback.take() {
+                    this.failed = this.error.take();
+                    this.dns_addr = dns_addr;
+                    this.names = names.into_iter();
+                    this.options.fresh = false;
+                    continue;
+                }
+                if let Some(e) = this.failed.take() {
+                    return Poll::Ready(Err(e));
+                }
+            }
+
+            let e = this
+                .error
+                .take()
+                .unwrap_or_else(|| io::Error::other("No valid DNS answers"));
+            return Poll::Ready(Err(e));
+        }
+    }
+}
+
+/// Parse a host into a fully qualified DNS name.
+fn parse_name(host: &str, redaction: Redaction) -> io::Result<trust_dns::rr::Name> {
+    // Add a `.` to the end of the host so that we can query the domain records,
+    // unless it is already fully qualified.
+    let fqdn = if host.ends_with('.') {
+        Cow::Borrowed(host)
+    } else {
+        Cow::Owned(format!("{}.", host))
+    };
+
+    trust_dns::rr::Name::parse(&fqdn, None)
+        .map_err(|_| Error::InvalidHost(redaction.apply(host).into_owned()).into())
+}
+
+/// Run the audit hook, if there is one, over the resolution for `host`,
+/// redacting `host` in the reason it gives for vetoing it.
+fn audit(
+    hook: &Option<Arc<AuditHook>>,
+    host: &str,
+    resolution: &Resolution,
+    redaction: Redaction,
+) -> io::Result<()> {
+    match *hook {
+        Some(ref hook) => hook(host, resolution)
+            .map_err(|reason| Error::Vetoed(reason).redact_reason(redaction, host).into()),
+        None => Ok(()),
+    }
+}
+
+/// Drop the addresses `host` resolved to which are bogus, when they are
+/// rejected, or which `filter` forbids, picking another address with
+/// `selection` if the chosen one was dropped.
+fn filter_addresses(
+    filter: &AddressFilter,
+    reject_bogus: bool,
+    selection: &dyn SelectionPolicy,
+    host: &str,
+    resolution: &mut Resolution,
+) -> Result<(), Error> {
+    if reject_bogus {
+        resolution.addrs.retain(|addr| !filter::is_bogus(addr));
+        if resolution.addrs.is_empty() {
+            return Err(Error::BogusAddress(host.to_string()));
+        }
+    }
+
+    resolution.addrs.retain(|addr| filter.allows(host, addr));
+    if resolution.addrs.is_empty() {
+        return Err(Error::ForbiddenAddress(host.to_string()));
+    }
+
+    if !resolution.addrs.contains(&resolution.ip) {
+        resolution.ip = selection.select(host, &resolution.addrs);
+    }
+    Ok(())
+}
+
+/// Run the `pre_connect` stage of each interceptor over `uri`.
+fn pre_connect(interceptors: &[Arc<dyn Interceptor>], uri: &mut Uri) -> io::Result<()> {
+    for interceptor in interceptors {
+        interceptor.pre_connect(uri)?;
+    }
+    Ok(())
+}
+
+impl<C> DnsConnector<C> {
+    /// Resolve the host of `uri` to the addresses to connect to, running the
+    /// interceptors and callbacks along the way, and ignoring cached entries
+    /// if `fresh`.
+    fn resolve(&self, uri: &Uri, fresh: bool) -> Resolve {
+        debug!(
+            "Trying to resolve {}://{}",
+            uri.scheme().unwrap(),
+            self.redaction.apply(uri.host().unwrap())
+        );
+        self.resolve_host(
+            uri.host().unwrap(),
+            uri.port_u16(),
+            self.record_type.for_port(uri.port_u16()),
+            fresh,
+        )
+    }
+
+    /// A resolver which resolves hosts as this connector does, sharing its
+    /// configuration and state (such as statistics and pins), for clients
+    /// other than hyper's.
+    pub fn resolver(&self) -> DnsResolver
+    where
+        C: Send + 'static,
+    {
+        let connector = self.clone();
+        let srv_connector = self.clone();
+        let records_connector = self.clone();
+        let resolver = DnsResolver::new(
+            Arc::new(
+                move |host: &str,
+                      port: Option<u16>,
+                      record_type: RecordType|
+                      -> BoxFuture<'static, io::Result<Resolution>> {
+                    connector
+                        .resolve_host(host, port, record_type, false)
+                        .map_ok(|resolved| resolved.resolution)
+                        .boxed()
+                },
+            ),
+            Arc::new(move |name: &str| srv_connector.srv_targets(name)),
+            Arc::new(move |name: &str, record_type| {
+                records_connector.lookup_raw(name, record_type)
+            }),
+            self.record_type,
+            self.redaction,
+        );
+
+        if !self.reuse_connections {
+            return resolver;
+        }
+        let mut unpooled = self.clone();
+        unpooled.reuse_connections = false;
+        resolver.with_unpooled(unpooled.resolver())
+    }
+
+    /// Every target of the SRV records for `name`, in order of priority,
+    /// with their addresses resolved as hosts of requests are.
+    fn srv_targets(&self, name: &str) -> BoxFuture<'static, io::Result<Vec<SrvTarget>>>
+    where
+        C: Send + 'static,
+    {
+        let connector = self.clone();
+        let (host, redaction) = (name.to_string(), self.redaction);
+
+        let future = self
+            .lookup_raw(name, trust_dns::rr::RecordType::SRV)
+            .and_then(move |res| -> BoxFuture<'static, Result<_, _>> {
+                let mut srvs: Vec<(trust_dns::rr::rdata::SRV, u32)> = res
+                    .answers()
+                    .iter()
+                    .filter_map(|record| match *record.rdata() {
+                        trust_dns::rr::RData::SRV(ref srv) => Some((srv.clone(), record.ttl())),
+                        _ => None,
+                    })
+                    .collect();
+                if srvs.is_empty() {
+                    return future::err(Error::NoRecords(host).redact(redaction).into()).boxed();
+                }
+                srvs.retain(|(srv, _)| !srv.target().is_root());
+                srvs.sort_by_key(|(srv, _)| srv.priority());
+
+                let targets: Vec<_> = srvs
+                    .into_iter()
+                    .map(|(srv, ttl)| {
+                        let target = srv.target().to_string();
+                        connector
+                            .resolve_host(&target, Some(srv.port()), RecordType::A, false)
+                            .map(move |res| SrvTarget {
+                                target: target,
+                                priority: srv.priority(),
+                                weight: srv.weight(),
+                                port: srv.port(),
+                                ttl: ttl,
+                                addrs: res
+                                    .map(|resolved| resolved.resolution.addrs)
+                                    .unwrap_or_default(),
+                            })
+                    })
+                    .collect();
+                future::join_all(targets).map(Ok).boxed()
+            });
+
+        future.boxed()
+    }
+
+    /// Resolve `host` without DNS if it's an IP address, or `None` if it's
+    /// a name. Host policies, address filters and callbacks are given
+    /// `original`, the host before any rewriting.
+    fn resolve_literal(
+        &self,
+        original: &str,
+        host: &str,
+        port: Option<u16>,
+    ) -> Option<io::Result<Resolved>> {
+        let start = Instant::now();
+        let original = original.to_string();
+
+        let addr = match host.trim_matches(|c| c == '[' || c == ']').parse() {
+            Ok(addr) => addr,
+            Err(_) => return None,
+        };
+        let elapsed = start.elapsed();
+        let resolution = Resolution {
+            ip: addr,
+            addrs: vec![addr],
+            port: port,
+            record_type: None,
+            ttl: None,
+            nameserver: None,
+            chain: Vec::new(),
+        };
+
+        if let Err(e) = self.check_host_policy(original.clone()) {
+            return Some(Err(e.redact(self.redaction).into()));
+        }
+
+        if !self.address_filter.allows(&original, &addr) {
+            let err = Error::ForbiddenAddress(original.clone());
+            return Some(Err(err.redact(self.redaction).into()));
+        }
+
+        if let Err(e) = audit(&self.audit, &original, &resolution, self.redaction) {
+            return Some(Err(e));
+        }
+
+        if let Some(ref on_resolve) = self.on_resolve {
+            on_resolve(&original, &[addr], ResolutionSource::Literal, elapsed);
+        }
+
+        Some(Ok(Resolved {
+            host: host.to_string(),
+            source: ResolutionSource::Literal,
+            resolution: resolution,
+            elapsed: elapsed,
+        }))
+    }
+
+    /// Resolve `host`, with `record_type` queries for `port`, as for a
+    /// request. If `fresh`, cached entries are ignored and replaced, including
+    /// resolutions returned by interceptors' `pre_query`.
+    fn resolve_host(
+        &self,
+        host: &str,
+        port: Option<u16>,
+        record_type: RecordType,
+        fresh: bool,
+    ) -> Resolve {
+        if let Some(resolved) = self.resolve_literal(host, host, port) {
+            return Resolve::Ready(Some(resolved));
+        }
+
+        let start = Instant::now();
+        let original = host.to_string();
+
+        let host = match self.host_rewrite {
+            Some(ref rewrite) => self.normalize_host(&rewrite(&original)),
+            None => self.normalize_host(&original),
+        };
+
+        let host = match host {
+            Ok(host) => host,
+            Err(e) => return Resolve::Ready(Some(Err(e.into()))),
+        };
+        let fresh = self.forgotten.take(&host) || fresh;
+
+        // A host rewritten to an IP address needs no lookup.
+        if let Some(resolved) = self.resolve_literal(&original, &host, port) {
+            debug!("Host rewritten to {}", self.redaction.apply(&host));
+            return Resolve::Ready(Some(resolved));
+        }
+
+        let redacted = self.redaction.apply(&host).into_owned();
+        let span = Span::resolve(&redacted);
+        let _entered = span.enter();
+
+        let mut query = Query {
+            host: host,
+            port: port,
+            record_type: record_type,
+        };
+
+        let mut cached = None;
+        for interceptor in &self.interceptors {
+            match interceptor.pre_query(&mut query) {
+                Ok(None) => {}
+                Ok(Some(_)) if fresh => {}
+                Ok(Some(resolution)) => {
+                    cached = Some(resolution);
+                    break;
+                }
+                Err(e) => return Resolve::Ready(Some(Err(e))),
+            }
+        }
+
+        // Interceptors may have rewritten the host, which must be allowed too.
+        if let Err(e) = self.check_host_policy(query.host.clone()) {
+            return Resolve::Ready(Some(Err(e.redact(self.redaction).into())));
+        }
+
+        let source = if cached.is_some() {
+            ResolutionSource::Interceptor
+        } else {
+            ResolutionSource::Dns
+        };
+        span.record_source(source);
+
+        let lookup = match cached {
+            Some(resolution) => Lookup::Ready(Some(Ok(resolution))),
+            None => self.lookup(&query, fresh),
+        };
+
+        let resolving = Resolving {
+            lookup: lookup,
+            query: query,
+            original: original,
+            redacted: redacted,
+            source: source,
+            start: start,
+            interceptors: self.interceptors.clone(),
+            address_filter: self.address_filter.clone(),
+            reject_bogus_addresses: self.reject_bogus_addresses,
+            selection: self.selection(),
+            redaction: self.redaction,
+            audit: self.audit.clone(),
+            on_resolve: self.on_resolve.clone(),
+            on_error: self.on_error.clone(),
+            #[cfg(feature = "prometheus")]
+            prometheus: self.prometheus.clone(),
+            stats: self.stats.clone(),
+            slow_lookup: self.slow_lookup,
+            history: self.history.clone(),
+        };
+        Resolve::Resolving(span.instrument(resolving))
+    }
+}
+
+/// The resolution of a request's host, returned by
+/// `DnsConnector::resolve_host`.
+#[allow(clippy::large_enum_variant)]
+enum Resolve {
+    /// An IP address, or a host which failed before it could be looked up.
+    Ready(Option<io::Result<Resolved>>),
+    Resolving(Instrumented<Resolving>),
+}
+
+impl Future for Resolve {
+    type Output = io::Result<Resolved>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match *self {
+            Resolve::Ready(ref mut resolved) => {
+                Poll::Ready(resolved.take().expect("polled after completion"))
+            }
+            Resolve::Resolving(ref mut resolving) => Pin::new(resolving).poll(cx),
+        }
+    }
+}
+
+/// A lookup of a request's host, or its resolution by an interceptor, which
+/// is then filtered, audited and reported.
+struct Resolving {
+    lookup: Lookup,
+    query: Query,
+    original: String,
+    redacted: String,
+    source: ResolutionSource,
+    start: Instant,
+    interceptors: Vec<Arc<dyn Interceptor>>,
+    address_filter: AddressFilter,
+    reject_bogus_addresses: bool,
+    selection: Arc<dyn SelectionPolicy>,
+    redaction: Redaction,
+    audit: Option<Arc<AuditHook>>,
+    on_resolve: Option<Arc<ResolveCallback>>,
+    on_error: Option<Arc<ErrorCallback>>,
+    #[cfg(feature = "prometheus")]
+    prometheus: Option<prometheus::Registry>,
+    stats: Arc<Stats>,
+    slow_lookup: Option<Duration>,
+    history: Option<Arc<History>>,
+}
+
+impl Resolving {
+    /// Every resolution goes through the same steps, whether it came from
+    /// the DNS server or from an interceptor.
+    fn filter(&self, result: io::Result<Resolution>) -> io::Result<Resolution> {
+        let mut resolution = result?;
+        for interceptor in &self.interceptors {
+            interceptor.post_response(&self.query, &mut resolution)?;
+        }
+        filter_addresses(
+            &self.address_filter,
+            self.reject_bogus_addresses,
+            &*self.selection,
+            &self.query.host,
+            &mut resolution,
+        )
+        .map_err(|e| e.redact(self.redaction))?;
+        audit(&self.audit, &self.original, &resolution, self.redaction)?;
+        Ok(resolution)
+    }
+
+    /// Report the outcome of the resolution.
+    fn finish(&self, result: io::Result<Resolution>) -> io::Result<Resolved> {
+        let Resolving {
+            ref redacted,
+            source,
+            start,
+            ref on_resolve,
+            ref on_error,
+            #[cfg(feature = "prometheus")]
+            ref prometheus,
+            ref stats,
+            slow_lookup,
+            ref history,
+            ..
+        } = *self;
+        let host = self.query.host.clone();
+        let record_type = self.query.record_type;
+
+        let elapsed = start.elapsed();
+        let slow = slow_lookup.is_some_and(|threshold| elapsed > threshold);
+
+        match result {
+            Ok(ref resolution) => {
+                debug!("Resolved {} in {:?}", &redacted, elapsed);
+
+                if slow {
+                    warn!(
+                        "Slow lookup of {} took {:?}: source={:?} type={:?} server={:?} addrs={:?}",
+                        &redacted,
+                        elapsed,
+                        source,
+                        record_type,
+                        resolution.nameserver,
+                        resolution.addrs
+                    );
+                }
+
+                counters::lookup_succeeded(source, elapsed);
+                stats.resolved(&host, resolution);
+
+                #[cfg(feature = "prometheus")]
+                {
+                    if let Some(ref prometheus) = prometheus {
+                        prometheus.resolved(source, elapsed);
+                    }
+                }
+
+                if let Some(on_resolve) = on_resolve {
+                    on_resolve(&host, &resolution.addrs, source, elapsed);
+                }
+            }
+            Err(ref e) => {
+                if slow {
+                    warn!(
+                        "Slow lookup of {} took {:?} and failed: source={:?} type={:?}: {}",
+                        &redacted, elapsed, source, record_type, e
+                    );
+                }
+
+                counters::lookup_failed(e);
+                stats.failed(&host, e);
+
+                #[cfg(feature = "prometheus")]
+                {
+                    if let Some(ref prometheus) = prometheus {
+                        prometheus.failed(e);
+                    }
+                }
+
+                if let Some(on_error) = on_error {
+                    on_error(&host, e);
+                }
+            }
+        }
+
+        if let Some(history) = history {
+            history.push(HistoryEntry {
+                time: SystemTime::now(),
+                host: host.clone(),
+                source: source,
+                outcome: match result {
+                    Ok(ref resolution) => Ok(resolution.clone()),
+                    Err(ref e) => Err(e.to_string()),
+                },
+                duration: elapsed,
+            });
+        }
+
+        result.map(|resolution| Resolved {
+            host: host,
+            source: source,
+            resolution: resolution,
+            elapsed: elapsed,
+        })
+    }
+}
+
+impl Future for Resolving {
+    type Output = io::Result<Resolved>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let result = match Pin::new(&mut self.lookup).poll(cx) {
+            Poll::Ready(result) => result,
+            Poll::Pending => return Poll::Pending,
+        };
+        let result = self.filter(result);
+        Poll::Ready(self.finish(result))
+    }
+}
+
+/// The outcome of resolving a request's host.
+struct Resolved {
+    /// The host which was resolved, after any rewriting.
+    host: String,
+    source: ResolutionSource,
+    resolution: Resolution,
+    /// How long resolving the host took.
+    elapsed: Duration,
+}
+
+impl<C> DnsConnector<C>
+where
+    C: Service<Uri> + Send + 'static,
+    C::Response: Send + 'static,
+    C::Future: Send + 'static,
+    C::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    /// Connect for a request to `uri` as `call` does, but with a fresh lookup
+    /// of its host, ignoring and then replacing its pinned addresses,
+    /// NXDOMAIN backoff, memoized responses and any resolution interceptors
+    /// return from `pre_query`, e.g. to retry a request which failed after a
+    /// DNS failover. Other requests to the host are unaffected.
+    ///
+    /// This is only for callers connecting with the connector themselves,
+    /// such as in a retry loop of their own. Requests made through hyper's
+    /// `Client` always go through `call`, as hyper's `Connect` contract only
+    /// hands the connector a `Uri`, so use `forget` for those instead.
+    pub fn call_fresh(&self, uri: Uri) -> ConnectFuture<C> {
+        self.connect(uri, true)
+    }
+
+    fn connect(&self, uri: Uri, fresh: bool) -> ConnectFuture<C> {
+        let dialer = Dialer {
+            connector: self.connector.clone(),
+            uri: uri.clone(),
+            host: String::new(),
+            interceptors: self.interceptors.clone(),
+            selection: self.selection(),
+            blacklist: self.blacklist.clone(),
+            retry: self.retry_addresses,
+            race: self.connect_race,
+            happy_eyeballs: self.happy_eyeballs,
+            runtime: self.runtime.clone(),
+            redaction: self.redaction,
+            ipv6_zone: self.ipv6_zone.clone(),
+            elapsed: Duration::from_secs(0),
+        };
+
+        // IP addresses are resolved there and then, without boxing.
+        let host = uri.host().unwrap();
+        match self.resolve_literal(host, host, uri.port_u16()) {
+            Some(resolved) => ConnectFuture::resolved(resolved, dialer, uri),
+            None => ConnectFuture::resolving(self.resolve(&uri, fresh), dialer, uri),
+        }
+    }
+}
+
+/// Connects to the addresses each request's host resolves to with the
+/// wrapped connector, so that the connector can be used by hyper's `Client`.
+///
+/// The connector is ready once fewer than the `set_max_pending_lookups`
+/// limit of lookups are in flight.
+impl<C> Service<Uri> for DnsConnector<C>
+where
+    C: Service<Uri> + Send + 'static,
+    C::Response: Send + 'static,
+    C::Future: Send + Unpin + 'static,
+    C::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    type Response = Connection<C::Response>;
+    type Error = io::Error;
+    type Future = ConnectFuture<C>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        DnsConnector::poll_ready(self, cx).map(Ok)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        self.connect(uri, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "json")]
+    use crate::backend::response;
+    use crate::testing::StaticResolver;
+    #[cfg(feature = "json")]
+    use crate::Redaction;
+    use crate::{query_error, Connection, DnsConnector, Error, Interceptor, Query, Resolution};
+    use futures::executor::block_on;
+    use futures::future;
+    #[cfg(feature = "json")]
+    use futures::future::{BoxFuture, FutureExt};
+    use futures::stream::TryStreamExt;
+    use hyper::service::Service;
+    use hyper::Uri;
+    use std::io;
+    use std::net::IpAddr;
+    #[cfg(feature = "json")]
+    use std::str::FromStr;
+    use std::task::{Context, Poll};
+    use std::time::Duration;
+    use trust_dns::error::ClientError;
+    use trust_dns::op::ResponseCode;
+    #[cfg(feature = "json")]
+    use trust_dns::rr::{Name, RData, Record, RecordType};
+    #[cfg(feature = "json")]
+    use trust_dns_proto::xfer::DnsResponse;
+
+    struct Noop;
+
+    impl Service<Uri> for Noop {
+        type Response = ();
+        type Error = io::Error;
+        type Future = future::Ready<io::Result<()>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _uri: Uri) -> Self::Future {
+            future::ok(())
+        }
+    }
+
+    /// Answers every query with the same resolution.
+    struct Fixed(Resolution);
+
+    impl Interceptor for Fixed {
+        fn pre_query(&self, _query: &mut Query) -> io::Result<Option<Resolution>> {
+            Ok(Some(self.0.clone()))
+        }
+    }
+
+    /// Rewrites every query to the same host.
+    struct Rewrite(&'static str);
+
+    /// Answers queries for `vetoed.` names, fails those for `transport.`
+    /// names with an error mentioning them, and answers others with a
+    /// response to a different question.
+    #[cfg(feature = "json")]
+    struct Leaky;
+
+    #[cfg(feature = "json")]
+    impl crate::Backend for Leaky {
+        fn query(
+            &self,
+            _server: std::net::SocketAddr,
+            name: Name,
+            record_type: RecordType,
+        ) -> BoxFuture<'static, io::Result<DnsResponse>> {
+            let host = name.to_string();
+            if host.starts_with("transport.") {
+                let err = Error::Transport(format!("connection reset querying {}", host));
+                return future::err(err.into()).boxed();
+            }
+
+            let (owner, answers) = if host.starts_with("vetoed.") {
+                let addr = RData::A("192.0.2.1".parse().unwrap());
+                (name.clone(), vec![Record::from_rdata(name, 60, addr)])
+            } else {
+                (
+                    Name::from_str(&format!("other.{}", host)).unwrap(),
+                    Vec::new(),
+                )
+            };
+            future::ok(response(owner, record_type, ResponseCode::NoError, answers)).boxed()
+        }
+    }
+
+    impl Interceptor for Rewrite {
+        fn pre_query(&self, query: &mut Query) -> io::Result<Option<Resolution>> {
+            query.host = self.0.to_string();
+            Ok(None)
+        }
+    }
+
+    #[test]
+    fn it_works() {}
+
+    #[test]
+    fn streams_addresses_as_a_request_would_resolve_them() {
+        let addrs: Vec<IpAddr> = vec!["192.0.2.1".parse().unwrap(), "192.0.2.2".parse().unwrap()];
+        let mut connector = DnsConnector::new("127.0.0.1:53".parse().unwrap(), ());
+        connector.add_interceptor(Fixed(Resolution {
+            ip: addrs[1],
+            addrs: addrs.clone(),
+            port: None,
+            record_type: None,
+            ttl: None,
+            nameserver: None,
+            chain: Vec::new(),
+        }));
+
+        assert_eq!(
+            block_on(
+                connector
+                    .resolve_stream("example.com")
+                    .try_collect::<Vec<_>>()
+            )
+            .unwrap(),
+            vec![addrs[1], addrs[0]]
+        );
+        assert_eq!(
+            block_on(connector.resolve_stream("[::1]").try_collect::<Vec<_>>()).unwrap(),
+            vec!["::1".parse::<IpAddr>().unwrap()]
+        );
+    }
+
+    #[test]
+    fn stream_and_many_follow_srv_records_as_requests_do() {
+        let resolver = StaticResolver::new();
+        resolver.add_ip("web.example.com", "192.0.2.1".parse().unwrap());
+        resolver.add_srv("svc.example.com", 0, 0, 8080, "web.example.com");
+        let connector = resolver.connector(());
+
+        assert_eq!(
+            block_on(
+                connector
+                    .resolve_stream("svc.example.com")
+                    .try_collect::<Vec<_>>()
+            )
+            .unwrap(),
+            vec!["192.0.2.1".parse::<IpAddr>().unwrap()]
+        );
+        let resolutions = block_on(connector.resolve_many(&["svc.example.com"])).unwrap();
+        let resolution = resolutions["svc.example.com"].as_ref().unwrap();
+        assert_eq!(resolution.ip, "192.0.2.1".parse::<IpAddr>().unwrap());
+        assert_eq!(resolution.port, Some(8080));
+    }
+
+    #[test]
+    fn rejects_private_addresses_from_interceptors() {
+        let mut connector = DnsConnector::new("127.0.0.1:53".parse().unwrap(), ());
+        connector.set_reject_private_addresses(true);
+        connector.add_interceptor(Fixed(Resolution {
+            ip: "127.0.0.1".parse().unwrap(),
+            addrs: vec!["127.0.0.1".parse().unwrap()],
+            port: None,
+            record_type: None,
+            ttl: None,
+            nameserver: None,
+            chain: Vec::new(),
+        }));
+
+        let err = block_on(
+            connector
+                .resolve_stream("example.com")
+                .try_collect::<Vec<_>>(),
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        match err.get_ref().and_then(|e| e.downcast_ref::<Error>()) {
+            Some(Error::ForbiddenAddress(host)) => assert_eq!(host, "example.com"),
+            other => panic!("unexpected error: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_bogus_addresses_from_interceptors() {
+        let mut connector = DnsConnector::new("127.0.0.1:53".parse().unwrap(), ());
+        connector.set_reject_bogus_addresses(true);
+        connector.add_interceptor(Fixed(Resolution {
+            ip: "0.0.0.0".parse().unwrap(),
+            addrs: vec!["0.0.0.0".parse().unwrap(), "192.0.2.1".parse().unwrap()],
+            port: None,
+            record_type: None,
+            ttl: None,
+            nameserver: None,
+            chain: Vec::new(),
+        }));
+
+        assert_eq!(
+            block_on(
+                connector
+                    .resolve_stream("example.com")
+                    .try_collect::<Vec<_>>()
+            )
+            .unwrap(),
+            vec!["192.0.2.1".parse::<IpAddr>().unwrap()]
+        );
+    }
+
+    #[test]
+    fn filters_addresses_from_the_server() {
+        let resolver = StaticResolver::new();
+        resolver.add_ip("web.example.com", "0.0.0.0".parse().unwrap());
+        resolver.add_ip("web.example.com", "192.0.2.1".parse().unwrap());
+        resolver.add_ip("web.example.com", "192.0.2.2".parse().unwrap());
+        resolver.add_ip("bogus.example.com", "0.0.0.0".parse().unwrap());
+        let mut connector = DnsConnector::new_with_resolve_type(
+            "127.0.0.1:53".parse().unwrap(),
+            (),
+            crate::RecordType::A,
+        );
+        connector.set_backend(resolver);
+        connector.set_reject_bogus_addresses(true);
+        connector.set_address_filter(|addr, _| addr != "192.0.2.2".parse::<IpAddr>().unwrap());
+
+        let resolutions =
+            block_on(connector.resolve_many(&["web.example.com", "bogus.example.com"])).unwrap();
+        let resolution = resolutions["web.example.com"].as_ref().unwrap();
+        assert_eq!(resolution.ip, "192.0.2.1".parse::<IpAddr>().unwrap());
+        assert_eq!(resolution.addrs, vec![resolution.ip]);
+        let err = resolutions["bogus.example.com"].as_ref().unwrap_err();
+        match err.get_ref().and_then(|e| e.downcast_ref::<Error>()) {
+            Some(Error::BogusAddress(host)) => assert_eq!(host, "bogus.example.com"),
+            other => panic!("unexpected error: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn checks_host_policy_after_interceptors() {
+        let mut connector = DnsConnector::new("127.0.0.1:53".parse().unwrap(), ());
+        connector.set_host_policy(Vec::<&str>::new(), vec![".internal"]);
+        connector.add_interceptor(Rewrite("db.internal"));
+
+        let err = block_on(
+            connector
+                .resolve_stream("example.com")
+                .try_collect::<Vec<_>>(),
+        )
+        .unwrap_err();
+        match err.get_ref().and_then(|e| e.downcast_ref::<Error>()) {
+            Some(Error::HostDenied(host)) => assert_eq!(host, "db.internal"),
+            other => panic!("unexpected error: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn refused_queries_are_rejected_without_failing_the_server() {
+        let resolver = StaticResolver::new();
+        resolver.set_response_code("web.example.com", ResponseCode::Refused);
+        let connector = resolver.connector(());
+
+        let err = block_on(
+            connector
+                .resolve_stream("web.example.com")
+                .try_collect::<Vec<_>>(),
+        )
+        .unwrap_err();
+        match err.get_ref().and_then(|e| e.downcast_ref::<Error>()) {
+            Some(Error::Rejected(host, ResponseCode::Refused)) => {
+                assert_eq!(host, "web.example.com.")
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+
+        assert_eq!(connector.failure_counts()["rejected"], 1);
+        assert!(!connector.failure_counts().contains_key("servfail"));
+        let health = connector.nameserver_health();
+        assert!(!health.is_empty());
+        for health in health.values() {
+            assert_eq!(health.consecutive_failures, 0);
+            assert!(health.healthy());
+        }
+    }
+
+    #[test]
+    fn classifies_timed_out_io_errors_as_timeouts() {
+        let err = ClientError::from(io::Error::new(io::ErrorKind::TimedOut, "timed out"));
+        match query_error(&err) {
+            Error::Timeout => {}
+            other => panic!("unexpected error: {:?}", other),
+        }
+
+        let err = ClientError::from(io::Error::other("refused"));
+        match query_error(&err) {
+            Error::Transport(_) => {}
+            other => panic!("unexpected error: {:?}", other),
+        }
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn snapshots_redact_hosts_in_every_error() {
+        let mut connector = DnsConnector::new("127.0.0.1:53".parse().unwrap(), Noop);
+        connector.set_backend(Leaky);
+        connector.set_redaction(Redaction::Hash([7; 16]));
+        connector.set_audit_hook(|host, _| Err(format!("{} is not allowed", host)));
+
+        for uri in &[
+            "http://secret.example.com/",
+            "http://transport.secret.example.com/",
+            "http://vetoed.secret.example.com/",
+        ] {
+            let err = block_on(connector.call(uri.parse().unwrap())).unwrap_err();
+            assert!(!err.to_string().contains("secret"), "{}", err);
+        }
+
+        let snapshot = connector.snapshot_json();
+        let errors = snapshot["recent_errors"].as_array().unwrap();
+        assert_eq!(errors.len(), 3);
+        for error in errors {
+            let error = error["error"].as_str().unwrap();
+            assert!(!error.contains("secret"), "{}", error);
+        }
+        let last_error = snapshot["nameservers"][0]["last_error"].as_str().unwrap();
+        assert!(!last_error.contains("secret"), "{}", last_error);
+        assert!(!snapshot.to_string().contains("secret"), "{}", snapshot);
+    }
+
+    #[cfg(any(feature = "tokio", feature = "async-std", feature = "smol"))]
+    #[test]
+    fn forgotten_hosts_are_looked_up_afresh_by_hyper_clients() {
+        use hyper::client::HttpConnector;
+        use hyper::service::{make_service_fn, service_fn};
+        use hyper::{Body, Client, Response, Server};
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let server =
+                Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make_service_fn(|_| async {
+                    Ok::<_, io::Error>(service_fn(|_| async {
+                        Ok::<_, io::Error>(Response::new(Body::empty()))
+                    }))
+                }));
+            let addr = server.local_addr();
+            tokio::spawn(server);
+
+            let resolver = StaticResolver::new();
+            let mut connector = resolver.connector(HttpConnector::new());
+            connector.set_nxdomain_backoff(Some(Duration::from_secs(3600)));
+            let client = Client::builder().build::<_, Body>(connector.clone());
+            let uri: Uri = format!("http://web.example.com:{}/", addr.port())
+                .parse()
+                .unwrap();
+
+            // The host is backed off from once it doesn't exist, even after
+            // it is added.
+            assert!(client.get(uri.clone()).await.is_err());
+            resolver.add_ip("web.example.com", addr.ip());
+            assert!(client.get(uri.clone()).await.is_err());
+
+            connector.forget("web.example.com");
+            assert!(client.get(uri.clone()).await.unwrap().status().is_success());
+        });
+    }
+
+    #[test]
+    fn fresh_calls_bypass_pins_for_that_request_only() {
+        let resolver = StaticResolver::new();
+        resolver.add_ip("web.example.com", "192.0.2.1".parse().unwrap());
+        let mut connector = resolver.connector(Noop);
+        connector.set_rebinding_protection(Some(Duration::from_secs(60)));
+        let uri: Uri = "http://web.example.com:80/".parse().unwrap();
+        let ip = |connection: Connection<()>| connection.resolution().ip.to_string();
+
+        assert_eq!(
+            ip(block_on(connector.call(uri.clone())).unwrap()),
+            "192.0.2.1"
+        );
+        resolver.remove("web.example.com");
+        resolver.add_ip("web.example.com", "192.0.2.2".parse().unwrap());
+        assert_eq!(
+            ip(block_on(connector.call(uri.clone())).unwrap()),
+            "192.0.2.1"
+        );
+
+        assert_eq!(
+            ip(block_on(connector.call_fresh(uri.clone())).unwrap()),
+            "192.0.2.2"
+        );
+        assert_eq!(ip(block_on(connector.call(uri)).unwrap()), "192.0.2.2");
+    }
 }
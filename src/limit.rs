@@ -0,0 +1,168 @@
+//! A semaphore bounding how many queries are in flight at once.
+
+use futures::channel::oneshot;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+/// Allows up to a fixed number of queries in flight, queueing the rest in
+/// the order they were made.
+#[derive(Debug)]
+pub struct ConcurrencyLimit {
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    available: usize,
+    waiters: VecDeque<oneshot::Sender<()>>,
+}
+
+impl ConcurrencyLimit {
+    pub fn new(max: usize) -> ConcurrencyLimit {
+        ConcurrencyLimit {
+            state: Mutex::new(State {
+                available: max,
+                waiters: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Wait for a permit to send a query, which is held until it is dropped.
+    pub fn acquire(limit: &Arc<ConcurrencyLimit>) -> Acquire {
+        let mut state = limit.state.lock().unwrap();
+        let waiting = if state.available > 0 {
+            state.available -= 1;
+            None
+        } else {
+            let (sender, receiver) = oneshot::channel();
+            state.waiters.push_back(sender);
+            Some(receiver)
+        };
+
+        Acquire {
+            limit: limit.clone(),
+            waiting: waiting,
+            done: false,
+        }
+    }
+
+    /// Hand a permit to the longest waiting caller, or make it available if
+    /// there are none.
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        while let Some(waiter) = state.waiters.pop_front() {
+            if waiter.send(()).is_ok() {
+                return;
+            }
+        }
+        state.available += 1;
+    }
+}
+
+/// A future resolving to a `Permit` once one is available.
+#[derive(Debug)]
+pub struct Acquire {
+    limit: Arc<ConcurrencyLimit>,
+    waiting: Option<oneshot::Receiver<()>>,
+    done: bool,
+}
+
+impl Future for Acquire {
+    type Output = io::Result<Permit>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<Permit>> {
+        if let Some(ref mut waiting) = self.waiting {
+            match Pin::new(waiting).poll(cx) {
+                Poll::Ready(Ok(())) => {}
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Err(_)) => {
+                    return Poll::Ready(Err(io::Error::other("Concurrency limit dropped")))
+                }
+            }
+        }
+
+        self.done = true;
+        Poll::Ready(Ok(Permit {
+            limit: self.limit.clone(),
+        }))
+    }
+}
+
+impl Drop for Acquire {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+
+        // Pass on a permit which was handed to this caller but never taken.
+        let handed = match self.waiting {
+            Some(ref mut waiting) => {
+                waiting.close();
+                matches!(waiting.try_recv(), Ok(Some(())))
+            }
+            None => true,
+        };
+        if handed {
+            self.limit.release();
+        }
+    }
+}
+
+/// Permission to have a query in flight, released when dropped.
+#[derive(Debug)]
+pub struct Permit {
+    limit: Arc<ConcurrencyLimit>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.limit.release();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use futures::future;
+
+    /// Whether `acquire` is still waiting for a permit.
+    fn waiting(acquire: &mut Acquire) -> bool {
+        block_on(future::poll_fn(|cx| {
+            Poll::Ready(Pin::new(&mut *acquire).poll(cx).is_pending())
+        }))
+    }
+
+    #[test]
+    fn queues_excess_callers() {
+        let limit = Arc::new(ConcurrencyLimit::new(1));
+
+        let first = block_on(ConcurrencyLimit::acquire(&limit)).unwrap();
+        let mut second = ConcurrencyLimit::acquire(&limit);
+        let mut third = ConcurrencyLimit::acquire(&limit);
+        assert!(waiting(&mut second));
+
+        drop(first);
+        let second = block_on(second).unwrap();
+        assert!(waiting(&mut third));
+
+        drop(second);
+        block_on(third).unwrap();
+    }
+
+    #[test]
+    fn abandoned_callers_pass_on_permits() {
+        let limit = Arc::new(ConcurrencyLimit::new(1));
+
+        let first = block_on(ConcurrencyLimit::acquire(&limit)).unwrap();
+        let abandoned = ConcurrencyLimit::acquire(&limit);
+        drop(first);
+        drop(abandoned);
+
+        block_on(ConcurrencyLimit::acquire(&limit)).unwrap();
+    }
+}
@@ -0,0 +1,47 @@
+//! Resolution of single-label hosts with LLMNR (RFC 4795), enabled by the
+//! `llmnr` feature.
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use futures::future::{BoxFuture, FutureExt, TryFutureExt};
+use trust_dns::error::ClientError;
+use trust_dns::multicast::{MdnsClientStream, MdnsQueryType};
+use trust_dns_proto::xfer::DnsExchange;
+use trust_dns_proto::TokioTime;
+
+use crate::runtime;
+
+/// The IPv4 LLMNR group, which queries are sent to.
+pub fn addr() -> SocketAddr {
+    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(224, 0, 0, 252)), 5355)
+}
+
+/// Whether `host` is a single label, such as `fileserver`.
+pub fn is_single_label(host: &str) -> bool {
+    let host = host.trim_end_matches('.');
+    !host.is_empty() && !host.contains('.')
+}
+
+/// A client which sends queries to the LLMNR group, which are answered by
+/// unicast. LLMNR messages have the same format as DNS ones.
+pub fn client() -> BoxFuture<'static, Result<DnsExchange, ClientError>> {
+    let (stream, sender) = MdnsClientStream::new(addr(), MdnsQueryType::OneShot, None, None, None);
+    runtime::exchange::<_, TokioTime, _>(stream.boxed(), sender, |background| {
+        tokio::spawn(background);
+    })
+    .map_err(ClientError::from)
+    .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_label_hosts() {
+        assert!(is_single_label("fileserver"));
+        assert!(is_single_label("fileserver."));
+        assert!(!is_single_label("fileserver.example.com"));
+        assert!(!is_single_label(""));
+    }
+}
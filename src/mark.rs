@@ -0,0 +1,83 @@
+//! Marking the sockets of connections to DNS servers with `SO_MARK`, so that
+//! policy routing can steer them differently from other traffic.
+
+use futures::future::{self, BoxFuture, FutureExt};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::{TcpSocket, TcpStream as TokioTcpStream};
+use tokio::time;
+use trust_dns::tcp::{TcpClientStream, TcpStream};
+use trust_dns_proto::error::ProtoError;
+use trust_dns_proto::iocompat::AsyncIoTokioAsStd;
+use trust_dns_proto::xfer::DnsStreamHandle;
+use trust_dns_proto::{BufDnsStreamHandle, BufStreamHandle};
+
+/// A stream connected with tokio, as trust-dns' own TCP streams are.
+pub type MarkedClientStream = TcpClientStream<AsyncIoTokioAsStd<TokioTcpStream>>;
+
+/// The connecting stream and its handle, as trust-dns'
+/// `TcpClientStream::with_timeout` returns them.
+type MarkedStream = (
+    BoxFuture<'static, Result<MarkedClientStream, ProtoError>>,
+    Box<dyn DnsStreamHandle + Send>,
+);
+
+/// A stream to `server` whose socket has `SO_MARK` set to `mark` before it
+/// connects, which trust-dns' own streams can't be given.
+pub fn stream(server: SocketAddr, timeout: Duration, mark: u32) -> MarkedStream {
+    let (message_sender, outbound_messages) = BufStreamHandle::create();
+    let sender = Box::new(BufDnsStreamHandle::new(server, message_sender));
+
+    let socket = match socket(server, mark) {
+        Ok(socket) => socket,
+        Err(e) => return (future::err(e.into()).boxed(), sender),
+    };
+
+    let stream = async move {
+        let socket = TcpSocket::from_std_stream(socket.into());
+        let tcp = match time::timeout(timeout, socket.connect(server)).await {
+            Ok(tcp) => tcp?,
+            Err(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("timed out connecting to: {}", server),
+                )
+                .into())
+            }
+        };
+        Ok(TcpClientStream::from_stream(
+            TcpStream::from_stream_with_receiver(AsyncIoTokioAsStd(tcp), server, outbound_messages),
+        ))
+    };
+    (stream.boxed(), sender)
+}
+
+/// An unconnected TCP socket for `server` with `SO_MARK` set to `mark`.
+fn socket(server: SocketAddr, mark: u32) -> io::Result<Socket> {
+    let socket = Socket::new(
+        Domain::for_address(server),
+        Type::STREAM,
+        Some(Protocol::TCP),
+    )?;
+    socket.set_mark(mark)?;
+    socket.set_nonblocking(true)?;
+    Ok(socket)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sets_the_mark_on_the_socket() {
+        let server = "127.0.0.1:53".parse().unwrap();
+        match socket(server, 42) {
+            Ok(socket) => assert_eq!(socket.mark().unwrap(), 42),
+            // Setting the mark needs CAP_NET_ADMIN.
+            Err(ref e) if e.kind() == io::ErrorKind::PermissionDenied => {}
+            Err(e) => panic!("{}", e),
+        }
+    }
+}
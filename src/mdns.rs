@@ -0,0 +1,49 @@
+//! Resolution of `.local` hosts with multicast DNS (RFC 6762), enabled by
+//! the `mdns` feature.
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use futures::future::{BoxFuture, FutureExt, TryFutureExt};
+use trust_dns::error::ClientError;
+use trust_dns::multicast::{MdnsClientStream, MdnsQueryType};
+use trust_dns_proto::xfer::DnsExchange;
+use trust_dns_proto::TokioTime;
+
+use crate::runtime;
+
+/// The IPv4 mDNS group, which queries for `.local` hosts are sent to.
+pub fn addr() -> SocketAddr {
+    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(224, 0, 0, 251)), 5353)
+}
+
+/// Whether `host` is in the `.local` domain.
+pub fn is_local(host: &str) -> bool {
+    host.trim_end_matches('.')
+        .to_ascii_lowercase()
+        .ends_with(".local")
+}
+
+/// A client which sends one-shot queries to the mDNS group, which are
+/// answered by unicast.
+pub fn client() -> BoxFuture<'static, Result<DnsExchange, ClientError>> {
+    let (stream, sender) = MdnsClientStream::new_ipv4(MdnsQueryType::OneShot, None, None);
+    runtime::exchange::<_, TokioTime, _>(stream.boxed(), sender, |background| {
+        tokio::spawn(background);
+    })
+    .map_err(ClientError::from)
+    .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local_hosts() {
+        assert!(is_local("printer.local"));
+        assert!(is_local("Printer.LOCAL."));
+        assert!(!is_local("local"));
+        assert!(!is_local("printer.local.example.com"));
+        assert!(!is_local("printerlocal"));
+    }
+}
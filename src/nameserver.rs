@@ -0,0 +1,87 @@
+//! Choosing which of the configured nameservers to send a lookup to.
+
+use rand::Rng;
+use std::net::SocketAddr;
+
+use crate::SharedRng;
+
+/// Nameservers which lookups are spread across in proportion to their
+/// weights.
+#[derive(Debug, Clone)]
+pub struct Nameservers {
+    servers: Vec<(SocketAddr, u32)>,
+}
+
+impl Nameservers {
+    pub fn new(servers: Vec<(SocketAddr, u32)>) -> Nameservers {
+        Nameservers { servers: servers }
+    }
+
+    /// The nameservers and their weights.
+    pub fn servers(&self) -> &[(SocketAddr, u32)] {
+        &self.servers
+    }
+
+    /// Pick the nameserver for a lookup, or `None` if there are no servers
+    /// with a non-zero weight.
+    pub fn pick(&self, rng: &SharedRng) -> Option<SocketAddr> {
+        // Summed as u64 so that large weights can't overflow.
+        let total: u64 = self
+            .servers
+            .iter()
+            .map(|&(_, weight)| u64::from(weight))
+            .sum();
+        if total == 0 {
+            return None;
+        }
+
+        let mut point = rng.with(|rng| rng.gen_range(0, total));
+        for &(addr, weight) in &self.servers {
+            if point < u64::from(weight) {
+                return Some(addr);
+            }
+            point -= u64::from(weight);
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn honours_weights() {
+        let local: SocketAddr = "127.0.0.1:53".parse().unwrap();
+        let central: SocketAddr = "10.0.0.1:53".parse().unwrap();
+        let unused: SocketAddr = "10.0.0.2:53".parse().unwrap();
+        let nameservers = Nameservers::new(vec![(local, 9), (unused, 0), (central, 1)]);
+        let rng = SharedRng::seeded(1);
+
+        let picks: Vec<SocketAddr> = (0..1000).map(|_| nameservers.pick(&rng).unwrap()).collect();
+        let local_picks = picks.iter().filter(|addr| **addr == local).count();
+
+        assert!(!picks.contains(&unused));
+        assert!(local_picks > 850 && local_picks < 950);
+    }
+
+    #[test]
+    fn no_servers_with_weight() {
+        let nameservers = Nameservers::new(vec![("127.0.0.1:53".parse().unwrap(), 0)]);
+        assert_eq!(nameservers.pick(&SharedRng::thread()), None);
+    }
+
+    #[test]
+    fn large_weights_do_not_overflow() {
+        let first: SocketAddr = "10.0.0.1:53".parse().unwrap();
+        let second: SocketAddr = "10.0.0.2:53".parse().unwrap();
+        let nameservers = Nameservers::new(vec![(first, u32::MAX), (second, u32::MAX)]);
+        let rng = SharedRng::seeded(1);
+
+        let picks: Vec<SocketAddr> = (0..100).map(|_| nameservers.pick(&rng).unwrap()).collect();
+
+        assert!(picks.contains(&first));
+        assert!(picks.contains(&second));
+    }
+}
@@ -0,0 +1,96 @@
+//! Tracking of lookups in flight, for backpressure.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use crate::counters;
+
+/// The number of lookups in flight, and the tasks waiting for it to drop.
+#[derive(Debug, Default)]
+pub struct Pending {
+    count: AtomicUsize,
+    waiters: Mutex<Vec<Waker>>,
+}
+
+impl Pending {
+    /// Count a lookup as in flight until the returned guard is dropped.
+    pub fn start(pending: &Arc<Pending>) -> InFlight {
+        let count = pending.count.fetch_add(1, Ordering::SeqCst) + 1;
+        counters::lookups_in_flight(count);
+        InFlight {
+            pending: pending.clone(),
+        }
+    }
+
+    /// How many lookups are in flight.
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    /// Whether fewer than `max` lookups are in flight. If not, the current
+    /// task is notified when a lookup finishes.
+    pub fn poll_ready(&self, cx: &mut Context<'_>, max: usize) -> Poll<()> {
+        if self.count() < max {
+            return Poll::Ready(());
+        }
+
+        let mut waiters = self.waiters.lock().unwrap();
+        if !waiters.iter().any(|waker| waker.will_wake(cx.waker())) {
+            waiters.push(cx.waker().clone());
+        }
+
+        // Check again in case a lookup finished before the task was added.
+        if self.count() < max {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// A lookup in flight.
+#[derive(Debug)]
+pub struct InFlight {
+    pending: Arc<Pending>,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        let count = self.pending.count.fetch_sub(1, Ordering::SeqCst) - 1;
+        counters::lookups_in_flight(count);
+
+        for waker in self.pending.waiters.lock().unwrap().drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use futures::future;
+
+    #[test]
+    fn counts_lookups_in_flight() {
+        let pending = Arc::new(Pending::default());
+        let first = Pending::start(&pending);
+        let second = Pending::start(&pending);
+        assert_eq!(pending.count(), 2);
+
+        let ready = || {
+            block_on(future::poll_fn(|cx| {
+                Poll::Ready(pending.poll_ready(cx, 2).is_ready())
+            }))
+        };
+        assert!(!ready());
+
+        drop(first);
+        assert_eq!(pending.count(), 1);
+        assert!(ready());
+
+        drop(second);
+        assert_eq!(pending.count(), 0);
+    }
+}
@@ -0,0 +1,167 @@
+//! Pinning of resolutions, to protect against DNS rebinding.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::filter::is_private;
+use crate::time::Instant;
+use crate::{RecordType, Resolution};
+
+/// How many hosts resolutions are pinned for, after which the pin which
+/// expired longest ago is dropped to make room for a new one.
+const MAX_PINS: usize = 10_000;
+
+/// The resolution most recently used for each host.
+#[derive(Debug)]
+pub struct Pins {
+    duration: Duration,
+    pins: Mutex<HashMap<(String, RecordType), Pin>>,
+}
+
+#[derive(Debug)]
+struct Pin {
+    resolution: Resolution,
+    until: Instant,
+}
+
+impl Pins {
+    pub fn new(duration: Duration) -> Pins {
+        Pins {
+            duration: duration,
+            pins: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The resolution pinned for `host`, if it hasn't expired.
+    pub fn get(&self, host: &str, record_type: RecordType) -> Option<Resolution> {
+        let pins = self.pins.lock().unwrap();
+        pins.get(&(host.to_string(), record_type))
+            .filter(|pin| pin.until > Instant::now())
+            .map(|pin| pin.resolution.clone())
+    }
+
+    /// Pin `resolution` for `host`, unless it looks like a rebinding attack:
+    /// the host was last resolved to a public address, and now resolves to
+    /// private ones. Returns whether it was pinned.
+    ///
+    /// Rebinding is only looked for until the previous pin has been expired
+    /// for another period, after which the host is pinned to whatever it now
+    /// resolves to.
+    pub fn pin(&self, host: &str, record_type: RecordType, resolution: &Resolution) -> bool {
+        let mut pins = self.pins.lock().unwrap();
+        let key = (host.to_string(), record_type);
+        let now = Instant::now();
+
+        if let Some(pin) = pins.get(&key) {
+            let stale = pin.until + self.duration <= now;
+            if !stale && !is_private(&pin.resolution.ip) && resolution.addrs.iter().any(is_private)
+            {
+                return false;
+            }
+        }
+
+        // Expired pins are kept for another period to catch rebinding after
+        // they expire, and are dropped once there are too many.
+        if pins.len() >= MAX_PINS && !pins.contains_key(&key) {
+            let duration = self.duration;
+            pins.retain(|_, pin| pin.until + duration > now);
+            if pins.len() >= MAX_PINS {
+                let oldest = pins
+                    .iter()
+                    .min_by_key(|&(_, pin)| pin.until)
+                    .map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    pins.remove(&oldest);
+                }
+            }
+        }
+
+        pins.insert(
+            key,
+            Pin {
+                resolution: resolution.clone(),
+                until: now + self.duration,
+            },
+        );
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::IpAddr;
+
+    fn resolution(addr: &str) -> Resolution {
+        let addr: IpAddr = addr.parse().unwrap();
+        Resolution {
+            ip: addr,
+            addrs: vec![addr],
+            port: None,
+            record_type: Some(RecordType::A),
+            ttl: Some(0),
+            nameserver: None,
+            chain: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn pins_resolutions() {
+        let pins = Pins::new(Duration::from_secs(60));
+        assert!(pins.get("example.com", RecordType::A).is_none());
+
+        assert!(pins.pin("example.com", RecordType::A, &resolution("93.184.216.34")));
+        let pinned = pins.get("example.com", RecordType::A).unwrap();
+        assert_eq!(pinned.ip, "93.184.216.34".parse::<IpAddr>().unwrap());
+        assert!(pins.get("example.com", RecordType::SRV).is_none());
+    }
+
+    /// Make the pin for `host` look as if it expired `ago`.
+    fn expire(pins: &Pins, host: &str, ago: Duration) {
+        let mut pins = pins.pins.lock().unwrap();
+        pins.get_mut(&(host.to_string(), RecordType::A))
+            .unwrap()
+            .until = Instant::now() - ago;
+    }
+
+    #[test]
+    fn rejects_rebinding() {
+        let pins = Pins::new(Duration::from_secs(60));
+        assert!(pins.pin("example.com", RecordType::A, &resolution("93.184.216.34")));
+        expire(&pins, "example.com", Duration::from_secs(1));
+        assert!(pins.get("example.com", RecordType::A).is_none());
+        assert!(!pins.pin("example.com", RecordType::A, &resolution("127.0.0.1")));
+        assert!(pins.pin("example.com", RecordType::A, &resolution("93.184.216.35")));
+
+        assert!(pins.pin("internal", RecordType::A, &resolution("10.0.0.1")));
+        assert!(pins.pin("internal", RecordType::A, &resolution("10.0.0.2")));
+    }
+
+    #[test]
+    fn refreshes_stale_pins() {
+        let pins = Pins::new(Duration::from_secs(60));
+        assert!(pins.pin("example.com", RecordType::A, &resolution("93.184.216.34")));
+        expire(&pins, "example.com", Duration::from_secs(61));
+        assert!(pins.pin("example.com", RecordType::A, &resolution("10.0.0.1")));
+
+        let pinned = pins.get("example.com", RecordType::A).unwrap();
+        assert_eq!(pinned.ip, "10.0.0.1".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn drops_oldest_pins_when_full() {
+        let pins = Pins::new(Duration::from_secs(60));
+        for i in 0..MAX_PINS {
+            assert!(pins.pin(
+                &format!("host{}", i),
+                RecordType::A,
+                &resolution("10.0.0.1")
+            ));
+        }
+
+        assert!(pins.pin("new", RecordType::A, &resolution("10.0.0.1")));
+        assert_eq!(pins.pins.lock().unwrap().len(), MAX_PINS);
+        assert!(pins.get("new", RecordType::A).is_some());
+    }
+}
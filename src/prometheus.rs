@@ -0,0 +1,178 @@
+//! Resolver metrics in the Prometheus text format, enabled by the
+//! `prometheus` feature, for applications which do not use the `metrics`
+//! facade.
+//!
+//! A `Registry` is given to each connector to record into with
+//! `DnsConnector::set_prometheus`, and is itself a hyper `Service` which
+//! serves the metrics, so it can be mounted on e.g. `/metrics` in an existing
+//! server by passing those requests to it.
+
+use futures::future::{self, Ready};
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+use hyper::service::Service;
+use hyper::{Body, Request, Response};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use crate::counters::failure_class;
+use crate::ResolutionSource;
+
+/// The upper bounds of the lookup duration histogram buckets, in seconds.
+const BUCKETS: [f64; 12] = [
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Resolver metrics, which are shared between clones.
+#[derive(Debug, Clone, Default)]
+pub struct Registry {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    literal: AtomicUsize,
+    interceptor: AtomicUsize,
+    dns: AtomicUsize,
+    failures: Mutex<BTreeMap<&'static str, usize>>,
+    buckets: [AtomicUsize; 12],
+    duration_micros: AtomicUsize,
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry::default()
+    }
+
+    /// Record a successful resolution.
+    pub(crate) fn resolved(&self, source: ResolutionSource, elapsed: Duration) {
+        match source {
+            ResolutionSource::Literal => self.inner.literal.fetch_add(1, Ordering::Relaxed),
+            ResolutionSource::Interceptor => self.inner.interceptor.fetch_add(1, Ordering::Relaxed),
+            ResolutionSource::Dns => self.inner.dns.fetch_add(1, Ordering::Relaxed),
+        };
+
+        if source == ResolutionSource::Dns {
+            let secs = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+            for (bound, bucket) in BUCKETS.iter().zip(self.inner.buckets.iter()) {
+                if secs <= *bound {
+                    bucket.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            self.inner
+                .duration_micros
+                .fetch_add((secs * 1e6) as usize, Ordering::Relaxed);
+        }
+    }
+
+    /// Record a failed resolution.
+    pub(crate) fn failed(&self, err: &io::Error) {
+        *self
+            .inner
+            .failures
+            .lock()
+            .unwrap()
+            .entry(failure_class(err))
+            .or_insert(0) += 1;
+    }
+
+    /// Render the metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let inner = &self.inner;
+        let mut out = String::new();
+
+        out.push_str("# HELP hyper_dns_resolutions_total Successful resolutions by source.\n");
+        out.push_str("# TYPE hyper_dns_resolutions_total counter\n");
+        for &(source, count) in &[
+            ("literal", &inner.literal),
+            ("interceptor", &inner.interceptor),
+            ("dns", &inner.dns),
+        ] {
+            let _ = writeln!(
+                out,
+                "hyper_dns_resolutions_total{{source=\"{}\"}} {}",
+                source,
+                count.load(Ordering::Relaxed)
+            );
+        }
+
+        out.push_str("# HELP hyper_dns_failures_total Failed resolutions by class.\n");
+        out.push_str("# TYPE hyper_dns_failures_total counter\n");
+        for (class, count) in inner.failures.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "hyper_dns_failures_total{{class=\"{}\"}} {}",
+                class, count
+            );
+        }
+
+        out.push_str("# HELP hyper_dns_lookup_duration_seconds Duration of DNS lookups.\n");
+        out.push_str("# TYPE hyper_dns_lookup_duration_seconds histogram\n");
+        for (bound, bucket) in BUCKETS.iter().zip(inner.buckets.iter()) {
+            let _ = writeln!(
+                out,
+                "hyper_dns_lookup_duration_seconds_bucket{{le=\"{}\"}} {}",
+                bound,
+                bucket.load(Ordering::Relaxed)
+            );
+        }
+        let count = inner.dns.load(Ordering::Relaxed);
+        let _ = writeln!(
+            out,
+            "hyper_dns_lookup_duration_seconds_bucket{{le=\"+Inf\"}} {}",
+            count
+        );
+        let _ = writeln!(
+            out,
+            "hyper_dns_lookup_duration_seconds_sum {}",
+            inner.duration_micros.load(Ordering::Relaxed) as f64 / 1e6
+        );
+        let _ = writeln!(out, "hyper_dns_lookup_duration_seconds_count {}", count);
+
+        out
+    }
+}
+
+impl Service<Request<Body>> for Registry {
+    type Response = Response<Body>;
+    type Error = hyper::Error;
+    type Future = Ready<Result<Response<Body>, hyper::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), hyper::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _req: Request<Body>) -> Self::Future {
+        let mut response = Response::new(Body::from(self.render()));
+        response.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("text/plain; version=0.0.4"),
+        );
+        future::ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_resolutions() {
+        let registry = Registry::new();
+        registry.resolved(ResolutionSource::Dns, Duration::from_millis(20));
+        registry.resolved(ResolutionSource::Interceptor, Duration::from_millis(0));
+        registry.failed(&io::Error::new(io::ErrorKind::TimedOut, "timed out"));
+
+        let text = registry.render();
+        assert!(text.contains("hyper_dns_resolutions_total{source=\"dns\"} 1\n"));
+        assert!(text.contains("hyper_dns_resolutions_total{source=\"interceptor\"} 1\n"));
+        assert!(text.contains("hyper_dns_failures_total{class=\"timeout\"} 1\n"));
+        assert!(text.contains("hyper_dns_lookup_duration_seconds_bucket{le=\"0.01\"} 0\n"));
+        assert!(text.contains("hyper_dns_lookup_duration_seconds_bucket{le=\"0.025\"} 1\n"));
+        assert!(text.contains("hyper_dns_lookup_duration_seconds_count 1\n"));
+    }
+}
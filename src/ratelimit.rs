@@ -0,0 +1,72 @@
+//! A token bucket limiting the rate queries are sent to DNS servers.
+
+use std::sync::Mutex;
+
+use crate::time::Instant;
+
+/// Allows up to `rate` queries per second, with bursts of up to a second's
+/// worth of queries.
+#[derive(Debug)]
+pub struct RateLimiter {
+    rate: f64,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(rate: u32) -> RateLimiter {
+        RateLimiter {
+            rate: f64::from(rate),
+            bucket: Mutex::new(Bucket {
+                tokens: f64::from(rate),
+                refilled: Instant::now(),
+            }),
+        }
+    }
+
+    /// Take a token if there is one, returning whether a query may be sent.
+    pub fn try_acquire(&self) -> bool {
+        self.try_acquire_at(Instant::now())
+    }
+
+    fn try_acquire_at(&self, now: Instant) -> bool {
+        let mut bucket = self.bucket.lock().unwrap();
+        if now > bucket.refilled {
+            let elapsed = now - bucket.refilled;
+            let secs = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+            bucket.tokens = (bucket.tokens + secs * self.rate).min(self.rate);
+            bucket.refilled = now;
+        }
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn limits_rate() {
+        let limiter = RateLimiter::new(10);
+        let start = Instant::now();
+
+        let burst = (0..20).filter(|_| limiter.try_acquire_at(start)).count();
+        assert_eq!(burst, 10);
+
+        let later = start + Duration::from_millis(250);
+        let refilled = (0..20).filter(|_| limiter.try_acquire_at(later)).count();
+        assert_eq!(refilled, 2);
+    }
+}
@@ -0,0 +1,146 @@
+//! Redaction of hostnames in logs, errors and diagnostics.
+
+use std::borrow::Cow;
+use std::fmt;
+#[allow(deprecated)]
+use std::hash::{Hasher, SipHasher};
+
+/// How hostnames are redacted in log lines, error messages and diagnostics,
+/// for deployments where the hosts being resolved are sensitive.
+///
+/// Redacted hostnames stay correlatable, the same host is always redacted to
+/// the same value.
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub enum Redaction {
+    /// Hostnames are shown in full.
+    #[default]
+    Off,
+    /// Hostnames are replaced with a SipHash-2-4 hash of them, keyed with the
+    /// given 16 byte key, which should be secret so that hosts can't be
+    /// recovered by hashing guesses. The same host and key always hash to the
+    /// same value, across processes and versions of this crate, so redacted
+    /// hosts can be correlated between services which share the key.
+    Hash([u8; 16]),
+    /// Only the last `n` labels of hostnames are shown, e.g. `*.example.com`
+    /// for `Truncate(2)`.
+    Truncate(usize),
+}
+
+/// Doesn't show the key of `Hash`.
+impl fmt::Debug for Redaction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Redaction::Off => f.write_str("Off"),
+            Redaction::Hash(_) => f.write_str("Hash(..)"),
+            Redaction::Truncate(n) => f.debug_tuple("Truncate").field(&n).finish(),
+        }
+    }
+}
+
+impl Redaction {
+    /// Redact `host`.
+    pub fn apply<'a>(&self, host: &'a str) -> Cow<'a, str> {
+        match *self {
+            Redaction::Off => Cow::Borrowed(host),
+            Redaction::Hash(ref key) => Cow::Owned(format!("host-{:016x}", hash(key, host))),
+            Redaction::Truncate(n) => {
+                let trimmed = host.trim_end_matches('.');
+                let labels = trimmed.split('.').count();
+                if labels <= n {
+                    return Cow::Borrowed(host);
+                }
+
+                let kept: Vec<&str> = trimmed.split('.').skip(labels - n).collect();
+                if kept.is_empty() {
+                    Cow::Borrowed("*")
+                } else {
+                    Cow::Owned(format!("*.{}", kept.join(".")))
+                }
+            }
+        }
+    }
+
+    /// Redact each mention of `host`, with or without a trailing dot, in
+    /// `text`, such as the reason given for an error.
+    pub(crate) fn apply_in<'a>(&self, text: &'a str, host: &str) -> Cow<'a, str> {
+        let bare = host.trim_end_matches('.');
+        if *self == Redaction::Off || bare.is_empty() || !text.contains(bare) {
+            return Cow::Borrowed(text);
+        }
+
+        let redacted = self.apply(bare);
+        Cow::Owned(
+            text.replace(&format!("{}.", bare), &redacted)
+                .replace(bare, &redacted),
+        )
+    }
+}
+
+/// Hash `host`, without any trailing dot, with SipHash-2-4 keyed with `key`.
+#[allow(deprecated)]
+fn hash(key: &[u8; 16], host: &str) -> u64 {
+    let mut k0 = [0; 8];
+    let mut k1 = [0; 8];
+    k0.copy_from_slice(&key[..8]);
+    k1.copy_from_slice(&key[8..]);
+    let mut hasher = SipHasher::new_with_keys(u64::from_le_bytes(k0), u64::from_le_bytes(k1));
+    hasher.write(host.trim_end_matches('.').as_bytes());
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncates_labels() {
+        let redaction = Redaction::Truncate(2);
+        assert_eq!(redaction.apply("api.internal.example.com"), "*.example.com");
+        assert_eq!(redaction.apply("example.com"), "example.com");
+        assert_eq!(Redaction::Truncate(0).apply("example.com"), "*");
+    }
+
+    #[test]
+    fn redacts_mentions_in_text() {
+        let redaction = Redaction::Hash([7; 16]);
+        let text = redaction.apply_in(
+            "answer is for secret.example.com., not secret.example.com",
+            "secret.example.com.",
+        );
+        assert!(!text.contains("secret"));
+        assert_eq!(
+            text,
+            format!(
+                "answer is for {0}, not {0}",
+                redaction.apply("secret.example.com")
+            )
+        );
+        assert_eq!(
+            Redaction::Off.apply_in("secret.example.com", "secret.example.com"),
+            "secret.example.com"
+        );
+    }
+
+    #[test]
+    fn hashes_consistently() {
+        let redaction = Redaction::Hash([7; 16]);
+        let hashed = redaction.apply("secret.example.com");
+        assert!(!hashed.contains("secret"));
+        assert_eq!(hashed, redaction.apply("secret.example.com."));
+        assert!(hashed != redaction.apply("other.example.com"));
+        assert!(hashed != Redaction::Hash([8; 16]).apply("secret.example.com"));
+        assert_eq!(format!("{:?}", redaction), "Hash(..)");
+    }
+
+    #[test]
+    fn hashes_are_siphash_2_4() {
+        // The test vector for an empty input from the SipHash paper's
+        // reference implementation, whose key is the bytes 0 to 15.
+        let mut key = [0; 16];
+        for (i, byte) in key.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        assert_eq!(hash(&key, ""), 0x726f_db47_dd0e_0e31);
+        assert_eq!(Redaction::Hash(key).apply(""), "host-726fdb47dd0e0e31");
+    }
+}
@@ -0,0 +1,92 @@
+//! A resolver for reqwest's `ClientBuilder::dns_resolver`, enabled by the
+//! `reqwest` feature.
+
+use reqwest_012::dns::{Addrs, Name, Resolve, Resolving};
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::tower::{ResolveFuture, TowerResolver};
+use crate::DnsResolver;
+
+/// A resolver implementing reqwest's `Resolve` trait, so that applications
+/// using reqwest rather than hyper get the same resolution, with the
+/// connector's servers, interceptors and policies.
+///
+/// reqwest only asks for a host's addresses and connects to the URI's port,
+/// so hosts are resolved with address records, even where the connector
+/// would follow SRV records. Lookups run on the runtime reqwest polls them
+/// on, as for `TowerResolver`.
+#[derive(Debug, Clone)]
+pub struct ReqwestResolver {
+    resolver: TowerResolver,
+}
+
+impl ReqwestResolver {
+    /// Resolve hosts with `resolver`, e.g. from `DnsConnector::resolver`.
+    pub fn new(resolver: DnsResolver) -> io::Result<ReqwestResolver> {
+        Ok(ReqwestResolver {
+            resolver: TowerResolver::new(resolver)?,
+        })
+    }
+}
+
+impl Resolve for ReqwestResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(Lookup {
+            addrs: self.resolver.lookup(name.as_str()),
+        })
+    }
+}
+
+/// The addresses a host resolved to, as reqwest expects them.
+struct Lookup {
+    addrs: ResolveFuture,
+}
+
+impl Future for Lookup {
+    type Output = Result<Addrs, Box<dyn ::std::error::Error + Send + Sync>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        match Pin::new(&mut self.addrs).poll(cx) {
+            Poll::Ready(Ok(addrs)) => Poll::Ready(Ok(Box::new(addrs))),
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e.into())),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{check_adapter, StaticResolver};
+    use futures::executor::block_on;
+    use std::net::SocketAddr;
+    use std::str::FromStr;
+
+    #[test]
+    fn resolves_with_the_resolver() {
+        check_adapter(|resolver, host| {
+            let resolver = ReqwestResolver::new(resolver).unwrap();
+            match block_on(resolver.resolve(Name::from_str(host).unwrap())) {
+                Ok(addrs) => Ok(addrs.map(|addr| addr.ip()).collect()),
+                // The resolver's error is boxed as it is.
+                Err(e) => Err(*e.downcast::<io::Error>().unwrap()),
+            }
+        });
+    }
+
+    #[test]
+    fn addresses_have_port_zero() {
+        let static_resolver = StaticResolver::new();
+        static_resolver.add_ip("web.example.com", "192.0.2.1".parse().unwrap());
+        static_resolver.add_srv("web.example.com", 0, 0, 8080, "web.example.com");
+        let resolver = ReqwestResolver::new(static_resolver.connector(()).resolver()).unwrap();
+
+        // reqwest connects to the URI's port, so SRV records aren't followed.
+        let name = Name::from_str("web.example.com").unwrap();
+        let addrs: Vec<SocketAddr> = block_on(resolver.resolve(name)).unwrap().collect();
+        assert_eq!(addrs, vec!["192.0.2.1:0".parse::<SocketAddr>().unwrap()]);
+    }
+}
@@ -0,0 +1,487 @@
+//! Resolution of hosts for clients other than hyper's.
+
+use futures::future::{BoxFuture, FutureExt, TryFutureExt};
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use trust_dns::op::ResponseCode;
+use trust_dns::rr::{self, RData};
+
+use crate::{DnsResponse, Error, RecordType, Redaction, Resolution};
+
+/// Resolves a host with queries of a record type, for a port.
+pub(crate) type ResolveHost = dyn Fn(&str, Option<u16>, RecordType) -> BoxFuture<'static, io::Result<Resolution>>
+    + Send
+    + Sync;
+
+/// Looks up the targets of a service's SRV records.
+pub(crate) type LookupSrv =
+    dyn Fn(&str) -> BoxFuture<'static, io::Result<Vec<SrvTarget>>> + Send + Sync;
+
+/// Queries for the records of a type for a name, returning the response.
+pub(crate) type LookupRecords =
+    dyn Fn(&str, rr::RecordType) -> BoxFuture<'static, io::Result<DnsResponse>> + Send + Sync;
+
+/// A mail exchange for a domain, from its MX records.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MxRecord {
+    /// The host of the mail exchange, e.g. `mx1.example.com.`.
+    pub exchange: String,
+    /// The exchange's preference, where lower preferences are tried first.
+    pub preference: u16,
+    /// How many seconds the MX record may be cached for.
+    pub ttl: u32,
+}
+
+/// The start of authority of a zone, from its SOA record.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Soa {
+    /// The zone, e.g. `internal.example.com.`.
+    pub zone: String,
+    /// The zone's primary name server.
+    pub primary: String,
+    /// The mailbox of the person responsible for the zone, with the `@`
+    /// written as a `.`.
+    pub responsible: String,
+    /// The version of the zone, which increases with each change to it.
+    pub serial: u32,
+    /// How many seconds secondary servers wait before checking for changes.
+    pub refresh: i32,
+    /// How many seconds secondary servers wait after a failed refresh.
+    pub retry: i32,
+    /// How many seconds secondary servers keep serving the zone without
+    /// refreshing it.
+    pub expire: i32,
+    /// How many seconds negative responses may be cached for.
+    pub minimum: u32,
+    /// How many seconds the SOA record may be cached for.
+    pub ttl: u32,
+}
+
+/// A target of a service's SRV records, with everything needed to balance
+/// across the targets, as connection pools and gRPC balancers do.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SrvTarget {
+    /// The host of the target, e.g. `node1.example.com.`.
+    pub target: String,
+    /// The target's priority, where lower priorities are preferred.
+    pub priority: u16,
+    /// The target's share of connections among those with its priority.
+    pub weight: u16,
+    /// The port the service listens on.
+    pub port: u16,
+    /// How many seconds the SRV record may be cached for.
+    pub ttl: u32,
+    /// The addresses of the host, which are empty if it couldn't be
+    /// resolved.
+    pub addrs: Vec<IpAddr>,
+}
+
+impl SrvTarget {
+    /// The addresses the target can be connected to.
+    pub fn socket_addrs(&self) -> Vec<SocketAddr> {
+        self.addrs
+            .iter()
+            .map(|&addr| SocketAddr::new(addr, self.port))
+            .collect()
+    }
+}
+
+/// A resolver with the configuration and state of the connector it was
+/// created from by `DnsConnector::resolver`, so that other clients, such as
+/// raw TCP or gRPC ones, can use the same resolution stack.
+///
+/// Hosts are resolved as they are for requests: interceptors, policies,
+/// callbacks and statistics all apply.
+#[derive(Clone)]
+pub struct DnsResolver {
+    resolve: Arc<ResolveHost>,
+    lookup_srv: Arc<LookupSrv>,
+    lookup_records: Arc<LookupRecords>,
+    record_type: RecordType,
+    redaction: Redaction,
+    /// The same resolver without pooled connections to DNS servers, if this
+    /// one pools them.
+    unpooled: Option<Arc<DnsResolver>>,
+}
+
+impl DnsResolver {
+    pub(crate) fn new(
+        resolve: Arc<ResolveHost>,
+        lookup_srv: Arc<LookupSrv>,
+        lookup_records: Arc<LookupRecords>,
+        record_type: RecordType,
+        redaction: Redaction,
+    ) -> DnsResolver {
+        DnsResolver {
+            resolve: resolve,
+            lookup_srv: lookup_srv,
+            lookup_records: lookup_records,
+            record_type: record_type,
+            redaction: redaction,
+            unpooled: None,
+        }
+    }
+
+    /// Use `unpooled` for lookups whose runtime doesn't outlive them.
+    pub(crate) fn with_unpooled(mut self, unpooled: DnsResolver) -> DnsResolver {
+        self.unpooled = Some(Arc::new(unpooled));
+        self
+    }
+
+    /// The resolver to use for lookups on a runtime which is dropped
+    /// once they finish, which would close any connections they pooled.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn unpooled(&self) -> &DnsResolver {
+        self.unpooled.as_ref().map_or(self, |unpooled| &**unpooled)
+    }
+
+    /// Resolve `host` to the addresses to connect to on `port`, the one the
+    /// connector would pick first.
+    pub fn resolve(
+        &self,
+        host: &str,
+        port: u16,
+    ) -> BoxFuture<'static, io::Result<Vec<SocketAddr>>> {
+        (self.resolve)(host, Some(port), RecordType::A)
+            .map_ok(move |resolution| socket_addrs(&resolution, port))
+            .boxed()
+    }
+
+    /// Resolve `host` as the connector would for a request to it, querying
+    /// the connector's record type for `port`, to the addresses to connect
+    /// to. The port is the one SRV records give, otherwise `port`, or 0 if
+    /// neither is known and the caller has a default for the scheme.
+    pub fn resolve_request(
+        &self,
+        host: &str,
+        port: Option<u16>,
+    ) -> BoxFuture<'static, io::Result<Vec<SocketAddr>>> {
+        (self.resolve)(host, port, self.record_type.for_port(port))
+            .map_ok(move |resolution| {
+                let port = resolution.port.or(port).unwrap_or(0);
+                socket_addrs(&resolution, port)
+            })
+            .boxed()
+    }
+
+    /// Resolve the SRV records for `name`, e.g. `_grpc._tcp.example.com`, to
+    /// the addresses of the target picked and the port it gives, the
+    /// address the connector would pick first.
+    pub fn resolve_srv(&self, name: &str) -> BoxFuture<'static, io::Result<Vec<SocketAddr>>> {
+        (self.resolve)(name, None, RecordType::SRV)
+            .map_ok(|resolution| {
+                let port = resolution.port.unwrap_or(0);
+                socket_addrs(&resolution, port)
+            })
+            .boxed()
+    }
+
+    /// Look up every target of the SRV records for `name`, in order of
+    /// priority, with its weight, port, TTL and addresses, so that callers
+    /// can balance across them themselves. Targets of `.`, which mean the
+    /// service isn't available, are left out.
+    pub fn lookup_srv(&self, name: &str) -> BoxFuture<'static, io::Result<Vec<SrvTarget>>> {
+        (self.lookup_srv)(name)
+    }
+
+    /// Look up the mail exchanges for `domain` from its MX records, in order
+    /// of preference. There are none if the domain has no MX records, in
+    /// which case mail is delivered to the domain itself, but it's an error
+    /// if the domain doesn't exist or the server failed to answer.
+    pub fn lookup_mx(&self, domain: &str) -> BoxFuture<'static, io::Result<Vec<MxRecord>>> {
+        let (domain, redaction) = (domain.to_string(), self.redaction);
+        (self.lookup_records)(&domain, rr::RecordType::MX)
+            .and_then(move |res| async move {
+                check_response_code(&res, domain, redaction)?;
+                let mut exchanges: Vec<MxRecord> = res
+                    .answers()
+                    .iter()
+                    .filter_map(|record| match *record.rdata() {
+                        RData::MX(ref mx) => Some(MxRecord {
+                            exchange: mx.exchange().to_string(),
+                            preference: mx.preference(),
+                            ttl: record.ttl(),
+                        }),
+                        _ => None,
+                    })
+                    .collect();
+                exchanges.sort_by_key(|mx| mx.preference);
+                Ok(exchanges)
+            })
+            .boxed()
+    }
+
+    /// Look up the SOA record of `zone`, e.g. for health checks verifying
+    /// that changes to an internal zone have propagated, by comparing its
+    /// serial across servers. For a name inside a zone, the SOA record of
+    /// the enclosing zone is given if the server includes it.
+    pub fn lookup_soa(&self, zone: &str) -> BoxFuture<'static, io::Result<Soa>> {
+        let (zone, redaction) = (zone.to_string(), self.redaction);
+        (self.lookup_records)(&zone, rr::RecordType::SOA)
+            .and_then(move |res| async move {
+                check_response_code(&res, zone.clone(), redaction)?;
+                res.answers()
+                    .iter()
+                    .chain(res.name_servers())
+                    .filter_map(|record| match *record.rdata() {
+                        RData::SOA(ref soa) => Some(Soa {
+                            zone: record.name().to_string(),
+                            primary: soa.mname().to_string(),
+                            responsible: soa.rname().to_string(),
+                            serial: soa.serial(),
+                            refresh: soa.refresh(),
+                            retry: soa.retry(),
+                            expire: soa.expire(),
+                            minimum: soa.minimum(),
+                            ttl: record.ttl(),
+                        }),
+                        _ => None,
+                    })
+                    .next()
+                    .ok_or_else(|| Error::NoRecords(zone).redact(redaction).into())
+            })
+            .boxed()
+    }
+}
+
+impl fmt::Debug for DnsResolver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DnsResolver").finish()
+    }
+}
+
+/// Fail lookups of `name` whose response has an error code, as resolving
+/// hosts does: with `Error::NxDomain` if it doesn't exist,
+/// `Error::ServFail` if the server couldn't answer for it, and
+/// `Error::Rejected` if it wouldn't.
+fn check_response_code(res: &DnsResponse, name: String, redaction: Redaction) -> io::Result<()> {
+    let err = match res.response_code() {
+        ResponseCode::NoError => return Ok(()),
+        ResponseCode::NXDomain => Error::NxDomain(name),
+        ResponseCode::ServFail => Error::ServFail(name),
+        code => Error::Rejected(name, code),
+    };
+    Err(err.redact(redaction).into())
+}
+
+/// The addresses of `resolution` on `port`, the one picked first.
+fn socket_addrs(resolution: &Resolution, port: u16) -> Vec<SocketAddr> {
+    let mut addrs = vec![SocketAddr::new(resolution.ip, port)];
+    addrs.extend(
+        resolution
+            .addrs
+            .iter()
+            .filter(|&&addr| addr != resolution.ip)
+            .map(|&addr| SocketAddr::new(addr, port)),
+    );
+    addrs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend;
+    use crate::testing::StaticResolver;
+    use futures::executor::block_on;
+    use futures::future;
+    use std::str::FromStr;
+    use trust_dns::rr::rdata::{MX, SOA};
+    use trust_dns::rr::{Name, Record};
+
+    /// A resolver which answers lookups of records with `answers`, and no
+    /// other lookups.
+    fn answering<F>(answers: F) -> DnsResolver
+    where
+        F: Fn() -> Vec<RData> + Send + Sync + 'static,
+    {
+        DnsResolver::new(
+            Arc::new(
+                |_: &str,
+                 _: Option<u16>,
+                 _: RecordType|
+                 -> BoxFuture<'static, io::Result<Resolution>> { unreachable!() },
+            ),
+            Arc::new(
+                |_: &str| -> BoxFuture<'static, io::Result<Vec<SrvTarget>>> { unreachable!() },
+            ),
+            Arc::new(move |name: &str, record_type: rr::RecordType| {
+                let name = Name::from_str(name).unwrap();
+                let answers = answers()
+                    .into_iter()
+                    .map(|rdata| Record::from_rdata(name.clone(), 300, rdata))
+                    .collect();
+                let res = backend::response(name, record_type, ResponseCode::NoError, answers);
+                future::ok(res).boxed()
+            }),
+            RecordType::AUTO,
+            Redaction::Off,
+        )
+    }
+
+    #[test]
+    fn picked_address_comes_first() {
+        let resolver = DnsResolver::new(
+            Arc::new(|_: &str, port: Option<u16>, record_type: RecordType| {
+                let resolution = Resolution {
+                    ip: "10.0.0.2".parse().unwrap(),
+                    addrs: vec!["10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap()],
+                    port: if record_type == RecordType::SRV {
+                        Some(8080)
+                    } else {
+                        port
+                    },
+                    record_type: Some(record_type),
+                    ttl: Some(60),
+                    nameserver: None,
+                    chain: Vec::new(),
+                };
+                future::ok(resolution).boxed()
+            }),
+            Arc::new(|_: &str| {
+                let target = SrvTarget {
+                    target: "node1.example.com.".to_string(),
+                    priority: 10,
+                    weight: 5,
+                    port: 8080,
+                    ttl: 60,
+                    addrs: vec!["10.0.0.3".parse().unwrap()],
+                };
+                future::ok(vec![target]).boxed()
+            }),
+            Arc::new(
+                |_: &str, _: rr::RecordType| -> BoxFuture<'static, io::Result<DnsResponse>> {
+                    unreachable!()
+                },
+            ),
+            RecordType::AUTO,
+            Redaction::Off,
+        );
+
+        assert_eq!(
+            block_on(resolver.resolve("example.com", 443)).unwrap(),
+            vec![
+                "10.0.0.2:443".parse::<SocketAddr>().unwrap(),
+                "10.0.0.1:443".parse().unwrap()
+            ]
+        );
+        assert_eq!(
+            block_on(resolver.resolve_srv("_http._tcp.example.com")).unwrap()[0],
+            "10.0.0.2:8080".parse().unwrap()
+        );
+        assert_eq!(
+            block_on(resolver.resolve_request("web", None)).unwrap()[0],
+            "10.0.0.2:8080".parse().unwrap()
+        );
+        assert_eq!(
+            block_on(resolver.resolve_request("web", Some(80))).unwrap()[0],
+            "10.0.0.2:80".parse().unwrap()
+        );
+        assert_eq!(
+            block_on(resolver.lookup_srv("_http._tcp.example.com")).unwrap()[0].socket_addrs(),
+            vec!["10.0.0.3:8080".parse().unwrap()]
+        );
+    }
+
+    #[test]
+    fn mx_records_are_sorted_by_preference() {
+        let resolver = answering(|| {
+            vec![
+                RData::MX(MX::new(20, Name::from_str("mx2.example.com.").unwrap())),
+                RData::MX(MX::new(10, Name::from_str("mx1.example.com.").unwrap())),
+            ]
+        });
+
+        assert_eq!(
+            block_on(resolver.lookup_mx("example.com.")).unwrap(),
+            vec![
+                MxRecord {
+                    exchange: "mx1.example.com.".to_string(),
+                    preference: 10,
+                    ttl: 300,
+                },
+                MxRecord {
+                    exchange: "mx2.example.com.".to_string(),
+                    preference: 20,
+                    ttl: 300,
+                },
+            ]
+        );
+        assert!(block_on(answering(Vec::new).lookup_mx("example.com."))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn soa_record_is_found_in_answers() {
+        let parse = |name: &str| Name::from_str(name).unwrap();
+        let resolver = answering(move || {
+            vec![RData::SOA(SOA::new(
+                parse("ns1.example.com."),
+                parse("hostmaster.example.com."),
+                2024010101,
+                7200,
+                900,
+                1209600,
+                300,
+            ))]
+        });
+
+        assert_eq!(
+            block_on(resolver.lookup_soa("example.com.")).unwrap(),
+            Soa {
+                zone: "example.com.".to_string(),
+                primary: "ns1.example.com.".to_string(),
+                responsible: "hostmaster.example.com.".to_string(),
+                serial: 2024010101,
+                refresh: 7200,
+                retry: 900,
+                expire: 1209600,
+                minimum: 300,
+                ttl: 300,
+            }
+        );
+        let err = block_on(answering(Vec::new).lookup_soa("example.com.")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn record_lookups_fail_on_error_response_codes() {
+        let hosts = StaticResolver::new();
+        hosts.set_response_code("missing.example.com", ResponseCode::NXDomain);
+        hosts.set_response_code("broken.example.com", ResponseCode::ServFail);
+        hosts.set_response_code("refused.example.com", ResponseCode::Refused);
+        let resolver = hosts.connector(()).resolver();
+
+        let error = |err: io::Error| err.into_inner().unwrap().downcast::<Error>().unwrap();
+        for domain in &["missing.example.com", "nowhere.example.com"] {
+            match *error(block_on(resolver.lookup_mx(domain)).unwrap_err()) {
+                Error::NxDomain(ref name) => assert_eq!(name, domain),
+                ref other => panic!("unexpected error: {:?}", other),
+            }
+            match *error(block_on(resolver.lookup_soa(domain)).unwrap_err()) {
+                Error::NxDomain(ref name) => assert_eq!(name, domain),
+                ref other => panic!("unexpected error: {:?}", other),
+            }
+        }
+        let domain = "broken.example.com";
+        match *error(block_on(resolver.lookup_mx(domain)).unwrap_err()) {
+            Error::ServFail(ref name) => assert_eq!(name, domain),
+            ref other => panic!("unexpected error: {:?}", other),
+        }
+        match *error(block_on(resolver.lookup_soa(domain)).unwrap_err()) {
+            Error::ServFail(ref name) => assert_eq!(name, domain),
+            ref other => panic!("unexpected error: {:?}", other),
+        }
+        let domain = "refused.example.com";
+        match *error(block_on(resolver.lookup_mx(domain)).unwrap_err()) {
+            Error::Rejected(ref name, ResponseCode::Refused) => assert_eq!(name, domain),
+            ref other => panic!("unexpected error: {:?}", other),
+        }
+        match *error(block_on(resolver.lookup_soa(domain)).unwrap_err()) {
+            Error::Rejected(ref name, ResponseCode::Refused) => assert_eq!(name, domain),
+            ref other => panic!("unexpected error: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,269 @@
+//! Destination address ordering from RFC 6724.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::time::Instant;
+
+/// Scope values from RFC 4291 and RFC 6724 section 3.1.
+const SCOPE_LINK_LOCAL: u8 = 0x2;
+const SCOPE_SITE_LOCAL: u8 = 0x5;
+const SCOPE_GLOBAL: u8 = 0xe;
+
+/// How long the source address found for a destination prefix is used for.
+const SOURCE_TTL: Duration = Duration::from_secs(30);
+
+/// The most destination prefixes whose source addresses are remembered.
+const MAX_SOURCES: usize = 1024;
+
+/// The default policy table from RFC 6724 section 2.1, as (prefix, prefix
+/// length, precedence, label).
+const POLICY_TABLE: &[(u128, u8, u8, u8)] = &[
+    (0x0000_0000_0000_0000_0000_0000_0000_0001, 128, 50, 0),
+    (0x0000_0000_0000_0000_0000_ffff_0000_0000, 96, 35, 4),
+    (0x0000_0000_0000_0000_0000_0000_0000_0000, 96, 1, 3),
+    (0x2001_0000_0000_0000_0000_0000_0000_0000, 32, 5, 5),
+    (0x2002_0000_0000_0000_0000_0000_0000_0000, 16, 30, 2),
+    (0x3ffe_0000_0000_0000_0000_0000_0000_0000, 16, 1, 12),
+    (0xfec0_0000_0000_0000_0000_0000_0000_0000, 10, 1, 11),
+    (0xfc00_0000_0000_0000_0000_0000_0000_0000, 7, 3, 13),
+    (0x0000_0000_0000_0000_0000_0000_0000_0000, 0, 40, 1),
+];
+
+/// The properties of a destination which RFC 6724 orders by, in decreasing
+/// order of importance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Key {
+    /// Rule 1: avoid unusable destinations.
+    usable: bool,
+    /// Rule 2: prefer matching scope.
+    matching_scope: bool,
+    /// Rule 5: prefer matching label.
+    matching_label: bool,
+    /// Rule 6: prefer higher precedence.
+    precedence: u8,
+    /// Rule 8: prefer smaller scope.
+    smaller_scope: u8,
+    /// Rule 9: use longest matching prefix.
+    common_prefix: u8,
+}
+
+/// The source addresses the OS uses to reach destination prefixes (the /24
+/// of IPv4 addresses and the /64 of IPv6 ones), remembered for a short time
+/// so that every lookup doesn't connect a socket for each address.
+#[derive(Debug, Default)]
+pub struct Sources {
+    prefixes: Mutex<HashMap<IpAddr, (Option<IpAddr>, Instant)>>,
+}
+
+impl Sources {
+    /// The source address for `dest`, if it can be reached.
+    fn source_for(&self, dest: IpAddr) -> Option<IpAddr> {
+        let prefix = prefix(dest);
+        let now = Instant::now();
+        if let Some(&(source, since)) = self.prefixes.lock().unwrap().get(&prefix) {
+            if now.duration_since(since) < SOURCE_TTL {
+                return source;
+            }
+        }
+
+        let source = source_for(dest);
+        let mut prefixes = self.prefixes.lock().unwrap();
+        if prefixes.len() >= MAX_SOURCES {
+            prefixes.retain(|_, &mut (_, since)| now.duration_since(since) < SOURCE_TTL);
+        }
+        if prefixes.len() < MAX_SOURCES {
+            prefixes.insert(prefix, (source, now));
+        }
+        source
+    }
+}
+
+/// Sort `addrs` according to the destination address selection rules of
+/// RFC 6724, returning the rank of each sorted address. Addresses which the
+/// rules consider equally good share a rank, and rank 0 is the best. A single
+/// address is left as it is.
+///
+/// Rules 3, 4 and 7 need information about source addresses which is not
+/// available portably, so they are skipped. The sort is stable, so equally
+/// good addresses stay in the order the DNS server returned them (rule 10).
+pub fn sort(addrs: &mut [IpAddr], sources: &Sources) -> Vec<usize> {
+    if addrs.len() < 2 {
+        return vec![0; addrs.len()];
+    }
+
+    let mut keyed: Vec<(Key, IpAddr)> = addrs
+        .iter()
+        .map(|addr| (key(*addr, sources), *addr))
+        .collect();
+    keyed.sort_by_key(|&(key, _)| Reverse(key));
+
+    let mut ranks = Vec::with_capacity(keyed.len());
+    let mut rank = 0;
+    for (i, &(key, addr)) in keyed.iter().enumerate() {
+        if i > 0 && key != keyed[i - 1].0 {
+            rank += 1;
+        }
+        addrs[i] = addr;
+        ranks.push(rank);
+    }
+
+    ranks
+}
+
+/// Work out the sort key for a destination.
+fn key(dest: IpAddr, sources: &Sources) -> Key {
+    let dest6 = to_ipv6(dest);
+    let (dest_precedence, dest_label) = policy(dest6);
+    let dest_scope = scope(dest);
+
+    match sources.source_for(dest) {
+        Some(source) => {
+            let source6 = to_ipv6(source);
+            let common_prefix = if dest.is_ipv6() {
+                common_prefix_len(source6, dest6)
+            } else {
+                0
+            };
+
+            Key {
+                usable: true,
+                matching_scope: scope(source) == dest_scope,
+                matching_label: policy(source6).1 == dest_label,
+                precedence: dest_precedence,
+                smaller_scope: 0xf - dest_scope,
+                common_prefix: common_prefix,
+            }
+        }
+        None => Key {
+            usable: false,
+            matching_scope: false,
+            matching_label: false,
+            precedence: dest_precedence,
+            smaller_scope: 0xf - dest_scope,
+            common_prefix: 0,
+        },
+    }
+}
+
+/// Find the source address the OS would use to reach `dest`, by connecting a
+/// UDP socket to it (which does not send any packets).
+fn source_for(dest: IpAddr) -> Option<IpAddr> {
+    let bind: SocketAddr = match dest {
+        IpAddr::V4(_) => ([0u8; 4], 0).into(),
+        IpAddr::V6(_) => ([0u16; 8], 0).into(),
+    };
+
+    let socket = UdpSocket::bind(bind).ok()?;
+    socket.connect(SocketAddr::new(dest, 9)).ok()?;
+    socket.local_addr().ok().map(|addr| addr.ip())
+}
+
+/// The prefix of `dest` whose destinations are taken to share a source
+/// address.
+fn prefix(dest: IpAddr) -> IpAddr {
+    match dest {
+        IpAddr::V4(dest) => Ipv4Addr::from(u32::from(dest) & 0xffff_ff00).into(),
+        IpAddr::V6(dest) => Ipv6Addr::from(u128::from(dest) & !0u128 << 64).into(),
+    }
+}
+
+/// Look up the precedence and label of an address in the policy table.
+fn policy(addr: Ipv6Addr) -> (u8, u8) {
+    let bits = u128::from(addr);
+    for &(prefix, len, precedence, label) in POLICY_TABLE {
+        if len == 0 || bits >> (128 - u32::from(len)) == prefix >> (128 - u32::from(len)) {
+            return (precedence, label);
+        }
+    }
+    (40, 1)
+}
+
+/// The scope of an address, following RFC 6724 section 3.2 for IPv4.
+fn scope(addr: IpAddr) -> u8 {
+    match addr {
+        IpAddr::V4(addr) => {
+            if addr.is_loopback() || addr.is_link_local() {
+                SCOPE_LINK_LOCAL
+            } else {
+                SCOPE_GLOBAL
+            }
+        }
+        IpAddr::V6(addr) => {
+            let segments = addr.segments();
+            if addr.is_multicast() {
+                (segments[0] & 0x000f) as u8
+            } else if addr.is_loopback() || segments[0] & 0xffc0 == 0xfe80 {
+                SCOPE_LINK_LOCAL
+            } else if segments[0] & 0xffc0 == 0xfec0 {
+                SCOPE_SITE_LOCAL
+            } else {
+                SCOPE_GLOBAL
+            }
+        }
+    }
+}
+
+/// Represent an address as IPv6, mapping IPv4 addresses to `::ffff:a.b.c.d`.
+fn to_ipv6(addr: IpAddr) -> Ipv6Addr {
+    match addr {
+        IpAddr::V4(addr) => addr.to_ipv6_mapped(),
+        IpAddr::V6(addr) => addr,
+    }
+}
+
+/// The length of the common prefix of two addresses, up to the 64 bit
+/// interface identifier.
+fn common_prefix_len(a: Ipv6Addr, b: Ipv6Addr) -> u8 {
+    let diff = (u128::from(a) ^ u128::from(b)) >> 64;
+    std::cmp::min(diff.leading_zeros() - 64, 64) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policy_table_lookup() {
+        assert_eq!(policy("::1".parse().unwrap()), (50, 0));
+        assert_eq!(policy(to_ipv6("10.0.0.1".parse().unwrap())), (35, 4));
+        assert_eq!(policy("2001:db8::1".parse().unwrap()), (40, 1));
+        assert_eq!(policy("2001::1".parse().unwrap()), (5, 5));
+        assert_eq!(policy("fd00::1".parse().unwrap()), (3, 13));
+    }
+
+    #[test]
+    fn scopes() {
+        assert_eq!(scope("127.0.0.1".parse().unwrap()), SCOPE_LINK_LOCAL);
+        assert_eq!(scope("169.254.1.1".parse().unwrap()), SCOPE_LINK_LOCAL);
+        assert_eq!(scope("10.0.0.1".parse().unwrap()), SCOPE_GLOBAL);
+        assert_eq!(scope("fe80::1".parse().unwrap()), SCOPE_LINK_LOCAL);
+        assert_eq!(scope("fec0::1".parse().unwrap()), SCOPE_SITE_LOCAL);
+        assert_eq!(scope("ff02::1".parse().unwrap()), 0x2);
+    }
+
+    #[test]
+    fn common_prefix() {
+        let a: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        let b: Ipv6Addr = "2001:db8:8000::1".parse().unwrap();
+        assert_eq!(common_prefix_len(a, b), 32);
+        assert_eq!(common_prefix_len(a, a), 64);
+    }
+
+    #[test]
+    fn sources_are_remembered_per_prefix() {
+        let sources = Sources::default();
+        let mut addrs = vec!["127.0.0.1".parse().unwrap()];
+        assert_eq!(sort(&mut addrs, &sources), vec![0]);
+        assert!(sources.prefixes.lock().unwrap().is_empty());
+
+        let mut addrs = vec!["127.0.0.1".parse().unwrap(), "127.0.0.2".parse().unwrap()];
+        sort(&mut addrs, &sources);
+        let prefixes = sources.prefixes.lock().unwrap();
+        assert_eq!(prefixes.len(), 1);
+        assert!(prefixes.contains_key(&"127.0.0.0".parse::<IpAddr>().unwrap()));
+    }
+}
@@ -0,0 +1,58 @@
+//! The source of randomness used when picking records and addresses.
+
+use rand::rngs::StdRng;
+use rand::{self, RngCore, SeedableRng};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// A source of randomness which can be shared between connectors and
+/// selection policies.
+///
+/// By default the thread local RNG is used, a seeded RNG can be injected to
+/// make resolution reproducible in tests.
+#[derive(Clone, Default)]
+pub struct SharedRng {
+    rng: Option<Arc<Mutex<Box<dyn RngCore + Send>>>>,
+}
+
+impl SharedRng {
+    /// Use the thread local RNG.
+    pub fn thread() -> SharedRng {
+        SharedRng { rng: None }
+    }
+
+    /// Use `rng`, which is shared by all clones of the returned value.
+    pub fn new<R>(rng: R) -> SharedRng
+    where
+        R: RngCore + Send + 'static,
+    {
+        SharedRng {
+            rng: Some(Arc::new(Mutex::new(Box::new(rng)))),
+        }
+    }
+
+    /// Use a `StdRng` seeded with `seed`.
+    pub fn seeded(seed: u64) -> SharedRng {
+        SharedRng::new(StdRng::seed_from_u64(seed))
+    }
+
+    /// Run `f` with the RNG.
+    pub fn with<F, T>(&self, f: F) -> T
+    where
+        F: FnOnce(&mut dyn RngCore) -> T,
+    {
+        match self.rng {
+            Some(ref rng) => f(&mut **rng.lock().unwrap()),
+            None => f(&mut rand::thread_rng()),
+        }
+    }
+}
+
+impl fmt::Debug for SharedRng {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.rng {
+            Some(_) => f.write_str("SharedRng(injected)"),
+            None => f.write_str("SharedRng(thread)"),
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! The servers iterative resolution starts from.
+
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::Path;
+
+/// The IPv4 addresses of the root servers, `a` to `m`.
+const ROOT_SERVERS: [[u8; 4]; 13] = [
+    [198, 41, 0, 4],
+    [170, 247, 170, 2],
+    [192, 33, 4, 12],
+    [199, 7, 91, 13],
+    [192, 203, 230, 10],
+    [192, 5, 5, 241],
+    [192, 112, 36, 4],
+    [198, 97, 190, 53],
+    [192, 36, 148, 17],
+    [192, 58, 128, 30],
+    [193, 0, 14, 129],
+    [199, 7, 83, 42],
+    [202, 12, 27, 33],
+];
+
+/// The servers for the root zone, which iterative resolution starts from.
+///
+/// The default is the IANA root servers, alternatives can be given for
+/// private root zones and test networks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RootHints {
+    pub(crate) servers: Vec<SocketAddr>,
+}
+
+impl RootHints {
+    /// Start from `servers`.
+    pub fn new<I>(servers: I) -> RootHints
+    where
+        I: IntoIterator<Item = SocketAddr>,
+    {
+        RootHints {
+            servers: servers.into_iter().collect(),
+        }
+    }
+
+    /// Parse the A and AAAA records of a root hints file in zone file
+    /// format, such as IANA's `named.root`.
+    pub fn parse(hints: &str) -> io::Result<RootHints> {
+        let mut servers = Vec::new();
+
+        for line in hints.lines() {
+            let line = line.split(';').next().unwrap();
+            let fields: Vec<&str> = line.split_whitespace().collect();
+
+            if let Some(i) = fields.iter().position(|&f| f == "A" || f == "AAAA") {
+                let addr: IpAddr = fields
+                    .get(i + 1)
+                    .and_then(|addr| addr.parse().ok())
+                    .ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("Invalid root hint: {}", line.trim()),
+                        )
+                    })?;
+                servers.push(SocketAddr::new(addr, 53));
+            }
+        }
+
+        if servers.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "No addresses in root hints",
+            ));
+        }
+        Ok(RootHints { servers: servers })
+    }
+
+    /// Load a root hints file, as with `parse`.
+    pub fn from_file<P>(path: P) -> io::Result<RootHints>
+    where
+        P: AsRef<Path>,
+    {
+        RootHints::parse(&fs::read_to_string(path)?)
+    }
+}
+
+impl Default for RootHints {
+    fn default() -> RootHints {
+        RootHints::new(
+            ROOT_SERVERS
+                .iter()
+                .map(|&addr| SocketAddr::new(IpAddr::V4(Ipv4Addr::from(addr)), 53)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hints_files() {
+        let hints = RootHints::parse(
+            "; formerly NS.INTERNIC.NET
+.                        3600000      NS    A.ROOT-SERVERS.NET.
+A.ROOT-SERVERS.NET.      3600000      A     198.41.0.4
+A.ROOT-SERVERS.NET.      3600000      AAAA  2001:503:ba3e::2:30
+",
+        )
+        .unwrap();
+
+        assert_eq!(
+            hints,
+            RootHints::new(vec![
+                "198.41.0.4:53".parse().unwrap(),
+                "[2001:503:ba3e::2:30]:53".parse().unwrap(),
+            ])
+        );
+
+        assert!(RootHints::parse("ns.test. 60 A not-an-address").is_err());
+        assert!(RootHints::parse("; nothing").is_err());
+    }
+}
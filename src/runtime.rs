@@ -0,0 +1,491 @@
+//! The async runtimes the connector's DNS clients run on.
+
+#[cfg(any(feature = "tokio", not(any(feature = "async-std", feature = "smol"))))]
+use futures::future;
+#[cfg(any(feature = "tokio", feature = "async-std", feature = "smol"))]
+use futures::future::TryFutureExt;
+use futures::future::{BoxFuture, FutureExt};
+#[cfg(feature = "tokio")]
+use std::collections::hash_map::DefaultHasher;
+#[cfg(feature = "tokio")]
+use std::hash::{Hash, Hasher};
+#[cfg(not(any(feature = "tokio", feature = "async-std", feature = "smol")))]
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+#[cfg(feature = "tokio")]
+use tokio::net::TcpStream as TokioTcpStream;
+#[cfg(feature = "tokio")]
+use tokio::runtime::Handle;
+use trust_dns_proto::error::ProtoError;
+#[cfg(feature = "tokio")]
+use trust_dns_proto::iocompat::AsyncIoTokioAsStd;
+#[cfg(any(feature = "tokio", feature = "async-std", feature = "smol"))]
+use trust_dns_proto::op::NoopMessageFinalizer;
+#[cfg(feature = "tokio")]
+use trust_dns_proto::tcp::TcpClientStream;
+use trust_dns_proto::xfer::DnsExchange;
+#[cfg(any(feature = "tokio", feature = "async-std", feature = "smol"))]
+use trust_dns_proto::xfer::{DnsClientStream, DnsMultiplexer, DnsStreamHandle};
+#[cfg(any(feature = "tokio", feature = "async-std", feature = "smol"))]
+use trust_dns_proto::Time;
+#[cfg(feature = "tokio")]
+use trust_dns_proto::TokioTime;
+
+#[cfg(all(target_os = "linux", feature = "tokio"))]
+use crate::mark;
+
+#[cfg(feature = "async-std")]
+pub use self::io::AsyncStd;
+#[cfg(feature = "smol")]
+pub use self::io::Smol;
+
+/// Runs the connector's DNS clients: connects them to DNS servers over TCP,
+/// spawns the tasks owning their connections and times them out.
+///
+/// The `tokio` feature, enabled by default, adds `Tokio`, and the
+/// `async-std` and `smol` features add `AsyncStd` and `Smol`. Unless another
+/// is given to `DnsConnector::set_runtime`, the first of these enabled is
+/// used, or with none, as on wasm32, no runtime, so that DNS servers can't
+/// be queried without a `Backend`.
+pub trait Runtime: Send + Sync {
+    /// Connect a client to the DNS server at `server`, failing if that takes
+    /// longer than `timeout`, and spawn the task owning its connection. The
+    /// socket is marked with `mark`, if given, for policy routing.
+    fn connect(
+        &self,
+        server: SocketAddr,
+        timeout: Duration,
+        mark: Option<u32>,
+    ) -> BoxFuture<'static, Result<DnsExchange, ProtoError>>;
+
+    /// Wait for `duration`.
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+
+    /// The executor the task owning a client's connection would be spawned
+    /// on, if its `connect` future were polled now. Clients are only reused
+    /// on the executor they were connected from, as their tasks stop with
+    /// it. Runtimes spawning on a single global executor,
+    /// like async-std and smol, needn't override it.
+    fn executor(&self) -> Executor {
+        Executor::global()
+    }
+
+    /// Call `shutdown` once the executor that `executor` identifies stops,
+    /// ending the tasks owning connections spawned on it, so that the
+    /// clients whose connections they owned can be forgotten. Runtimes
+    /// spawning on a single global executor, which doesn't stop, needn't
+    /// override it.
+    fn on_shutdown(&self, _shutdown: Box<dyn FnOnce() + Send>) {}
+}
+
+/// Identifies the executor a `Runtime` spawns the tasks owning connections
+/// on, such as which tokio runtime, independently of the runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Executor(Option<u64>);
+
+impl Executor {
+    /// The executor identified by `id`, which must differ from those of the
+    /// runtime's other running executors.
+    pub fn new(id: u64) -> Executor {
+        Executor(Some(id))
+    }
+
+    /// The single global executor of runtimes like async-std and smol.
+    pub fn global() -> Executor {
+        Executor(None)
+    }
+}
+
+/// Runs DNS clients on the tokio 1.x runtime polling the connector's
+/// futures.
+#[cfg(feature = "tokio")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Tokio;
+
+#[cfg(feature = "tokio")]
+impl Runtime for Tokio {
+    fn connect(
+        &self,
+        server: SocketAddr,
+        timeout: Duration,
+        mark: Option<u32>,
+    ) -> BoxFuture<'static, Result<DnsExchange, ProtoError>> {
+        let (stream, sender) = match mark {
+            #[cfg(target_os = "linux")]
+            Some(mark) => mark::stream(server, timeout, mark),
+            _ => {
+                let (stream, sender) =
+                    TcpClientStream::<AsyncIoTokioAsStd<TokioTcpStream>>::with_timeout(
+                        server, timeout,
+                    );
+                (stream.boxed(), sender)
+            }
+        };
+        exchange::<_, TokioTime, _>(stream, sender, |background| {
+            tokio::spawn(background);
+        })
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        tokio::time::sleep(duration).boxed()
+    }
+
+    fn executor(&self) -> Executor {
+        match Handle::try_current() {
+            // Runtime ids can only be hashed or displayed, not converted.
+            Ok(handle) => {
+                let mut hasher = DefaultHasher::new();
+                handle.id().hash(&mut hasher);
+                Executor::new(hasher.finish())
+            }
+            Err(_) => Executor::global(),
+        }
+    }
+
+    fn on_shutdown(&self, shutdown: Box<dyn FnOnce() + Send>) {
+        // A runtime drops the tasks it hasn't finished as it shuts down, so
+        // a task which never finishes runs `shutdown` as it is dropped.
+        if let Ok(handle) = Handle::try_current() {
+            let on_drop = OnDrop(Some(shutdown));
+            handle.spawn(async move {
+                let _on_drop = on_drop;
+                future::pending::<()>().await
+            });
+        }
+    }
+}
+
+/// Calls a function when dropped.
+#[cfg(feature = "tokio")]
+struct OnDrop(Option<Box<dyn FnOnce() + Send>>);
+
+#[cfg(feature = "tokio")]
+impl Drop for OnDrop {
+    fn drop(&mut self) {
+        if let Some(f) = self.0.take() {
+            f();
+        }
+    }
+}
+
+/// The runtime without any of the runtime features until one is given, with
+/// which DNS servers can't be connected to, so queries need a `Backend`, and
+/// waits never finish, so that connection attempts are made one after
+/// another.
+#[cfg(not(any(feature = "tokio", feature = "async-std", feature = "smol")))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoRuntime;
+
+#[cfg(not(any(feature = "tokio", feature = "async-std", feature = "smol")))]
+impl Runtime for NoRuntime {
+    fn connect(
+        &self,
+        _server: SocketAddr,
+        _timeout: Duration,
+        _mark: Option<u32>,
+    ) -> BoxFuture<'static, Result<DnsExchange, ProtoError>> {
+        let err = io::Error::new(
+            io::ErrorKind::Unsupported,
+            "DNS servers can't be connected to without a runtime",
+        );
+        future::err(err.into()).boxed()
+    }
+
+    fn sleep(&self, _duration: Duration) -> BoxFuture<'static, ()> {
+        future::pending().boxed()
+    }
+}
+
+/// The runtime used unless another is given: the first of `Tokio`,
+/// `AsyncStd` and `Smol` which is enabled.
+pub fn default() -> Arc<dyn Runtime> {
+    #[cfg(feature = "tokio")]
+    return Arc::new(Tokio);
+    #[cfg(all(feature = "async-std", not(feature = "tokio")))]
+    return Arc::new(AsyncStd);
+    #[cfg(all(feature = "smol", not(any(feature = "tokio", feature = "async-std"))))]
+    return Arc::new(Smol);
+    #[cfg(not(any(feature = "tokio", feature = "async-std", feature = "smol")))]
+    return Arc::new(NoRuntime);
+}
+
+/// Connect a client to the server `stream` connects to, timing its queries
+/// out with `T`, and `spawn` the task owning its connection.
+#[cfg(any(feature = "tokio", feature = "async-std", feature = "smol"))]
+pub fn exchange<S, T, F>(
+    stream: BoxFuture<'static, Result<S, ProtoError>>,
+    sender: Box<dyn DnsStreamHandle + Send>,
+    spawn: F,
+) -> BoxFuture<'static, Result<DnsExchange, ProtoError>>
+where
+    S: DnsClientStream + Unpin + 'static,
+    T: Time + Unpin + Send + 'static,
+    F: FnOnce(BoxFuture<'static, ()>) + Send + 'static,
+{
+    let multiplexer = DnsMultiplexer::<_, NoopMessageFinalizer>::new(stream, sender, None);
+    DnsExchange::connect::<_, _, T>(multiplexer)
+        .map_ok(move |(exchange, background)| {
+            spawn(background.map(drop).boxed());
+            exchange
+        })
+        .boxed()
+}
+
+/// Runtimes on async-io's reactor, which async-std and smol share.
+#[cfg(any(feature = "async-std", feature = "smol"))]
+mod io {
+    use async_trait::async_trait;
+    use futures::future::{self, BoxFuture, Either, Future, FutureExt};
+    use futures::io::{AsyncRead, AsyncWrite};
+    use std::io;
+    use std::marker::PhantomData;
+    use std::net::SocketAddr;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use std::time::Duration;
+    use trust_dns_proto::error::ProtoError;
+    use trust_dns_proto::tcp::{Connect, DnsTcpStream, TcpClientStream};
+    use trust_dns_proto::xfer::DnsExchange;
+    use trust_dns_proto::Time;
+
+    use super::{exchange, Runtime};
+
+    /// A TCP connection to a DNS server, whose queries are timed out with
+    /// `T`.
+    struct Tcp<S, T> {
+        stream: S,
+        time: PhantomData<fn() -> T>,
+    }
+
+    impl<S, T> Tcp<S, T> {
+        fn new(stream: S) -> Tcp<S, T> {
+            Tcp {
+                stream: stream,
+                time: PhantomData,
+            }
+        }
+    }
+
+    impl<S: AsyncRead + Unpin, T> AsyncRead for Tcp<S, T> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            Pin::new(&mut self.stream).poll_read(cx, buf)
+        }
+    }
+
+    impl<S: AsyncWrite + Unpin, T> AsyncWrite for Tcp<S, T> {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            Pin::new(&mut self.stream).poll_write(cx, buf)
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.stream).poll_flush(cx)
+        }
+
+        fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.stream).poll_close(cx)
+        }
+    }
+
+    impl<S, T> DnsTcpStream for Tcp<S, T>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'static,
+        T: Time + 'static,
+    {
+        type Time = T;
+    }
+
+    /// `future`, failing with `ErrorKind::TimedOut` if `timer` finishes
+    /// first.
+    async fn timeout<F, D>(future: F, timer: D) -> io::Result<F::Output>
+    where
+        F: Future,
+        D: Future,
+    {
+        match future::select(Box::pin(future), Box::pin(timer)).await {
+            Either::Left((output, _)) => Ok(output),
+            Either::Right(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "future timed out")),
+        }
+    }
+
+    /// Connecting marked sockets needs the `Tokio` runtime.
+    fn unmarked(mark: Option<u32>) -> Result<(), ProtoError> {
+        match mark {
+            Some(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "socket marks are only set on the tokio runtime",
+            )
+            .into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Runs DNS clients on async-std, spawning the tasks owning their
+    /// connections with `async_std::task::spawn`.
+    #[cfg(feature = "async-std")]
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct AsyncStd;
+
+    /// Times out queries with async-std's timers.
+    #[cfg(feature = "async-std")]
+    struct AsyncStdTime;
+
+    #[cfg(feature = "async-std")]
+    #[async_trait]
+    impl Time for AsyncStdTime {
+        async fn delay_for(duration: Duration) {
+            async_std::task::sleep(duration).await
+        }
+
+        async fn timeout<F: 'static + Future + Send>(
+            duration: Duration,
+            future: F,
+        ) -> io::Result<F::Output> {
+            timeout(future, async_std::task::sleep(duration)).await
+        }
+    }
+
+    #[cfg(feature = "async-std")]
+    #[async_trait]
+    impl Connect for Tcp<async_std::net::TcpStream, AsyncStdTime> {
+        async fn connect(addr: SocketAddr) -> io::Result<Self> {
+            async_std::net::TcpStream::connect(addr).await.map(Tcp::new)
+        }
+    }
+
+    #[cfg(feature = "async-std")]
+    impl Runtime for AsyncStd {
+        fn connect(
+            &self,
+            server: SocketAddr,
+            timeout: Duration,
+            mark: Option<u32>,
+        ) -> BoxFuture<'static, Result<DnsExchange, ProtoError>> {
+            if let Err(e) = unmarked(mark) {
+                return future::err(e).boxed();
+            }
+            let (stream, sender) =
+                TcpClientStream::<Tcp<async_std::net::TcpStream, AsyncStdTime>>::with_timeout(
+                    server, timeout,
+                );
+            exchange::<_, AsyncStdTime, _>(stream.boxed(), sender, |background| {
+                async_std::task::spawn(background);
+            })
+        }
+
+        fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+            async_std::task::sleep(duration).boxed()
+        }
+    }
+
+    /// Runs DNS clients on smol, spawning the tasks owning their connections
+    /// on its global executor.
+    #[cfg(feature = "smol")]
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct Smol;
+
+    /// Times out queries with smol's timers.
+    #[cfg(feature = "smol")]
+    struct SmolTime;
+
+    #[cfg(feature = "smol")]
+    #[async_trait]
+    impl Time for SmolTime {
+        async fn delay_for(duration: Duration) {
+            smol::Timer::after(duration).await;
+        }
+
+        async fn timeout<F: 'static + Future + Send>(
+            duration: Duration,
+            future: F,
+        ) -> io::Result<F::Output> {
+            timeout(future, smol::Timer::after(duration)).await
+        }
+    }
+
+    #[cfg(feature = "smol")]
+    #[async_trait]
+    impl Connect for Tcp<smol::net::TcpStream, SmolTime> {
+        async fn connect(addr: SocketAddr) -> io::Result<Self> {
+            smol::net::TcpStream::connect(addr).await.map(Tcp::new)
+        }
+    }
+
+    #[cfg(feature = "smol")]
+    impl Runtime for Smol {
+        fn connect(
+            &self,
+            server: SocketAddr,
+            timeout: Duration,
+            mark: Option<u32>,
+        ) -> BoxFuture<'static, Result<DnsExchange, ProtoError>> {
+            if let Err(e) = unmarked(mark) {
+                return future::err(e).boxed();
+            }
+            let (stream, sender) =
+                TcpClientStream::<Tcp<smol::net::TcpStream, SmolTime>>::with_timeout(
+                    server, timeout,
+                );
+            exchange::<_, SmolTime, _>(stream.boxed(), sender, |background| {
+                smol::spawn(background).detach();
+            })
+        }
+
+        fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+            smol::Timer::after(duration).map(drop).boxed()
+        }
+    }
+}
+
+#[cfg(all(test, any(feature = "async-std", feature = "smol")))]
+mod tests {
+    use super::*;
+    use crate::testing::{TestServer, Zone};
+    use crate::DnsResolver;
+    use std::net::Ipv4Addr;
+    use trust_dns::rr::RData;
+
+    /// A resolver querying a `TestServer` on `runtime`, without tokio.
+    fn resolver<R: Runtime + 'static>(runtime: R) -> (TestServer, DnsResolver) {
+        let mut zone = Zone::new();
+        zone.add("web.example.com", 60, RData::A(Ipv4Addr::new(192, 0, 2, 1)));
+        let server = TestServer::start(zone).unwrap();
+        let mut connector = server.connector(());
+        connector.set_runtime(runtime);
+        let resolver = connector.resolver();
+        (server, resolver)
+    }
+
+    #[cfg(feature = "async-std")]
+    #[test]
+    fn queries_on_async_std() {
+        let (_server, resolver) = resolver(AsyncStd);
+        for _ in 0..2 {
+            assert_eq!(
+                async_std::task::block_on(resolver.resolve("web.example.com", 80)).unwrap(),
+                vec!["192.0.2.1:80".parse::<SocketAddr>().unwrap()]
+            );
+        }
+    }
+
+    #[cfg(feature = "smol")]
+    #[test]
+    fn queries_on_smol() {
+        let (_server, resolver) = resolver(Smol);
+        for _ in 0..2 {
+            assert_eq!(
+                smol::block_on(resolver.resolve("web.example.com", 80)).unwrap(),
+                vec!["192.0.2.1:80".parse::<SocketAddr>().unwrap()]
+            );
+        }
+    }
+}
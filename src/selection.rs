@@ -0,0 +1,445 @@
+//! Policies for picking which resolved address to connect to.
+//!
+//! Custom policies can be plugged into the connector by implementing
+//! `SelectionPolicy`, this module provides the built in ones.
+
+use rand::Rng;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::time::Instant;
+use crate::SharedRng;
+
+/// A policy for picking which of a host's resolved addresses to connect to.
+pub trait SelectionPolicy: Send + Sync {
+    /// Pick the address to connect to for `host`, `candidates` is never empty.
+    fn select(&self, host: &str, candidates: &[IpAddr]) -> IpAddr;
+
+    /// Called when connecting to the selected `addr` for `host` succeeded.
+    fn connected(&self, _host: &str, _addr: IpAddr) {}
+
+    /// Called when connecting to the selected `addr` for `host` failed.
+    fn failed(&self, _host: &str, _addr: IpAddr) {}
+
+    /// Called when a connection to `addr` for `host`, which was previously
+    /// reported as `connected`, is closed.
+    fn released(&self, _host: &str, _addr: IpAddr) {}
+}
+
+/// Always picks the first address, in the order the DNS server returned them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct First;
+
+impl SelectionPolicy for First {
+    fn select(&self, _host: &str, candidates: &[IpAddr]) -> IpAddr {
+        candidates[0]
+    }
+}
+
+/// Picks an address at random, so that load is spread across all of the
+/// addresses a host resolves to.
+#[derive(Debug, Clone, Default)]
+pub struct Random {
+    rng: SharedRng,
+}
+
+impl Random {
+    /// Create a policy using the thread local RNG.
+    pub fn new() -> Random {
+        Random::default()
+    }
+
+    /// Create a policy using `rng`, e.g. to make selection reproducible.
+    pub fn with_rng(rng: SharedRng) -> Random {
+        Random { rng: rng }
+    }
+}
+
+impl SelectionPolicy for Random {
+    fn select(&self, _host: &str, candidates: &[IpAddr]) -> IpAddr {
+        *self
+            .rng
+            .with(|rng| rng.choose(candidates))
+            .expect("no candidate addresses")
+    }
+}
+
+/// How many hosts a round-robin rotation is kept for, after which the host
+/// selected for least recently is forgotten to make room for a new one.
+const MAX_HOSTS: usize = 10_000;
+
+/// Cycles through a host's addresses on successive connections.
+///
+/// Addresses are cycled in sorted order, so the rotation is deterministic even
+/// if the DNS server shuffles its answers. The rotation is shared by all clones
+/// of the connector.
+#[derive(Debug, Default)]
+pub struct RoundRobin {
+    /// Each host's next index, and the selection it was last used by.
+    next: Mutex<HashMap<String, (usize, usize)>>,
+    selections: AtomicUsize,
+}
+
+impl RoundRobin {
+    /// Create a new round-robin policy.
+    pub fn new() -> RoundRobin {
+        RoundRobin::default()
+    }
+}
+
+impl SelectionPolicy for RoundRobin {
+    fn select(&self, host: &str, candidates: &[IpAddr]) -> IpAddr {
+        let mut sorted = candidates.to_vec();
+        sorted.sort();
+
+        let selection = self.selections.fetch_add(1, Ordering::Relaxed);
+        let mut next = self.next.lock().unwrap();
+        if next.len() >= MAX_HOSTS && !next.contains_key(host) {
+            let oldest = next
+                .iter()
+                .min_by_key(|&(_, &(_, last))| last)
+                .map(|(host, _)| host.clone());
+            if let Some(oldest) = oldest {
+                next.remove(&oldest);
+            }
+        }
+
+        let entry = next.entry(host.to_string()).or_insert((0, selection));
+        entry.1 = selection;
+        let addr = sorted[entry.0 % sorted.len()];
+        entry.0 = entry.0.wrapping_add(1);
+        addr
+    }
+}
+
+/// Picks an address at random, with each address' chance of being picked
+/// proportional to its weight.
+///
+/// Addresses without a configured weight have a weight of 1, and addresses with
+/// a weight of 0 are only picked if every candidate has a weight of 0.
+#[derive(Debug, Clone, Default)]
+pub struct Weighted {
+    weights: HashMap<IpAddr, u32>,
+    rng: SharedRng,
+}
+
+impl Weighted {
+    /// Create a policy using the given weights for addresses.
+    pub fn new<I>(weights: I) -> Weighted
+    where
+        I: IntoIterator<Item = (IpAddr, u32)>,
+    {
+        Weighted {
+            weights: weights.into_iter().collect(),
+            rng: SharedRng::thread(),
+        }
+    }
+
+    /// Use `rng` rather than the thread local RNG.
+    pub fn with_rng(mut self, rng: SharedRng) -> Weighted {
+        self.rng = rng;
+        self
+    }
+
+    fn weight(&self, addr: &IpAddr) -> u32 {
+        self.weights.get(addr).cloned().unwrap_or(1)
+    }
+}
+
+impl SelectionPolicy for Weighted {
+    fn select(&self, host: &str, candidates: &[IpAddr]) -> IpAddr {
+        let total: u64 = candidates
+            .iter()
+            .map(|addr| u64::from(self.weight(addr)))
+            .sum();
+        if total == 0 {
+            return Random::with_rng(self.rng.clone()).select(host, candidates);
+        }
+
+        let mut point = self.rng.with(|rng| rng.gen_range(0, total));
+        for addr in candidates {
+            let weight = u64::from(self.weight(addr));
+            if point < weight {
+                return *addr;
+            }
+            point -= weight;
+        }
+
+        candidates[candidates.len() - 1]
+    }
+}
+
+/// Prefers addresses which have not failed recently, picking among the
+/// addresses with the fewest recent connection failures using another policy.
+///
+/// A failure is forgotten once `window` has passed without the address failing
+/// again, or as soon as a connection to the address succeeds.
+#[derive(Debug)]
+pub struct FailureAware<P> {
+    inner: P,
+    window: Duration,
+    failures: Mutex<HashMap<IpAddr, (u32, Instant)>>,
+}
+
+impl<P: SelectionPolicy> FailureAware<P> {
+    /// Wrap `inner`, remembering failures for `window`.
+    pub fn new(inner: P, window: Duration) -> FailureAware<P> {
+        FailureAware {
+            inner: inner,
+            window: window,
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<P: SelectionPolicy> SelectionPolicy for FailureAware<P> {
+    fn select(&self, host: &str, candidates: &[IpAddr]) -> IpAddr {
+        let preferred = {
+            let mut failures = self.failures.lock().unwrap();
+            let window = self.window;
+            failures.retain(|_, &mut (_, last)| last.elapsed() < window);
+
+            let recent = |addr: &IpAddr| failures.get(addr).map(|&(count, _)| count).unwrap_or(0);
+            let fewest = candidates.iter().map(&recent).min().unwrap_or(0);
+
+            candidates
+                .iter()
+                .cloned()
+                .filter(|addr| recent(addr) == fewest)
+                .collect::<Vec<_>>()
+        };
+
+        self.inner.select(host, &preferred)
+    }
+
+    fn connected(&self, host: &str, addr: IpAddr) {
+        self.failures.lock().unwrap().remove(&addr);
+        self.inner.connected(host, addr);
+    }
+
+    fn failed(&self, host: &str, addr: IpAddr) {
+        {
+            let mut failures = self.failures.lock().unwrap();
+            let entry = failures.entry(addr).or_insert((0, Instant::now()));
+            entry.0 += 1;
+            entry.1 = Instant::now();
+        }
+        self.inner.failed(host, addr);
+    }
+
+    fn released(&self, host: &str, addr: IpAddr) {
+        self.inner.released(host, addr);
+    }
+}
+
+/// Prefers the addresses with the fewest open connections, picking among them
+/// using another policy. This gives a basic client side load balancer across
+/// the addresses a host resolves to.
+#[derive(Debug)]
+pub struct LeastConnections<P> {
+    inner: P,
+    open: Mutex<HashMap<IpAddr, usize>>,
+}
+
+impl<P: SelectionPolicy> LeastConnections<P> {
+    /// Wrap `inner`, which picks between equally loaded addresses.
+    pub fn new(inner: P) -> LeastConnections<P> {
+        LeastConnections {
+            inner: inner,
+            open: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<P: SelectionPolicy> SelectionPolicy for LeastConnections<P> {
+    fn select(&self, host: &str, candidates: &[IpAddr]) -> IpAddr {
+        let preferred = {
+            let open = self.open.lock().unwrap();
+            let count = |addr: &IpAddr| open.get(addr).cloned().unwrap_or(0);
+            let fewest = candidates.iter().map(&count).min().unwrap_or(0);
+
+            candidates
+                .iter()
+                .cloned()
+                .filter(|addr| count(addr) == fewest)
+                .collect::<Vec<_>>()
+        };
+
+        self.inner.select(host, &preferred)
+    }
+
+    fn connected(&self, host: &str, addr: IpAddr) {
+        *self.open.lock().unwrap().entry(addr).or_insert(0) += 1;
+        self.inner.connected(host, addr);
+    }
+
+    fn failed(&self, host: &str, addr: IpAddr) {
+        self.inner.failed(host, addr);
+    }
+
+    fn released(&self, host: &str, addr: IpAddr) {
+        {
+            let mut open = self.open.lock().unwrap();
+            let remove = match open.get_mut(&addr) {
+                Some(count) => {
+                    *count -= 1;
+                    *count == 0
+                }
+                None => false,
+            };
+            if remove {
+                open.remove(&addr);
+            }
+        }
+        self.inner.released(host, addr);
+    }
+}
+
+/// Consistently maps a host, or a fixed affinity key, to the same address for
+/// as long as that address is among the candidates.
+///
+/// This uses rendezvous hashing, so when an address disappears only the keys
+/// which mapped to it move elsewhere. Wrap it in `FailureAware` to move keys
+/// away from addresses which are failing.
+#[derive(Debug, Clone, Default)]
+pub struct ConsistentHash {
+    key: Option<String>,
+}
+
+impl ConsistentHash {
+    /// Create a policy which maps each host to its own address.
+    pub fn new() -> ConsistentHash {
+        ConsistentHash::default()
+    }
+
+    /// Create a policy which maps every host using the same affinity `key`.
+    pub fn with_key<S: Into<String>>(key: S) -> ConsistentHash {
+        ConsistentHash {
+            key: Some(key.into()),
+        }
+    }
+}
+
+impl SelectionPolicy for ConsistentHash {
+    fn select(&self, host: &str, candidates: &[IpAddr]) -> IpAddr {
+        let key = self.key.as_ref().map(|key| &key[..]).unwrap_or(host);
+
+        *candidates
+            .iter()
+            .max_by_key(|addr| {
+                let mut hasher = DefaultHasher::new();
+                key.hash(&mut hasher);
+                addr.hash(&mut hasher);
+                hasher.finish()
+            })
+            .expect("no candidate addresses")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_robin_cycles_per_host() {
+        let policy = RoundRobin::new();
+        let a: IpAddr = "10.0.0.1".parse().unwrap();
+        let b: IpAddr = "10.0.0.2".parse().unwrap();
+
+        assert_eq!(policy.select("foo", &[b, a]), a);
+        assert_eq!(policy.select("foo", &[a, b]), b);
+        assert_eq!(policy.select("bar", &[a, b]), a);
+        assert_eq!(policy.select("foo", &[b, a]), a);
+    }
+
+    #[test]
+    fn round_robin_forgets_least_recently_selected_hosts() {
+        let policy = RoundRobin::new();
+        let a: IpAddr = "10.0.0.1".parse().unwrap();
+        let b: IpAddr = "10.0.0.2".parse().unwrap();
+
+        for i in 0..MAX_HOSTS {
+            policy.select(&format!("host{}", i), &[a, b]);
+        }
+        policy.select("host0", &[a, b]);
+        policy.select("new", &[a, b]);
+
+        let next = policy.next.lock().unwrap();
+        assert_eq!(next.len(), MAX_HOSTS);
+        assert_eq!(next["host0"].0, 2);
+        assert!(!next.contains_key("host1"));
+        assert!(next.contains_key("new"));
+    }
+
+    #[test]
+    fn weighted_skips_zero_weights() {
+        let a: IpAddr = "10.0.0.1".parse().unwrap();
+        let b: IpAddr = "10.0.0.2".parse().unwrap();
+        let policy = Weighted::new(vec![(a, 0), (b, 5)]);
+
+        for _ in 0..10 {
+            assert_eq!(policy.select("foo", &[a, b]), b);
+        }
+    }
+
+    #[test]
+    fn seeded_random_is_reproducible() {
+        let addrs: Vec<IpAddr> = (1..6)
+            .map(|i| format!("10.0.0.{}", i).parse().unwrap())
+            .collect();
+        let first = Random::with_rng(SharedRng::seeded(7));
+        let second = Random::with_rng(SharedRng::seeded(7));
+
+        for _ in 0..10 {
+            assert_eq!(first.select("foo", &addrs), second.select("foo", &addrs));
+        }
+    }
+
+    #[test]
+    fn failure_aware_avoids_failed_addresses() {
+        let policy = FailureAware::new(First, Duration::from_secs(60));
+        let a: IpAddr = "10.0.0.1".parse().unwrap();
+        let b: IpAddr = "10.0.0.2".parse().unwrap();
+
+        policy.failed("foo", a);
+        assert_eq!(policy.select("foo", &[a, b]), b);
+
+        policy.connected("foo", a);
+        assert_eq!(policy.select("foo", &[a, b]), a);
+    }
+
+    #[test]
+    fn least_connections_prefers_idle_addresses() {
+        let policy = LeastConnections::new(First);
+        let a: IpAddr = "10.0.0.1".parse().unwrap();
+        let b: IpAddr = "10.0.0.2".parse().unwrap();
+
+        policy.connected("foo", a);
+        assert_eq!(policy.select("foo", &[a, b]), b);
+
+        policy.released("foo", a);
+        assert_eq!(policy.select("foo", &[a, b]), a);
+    }
+
+    #[test]
+    fn consistent_hash_is_sticky() {
+        let policy = ConsistentHash::new();
+        let addrs: Vec<IpAddr> = (1..6)
+            .map(|i| format!("10.0.0.{}", i).parse().unwrap())
+            .collect();
+
+        let picked = policy.select("foo", &addrs);
+        let mut reversed = addrs.clone();
+        reversed.reverse();
+        assert_eq!(policy.select("foo", &reversed), picked);
+
+        let others: Vec<IpAddr> = addrs.iter().cloned().filter(|a| *a != picked).collect();
+        let moved = policy.select("foo", &others);
+        assert!(moved != picked);
+    }
+}
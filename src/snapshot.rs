@@ -0,0 +1,92 @@
+//! A JSON snapshot of the resolver's state, enabled by the `json` feature.
+
+use serde_json::Value;
+use std::net::SocketAddr;
+
+use crate::stats::Stats;
+use crate::time::UNIX_EPOCH;
+use crate::Redaction;
+
+/// Summarize `stats` and the configured `nameservers` (with their weights),
+/// redacting hosts with `redaction`.
+pub fn snapshot(stats: &Stats, nameservers: &[(SocketAddr, u32)], redaction: Redaction) -> Value {
+    let health = stats.nameservers();
+    let mut addrs: Vec<SocketAddr> = nameservers.iter().map(|&(addr, _)| addr).collect();
+    for addr in health.keys() {
+        if !addrs.contains(addr) {
+            addrs.push(*addr);
+        }
+    }
+
+    let nameservers: Vec<Value> = addrs
+        .iter()
+        .map(|addr| {
+            let weight = nameservers
+                .iter()
+                .find(|&&(server, _)| server == *addr)
+                .map(|&(_, weight)| weight);
+            let health = health.get(addr).cloned().unwrap_or_default();
+            let srtt = health
+                .srtt
+                .map(|srtt| srtt.as_secs() * 1000 + u64::from(srtt.subsec_millis()));
+            json!({
+                "addr": addr.to_string(),
+                "weight": weight,
+                "healthy": health.healthy(),
+                "successes": health.successes,
+                "failures": health.failures,
+                "consecutive_failures": health.consecutive_failures,
+                "last_error": health.last_error,
+                "srtt_ms": srtt,
+            })
+        })
+        .collect();
+
+    let hosts = stats.snapshot();
+    let entries: serde_json::Map<String, Value> = hosts
+        .iter()
+        .map(|(host, stats)| {
+            let addrs: Vec<String> = stats
+                .last_addrs
+                .iter()
+                .map(|addr| addr.to_string())
+                .collect();
+            (
+                redaction.apply(host).into_owned(),
+                json!({
+                    "lookups": stats.lookups,
+                    "failures": stats.failures,
+                    "last_addrs": addrs,
+                    "last_ttl": stats.last_ttl,
+                }),
+            )
+        })
+        .collect();
+
+    let errors: Vec<Value> = stats
+        .recent_errors()
+        .into_iter()
+        .map(|error| {
+            let time = error
+                .time
+                .duration_since(UNIX_EPOCH)
+                .map(|time| time.as_secs())
+                .unwrap_or(0);
+            json!({
+                "host": redaction.apply(&error.host),
+                "error": error.error,
+                "unix_time": time,
+            })
+        })
+        .collect();
+
+    json!({
+        "nameservers": nameservers,
+        "hosts": {
+            "count": hosts.len(),
+            "entries": entries,
+        },
+        "failures": stats.failure_classes(),
+        "recent_errors": errors,
+    })
+}
@@ -0,0 +1,299 @@
+//! Per-host resolution statistics.
+
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::counters::failure_class;
+use crate::time::SystemTime;
+use crate::Resolution;
+
+/// Statistics for resolving a single host.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HostStats {
+    /// How many times the host has been resolved, including failures.
+    pub lookups: u64,
+    /// How many times resolving the host failed.
+    pub failures: u64,
+    /// The addresses the host last resolved to.
+    pub last_addrs: Vec<IpAddr>,
+    /// The TTL of the records the host last resolved from, if known.
+    pub last_ttl: Option<u32>,
+}
+
+/// How many hosts statistics are kept for, after which the host resolved
+/// least recently is forgotten to make room for a new one.
+const MAX_HOSTS: usize = 10_000;
+
+/// How many of the most recent errors are kept.
+const RECENT_ERRORS: usize = 32;
+
+/// How many consecutive lookups must fail with the nameserver not answering
+/// (timeouts, transport errors and `SERVFAIL`) before it is unhealthy.
+const UNHEALTHY_AFTER: u32 = 3;
+
+/// How lookups sent to a nameserver have fared.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NameserverStats {
+    /// Lookups which returned addresses.
+    pub successes: u64,
+    /// Lookups which failed, including those with no usable answers.
+    pub failures: u64,
+    /// How many lookups in a row have failed with the nameserver not
+    /// answering. Failures such as `NXDOMAIN` are answers, so reset this.
+    pub consecutive_failures: u32,
+    /// The error the most recent failed lookup failed with.
+    pub last_error: Option<String>,
+    /// The smoothed time taken for the nameserver to answer lookups, once it
+    /// has answered one.
+    pub srtt: Option<Duration>,
+}
+
+impl NameserverStats {
+    /// Whether the nameserver is answering lookups, i.e. fewer than three in a
+    /// row have failed with it not answering.
+    pub fn healthy(&self) -> bool {
+        self.consecutive_failures < UNHEALTHY_AFTER
+    }
+}
+
+/// A recent failure to resolve a host.
+#[derive(Debug, Clone)]
+pub struct RecentError {
+    /// The host which failed to resolve.
+    pub host: String,
+    /// The error it failed with.
+    pub error: String,
+    /// When it failed.
+    pub time: SystemTime,
+}
+
+/// Statistics for every host which has been resolved.
+#[derive(Debug, Default)]
+pub struct Stats {
+    /// Each host's statistics, and the lookup it was last resolved by.
+    hosts: Mutex<HashMap<String, (HostStats, usize)>>,
+    lookups: AtomicUsize,
+    nameservers: Mutex<HashMap<SocketAddr, NameserverStats>>,
+    errors: Mutex<VecDeque<RecentError>>,
+    failure_classes: Mutex<HashMap<&'static str, u64>>,
+}
+
+impl Stats {
+    /// Record that resolving `host` succeeded.
+    pub fn resolved(&self, host: &str, resolution: &Resolution) {
+        let mut hosts = self.hosts.lock().unwrap();
+        let stats = host_stats(
+            &mut hosts,
+            host,
+            self.lookups.fetch_add(1, Ordering::Relaxed),
+        );
+        stats.lookups += 1;
+        stats.last_addrs = resolution.addrs.clone();
+        stats.last_ttl = resolution.ttl;
+    }
+
+    /// Record that resolving `host` failed with `err`.
+    pub fn failed(&self, host: &str, err: &io::Error) {
+        {
+            let mut hosts = self.hosts.lock().unwrap();
+            let stats = host_stats(
+                &mut hosts,
+                host,
+                self.lookups.fetch_add(1, Ordering::Relaxed),
+            );
+            stats.lookups += 1;
+            stats.failures += 1;
+        }
+
+        *self
+            .failure_classes
+            .lock()
+            .unwrap()
+            .entry(failure_class(err))
+            .or_insert(0) += 1;
+
+        let mut errors = self.errors.lock().unwrap();
+        if errors.len() == RECENT_ERRORS {
+            errors.pop_front();
+        }
+        errors.push_back(RecentError {
+            host: host.to_string(),
+            error: err.to_string(),
+            time: SystemTime::now(),
+        });
+    }
+
+    /// Record that a lookup sent to `nameserver` took `elapsed`, and the error
+    /// it failed with if it did.
+    pub fn nameserver(&self, nameserver: SocketAddr, elapsed: Duration, err: Option<&io::Error>) {
+        let mut nameservers = self.nameservers.lock().unwrap();
+        let stats = nameservers.entry(nameserver).or_default();
+
+        // `None` if the lookup was never sent to the nameserver.
+        let answered = match err {
+            None => {
+                stats.successes += 1;
+                Some(true)
+            }
+            Some(err) => {
+                stats.failures += 1;
+                stats.last_error = Some(err.to_string());
+                match failure_class(err) {
+                    "timeout" | "transport" | "servfail" => Some(false),
+                    "rate_limited" => None,
+                    _ => Some(true),
+                }
+            }
+        };
+
+        match answered {
+            Some(true) => {
+                stats.consecutive_failures = 0;
+                stats.srtt = Some(match stats.srtt {
+                    Some(srtt) => (srtt * 7 + elapsed) / 8,
+                    None => elapsed,
+                });
+            }
+            Some(false) => stats.consecutive_failures += 1,
+            None => {}
+        }
+    }
+
+    /// A snapshot of the statistics for each nameserver.
+    pub fn nameservers(&self) -> HashMap<SocketAddr, NameserverStats> {
+        self.nameservers.lock().unwrap().clone()
+    }
+
+    /// How many failures there have been of each class.
+    pub fn failure_classes(&self) -> HashMap<&'static str, u64> {
+        self.failure_classes.lock().unwrap().clone()
+    }
+
+    /// The most recent errors, oldest first.
+    pub fn recent_errors(&self) -> Vec<RecentError> {
+        self.errors.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// A snapshot of the statistics for each host.
+    pub fn snapshot(&self) -> HashMap<String, HostStats> {
+        let hosts = self.hosts.lock().unwrap();
+        hosts
+            .iter()
+            .map(|(host, (stats, _))| (host.clone(), stats.clone()))
+            .collect()
+    }
+}
+
+/// The statistics for `host`, marked as resolved by `lookup`, forgetting the
+/// host resolved least recently if there are too many.
+fn host_stats<'a>(
+    hosts: &'a mut HashMap<String, (HostStats, usize)>,
+    host: &str,
+    lookup: usize,
+) -> &'a mut HostStats {
+    if hosts.len() >= MAX_HOSTS && !hosts.contains_key(host) {
+        let oldest = hosts
+            .iter()
+            .min_by_key(|&(_, &(_, last))| last)
+            .map(|(host, _)| host.clone());
+        if let Some(oldest) = oldest {
+            hosts.remove(&oldest);
+        }
+    }
+
+    let entry = hosts
+        .entry(host.to_string())
+        .or_insert_with(|| (HostStats::default(), lookup));
+    entry.1 = lookup;
+    &mut entry.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+
+    #[test]
+    fn tracks_lookups_per_host() {
+        let stats = Stats::default();
+        let addr: IpAddr = "10.0.0.1".parse().unwrap();
+        let resolution = Resolution {
+            ip: addr,
+            addrs: vec![addr],
+            port: None,
+            record_type: None,
+            ttl: Some(30),
+            nameserver: None,
+            chain: Vec::new(),
+        };
+
+        stats.resolved("foo", &resolution);
+        let err = io::Error::other("No valid DNS answers");
+        stats.failed("foo", &err);
+        stats.failed("bar", &err);
+
+        let snapshot = stats.snapshot();
+        assert_eq!(
+            snapshot["foo"],
+            HostStats {
+                lookups: 2,
+                failures: 1,
+                last_addrs: vec![addr],
+                last_ttl: Some(30),
+            }
+        );
+        assert_eq!(snapshot["bar"].failures, 1);
+        assert!(snapshot["bar"].last_addrs.is_empty());
+
+        let errors = stats.recent_errors();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[1].host, "bar");
+        assert_eq!(stats.failure_classes()["other"], 2);
+    }
+
+    #[test]
+    fn forgets_least_recently_resolved_hosts() {
+        let stats = Stats::default();
+        let err = io::Error::other("No valid DNS answers");
+        for i in 0..MAX_HOSTS {
+            stats.failed(&format!("host{}", i), &err);
+        }
+        stats.failed("host0", &err);
+        stats.failed("new", &err);
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.len(), MAX_HOSTS);
+        assert!(snapshot.contains_key("host0"));
+        assert!(!snapshot.contains_key("host1"));
+        assert!(snapshot.contains_key("new"));
+    }
+
+    #[test]
+    fn tracks_nameserver_health() {
+        let stats = Stats::default();
+        let nameserver: SocketAddr = "127.0.0.1:53".parse().unwrap();
+        let timeout = io::Error::from(Error::Timeout);
+        let nxdomain = io::Error::from(Error::NxDomain("foo.".into()));
+
+        stats.nameserver(nameserver, Duration::from_millis(80), None);
+        stats.nameserver(nameserver, Duration::from_millis(160), Some(&nxdomain));
+        for _ in 0..3 {
+            stats.nameserver(nameserver, Duration::from_secs(30), Some(&timeout));
+        }
+
+        let health = &stats.nameservers()[&nameserver];
+        assert_eq!(health.successes, 1);
+        assert_eq!(health.failures, 4);
+        assert_eq!(health.consecutive_failures, 3);
+        assert_eq!(health.last_error, Some(timeout.to_string()));
+        assert_eq!(health.srtt, Some(Duration::from_millis(90)));
+        assert!(!health.healthy());
+
+        stats.nameserver(nameserver, Duration::from_millis(90), None);
+        assert!(stats.nameservers()[&nameserver].healthy());
+    }
+}
@@ -0,0 +1,307 @@
+//! Utilities for testing clients which use the connector: a resolver
+//! answering without a DNS server, and a DNS server on localhost.
+
+use futures::future::{self, BoxFuture, FutureExt};
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use trust_dns::op::ResponseCode;
+use trust_dns::rr::rdata::SRV;
+use trust_dns::rr::{Name, RData, Record, RecordType};
+use trust_dns_proto::xfer::DnsResponse;
+
+use crate::backend::{self, Backend};
+use crate::runtime;
+use crate::DnsConnector;
+
+pub use crate::testserver::{TestServer, Zone};
+
+/// The TTL of programmed records.
+const TTL: u32 = 60;
+
+/// A backend answering queries with programmed records, and failing or
+/// delaying them as told to, so that hyper clients can be unit tested with
+/// the connector's resolution stack but no DNS server.
+///
+/// Hosts with nothing programmed don't exist. Clones share what has been
+/// programmed, so hosts can be changed after the resolver has been given
+/// to a connector.
+///
+/// # Panics
+///
+/// The methods programming hosts panic if given a name which isn't valid.
+#[derive(Clone, Default)]
+pub struct StaticResolver {
+    hosts: Arc<Mutex<HashMap<Name, Host>>>,
+}
+
+#[derive(Default)]
+struct Host {
+    records: Vec<Record>,
+    response_code: Option<ResponseCode>,
+    error: Option<io::ErrorKind>,
+    delay: Option<Duration>,
+}
+
+impl StaticResolver {
+    /// A resolver with nothing programmed, for which no hosts exist.
+    pub fn new() -> StaticResolver {
+        StaticResolver::default()
+    }
+
+    /// Answer A or AAAA queries for `host` with `addr`, as well as any
+    /// addresses added before.
+    pub fn add_ip(&self, host: &str, addr: IpAddr) {
+        let name = parse(host);
+        let record = match addr {
+            IpAddr::V4(addr) => Record::from_rdata(name.clone(), TTL, RData::A(addr)),
+            IpAddr::V6(addr) => Record::from_rdata(name.clone(), TTL, RData::AAAA(addr)),
+        };
+        self.with_host(name, |host| host.records.push(record));
+    }
+
+    /// Answer SRV queries for `name`, e.g. `_http._tcp.example.com`, with a
+    /// record pointing at port `port` of `target`, whose addresses are
+    /// included in the response if they have been added.
+    pub fn add_srv(&self, name: &str, priority: u16, weight: u16, port: u16, target: &str) {
+        let name = parse(name);
+        let srv = SRV::new(priority, weight, port, parse(target));
+        let record = Record::from_rdata(name.clone(), TTL, RData::SRV(srv));
+        self.with_host(name, |host| host.records.push(record));
+    }
+
+    /// Respond to queries for `host` with `response_code` and no records,
+    /// e.g. `ResponseCode::ServFail`.
+    pub fn set_response_code(&self, host: &str, response_code: ResponseCode) {
+        self.with_host(parse(host), |host| host.response_code = Some(response_code));
+    }
+
+    /// Fail queries for `host` with an error of `kind`, as if the server
+    /// couldn't be reached, e.g. `io::ErrorKind::TimedOut`.
+    pub fn set_error(&self, host: &str, kind: io::ErrorKind) {
+        self.with_host(parse(host), |host| host.error = Some(kind));
+    }
+
+    /// Answer queries for `host` only after `delay`, waited for with the
+    /// default runtime, e.g. needing the query to be made on a tokio runtime
+    /// with the timer enabled with the `tokio` feature.
+    pub fn set_delay(&self, host: &str, delay: Duration) {
+        self.with_host(parse(host), |host| host.delay = Some(delay));
+    }
+
+    /// Forget everything programmed for `host`, so that it doesn't exist.
+    pub fn remove(&self, host: &str) {
+        self.hosts.lock().unwrap().remove(&parse(host));
+    }
+
+    /// A connector wrapping `connector` which resolves hosts with this
+    /// resolver, configured as `DnsConnector::new` would be.
+    pub fn connector<C>(&self, connector: C) -> DnsConnector<C> {
+        let mut dns = DnsConnector::new("127.0.0.1:53".parse().unwrap(), connector);
+        dns.set_backend(self.clone());
+        dns
+    }
+
+    fn with_host<F>(&self, name: Name, f: F)
+    where
+        F: FnOnce(&mut Host),
+    {
+        f(self.hosts.lock().unwrap().entry(name).or_default());
+    }
+
+    /// The response to a query for `record_type` records of `name`.
+    fn answer(&self, name: Name, record_type: RecordType) -> io::Result<DnsResponse> {
+        let hosts = self.hosts.lock().unwrap();
+        let host = match hosts.get(&name.to_lowercase()) {
+            Some(host) => host,
+            None => {
+                return Ok(backend::response(
+                    name,
+                    record_type,
+                    ResponseCode::NXDomain,
+                    Vec::new(),
+                ))
+            }
+        };
+
+        if let Some(kind) = host.error {
+            return Err(io::Error::new(kind, format!("injected error for {}", name)));
+        }
+        if let Some(response_code) = host.response_code {
+            return Ok(backend::response(
+                name,
+                record_type,
+                response_code,
+                Vec::new(),
+            ));
+        }
+
+        let answers: Vec<Record> = host
+            .records
+            .iter()
+            .filter(|record| record.rr_type() == record_type)
+            .cloned()
+            .collect();
+        let targets: Vec<Name> = answers
+            .iter()
+            .filter_map(|record| match *record.rdata() {
+                RData::SRV(ref srv) => Some(srv.target().to_lowercase()),
+                _ => None,
+            })
+            .collect();
+
+        let mut res = backend::response(name, record_type, ResponseCode::NoError, answers);
+        for target in targets {
+            if let Some(target) = hosts.get(&target) {
+                for record in &target.records {
+                    match record.rr_type() {
+                        RecordType::A | RecordType::AAAA => {
+                            res.add_additional(record.clone());
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        Ok(res)
+    }
+}
+
+impl Backend for StaticResolver {
+    fn query(
+        &self,
+        _server: SocketAddr,
+        name: Name,
+        record_type: RecordType,
+    ) -> BoxFuture<'static, io::Result<DnsResponse>> {
+        let delay = self
+            .hosts
+            .lock()
+            .unwrap()
+            .get(&name.to_lowercase())
+            .and_then(|host| host.delay);
+        let res = self.answer(name, record_type);
+
+        match delay {
+            Some(delay) => runtime::default().sleep(delay).map(move |()| res).boxed(),
+            None => future::ready(res).boxed(),
+        }
+    }
+}
+
+impl fmt::Debug for StaticResolver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("StaticResolver").finish()
+    }
+}
+
+/// `host` as a fully qualified, lowercase name, as queries are made for.
+fn parse(host: &str) -> Name {
+    let fqdn = if host.ends_with('.') {
+        host.to_string()
+    } else {
+        format!("{}.", host)
+    };
+    Name::parse(&fqdn, None)
+        .unwrap_or_else(|_| panic!("invalid host: {}", host))
+        .to_lowercase()
+}
+
+/// Check that an adapter resolves hosts with the `DnsResolver` it's made
+/// from, to each of their addresses in the order picked, and fails for hosts
+/// which don't exist with the resolver's error. `resolve` makes an adapter
+/// from the resolver and resolves a host with it to completion.
+#[cfg(all(test, any(feature = "tower", feature = "hyper-012")))]
+pub(crate) fn check_adapter<F>(resolve: F)
+where
+    F: Fn(crate::DnsResolver, &str) -> io::Result<Vec<IpAddr>>,
+{
+    let addrs: Vec<IpAddr> = vec!["192.0.2.1".parse().unwrap(), "192.0.2.2".parse().unwrap()];
+    let hosts = StaticResolver::new();
+    for &addr in &addrs {
+        hosts.add_ip("web.example.com", addr);
+    }
+    let mut connector = hosts.connector(());
+    connector.set_selection_policy(crate::selection::First);
+
+    assert_eq!(
+        resolve(connector.resolver(), "web.example.com").unwrap(),
+        addrs
+    );
+
+    let err = resolve(connector.resolver(), "missing.example.com").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    match err.get_ref().and_then(|e| e.downcast_ref::<crate::Error>()) {
+        Some(crate::Error::NxDomain(_)) => {}
+        other => panic!("unexpected error: {:?}", other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    fn query(
+        resolver: &StaticResolver,
+        host: &str,
+        record_type: RecordType,
+    ) -> io::Result<DnsResponse> {
+        block_on(resolver.query("127.0.0.1:53".parse().unwrap(), parse(host), record_type))
+    }
+
+    #[test]
+    fn answers_programmed_queries() {
+        let resolver = StaticResolver::new();
+        resolver.add_ip("Web.example.com", "192.0.2.1".parse().unwrap());
+        resolver.add_ip("web.example.com", "2001:db8::1".parse().unwrap());
+        resolver.add_srv("_http._tcp.example.com", 0, 0, 8080, "web.example.com");
+
+        let res = query(&resolver, "web.example.com", RecordType::A).unwrap();
+        assert_eq!(res.answers().len(), 1);
+        assert_eq!(
+            *res.answers()[0].rdata(),
+            RData::A("192.0.2.1".parse().unwrap())
+        );
+
+        let res = query(&resolver, "_http._tcp.example.com", RecordType::SRV).unwrap();
+        assert_eq!(res.answers().len(), 1);
+        assert_eq!(res.additionals().len(), 2);
+
+        let res = query(&resolver, "missing.example.com", RecordType::A).unwrap();
+        assert_eq!(res.response_code(), ResponseCode::NXDomain);
+    }
+
+    #[test]
+    fn injects_failures() {
+        let resolver = StaticResolver::new();
+        resolver.set_response_code("broken.example.com", ResponseCode::ServFail);
+        resolver.set_error("down.example.com", io::ErrorKind::TimedOut);
+
+        let res = query(&resolver, "broken.example.com", RecordType::A).unwrap();
+        assert_eq!(res.response_code(), ResponseCode::ServFail);
+
+        let err = query(&resolver, "down.example.com", RecordType::A).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+        resolver.remove("down.example.com");
+        let res = query(&resolver, "down.example.com", RecordType::A).unwrap();
+        assert_eq!(res.response_code(), ResponseCode::NXDomain);
+    }
+
+    #[test]
+    fn connectors_resolve_with_it() {
+        let resolver = StaticResolver::new();
+        resolver.add_ip("web.example.com", "192.0.2.1".parse().unwrap());
+
+        let dns = resolver.connector(()).resolver();
+        assert_eq!(
+            block_on(dns.resolve("web.example.com", 443)).unwrap(),
+            vec!["192.0.2.1:443".parse::<SocketAddr>().unwrap()]
+        );
+        assert!(block_on(dns.resolve("missing.example.com", 443)).is_err());
+    }
+}
@@ -0,0 +1,374 @@
+//! A DNS server on localhost serving a zone, for integration tests of the
+//! connector's full path, over both UDP and TCP.
+//!
+//! The connector queries over TCP, so it is served the same answers as UDP
+//! clients such as `dig`.
+
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use trust_dns::op::{Message, MessageType, ResponseCode};
+use trust_dns::rr::{Name, RData, Record, RecordType};
+
+use crate::DnsConnector;
+
+/// How often the server's threads check whether it has been stopped.
+const POLL: Duration = Duration::from_millis(50);
+
+/// How many CNAME records are followed within the zone for an answer.
+const MAX_CNAMES: usize = 8;
+
+/// The records a `TestServer` serves, and how it misbehaves.
+#[derive(Debug, Clone, Default)]
+pub struct Zone {
+    records: HashMap<Name, Vec<Record>>,
+    silent: HashSet<Name>,
+}
+
+impl Zone {
+    /// A zone with no records, in which no names exist.
+    pub fn new() -> Zone {
+        Zone::default()
+    }
+
+    /// Serve `rdata` for `name` with a TTL of `ttl` seconds.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` isn't a valid name.
+    pub fn add(&mut self, name: &str, ttl: u32, rdata: RData) {
+        let name = parse(name);
+        let record = Record::from_rdata(name.clone(), ttl, rdata);
+        self.records.entry(name).or_default().push(record);
+    }
+
+    /// Never answer queries for `name`, so that they time out.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` isn't a valid name.
+    pub fn set_silent(&mut self, name: &str) {
+        self.silent.insert(parse(name));
+    }
+
+    /// The response to the query in `buf`, or `None` if it shouldn't be
+    /// answered.
+    fn respond(&self, buf: &[u8]) -> Option<Vec<u8>> {
+        let request = Message::from_vec(buf).ok()?;
+        let query = request.queries().first()?.clone();
+        let name = query.name().to_lowercase();
+        if self.silent.contains(&name) {
+            return None;
+        }
+
+        let mut response = Message::new();
+        response
+            .set_id(request.id())
+            .set_message_type(MessageType::Response)
+            .set_op_code(request.op_code())
+            .set_authoritative(true)
+            .set_recursion_desired(request.recursion_desired())
+            .add_query(query.clone());
+
+        if !self.records.contains_key(&name) {
+            response.set_response_code(ResponseCode::NXDomain);
+            return response.to_vec().ok();
+        }
+
+        let answers = self.answers(name, query.query_type());
+
+        // Clients find the addresses of SRV targets in the additional records.
+        let targets: Vec<Name> = answers
+            .iter()
+            .filter_map(|record| match *record.rdata() {
+                RData::SRV(ref srv) => Some(srv.target().to_lowercase()),
+                _ => None,
+            })
+            .collect();
+        for records in targets.iter().filter_map(|target| self.records.get(target)) {
+            for record in records {
+                match record.rr_type() {
+                    RecordType::A | RecordType::AAAA => {
+                        response.add_additional(record.clone());
+                    }
+                    _ => {}
+                }
+            }
+        }
+        response.add_answers(answers);
+        response.to_vec().ok()
+    }
+
+    /// The records of `record_type` for `name`, following CNAME records
+    /// within the zone.
+    fn answers(&self, mut name: Name, record_type: RecordType) -> Vec<Record> {
+        let mut answers = Vec::new();
+        for _ in 0..MAX_CNAMES {
+            let records = match self.records.get(&name) {
+                Some(records) => records,
+                None => break,
+            };
+            answers.extend(
+                records
+                    .iter()
+                    .filter(|record| record.rr_type() == record_type)
+                    .cloned(),
+            );
+            if record_type == RecordType::CNAME || !answers.is_empty() {
+                break;
+            }
+
+            let cname = records
+                .iter()
+                .filter_map(|record| match *record.rdata() {
+                    RData::CNAME(ref target) => Some((record.clone(), target.to_lowercase())),
+                    _ => None,
+                })
+                .next();
+            match cname {
+                Some((record, target)) => {
+                    answers.push(record);
+                    name = target;
+                }
+                None => break,
+            }
+        }
+        answers
+    }
+}
+
+/// A DNS server on localhost, serving a `Zone` over UDP and TCP on the same
+/// port from background threads until it is dropped.
+#[derive(Debug)]
+pub struct TestServer {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+}
+
+impl TestServer {
+    /// Start serving `zone` on a free port.
+    pub fn start(zone: Zone) -> io::Result<TestServer> {
+        let tcp = TcpListener::bind("127.0.0.1:0")?;
+        let addr = tcp.local_addr()?;
+        let udp = UdpSocket::bind(addr)?;
+        tcp.set_nonblocking(true)?;
+        udp.set_read_timeout(Some(POLL))?;
+
+        let zone = Arc::new(zone);
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let (udp_zone, udp_stop) = (zone.clone(), stop.clone());
+        thread::Builder::new()
+            .name("hyper-dns-test-udp".to_string())
+            .spawn(move || serve_udp(&udp, &udp_zone, &udp_stop))?;
+        let tcp_stop = stop.clone();
+        thread::Builder::new()
+            .name("hyper-dns-test-tcp".to_string())
+            .spawn(move || serve_tcp(&tcp, &zone, &tcp_stop))?;
+
+        Ok(TestServer {
+            addr: addr,
+            stop: stop,
+        })
+    }
+
+    /// The address the server is listening on.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// A connector wrapping `connector` which queries this server.
+    pub fn connector<C>(&self, connector: C) -> DnsConnector<C> {
+        DnsConnector::new(self.addr, connector)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+fn serve_udp(udp: &UdpSocket, zone: &Zone, stop: &AtomicBool) {
+    let mut buf = [0; 4096];
+    while !stop.load(Ordering::SeqCst) {
+        match udp.recv_from(&mut buf) {
+            Ok((len, from)) => {
+                if let Some(response) = zone.respond(&buf[..len]) {
+                    let _ = udp.send_to(&response, from);
+                }
+            }
+            Err(e) => match e.kind() {
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {}
+                _ => return,
+            },
+        }
+    }
+}
+
+fn serve_tcp(tcp: &TcpListener, zone: &Arc<Zone>, stop: &AtomicBool) {
+    while !stop.load(Ordering::SeqCst) {
+        match tcp.accept() {
+            Ok((stream, _)) => {
+                let zone = zone.clone();
+                let _ = thread::Builder::new()
+                    .name("hyper-dns-test-conn".to_string())
+                    .spawn(move || serve_connection(stream, &zone));
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(POLL),
+            Err(_) => return,
+        }
+    }
+}
+
+/// Answer the length-prefixed queries on `stream` until it is closed, as
+/// clients keep connections open between queries.
+fn serve_connection(mut stream: TcpStream, zone: &Zone) {
+    if stream.set_nonblocking(false).is_err() {
+        return;
+    }
+
+    loop {
+        let mut len = [0; 2];
+        if stream.read_exact(&mut len).is_err() {
+            return;
+        }
+        let mut buf = vec![0; (usize::from(len[0]) << 8) | usize::from(len[1])];
+        if stream.read_exact(&mut buf).is_err() {
+            return;
+        }
+
+        if let Some(response) = zone.respond(&buf) {
+            let len = [(response.len() >> 8) as u8, response.len() as u8];
+            if stream
+                .write_all(&len)
+                .and_then(|()| stream.write_all(&response))
+                .is_err()
+            {
+                return;
+            }
+        }
+    }
+}
+
+/// `name` as a fully qualified, lowercase name.
+fn parse(name: &str) -> Name {
+    let fqdn = if name.ends_with('.') {
+        name.to_string()
+    } else {
+        format!("{}.", name)
+    };
+    Name::parse(&fqdn, None)
+        .unwrap_or_else(|_| panic!("invalid name: {}", name))
+        .to_lowercase()
+}
+
+#[cfg(all(test, any(feature = "tokio", feature = "async-std", feature = "smol")))]
+mod tests {
+    use super::*;
+    use futures::future::{self, Ready};
+    use hyper::service::Service;
+    use hyper::Uri;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::task::{Context, Poll};
+    use tokio::runtime::Runtime;
+    use tokio::time;
+    use trust_dns::op::Query;
+
+    struct Noop;
+
+    impl Service<Uri> for Noop {
+        type Response = ();
+        type Error = io::Error;
+        type Future = Ready<io::Result<()>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _uri: Uri) -> Self::Future {
+            future::ok(())
+        }
+    }
+
+    fn ask(server: &TestServer, name: &str, record_type: RecordType) -> io::Result<Message> {
+        let mut request = Message::new();
+        request
+            .set_id(7)
+            .set_message_type(MessageType::Query)
+            .set_recursion_desired(true)
+            .add_query(Query::query(parse(name), record_type));
+
+        let udp = UdpSocket::bind("127.0.0.1:0")?;
+        udp.set_read_timeout(Some(Duration::from_millis(500)))?;
+        udp.send_to(&request.to_vec().unwrap(), server.addr())?;
+
+        let mut buf = [0; 4096];
+        let len = udp.recv(&mut buf)?;
+        Ok(Message::from_vec(&buf[..len]).unwrap())
+    }
+
+    #[test]
+    fn serves_zone() {
+        let mut zone = Zone::new();
+        zone.add("web.example.com", 60, RData::A(Ipv4Addr::new(192, 0, 2, 1)));
+        zone.add(
+            "www.example.com",
+            60,
+            RData::CNAME(parse("web.example.com")),
+        );
+        let server = TestServer::start(zone).unwrap();
+
+        let response = ask(&server, "WWW.example.com", RecordType::A).unwrap();
+        assert_eq!(response.id(), 7);
+        assert_eq!(response.answers().len(), 2);
+        assert_eq!(response.answers()[1].ttl(), 60);
+
+        let response = ask(&server, "missing.example.com", RecordType::A).unwrap();
+        assert_eq!(response.response_code(), ResponseCode::NXDomain);
+    }
+
+    #[test]
+    fn misbehaves_as_told() {
+        let mut zone = Zone::new();
+        zone.set_silent("slow.example.com");
+        let server = TestServer::start(zone).unwrap();
+
+        assert!(ask(&server, "slow.example.com", RecordType::A).is_err());
+    }
+
+    #[test]
+    fn serves_connector() {
+        let mut zone = Zone::new();
+        zone.add("web.example.com", 60, RData::A(Ipv4Addr::new(192, 0, 2, 1)));
+        zone.set_silent("slow.example.com");
+        let server = TestServer::start(zone).unwrap();
+        let mut connector = server.connector(Noop);
+        let runtime = Runtime::new().unwrap();
+        let uri = |uri: &str| uri.parse::<Uri>().unwrap();
+
+        let connection = runtime
+            .block_on(connector.call(uri("http://web.example.com:80/")))
+            .unwrap();
+        assert_eq!(
+            connection.resolution().ip,
+            IpAddr::from(Ipv4Addr::new(192, 0, 2, 1))
+        );
+
+        let err = runtime
+            .block_on(connector.call(uri("http://missing.example.com:80/")))
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+        // Queries for silent names are left to time out.
+        let slow = connector.call(uri("http://slow.example.com:80/"));
+        let unanswered =
+            runtime.block_on(async { time::timeout(Duration::from_millis(300), slow).await });
+        assert!(unanswered.is_err());
+    }
+}
@@ -0,0 +1,10 @@
+//! The clock, which on wasm32 is the browser's, as std's panics there.
+
+#[cfg(all(feature = "json", not(target_arch = "wasm32")))]
+pub use std::time::UNIX_EPOCH;
+#[cfg(not(target_arch = "wasm32"))]
+pub use std::time::{Instant, SystemTime};
+#[cfg(all(feature = "json", target_arch = "wasm32"))]
+pub use web_time::UNIX_EPOCH;
+#[cfg(target_arch = "wasm32")]
+pub use web_time::{Instant, SystemTime};
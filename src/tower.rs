@@ -0,0 +1,118 @@
+//! A resolver for std futures based clients, such as hyper 0.13 onwards,
+//! enabled by the `tower` feature.
+
+use futures::future::BoxFuture;
+use std::fmt::{self, Display};
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::vec;
+use tower_service::Service;
+
+use crate::DnsResolver;
+
+/// A resolver implementing `tower::Service` for host names, the contract for
+/// resolvers in hyper 0.13 and 0.14's `HttpConnector::new_with_resolver` and
+/// hyper-util's for hyper 1.x.
+///
+/// Hosts are resolved by a `DnsResolver` on the runtime the returned futures
+/// are polled on, which must be a tokio one for queries sent to DNS servers.
+#[derive(Debug, Clone)]
+pub struct TowerResolver {
+    resolver: DnsResolver,
+}
+
+impl TowerResolver {
+    /// Resolve hosts with `resolver`, e.g. from `DnsConnector::resolver`.
+    pub fn new(resolver: DnsResolver) -> io::Result<TowerResolver> {
+        Ok(TowerResolver { resolver: resolver })
+    }
+
+    /// Resolve `host`, with port 0 on the addresses.
+    pub(crate) fn lookup(&self, host: &str) -> ResolveFuture {
+        ResolveFuture {
+            addrs: self.resolver.resolve(host, 0),
+        }
+    }
+}
+
+/// Names are taken as anything which displays as a host, which covers the
+/// `Name` types of each hyper version without depending on them. The
+/// addresses have port 0, which hyper replaces with the URI's.
+impl<N> Service<N> for TowerResolver
+where
+    N: Display,
+{
+    type Response = vec::IntoIter<SocketAddr>;
+    type Error = io::Error;
+    type Future = ResolveFuture;
+
+    fn poll_ready(&mut self, _: &mut Context) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, name: N) -> Self::Future {
+        self.lookup(&name.to_string())
+    }
+}
+
+/// The addresses a host resolved to.
+pub struct ResolveFuture {
+    addrs: BoxFuture<'static, io::Result<Vec<SocketAddr>>>,
+}
+
+impl fmt::Debug for ResolveFuture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ResolveFuture").finish()
+    }
+}
+
+impl Future for ResolveFuture {
+    type Output = io::Result<vec::IntoIter<SocketAddr>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        self.addrs
+            .as_mut()
+            .poll(cx)
+            .map(|addrs| addrs.map(|addrs| addrs.into_iter()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{check_adapter, StaticResolver};
+    use futures::executor::block_on;
+
+    #[test]
+    fn resolves_with_the_resolver() {
+        check_adapter(|resolver, host| {
+            let mut resolver = TowerResolver::new(resolver).unwrap();
+            let addrs = block_on(resolver.call(host))?;
+            Ok(addrs.map(|addr| addr.ip()).collect())
+        });
+    }
+
+    /// A name type of another crate, which only displays as the host.
+    struct Name(&'static str);
+
+    impl Display for Name {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str(self.0)
+        }
+    }
+
+    #[test]
+    fn resolves_displayed_names_to_port_zero() {
+        let static_resolver = StaticResolver::new();
+        static_resolver.add_ip("web.example.com", "192.0.2.1".parse().unwrap());
+        let mut resolver = TowerResolver::new(static_resolver.connector(()).resolver()).unwrap();
+
+        let addrs: Vec<SocketAddr> = block_on(resolver.call(Name("web.example.com")))
+            .unwrap()
+            .collect();
+        assert_eq!(addrs, vec!["192.0.2.1:0".parse::<SocketAddr>().unwrap()]);
+    }
+}
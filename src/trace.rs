@@ -0,0 +1,169 @@
+//! `tracing` spans covering lookups, enabled by the `trace` feature.
+//!
+//! Without the feature these are no-ops, so the rest of the crate does not
+//! need to care whether it is enabled.
+//!
+//! Spans which talk to a DNS server also carry the OpenTelemetry semantic
+//! convention attributes (`dns.question.name`, `server.address`, ...) so
+//! that APM tools render them natively.
+
+use futures::future::Future;
+use std::io;
+use std::marker::PhantomData;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+#[cfg(feature = "trace")]
+use tracing::field;
+#[cfg(feature = "trace")]
+use tracing_futures::Instrument;
+
+use crate::{RecordType, ResolutionSource};
+
+/// A span, which is disabled unless the `trace` feature is enabled.
+#[derive(Debug, Clone)]
+pub struct Span {
+    #[cfg(feature = "trace")]
+    span: tracing::Span,
+}
+
+/// Guard returned by `Span::enter`, the span is exited when it is dropped.
+pub struct Entered<'a> {
+    #[cfg(feature = "trace")]
+    _entered: tracing::span::Entered<'a>,
+    _span: PhantomData<&'a Span>,
+}
+
+/// A future run in a span, returned by `Span::instrument`.
+pub struct Instrumented<F> {
+    #[cfg(feature = "trace")]
+    inner: tracing_futures::Instrumented<F>,
+    #[cfg(not(feature = "trace"))]
+    inner: F,
+}
+
+impl<F: Future + Unpin> Future for Instrumented<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        Pin::new(&mut self.inner).poll(cx)
+    }
+}
+
+#[cfg(feature = "trace")]
+impl Span {
+    /// The span covering the resolution of a request's host, including any
+    /// interceptors.
+    pub fn resolve(host: &str) -> Span {
+        Span {
+            span: tracing::info_span!("resolve", host = %host, source = field::Empty),
+        }
+    }
+
+    /// The span covering a lookup on the DNS server.
+    pub fn lookup(host: &str, record_type: RecordType, server: SocketAddr) -> Span {
+        Span {
+            span: tracing::info_span!(
+                "lookup",
+                host = %host,
+                record_type = ?record_type,
+                server = %server,
+                selected = field::Empty,
+                otel.kind = "client",
+                otel.status_code = field::Empty,
+                dns.question.name = %host,
+                server.address = %server.ip(),
+                server.port = server.port(),
+                error.type = field::Empty
+            ),
+        }
+    }
+
+    /// The span covering a single query sent to the DNS server.
+    pub fn query(name: &str, record_type: ::trust_dns::rr::RecordType, server: SocketAddr) -> Span {
+        Span {
+            span: tracing::debug_span!(
+                "query",
+                name = %name,
+                record_type = ?record_type,
+                server = %server,
+                otel.kind = "client",
+                otel.status_code = field::Empty,
+                dns.question.name = %name,
+                dns.question.type = %record_type,
+                server.address = %server.ip(),
+                server.port = server.port(),
+                network.transport = "tcp",
+                network.protocol.name = "dns",
+                error.type = field::Empty
+            ),
+        }
+    }
+
+    /// Mark the span as failed with `err`.
+    pub fn record_error(&self, err: &io::Error) {
+        self.span.record("otel.status_code", "ERROR");
+        self.span
+            .record("error.type", crate::counters::failure_class(err));
+    }
+
+    /// Record where the addresses came from, `Interceptor` being a cache hit.
+    pub fn record_source(&self, source: ResolutionSource) {
+        self.span.record("source", field::debug(source));
+    }
+
+    /// Record the address the selection policy picked.
+    pub fn record_selected(&self, ip: IpAddr) {
+        self.span.record("selected", field::display(ip));
+    }
+
+    /// Enter the span, so that spans created while building futures are its
+    /// children.
+    pub fn enter(&self) -> Entered<'_> {
+        Entered {
+            _entered: self.span.enter(),
+            _span: PhantomData,
+        }
+    }
+
+    /// Run `future` in the span.
+    pub fn instrument<F: Future>(&self, future: F) -> Instrumented<F> {
+        Instrumented {
+            inner: future.instrument(self.span.clone()),
+        }
+    }
+}
+
+#[cfg(not(feature = "trace"))]
+impl Span {
+    pub fn resolve(_host: &str) -> Span {
+        Span {}
+    }
+
+    pub fn lookup(_host: &str, _record_type: RecordType, _server: SocketAddr) -> Span {
+        Span {}
+    }
+
+    pub fn query(
+        _name: &str,
+        _record_type: ::trust_dns::rr::RecordType,
+        _server: SocketAddr,
+    ) -> Span {
+        Span {}
+    }
+
+    pub fn record_source(&self, _source: ResolutionSource) {}
+
+    pub fn record_selected(&self, _ip: IpAddr) {}
+
+    pub fn record_error(&self, _err: &io::Error) {}
+
+    pub fn enter(&self) -> Entered<'_> {
+        Entered { _span: PhantomData }
+    }
+
+    pub fn instrument<F: Future>(&self, future: F) -> Instrumented<F> {
+        Instrumented { inner: future }
+    }
+}
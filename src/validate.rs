@@ -0,0 +1,199 @@
+//! Validation that a response answers the question which was asked, and
+//! isn't unreasonably large.
+
+use trust_dns::op::Message;
+use trust_dns::rr::{DNSClass, Name, RData, RecordType};
+
+use crate::Redaction;
+
+/// Check that `response` is for a query of `name` and `record_type`, and
+/// that its answers are records of that type for the name, or CNAME records
+/// leading from it, returning what was wrong if not, with any names in it
+/// redacted with `redaction`.
+pub fn validate(
+    response: &Message,
+    name: &Name,
+    record_type: RecordType,
+    redaction: Redaction,
+) -> Result<(), String> {
+    let redact = |name: &Name| redaction.apply(&name.to_string()).into_owned();
+    match response.queries() {
+        [ref query] => {
+            if query.name() != name
+                || query.query_type() != record_type
+                || query.query_class() != DNSClass::IN
+            {
+                return Err(format!(
+                    "response is for {} {} {}",
+                    redact(query.name()),
+                    query.query_class(),
+                    query.query_type()
+                ));
+            }
+        }
+        queries => {
+            return Err(format!(
+                "response has {} questions, expected 1",
+                queries.len()
+            ))
+        }
+    }
+
+    // The names answers may be for: the name queried, and the targets of any
+    // CNAME records for it.
+    let mut owners = vec![name.clone()];
+
+    for answer in response.answers() {
+        if answer.dns_class() != DNSClass::IN {
+            return Err(format!("answer has class {}", answer.dns_class()));
+        }
+
+        if !owners.contains(answer.name()) {
+            return Err(format!("answer is for {}", redact(answer.name())));
+        }
+
+        match *answer.rdata() {
+            RData::CNAME(ref target) if answer.rr_type() == RecordType::CNAME => {
+                owners.push(target.clone())
+            }
+            _ if answer.rr_type() == record_type => {}
+            _ => return Err(format!("answer has type {}", answer.rr_type())),
+        }
+    }
+
+    Ok(())
+}
+
+/// Check that `response` has at most `max_records` records in total, and is
+/// at most `max_size` bytes.
+///
+/// The DNS client doesn't expose the payload it received, so the size is
+/// that of the response when encoded again, which may differ slightly.
+pub fn check_limits(
+    response: &Message,
+    max_records: Option<usize>,
+    max_size: Option<usize>,
+) -> Result<(), String> {
+    if let Some(max_records) = max_records {
+        let records =
+            response.answers().len() + response.name_servers().len() + response.additionals().len();
+        if records > max_records {
+            return Err(format!(
+                "response has {} records, the limit is {}",
+                records, max_records
+            ));
+        }
+    }
+
+    if let Some(max_size) = max_size {
+        let size = response.to_vec().map_err(|e| e.to_string())?.len();
+        if size > max_size {
+            return Err(format!(
+                "response is {} bytes, the limit is {}",
+                size, max_size
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use std::str::FromStr;
+    use trust_dns::op::Query;
+    use trust_dns::rr::Record;
+
+    fn name(name: &str) -> Name {
+        Name::from_str(name).unwrap()
+    }
+
+    fn a(owner: &str) -> Record {
+        Record::from_rdata(name(owner), 60, RData::A(Ipv4Addr::new(10, 0, 0, 1)))
+    }
+
+    fn response(question: &str, record_type: RecordType, answers: Vec<Record>) -> Message {
+        let mut response = Message::new();
+        response.add_query(Query::query(name(question), record_type));
+        for answer in answers {
+            response.add_answer(answer);
+        }
+        response
+    }
+
+    #[test]
+    fn accepts_matching_answers() {
+        let cname = Record::from_rdata(
+            name("www.example.com."),
+            300,
+            RData::CNAME(name("lb.example.com.")),
+        );
+        let res = response(
+            "www.example.com.",
+            RecordType::A,
+            vec![cname, a("lb.example.com.")],
+        );
+
+        assert!(validate(
+            &res,
+            &name("WWW.example.com."),
+            RecordType::A,
+            Redaction::Off
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn rejects_mismatched_responses() {
+        let qname = name("www.example.com.");
+
+        let res = response("evil.example.net.", RecordType::A, vec![]);
+        assert!(validate(&res, &qname, RecordType::A, Redaction::Off).is_err());
+
+        let res = response("www.example.com.", RecordType::AAAA, vec![]);
+        assert!(validate(&res, &qname, RecordType::A, Redaction::Off).is_err());
+
+        let res = response(
+            "www.example.com.",
+            RecordType::A,
+            vec![a("evil.example.net.")],
+        );
+        assert!(validate(&res, &qname, RecordType::A, Redaction::Off).is_err());
+
+        let mut chaos = a("www.example.com.");
+        chaos.set_dns_class(DNSClass::CH);
+        let res = response("www.example.com.", RecordType::A, vec![chaos]);
+        assert!(validate(&res, &qname, RecordType::A, Redaction::Off).is_err());
+    }
+
+    #[test]
+    fn redacts_names_in_reasons() {
+        let redaction = Redaction::Hash([7; 16]);
+        let qname = name("secret.example.com.");
+
+        let res = response("secret.example.net.", RecordType::A, vec![]);
+        let reason = validate(&res, &qname, RecordType::A, redaction).unwrap_err();
+        assert!(!reason.contains("secret"), "{}", reason);
+
+        let res = response(
+            "secret.example.com.",
+            RecordType::A,
+            vec![a("secret.example.net.")],
+        );
+        let reason = validate(&res, &qname, RecordType::A, redaction).unwrap_err();
+        assert!(!reason.contains("secret"), "{}", reason);
+    }
+
+    #[test]
+    fn limits_responses() {
+        let answers = (0..10).map(|_| a("www.example.com.")).collect();
+        let res = response("www.example.com.", RecordType::A, answers);
+
+        assert!(check_limits(&res, Some(10), None).is_ok());
+        assert!(check_limits(&res, Some(9), None).is_err());
+        assert!(check_limits(&res, None, Some(512)).is_ok());
+        assert!(check_limits(&res, None, Some(64)).is_err());
+    }
+}
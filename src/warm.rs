@@ -0,0 +1,293 @@
+//! Warming the path to hosts at startup, so that the first requests after a
+//! deploy don't wait on DNS or on connecting.
+
+use futures::future::{self, BoxFuture, Either, FutureExt, Ready, TryFutureExt};
+use hyper::service::Service;
+use hyper::Uri;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use crate::addrs::default_port;
+use crate::connect::ConnectFuture;
+use crate::time::Instant;
+use crate::{Connection, DnsConnector, Resolution};
+
+/// How long a parked connection is handed out for by default, as servers
+/// close idle connections.
+const MAX_IDLE: Duration = Duration::from_secs(60);
+
+/// Connections parked by `WarmConnector::warm`, keyed by `park_key`, with
+/// when each was parked.
+type Parked<T> = Arc<Mutex<HashMap<String, Vec<(Connection<T>, Instant)>>>>;
+
+/// A connector which can resolve hosts, and connect to them, before they are
+/// requested, parking the connections until a request to the same scheme,
+/// host and port takes them.
+///
+/// Clones share the parked connections.
+pub struct WarmConnector<C>
+where
+    C: Service<Uri>,
+{
+    dns: DnsConnector<C>,
+    max_idle: Duration,
+    parked: Parked<C::Response>,
+}
+
+impl<C> WarmConnector<C>
+where
+    C: Service<Uri> + Send + 'static,
+    C::Response: Send + 'static,
+    C::Future: Send + Unpin + 'static,
+    C::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    /// Wrap a `DnsConnector`, whose settings govern resolving and connecting.
+    pub fn new(dns: DnsConnector<C>) -> WarmConnector<C> {
+        WarmConnector {
+            dns: dns,
+            max_idle: MAX_IDLE,
+            parked: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// How long parked connections are handed to requests for, after which
+    /// they are dropped as the server has likely closed them. Defaults to 60
+    /// seconds.
+    pub fn set_max_idle(&mut self, max_idle: Duration) {
+        self.max_idle = max_idle;
+    }
+
+    /// Resolve the hosts of `uris` as requests to them would be, warming the
+    /// connector's DNS connections, pins and statistics. If `connect` is set,
+    /// a connection is also established to each and parked for the next
+    /// request to it.
+    ///
+    /// Each URI's resolution or error is keyed by the URI as given; the
+    /// future itself doesn't fail.
+    pub fn warm(
+        &self,
+        uris: &[Uri],
+        connect: bool,
+    ) -> BoxFuture<'static, io::Result<HashMap<String, io::Result<Resolution>>>> {
+        let warming: Vec<_> = uris
+            .iter()
+            .map(|uri| {
+                let key = uri.to_string();
+                let warmed: BoxFuture<'static, io::Result<Resolution>> =
+                    match (connect, park_key(uri)) {
+                        (true, Some(park)) => {
+                            let parked = self.parked.clone();
+                            let max_idle = self.max_idle;
+                            self.dns
+                                .connect(uri.clone(), false)
+                                .map_ok(move |connection| {
+                                    let resolution = connection.resolution().clone();
+                                    let mut parked = parked.lock().unwrap();
+                                    sweep(&mut parked, max_idle);
+                                    parked
+                                        .entry(park)
+                                        .or_default()
+                                        .push((connection, Instant::now()));
+                                    resolution
+                                })
+                                .boxed()
+                        }
+                        (false, Some(_)) => self
+                            .dns
+                            .resolve(uri, false)
+                            .map_ok(|resolved| resolved.resolution)
+                            .boxed(),
+                        (_, None) => future::err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("no host in {}", uri),
+                        ))
+                        .boxed(),
+                    };
+                warmed.map(move |res| (key, res))
+            })
+            .collect();
+
+        future::join_all(warming)
+            .map(|results| Ok(results.into_iter().collect()))
+            .boxed()
+    }
+
+    /// How many connections are parked, across all hosts, not counting
+    /// those which have been idle for too long.
+    pub fn parked(&self) -> usize {
+        let mut parked = self.parked.lock().unwrap();
+        sweep(&mut parked, self.max_idle);
+        parked.values().map(Vec::len).sum()
+    }
+
+    /// The most recently parked connection for `key` which hasn't been idle
+    /// for too long, dropping those which have.
+    fn take(&self, key: &str) -> Option<Connection<C::Response>> {
+        let mut parked = self.parked.lock().unwrap();
+        let connection = {
+            let connections = parked.get_mut(key)?;
+            let max_idle = self.max_idle;
+            connections.retain(|&(_, since)| since.elapsed() < max_idle);
+            connections.pop()
+        };
+        if parked.get(key).is_some_and(Vec::is_empty) {
+            parked.remove(key);
+        }
+        connection.map(|(connection, _)| connection)
+    }
+}
+
+impl<C> Clone for WarmConnector<C>
+where
+    C: Service<Uri>,
+{
+    fn clone(&self) -> WarmConnector<C> {
+        WarmConnector {
+            dns: self.dns.clone(),
+            max_idle: self.max_idle,
+            parked: self.parked.clone(),
+        }
+    }
+}
+
+impl<C> fmt::Debug for WarmConnector<C>
+where
+    C: Service<Uri>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WarmConnector")
+            .field("max_idle", &self.max_idle)
+            .field("hosts", &self.parked.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl<C> Service<Uri> for WarmConnector<C>
+where
+    C: Service<Uri> + Send + 'static,
+    C::Response: Send + 'static,
+    C::Future: Send + Unpin + 'static,
+    C::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    type Response = Connection<C::Response>;
+    type Error = io::Error;
+    type Future = Either<Ready<io::Result<Connection<C::Response>>>, ConnectFuture<C>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.dns.poll_ready(cx).map(Ok)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        match park_key(&uri).and_then(|key| self.take(&key)) {
+            Some(connection) => Either::Left(future::ok(connection)),
+            None => Either::Right(self.dns.call(uri)),
+        }
+    }
+}
+
+/// Drop parked connections which have been idle for longer than `max_idle`,
+/// and the hosts left with none, so hosts which are never requested don't
+/// hold connections forever.
+fn sweep<T>(parked: &mut HashMap<String, Vec<(Connection<T>, Instant)>>, max_idle: Duration) {
+    parked.retain(|_, connections| {
+        connections.retain(|&(_, since)| since.elapsed() < max_idle);
+        !connections.is_empty()
+    });
+}
+
+/// The scheme, host and port of `uri`, which a parked connection must match
+/// to be used for a request.
+fn park_key(uri: &Uri) -> Option<String> {
+    let scheme = uri.scheme_str().unwrap_or("http");
+    let host = uri.host()?;
+    let port = uri.port_u16().unwrap_or_else(|| default_port(scheme));
+    Some(format!(
+        "{}://{}:{}",
+        scheme.to_lowercase(),
+        host.to_lowercase(),
+        port
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::StaticResolver;
+    use futures::executor::block_on;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct Counting(Arc<AtomicUsize>);
+
+    impl Service<Uri> for Counting {
+        type Response = ();
+        type Error = io::Error;
+        type Future = Ready<io::Result<()>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _uri: Uri) -> Self::Future {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            future::ok(())
+        }
+    }
+
+    #[test]
+    fn keys_on_scheme_host_and_port() {
+        let key = |uri: &str| park_key(&uri.parse().unwrap());
+        assert_eq!(key("http://Example.com/a"), key("http://example.com:80/b"));
+        assert_ne!(key("http://example.com"), key("https://example.com"));
+        assert_eq!(key("/path"), None);
+    }
+
+    #[test]
+    fn requests_take_parked_connections() {
+        let resolver = StaticResolver::new();
+        resolver.add_ip("web.example.com", "192.0.2.1".parse().unwrap());
+        let connects = Arc::new(AtomicUsize::new(0));
+        let mut warm = WarmConnector::new(resolver.connector(Counting(connects.clone())));
+
+        let uris = vec![
+            "http://web.example.com:80/".parse().unwrap(),
+            "http://missing.example.com:80/".parse().unwrap(),
+        ];
+        let warmed = block_on(warm.warm(&uris, true)).unwrap();
+        assert!(warmed["http://web.example.com:80/"].is_ok());
+        assert!(warmed["http://missing.example.com:80/"].is_err());
+        assert_eq!(connects.load(Ordering::SeqCst), 1);
+        assert_eq!(warm.parked(), 1);
+
+        let uri: Uri = "http://web.example.com:80/index.html".parse().unwrap();
+        block_on(warm.call(uri.clone())).unwrap();
+        assert_eq!(connects.load(Ordering::SeqCst), 1);
+        assert_eq!(warm.parked(), 0);
+
+        block_on(warm.call(uri)).unwrap();
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn sweeps_idle_connections_of_other_hosts() {
+        let resolver = StaticResolver::new();
+        resolver.add_ip("a.example.com", "192.0.2.1".parse().unwrap());
+        resolver.add_ip("b.example.com", "192.0.2.2".parse().unwrap());
+        let connects = Arc::new(AtomicUsize::new(0));
+        let mut warm = WarmConnector::new(resolver.connector(Counting(connects.clone())));
+        warm.set_max_idle(Duration::from_millis(0));
+
+        let uris = vec![
+            "http://a.example.com:80/".parse().unwrap(),
+            "http://b.example.com:80/".parse().unwrap(),
+        ];
+        block_on(warm.warm(&uris, true)).unwrap();
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+        assert_eq!(warm.parked(), 0);
+        assert!(warm.parked.lock().unwrap().is_empty());
+    }
+}